@@ -0,0 +1,44 @@
+struct ColorTargets {
+    @location(0) color: vec4f,
+}
+
+struct Attributes {
+    @location(0) position: vec2f,
+    @location(1) uv: vec2f,
+    @location(2) color: vec4f,
+};
+
+struct Interpolated {
+    @builtin(position) clip_position: vec4f,
+    @location(0) uv: vec2f,
+    @location(1) color: vec4f,
+}
+
+@group(0) @binding(0)
+var font_texture: texture_2d<f32>;
+
+@group(0) @binding(1)
+var font_sampler: sampler;
+
+@vertex
+fn vs_main(in: Attributes) -> Interpolated {
+    var out: Interpolated;
+    out.clip_position = vec4f(in.position, 0.0, 1.0);
+    out.uv = in.uv;
+    out.color = in.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: Interpolated) -> ColorTargets {
+    var out: ColorTargets;
+
+    // rectangles have negative texture coordinates and are drawn in a solid colour
+    var coverage = 1.0;
+    if in.uv.x >= 0.0 {
+        coverage = textureSample(font_texture, font_sampler, in.uv).a;
+    }
+
+    out.color = vec4f(in.color.rgb, in.color.a * coverage);
+    return out;
+}
@@ -47,13 +47,35 @@ fn vs_main(in: Attributes) -> Interpolated {
     return out;
 }
 
+// opaque and cutout geometry
 @fragment
 fn fs_main(in: Interpolated) -> ColorTargets {
     var out: ColorTargets;
 
-    let light = min(in.light.xyz + vec3f(in.light.w), vec3f(1.0));
-    out.color = textureSample(texture_array, texture_array_sampler, in.uv, in.texture_index) * vec4f(light, 1.0);
-    // out.color = vec4f(light, 1.0); white world
+    let albedo = textureSample(texture_array, texture_array_sampler, in.uv, in.texture_index);
+
+    // alpha cutout
+    if albedo.a < 0.5 {
+        discard;
+    }
+
+    out.color = vec4f(albedo.rgb * get_light(in.light), 1.0);
+    // out.color = vec4f(get_light(in.light), 1.0); white world
 
     return out;
 }
+
+// translucent geometry, blended with the geometry behind it
+@fragment
+fn fs_translucent(in: Interpolated) -> ColorTargets {
+    var out: ColorTargets;
+
+    let albedo = textureSample(texture_array, texture_array_sampler, in.uv, in.texture_index);
+    out.color = vec4f(albedo.rgb * get_light(in.light), albedo.a);
+
+    return out;
+}
+
+fn get_light(light: vec4f) -> vec3f {
+    return min(light.xyz + vec3f(light.w), vec3f(1.0));
+}
@@ -0,0 +1,429 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use generational_arena::Index;
+use glam::IVec3;
+use winit::error::EventLoopError;
+
+use crate::{
+    core::tasks::Tasks,
+    dedicated_server::DedicatedServer,
+    load_block_registry,
+    net::{client::Client, NetError, DEFAULT_PORT},
+    open_terrain,
+    renderer::terrain::{
+        export::{MeshExportError, MeshExportFormat, TerrainMesh},
+        TerrainRenderer,
+    },
+    run_game,
+    settings::Settings,
+    terrain::{
+        chunk::CHUNK_SIZE,
+        load_area::{AreaShape, LoadArea},
+        position_types::{ChunkPosition, GlobalBlockPosition},
+        save::{SaveError, WorldSave},
+        schematic::{NamespacedIds, Schematic, SchematicError, SchematicVersion},
+        vox::{BlockColors, VoxError, VoxScene},
+        Terrain,
+    },
+    util::size::AsSize3,
+    REMOTE_WORLDS_DIRECTORY_PATH,
+};
+
+const USAGE: &str = "\
+usage:
+    voxels                  run the game
+    voxels vox import <file.vox> <x> <y> <z> [--mapping <mapping.toml>]
+                            place a MagicaVoxel model in the world with its minimum corner at
+                            the given block position
+    voxels vox export <file.vox> <x1> <y1> <z1> <x2> <y2> <z2> [--mapping <mapping.toml>]
+                            save the blocks in the box between two corners to a MagicaVoxel file
+    voxels schem import <file.schem> <x> <y> <z> [--mapping <ids.toml>]
+                            place a Sponge schematic in the world with its minimum corner at the
+                            given block position
+    voxels schem export <file.schem> <x1> <y1> <z1> <x2> <y2> <z2> [--mapping <ids.toml>] [--v2]
+                            save the blocks in the box between two corners to a Sponge schematic,
+                            in version 3 of the format unless `--v2` is given
+    voxels mesh export <file.obj|file.glb> <x1> <y1> <z1> <x2> <y2> <z2> [--no-light]
+                            save the mesh of the chunks containing the box between two corners
+                            as Wavefront OBJ or binary glTF, with light as vertex colours unless
+                            `--no-light` is given
+    voxels server [--port <port>] [--anchor <x> <y> <z>]...
+                            run a dedicated server without a window, keeping the chunks around
+                            each player and anchor point loaded. Admin commands are read from
+                            stdin
+    voxels connect <host>[:<port>]
+                            play on a server
+";
+
+/// Table of the namespaced IDs used in schematics, unless another is given with `--mapping`
+const SCHEMATIC_IDS_PATH: &str = "assets/schematic_ids.toml";
+
+/// Number of chunks loaded around an edited region, so that the features of the neighbouring
+/// chunks are placed before the region is read or edited
+const REGION_LOAD_MARGIN: i32 = 2;
+
+/// Time to wait between terrain updates while loading a region
+const REGION_LOAD_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Run the subcommand given by the command line arguments, which operates on the world without
+/// opening a window, other than `connect`
+pub fn run(args: &[String], saved_settings: Option<Settings>) -> Result<(), CliError> {
+    let settings = saved_settings.clone().unwrap_or_default();
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+
+    match args.as_slice() {
+        ["help" | "--help" | "-h"] => {
+            print!("{}", USAGE);
+            Ok(())
+        }
+        ["vox", "import", path, rest @ ..] => {
+            let (positional, mapping_path) = split_mapping_option(rest)?;
+            let [x, y, z] = positional.as_slice() else {
+                return Err(CliError::Usage);
+            };
+            let origin = parse_position(x, y, z)?;
+
+            let scene = VoxScene::load(path)?;
+            let mut tasks = Tasks::new(settings.performance.worker_threads);
+            let mut terrain = open_terrain();
+            let block_colors = load_block_colors(&terrain, mapping_path)?;
+
+            // load the chunks the model will be placed in
+            let clipboard = scene.to_clipboard(&block_colors);
+            let max = origin + GlobalBlockPosition::from(clipboard.size().as_ivec3() - 1);
+            load_region(&mut terrain, &mut tasks, origin, max);
+
+            let blocks_changed = terrain.paste(&clipboard, origin, false);
+            save_terrain(&mut terrain, &mut tasks);
+
+            log::info!("placed {} blocks from {}", blocks_changed, path);
+            Ok(())
+        }
+        ["vox", "export", path, rest @ ..] => {
+            let (positional, mapping_path) = split_mapping_option(rest)?;
+            let [x1, y1, z1, x2, y2, z2] = positional.as_slice() else {
+                return Err(CliError::Usage);
+            };
+            let a = parse_position(x1, y1, z1)?;
+            let b = parse_position(x2, y2, z2)?;
+
+            let mut tasks = Tasks::new(settings.performance.worker_threads);
+            let mut terrain = open_terrain();
+            let block_colors = load_block_colors(&terrain, mapping_path)?;
+
+            load_region(&mut terrain, &mut tasks, a, b);
+
+            let scene = VoxScene::copy_from_terrain(&terrain, a, b, &block_colors)?;
+            scene.save(path)?;
+
+            // the terrain may have been generated for the first time
+            save_terrain(&mut terrain, &mut tasks);
+
+            log::info!("saved {} models to {}", scene.models.len(), path);
+            Ok(())
+        }
+        ["schem", "import", path, rest @ ..] => {
+            let (positional, mapping_path) = split_mapping_option(rest)?;
+            let [x, y, z] = positional.as_slice() else {
+                return Err(CliError::Usage);
+            };
+            let origin = parse_position(x, y, z)?;
+
+            let schematic = Schematic::load(path)?;
+            let namespaced_ids = NamespacedIds::load(
+                mapping_path
+                    .as_deref()
+                    .unwrap_or(SCHEMATIC_IDS_PATH.as_ref()),
+            )?;
+            let mut tasks = Tasks::new(settings.performance.worker_threads);
+            let mut terrain = open_terrain();
+
+            // load the chunks the schematic will be placed in
+            let max = origin + GlobalBlockPosition::from(schematic.size.as_ivec3() - 1);
+            load_region(&mut terrain, &mut tasks, origin, max);
+
+            let import = schematic.place_in_terrain(&mut terrain, origin, &namespaced_ids);
+            save_terrain(&mut terrain, &mut tasks);
+
+            log::info!("placed {} blocks from {}", import.blocks_changed, path);
+            Ok(())
+        }
+        ["schem", "export", path, rest @ ..] => {
+            let version = if rest.contains(&"--v2") {
+                SchematicVersion::V2
+            } else {
+                SchematicVersion::V3
+            };
+            let rest = rest
+                .iter()
+                .copied()
+                .filter(|&arg| arg != "--v2")
+                .collect::<Vec<_>>();
+            let (positional, mapping_path) = split_mapping_option(&rest)?;
+            let [x1, y1, z1, x2, y2, z2] = positional.as_slice() else {
+                return Err(CliError::Usage);
+            };
+            let a = parse_position(x1, y1, z1)?;
+            let b = parse_position(x2, y2, z2)?;
+
+            let namespaced_ids = NamespacedIds::load(
+                mapping_path
+                    .as_deref()
+                    .unwrap_or(SCHEMATIC_IDS_PATH.as_ref()),
+            )?;
+            let mut tasks = Tasks::new(settings.performance.worker_threads);
+            let mut terrain = open_terrain();
+
+            load_region(&mut terrain, &mut tasks, a, b);
+
+            let schematic = Schematic::copy_from_terrain(&terrain, a, b, &namespaced_ids)
+                .ok_or(CliError::RegionNotLoaded)?;
+            schematic.save(path, version)?;
+
+            // the terrain may have been generated for the first time
+            save_terrain(&mut terrain, &mut tasks);
+
+            log::info!("saved {} blocks to {}", schematic.blocks.len(), path);
+            Ok(())
+        }
+        ["mesh", "export", path, rest @ ..] => {
+            let vertex_colors = !rest.contains(&"--no-light");
+            let positional = rest
+                .iter()
+                .filter(|&&arg| arg != "--no-light")
+                .collect::<Vec<_>>();
+            let [x1, y1, z1, x2, y2, z2] = positional.as_slice() else {
+                return Err(CliError::Usage);
+            };
+            let a = parse_position(x1, y1, z1)?;
+            let b = parse_position(x2, y2, z2)?;
+
+            let format = PathBuf::from(path)
+                .extension()
+                .and_then(|extension| {
+                    MeshExportFormat::from_extension(&extension.to_string_lossy())
+                })
+                .ok_or(CliError::UnknownMeshFormat(path.to_string()))?;
+
+            let mut tasks = Tasks::new(settings.performance.worker_threads);
+            let mut terrain = open_terrain();
+            let load_area_index = load_region(&mut terrain, &mut tasks, a, b);
+
+            let (_, chunk_a) = a.get_local_and_chunk_pos();
+            let (_, chunk_b) = b.get_local_and_chunk_pos();
+            let mesh = TerrainMesh::from_terrain(&terrain, load_area_index, chunk_a, chunk_b)?;
+            mesh.save(
+                path,
+                format,
+                TerrainRenderer::BLOCK_TEXTURE_PATH,
+                terrain.block_registry().texture_names(),
+                vertex_colors,
+            )?;
+
+            save_terrain(&mut terrain, &mut tasks);
+
+            log::info!("saved {} quads to {}", mesh.quad_count(), path);
+            Ok(())
+        }
+        ["server", rest @ ..] => {
+            let (port, anchors) = parse_server_options(rest)?;
+
+            let terrain = open_terrain();
+            let mut server = DedicatedServer::new(terrain, ("0.0.0.0", port), &anchors, &settings)
+                .map_err(CliError::ServerError)?;
+            server.run();
+
+            Ok(())
+        }
+        ["connect", addr] => {
+            let addr = if addr.contains(':') {
+                addr.to_string()
+            } else {
+                format!("{}:{}", addr, DEFAULT_PORT)
+            };
+
+            let block_registry = Arc::new(load_block_registry());
+            let client = Client::connect(addr.as_str(), &block_registry)?;
+
+            // the player's position is kept separately for each server, since the chunks are
+            // saved by the server
+            let world_save = Arc::new(WorldSave::open_or_create(
+                Path::new(REMOTE_WORLDS_DIRECTORY_PATH).join(addr.replace(':', "_")),
+                &block_registry,
+                client.seed(),
+                client.generator().clone(),
+            )?);
+            let terrain = client.create_terrain(block_registry, world_save)?;
+            log::info!("connected to {}", addr);
+
+            run_game(saved_settings, Some((client, terrain)))?;
+            Ok(())
+        }
+        _ => Err(CliError::Usage),
+    }
+}
+
+/// Parse the `--port <port>` and `--anchor <x> <y> <z>` options of the server subcommand
+fn parse_server_options(args: &[&str]) -> Result<(u16, Vec<GlobalBlockPosition>), CliError> {
+    let mut port = DEFAULT_PORT;
+    let mut anchors = Vec::new();
+    let mut args = args.iter();
+
+    while let Some(&arg) = args.next() {
+        match arg {
+            "--port" => {
+                let value = args.next().ok_or(CliError::Usage)?;
+                port =
+                    u16::from_str(value).map_err(|_| CliError::InvalidPort(value.to_string()))?;
+            }
+            "--anchor" => {
+                let (Some(x), Some(y), Some(z)) = (args.next(), args.next(), args.next()) else {
+                    return Err(CliError::Usage);
+                };
+                anchors.push(parse_position(x, y, z)?);
+            }
+            _ => return Err(CliError::Usage),
+        }
+    }
+
+    Ok((port, anchors))
+}
+
+/// Separate the `--mapping <path>` option from the positional arguments
+fn split_mapping_option<'a>(args: &[&'a str]) -> Result<(Vec<&'a str>, Option<PathBuf>), CliError> {
+    let mut positional = Vec::new();
+    let mut mapping_path = None;
+    let mut args = args.iter();
+
+    while let Some(&arg) = args.next() {
+        if arg == "--mapping" {
+            mapping_path = Some(PathBuf::from(args.next().ok_or(CliError::Usage)?));
+        } else {
+            positional.push(arg);
+        }
+    }
+
+    Ok((positional, mapping_path))
+}
+
+fn parse_position(x: &str, y: &str, z: &str) -> Result<GlobalBlockPosition, CliError> {
+    Ok(GlobalBlockPosition::new(
+        parse_coordinate(x)?,
+        parse_coordinate(y)?,
+        parse_coordinate(z)?,
+    ))
+}
+
+fn parse_coordinate(coordinate: &str) -> Result<i32, CliError> {
+    i32::from_str(coordinate).map_err(|_| CliError::InvalidCoordinate(coordinate.to_string()))
+}
+
+/// Find the colours of the blocks from their textures, then apply the mapping file if there is
+/// one
+fn load_block_colors(
+    terrain: &Terrain,
+    mapping_path: Option<PathBuf>,
+) -> Result<BlockColors, CliError> {
+    let mut block_colors = BlockColors::from_textures(
+        terrain.block_registry(),
+        TerrainRenderer::BLOCK_TEXTURE_PATH,
+    )?;
+
+    if let Some(mapping_path) = mapping_path {
+        block_colors.load_mapping(mapping_path, terrain.block_registry())?;
+    }
+
+    Ok(block_colors)
+}
+
+/// Load the chunks containing the box between the given corners, which are both inside the box,
+/// waiting until they and their features are loaded. Returns the index of the load area
+/// containing the chunks
+fn load_region(
+    terrain: &mut Terrain,
+    tasks: &mut Tasks,
+    a: GlobalBlockPosition,
+    b: GlobalBlockPosition,
+) -> Index {
+    let chunk_size = CHUNK_SIZE as i32;
+    let min = a
+        .as_ivec3()
+        .min(b.as_ivec3())
+        .div_euclid(IVec3::splat(chunk_size));
+    let max = a
+        .as_ivec3()
+        .max(b.as_ivec3())
+        .div_euclid(IVec3::splat(chunk_size));
+
+    let area_min = min - REGION_LOAD_MARGIN;
+    let area_size = (max - min + 1 + 2 * REGION_LOAD_MARGIN).as_size3();
+    let load_area_index = terrain.load_areas_mut().insert(LoadArea::new(
+        ChunkPosition::from(area_min),
+        area_size,
+        AreaShape::Cubic,
+    ));
+    let center = (area_min.as_vec3() + 0.5 * area_size.as_vec3()) * CHUNK_SIZE as f32;
+
+    loop {
+        terrain.update(tasks, center, Duration::ZERO);
+
+        let load_area = &terrain.load_areas()[load_area_index];
+        if load_area
+            .iter_positions()
+            .all(|chunk_pos| load_area.is_loaded(&chunk_pos))
+        {
+            break;
+        }
+
+        thread::sleep(REGION_LOAD_POLL_INTERVAL);
+    }
+
+    // receive the features placed by the decoration tasks
+    tasks.block_until_finished();
+    terrain.update(tasks, center, Duration::ZERO);
+
+    load_area_index
+}
+
+/// Save the modified chunks, waiting until everything is written
+fn save_terrain(terrain: &mut Terrain, tasks: &mut Tasks) {
+    tasks.block_until_finished();
+    terrain.save(tasks);
+    tasks.block_until_finished();
+}
+
+/// Errors returned by command line subcommands
+#[derive(Debug, thiserror::Error)]
+pub enum CliError {
+    #[error("invalid arguments\n{}", USAGE)]
+    Usage,
+    #[error("invalid coordinate `{0}`")]
+    InvalidCoordinate(String),
+    #[error("invalid port `{0}`")]
+    InvalidPort(String),
+    #[error("failed to start server: {0}")]
+    ServerError(io::Error),
+    #[error("unknown mesh format for `{0}`, expected .obj or .glb")]
+    UnknownMeshFormat(String),
+    #[error("{0}")]
+    VoxError(#[from] VoxError),
+    #[error("the region couldn't be loaded")]
+    RegionNotLoaded,
+    #[error("{0}")]
+    SchematicError(#[from] SchematicError),
+    #[error("{0}")]
+    MeshExportError(#[from] MeshExportError),
+    #[error("failed to connect to server: {0}")]
+    NetError(#[from] NetError),
+    #[error("{0}")]
+    SaveError(#[from] SaveError),
+    #[error("{0}")]
+    EventLoopError(#[from] EventLoopError),
+}
@@ -0,0 +1,1115 @@
+use std::{str::FromStr, sync::mpsc::Receiver};
+
+use generational_arena::Index;
+use glam::{I64Vec3, Vec3};
+use itertools::Itertools;
+
+use crate::{
+    core::{tasks::Tasks, time::Time},
+    net::client::Client,
+    player::PlayerController,
+    renderer::terrain::ChunkCullingMode,
+    settings::{Settings, SettingsError, MAX_RENDER_DISTANCE},
+    terrain::{
+        block::{entity::BlockEntity, registry::BlockRegistry, BlockId},
+        edit::{EditOperation, EditShape},
+        pathfinding::{Path, PathfindingSettings},
+        position_types::GlobalBlockPosition,
+        ChunkSource, Terrain, TICK_DURATION,
+    },
+};
+
+/// Usage of `/help`, which is handled by the registry itself since it lists the other commands
+const HELP_USAGE: &str = "/help [command] - list the commands, or show how to use one";
+
+/// Largest number of blocks in the box edited by `/fill`
+const MAX_FILL_VOLUME: u64 = 1 << 21;
+
+/// Modes of the commands which edit a shape, such as `/fill`
+const EDIT_MODE_NAMES: [&str; 3] = ["hollow", "walls", "replace"];
+
+/// Names of the chunk culling modes used by `/cull`
+const CULLING_MODE_NAMES: [&str; 3] = ["none", "frustum", "visibility"];
+
+/// The parts of the game commands can act on
+pub struct CommandContext<'a> {
+    pub terrain: &'a mut Terrain,
+    /// Load area following the player, which is edited by commands
+    pub load_area_index: Index,
+    pub player: &'a mut PlayerController,
+    pub time: &'a Time,
+    /// Block placed by the player
+    pub held_block: &'a mut BlockId,
+    /// Culling mode of the terrain renderer, which is applied once the command has run
+    pub culling_mode: &'a mut ChunkCullingMode,
+    /// Settings of the game, which are applied once the command has run
+    pub settings: &'a mut Settings,
+    pub tasks: &'a mut Tasks,
+    /// Path search started by `/path`, whose result is printed once it finishes
+    pub path_search: &'a mut Option<Receiver<Option<Path>>>,
+    /// Connection to the server, when playing on one
+    pub client: Option<&'a Client>,
+}
+
+/// What the value of an argument is, used for tab completion
+#[derive(Clone, Copy, Debug)]
+pub enum ArgKind {
+    /// A coordinate or other number, which isn't completed
+    Number,
+    /// The name of a block
+    Block,
+    /// One of a fixed list of words
+    Choice(&'static [&'static str]),
+    /// The name of a command
+    Command,
+    /// The name of a setting
+    Setting,
+}
+
+/// Function run by a command, returning the text to print
+pub type CommandFn = fn(&mut CommandContext, &[&str]) -> Result<String, CommandError>;
+
+/// A command which can be typed in the console
+pub struct Command {
+    /// Name typed to run the command, without the leading `/`
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub description: &'static str,
+    /// Kind of each argument, in order
+    pub args: &'static [ArgKind],
+    pub run: CommandFn,
+}
+
+/// The commands which can be run from the console or a script
+pub struct CommandRegistry {
+    commands: Vec<Command>,
+}
+
+impl CommandRegistry {
+    /// Create a registry containing the built-in commands
+    pub fn new() -> Self {
+        let mut registry = Self {
+            commands: Vec::new(),
+        };
+
+        registry.register(Command {
+            name: "tp",
+            usage: "/tp <x> <y> <z>",
+            description: "move the player's eyes to a position",
+            args: &[ArgKind::Number, ArgKind::Number, ArgKind::Number],
+            run: teleport,
+        });
+        registry.register(Command {
+            name: "setblock",
+            usage: "/setblock <x> <y> <z> <block>",
+            description: "set the block at a position",
+            args: &[
+                ArgKind::Number,
+                ArgKind::Number,
+                ArgKind::Number,
+                ArgKind::Block,
+            ],
+            run: set_block,
+        });
+        registry.register(Command {
+            name: "fill",
+            usage: "/fill <x1> <y1> <z1> <x2> <y2> <z2> <block> [hollow|walls|replace <block>]",
+            description: "fill the box between two corners with a block",
+            args: &[
+                ArgKind::Number,
+                ArgKind::Number,
+                ArgKind::Number,
+                ArgKind::Number,
+                ArgKind::Number,
+                ArgKind::Number,
+                ArgKind::Block,
+                ArgKind::Choice(&EDIT_MODE_NAMES),
+                ArgKind::Block,
+            ],
+            run: fill,
+        });
+        registry.register(Command {
+            name: "sphere",
+            usage: "/sphere <x> <y> <z> <radius> <block> [hollow|walls|replace <block>]",
+            description: "fill a sphere around a block with a block",
+            args: &[
+                ArgKind::Number,
+                ArgKind::Number,
+                ArgKind::Number,
+                ArgKind::Number,
+                ArgKind::Block,
+                ArgKind::Choice(&EDIT_MODE_NAMES),
+                ArgKind::Block,
+            ],
+            run: sphere,
+        });
+        registry.register(Command {
+            name: "cylinder",
+            usage: "/cylinder <x> <y> <z> <radius> <height> <block> [hollow|walls|replace <block>]",
+            description: "fill a vertical cylinder standing on a block with a block",
+            args: &[
+                ArgKind::Number,
+                ArgKind::Number,
+                ArgKind::Number,
+                ArgKind::Number,
+                ArgKind::Number,
+                ArgKind::Block,
+                ArgKind::Choice(&EDIT_MODE_NAMES),
+                ArgKind::Block,
+            ],
+            run: cylinder,
+        });
+        registry.register(Command {
+            name: "sign",
+            usage: "/sign <x> <y> <z> [text]",
+            description: "show or change the text of a sign",
+            args: &[ArgKind::Number, ArgKind::Number, ArgKind::Number],
+            run: sign,
+        });
+        registry.register(Command {
+            name: "path",
+            usage: "/path <x> <y> <z>",
+            description: "find a path for the player to walk to a position",
+            args: &[ArgKind::Number, ArgKind::Number, ArgKind::Number],
+            run: path,
+        });
+        registry.register(Command {
+            name: "history",
+            usage: "/history [clear]",
+            description: "show the size of the edit history, or forget it",
+            args: &[ArgKind::Choice(&["clear"])],
+            run: history,
+        });
+        registry.register(Command {
+            name: "hold",
+            usage: "/hold <block>",
+            description: "choose the block placed with the right mouse button",
+            args: &[ArgKind::Block],
+            run: hold,
+        });
+        registry.register(Command {
+            name: "seed",
+            usage: "/seed",
+            description: "show the seed of the world",
+            args: &[],
+            run: |context, _| Ok(format!("seed: {}", context.terrain.world_save().seed())),
+        });
+        registry.register(Command {
+            name: "players",
+            usage: "/players",
+            description: "list the other players on the server",
+            args: &[],
+            run: players,
+        });
+        registry.register(Command {
+            name: "render_distance",
+            usage: "/render_distance <chunks>",
+            description:
+                "set the horizontal distance in chunks that terrain is loaded around the player",
+            args: &[ArgKind::Number],
+            run: render_distance,
+        });
+        registry.register(Command {
+            name: "cull",
+            usage: "/cull none|frustum|visibility",
+            description: "choose how the chunks to draw are found",
+            args: &[ArgKind::Choice(&CULLING_MODE_NAMES)],
+            run: cull,
+        });
+        registry.register(Command {
+            name: "set",
+            usage: "/set [setting] [value]",
+            description: "list the settings, or show or change one",
+            args: &[ArgKind::Setting],
+            run: set,
+        });
+        registry.register(Command {
+            name: "time",
+            usage: "/time",
+            description: "show the world's tick and the frame rate",
+            args: &[],
+            run: time,
+        });
+
+        registry
+    }
+
+    /// Add a command, replacing any command with the same name
+    pub fn register(&mut self, command: Command) {
+        self.commands.retain(|other| other.name != command.name);
+        self.commands.push(command);
+    }
+
+    /// Returns the command with the given name, without the leading `/`
+    pub fn get(&self, name: &str) -> Option<&Command> {
+        self.commands.iter().find(|command| command.name == name)
+    }
+
+    /// Run a line typed in the console. The leading `/` is optional
+    pub fn execute(
+        &self,
+        line: &str,
+        context: &mut CommandContext,
+    ) -> Result<String, CommandError> {
+        let line = line.trim();
+        let mut words = line.strip_prefix('/').unwrap_or(line).split_whitespace();
+        let Some(name) = words.next() else {
+            return Ok(String::new());
+        };
+        let args = words.collect_vec();
+
+        if name == "help" {
+            return self.help(&args);
+        }
+
+        let command = self
+            .get(name)
+            .ok_or_else(|| CommandError::UnknownCommand(name.to_string()))?;
+
+        (command.run)(context, &args)
+    }
+
+    /// Returns the byte index in `line` of the start of the word being typed at its end, and the
+    /// words it could be completed to
+    pub fn completions(&self, line: &str, block_registry: &BlockRegistry) -> (usize, Vec<String>) {
+        let word_start = line.rfind(char::is_whitespace).map_or(0, |index| index + 1);
+        let word = &line[word_start..];
+
+        let mut previous_words = line[..word_start].split_whitespace();
+        let candidates = match previous_words.next() {
+            // complete the command's name, keeping the `/` if it was typed
+            None => {
+                let slash = if word.starts_with('/') { "/" } else { "" };
+                let prefix = word.strip_prefix('/').unwrap_or(word);
+
+                self.command_names()
+                    .filter(|name| name.starts_with(prefix))
+                    .map(|name| format!("{}{}", slash, name))
+                    .sorted()
+                    .collect_vec()
+            }
+            Some(name) => {
+                let name = name.strip_prefix('/').unwrap_or(name);
+                let arg_index = previous_words.count();
+                let arg_kind = if name == "help" {
+                    (arg_index == 0).then_some(&ArgKind::Command)
+                } else {
+                    self.get(name)
+                        .and_then(|command| command.args.get(arg_index))
+                };
+
+                let options = match arg_kind {
+                    Some(ArgKind::Block) => block_registry
+                        .iter()
+                        .map(|(_, block)| block.name.clone())
+                        .collect_vec(),
+                    Some(ArgKind::Choice(choices)) => choices
+                        .iter()
+                        .map(|choice| choice.to_string())
+                        .collect_vec(),
+                    Some(ArgKind::Command) => {
+                        self.command_names().map(str::to_string).collect_vec()
+                    }
+                    Some(ArgKind::Setting) => Settings::names(),
+                    Some(ArgKind::Number) | None => Vec::new(),
+                };
+
+                options
+                    .into_iter()
+                    .filter(|option| option.starts_with(word))
+                    .sorted()
+                    .collect_vec()
+            }
+        };
+
+        (word_start, candidates)
+    }
+
+    /// Names of the commands, including `help`
+    fn command_names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        std::iter::once("help").chain(self.commands.iter().map(|command| command.name))
+    }
+
+    fn help(&self, args: &[&str]) -> Result<String, CommandError> {
+        match args {
+            [] => Ok(std::iter::once(HELP_USAGE.to_string())
+                .chain(
+                    self.commands
+                        .iter()
+                        .map(|command| format!("{} - {}", command.usage, command.description)),
+                )
+                .join("\n")),
+            ["help" | "/help"] => Ok(HELP_USAGE.to_string()),
+            [name] => {
+                let name = name.strip_prefix('/').unwrap_or(name);
+                let command = self
+                    .get(name)
+                    .ok_or_else(|| CommandError::UnknownCommand(name.to_string()))?;
+
+                Ok(format!("{} - {}", command.usage, command.description))
+            }
+            _ => Err(CommandError::Usage("/help [command]")),
+        }
+    }
+}
+
+fn teleport(context: &mut CommandContext, args: &[&str]) -> Result<String, CommandError> {
+    let [x, y, z] = args else {
+        return Err(CommandError::Usage("/tp <x> <y> <z>"));
+    };
+
+    let current = context.player.camera.position;
+    let position = Vec3::new(
+        parse_coordinate(x, current.x)?,
+        parse_coordinate(y, current.y)?,
+        parse_coordinate(z, current.z)?,
+    );
+    context.player.teleport(position);
+
+    Ok(format!(
+        "teleported to {:.1} {:.1} {:.1}",
+        position.x, position.y, position.z
+    ))
+}
+
+fn set_block(context: &mut CommandContext, args: &[&str]) -> Result<String, CommandError> {
+    let [x, y, z, block] = args else {
+        return Err(CommandError::Usage("/setblock <x> <y> <z> <block>"));
+    };
+
+    check_local_terrain(context)?;
+    let pos = parse_block_position(context, x, y, z)?;
+    let block_id = parse_block(context.terrain.block_registry(), block)?;
+
+    if !context
+        .terrain
+        .set_block(context.load_area_index, &pos, block_id)
+    {
+        return Err(CommandError::NotLoaded);
+    }
+
+    Ok(format!(
+        "set the block at {} {} {} to {}",
+        pos.x(),
+        pos.y(),
+        pos.z(),
+        block
+    ))
+}
+
+fn fill(context: &mut CommandContext, args: &[&str]) -> Result<String, CommandError> {
+    const USAGE: &str =
+        "/fill <x1> <y1> <z1> <x2> <y2> <z2> <block> [hollow|walls|replace <block>]";
+
+    let [x1, y1, z1, x2, y2, z2, block, mode @ ..] = args else {
+        return Err(CommandError::Usage(USAGE));
+    };
+
+    check_local_terrain(context)?;
+    let a = parse_block_position(context, x1, y1, z1)?;
+    let b = parse_block_position(context, x2, y2, z2)?;
+    let operation = parse_edit_operation(context, block, mode, USAGE)?;
+
+    edit_shape(context, EditShape::cuboid(a, b), operation)
+}
+
+fn sphere(context: &mut CommandContext, args: &[&str]) -> Result<String, CommandError> {
+    const USAGE: &str = "/sphere <x> <y> <z> <radius> <block> [hollow|walls|replace <block>]";
+
+    let [x, y, z, radius, block, mode @ ..] = args else {
+        return Err(CommandError::Usage(USAGE));
+    };
+
+    check_local_terrain(context)?;
+    let center = parse_block_position(context, x, y, z)?;
+    let radius = parse_radius(radius)?;
+    let operation = parse_edit_operation(context, block, mode, USAGE)?;
+
+    edit_shape(context, EditShape::Sphere { center, radius }, operation)
+}
+
+fn cylinder(context: &mut CommandContext, args: &[&str]) -> Result<String, CommandError> {
+    const USAGE: &str =
+        "/cylinder <x> <y> <z> <radius> <height> <block> [hollow|walls|replace <block>]";
+
+    let [x, y, z, radius, height, block, mode @ ..] = args else {
+        return Err(CommandError::Usage(USAGE));
+    };
+
+    check_local_terrain(context)?;
+    let base = parse_block_position(context, x, y, z)?;
+    let radius = parse_radius(radius)?;
+    let height =
+        u32::from_str(height).map_err(|_| CommandError::InvalidNumber(height.to_string()))?;
+    let operation = parse_edit_operation(context, block, mode, USAGE)?;
+
+    edit_shape(
+        context,
+        EditShape::Cylinder {
+            base,
+            radius,
+            height,
+        },
+        operation,
+    )
+}
+
+/// Parse the block and optional mode at the end of the arguments of a command which edits a
+/// shape
+fn parse_edit_operation(
+    context: &CommandContext,
+    block: &str,
+    mode: &[&str],
+    usage: &'static str,
+) -> Result<EditOperation, CommandError> {
+    let block_registry = context.terrain.block_registry();
+    let block_id = parse_block(block_registry, block)?;
+
+    Ok(match mode {
+        [] => EditOperation::Fill(block_id),
+        ["hollow"] => EditOperation::Hollow(block_id),
+        ["walls"] => EditOperation::Walls(block_id),
+        ["replace", from] => EditOperation::Replace {
+            from: parse_block(block_registry, from)?,
+            to: block_id,
+        },
+        _ => return Err(CommandError::Usage(usage)),
+    })
+}
+
+/// Apply the operation to the shape, unless the box containing the shape has more than
+/// `MAX_FILL_VOLUME` blocks or reaches outside the range of block positions
+fn edit_shape(
+    context: &mut CommandContext,
+    shape: EditShape,
+    operation: EditOperation,
+) -> Result<String, CommandError> {
+    let (min, max) = match shape {
+        EditShape::Cuboid { min, max } => {
+            (min.as_ivec3().as_i64vec3(), max.as_ivec3().as_i64vec3())
+        }
+        EditShape::Sphere { center, radius } => {
+            let extent = I64Vec3::splat(radius_extent(radius));
+            let center = center.as_ivec3().as_i64vec3();
+            (center - extent, center + extent)
+        }
+        EditShape::Cylinder {
+            base,
+            radius,
+            height,
+        } => {
+            let extent = radius_extent(radius);
+            let base = base.as_ivec3().as_i64vec3();
+            (
+                base - I64Vec3::new(extent, 0, extent),
+                base + I64Vec3::new(extent, height.max(1) as i64 - 1, extent),
+            )
+        }
+    };
+
+    let volume = (max - min)
+        .to_array()
+        .into_iter()
+        .fold(1, |volume: u64, length| {
+            volume.saturating_mul(length as u64 + 1)
+        });
+    if volume > MAX_FILL_VOLUME {
+        return Err(CommandError::TooManyBlocks(volume, MAX_FILL_VOLUME));
+    }
+    if min.min_element() < i32::MIN as i64 || max.max_element() > i32::MAX as i64 {
+        return Err(CommandError::NotLoaded);
+    }
+
+    let blocks_changed = context.terrain.edit_region(&shape, operation);
+
+    Ok(format!("changed {} blocks", blocks_changed))
+}
+
+/// Block edits on a server's terrain have to be made by the server, so commands can't make them
+fn check_local_terrain(context: &CommandContext) -> Result<(), CommandError> {
+    match context.terrain.chunk_source() {
+        ChunkSource::Local => Ok(()),
+        ChunkSource::Remote => Err(CommandError::RemoteWorld),
+    }
+}
+
+fn sign(context: &mut CommandContext, args: &[&str]) -> Result<String, CommandError> {
+    let [x, y, z, words @ ..] = args else {
+        return Err(CommandError::Usage("/sign <x> <y> <z> [text]"));
+    };
+
+    let pos = parse_block_position(context, x, y, z)?;
+    let Some(BlockEntity::Sign { text }) = context.terrain.get_block_entity(&pos) else {
+        return Err(CommandError::NoSign);
+    };
+
+    if words.is_empty() {
+        return Ok(format!("the sign says \"{}\"", text));
+    }
+
+    check_local_terrain(context)?;
+    let new_text = words.join(" ");
+    let message = format!("the sign now says \"{}\"", new_text);
+    context.terrain.modify_block_entity(&pos, |block_entity| {
+        if let BlockEntity::Sign { text } = block_entity {
+            *text = new_text;
+        }
+    });
+
+    Ok(message)
+}
+
+fn path(context: &mut CommandContext, args: &[&str]) -> Result<String, CommandError> {
+    let [x, y, z] = args else {
+        return Err(CommandError::Usage("/path <x> <y> <z>"));
+    };
+
+    let goal = parse_block_position(context, x, y, z)?;
+    let start = GlobalBlockPosition::from(context.player.position.floor().as_ivec3());
+
+    *context.path_search = Some(context.terrain.submit_path_search(
+        context.tasks,
+        context.load_area_index,
+        start,
+        goal,
+        PathfindingSettings::default(),
+    ));
+
+    Ok(format!(
+        "searching for a path to {} {} {}",
+        goal.x(),
+        goal.y(),
+        goal.z()
+    ))
+}
+
+/// Describe the result of a search started by `/path`
+pub fn describe_path(path: Option<&Path>) -> String {
+    let Some(path) = path else {
+        return "no path found".to_string();
+    };
+
+    // the cells include the start
+    let steps = path.cells.len().saturating_sub(1);
+    match path.cells.last() {
+        Some(end) if !path.complete => format!(
+            "found a partial path of {} steps, ending at {} {} {}",
+            steps,
+            end.x(),
+            end.y(),
+            end.z()
+        ),
+        _ => format!("found a path of {} steps", steps),
+    }
+}
+
+fn history(context: &mut CommandContext, args: &[&str]) -> Result<String, CommandError> {
+    match args {
+        [] => {
+            let history = context.terrain.edit_history();
+            Ok(format!(
+                "{} edits can be undone and {} redone, using {} KiB",
+                history.undo_count(),
+                history.redo_count(),
+                history.memory_used().div_ceil(1024)
+            ))
+        }
+        ["clear"] => {
+            context.terrain.clear_edit_history();
+            Ok("cleared the edit history".to_string())
+        }
+        _ => Err(CommandError::Usage("/history [clear]")),
+    }
+}
+
+fn players(context: &mut CommandContext, _: &[&str]) -> Result<String, CommandError> {
+    let client = context.client.ok_or(CommandError::NotConnected)?;
+
+    let players = client
+        .other_players()
+        .iter()
+        .sorted_by_key(|&(&client_id, _)| client_id)
+        .map(|(client_id, position)| {
+            format!(
+                "    client {} at {:.1} {:.1} {:.1}",
+                client_id, position.x, position.y, position.z
+            )
+        })
+        .join("\n");
+
+    Ok(format!(
+        "you are client {}, {} other players connected\n{}",
+        client.client_id(),
+        client.other_players().len(),
+        players
+    )
+    .trim_end()
+    .to_string())
+}
+
+fn hold(context: &mut CommandContext, args: &[&str]) -> Result<String, CommandError> {
+    let [block] = args else {
+        return Err(CommandError::Usage("/hold <block>"));
+    };
+
+    *context.held_block = parse_block(context.terrain.block_registry(), block)?;
+
+    Ok(format!("holding {}", block))
+}
+
+fn render_distance(context: &mut CommandContext, args: &[&str]) -> Result<String, CommandError> {
+    let [distance] = args else {
+        return Err(CommandError::Usage("/render_distance <chunks>"));
+    };
+
+    let distance = usize::from_str(distance)
+        .ok()
+        .filter(|distance| (1..=MAX_RENDER_DISTANCE).contains(distance))
+        .ok_or_else(|| CommandError::OutOfRange(distance.to_string(), 1, MAX_RENDER_DISTANCE))?;
+
+    context.settings.graphics.render_distance = distance;
+
+    Ok(format!("render distance set to {} chunks", distance))
+}
+
+fn cull(context: &mut CommandContext, args: &[&str]) -> Result<String, CommandError> {
+    *context.culling_mode = match args {
+        ["none"] => ChunkCullingMode::CullNone,
+        ["frustum"] => ChunkCullingMode::Frustum,
+        ["visibility"] => ChunkCullingMode::VisibilitySearch,
+        _ => return Err(CommandError::Usage("/cull none|frustum|visibility")),
+    };
+
+    Ok(format!("culling mode set to {}", args[0]))
+}
+
+fn set(context: &mut CommandContext, args: &[&str]) -> Result<String, CommandError> {
+    match args {
+        [] => Ok(Settings::names()
+            .iter()
+            .filter_map(|name| {
+                let value = context.settings.get(name)?;
+                Some(format!("{} = {}", name, value))
+            })
+            .join("\n")),
+        [name] => {
+            let value = context
+                .settings
+                .get(name)
+                .ok_or_else(|| SettingsError::UnknownSetting(name.to_string()))?;
+
+            Ok(format!("{} = {}", name, value))
+        }
+        [name, value @ ..] => {
+            context.settings.set(name, &value.join(" "))?;
+            let value = context.settings.get(name).unwrap_or_default();
+
+            if Settings::requires_restart(name) {
+                Ok(format!(
+                    "{} set to {}, which takes effect after restarting",
+                    name, value
+                ))
+            } else {
+                Ok(format!("{} set to {}", name, value))
+            }
+        }
+    }
+}
+
+fn time(context: &mut CommandContext, _: &[&str]) -> Result<String, CommandError> {
+    let tick_index = context.terrain.tick_index();
+
+    Ok(format!(
+        "tick {} ({:.1} s of world time), {} fps ({:.2} ms per frame)",
+        tick_index,
+        tick_index as f64 * TICK_DURATION.as_secs_f64(),
+        context.time.get_frames_last_second(),
+        context.time.delta_seconds() * 1000.0
+    ))
+}
+
+/// Parse a coordinate, which is relative to `current` if it starts with `~`
+fn parse_coordinate(coordinate: &str, current: f32) -> Result<f32, CommandError> {
+    let invalid = || CommandError::InvalidNumber(coordinate.to_string());
+
+    match coordinate.strip_prefix('~') {
+        Some("") => Ok(current),
+        Some(offset) => Ok(current + f32::from_str(offset).map_err(|_| invalid())?),
+        None => f32::from_str(coordinate).map_err(|_| invalid()),
+    }
+}
+
+/// Parse the position of a block. Coordinates starting with `~` are relative to the block
+/// containing the player's eyes
+fn parse_block_position(
+    context: &CommandContext,
+    x: &str,
+    y: &str,
+    z: &str,
+) -> Result<GlobalBlockPosition, CommandError> {
+    let current = context.player.camera.position.floor();
+
+    Ok(GlobalBlockPosition::from(
+        Vec3::new(
+            parse_coordinate(x, current.x)?,
+            parse_coordinate(y, current.y)?,
+            parse_coordinate(z, current.z)?,
+        )
+        .floor()
+        .as_ivec3(),
+    ))
+}
+
+/// Returns the distance in blocks from the centre of a shape with the given radius to its edge,
+/// limited so that it can be added to a block position without overflowing
+fn radius_extent(radius: f32) -> i64 {
+    (radius as i64).min(i32::MAX as i64)
+}
+
+/// Parse the radius of a shape, which can't be negative
+fn parse_radius(radius: &str) -> Result<f32, CommandError> {
+    f32::from_str(radius)
+        .ok()
+        .filter(|radius| radius.is_finite() && *radius >= 0.0)
+        .ok_or_else(|| CommandError::InvalidNumber(radius.to_string()))
+}
+
+fn parse_block(block_registry: &BlockRegistry, name: &str) -> Result<BlockId, CommandError> {
+    block_registry
+        .get_id(name)
+        .ok_or_else(|| CommandError::UnknownBlock(name.to_string()))
+}
+
+/// Errors returned by console commands
+#[derive(Debug, thiserror::Error)]
+pub enum CommandError {
+    #[error("unknown command `{0}`, type /help for a list of commands")]
+    UnknownCommand(String),
+    #[error("usage: {0}")]
+    Usage(&'static str),
+    #[error("invalid number `{0}`")]
+    InvalidNumber(String),
+    #[error("`{0}` is not between {1} and {2}")]
+    OutOfRange(String, usize, usize),
+    #[error("unknown block `{0}`")]
+    UnknownBlock(String),
+    #[error("the block isn't loaded")]
+    NotLoaded,
+    #[error("the box contains {0} blocks, more than the limit of {1}")]
+    TooManyBlocks(u64, u64),
+    #[error("there is no sign at that position")]
+    NoSign,
+    #[error("blocks can't be edited with commands while playing on a server")]
+    RemoteWorld,
+    #[error("not playing on a server")]
+    NotConnected,
+    #[error(transparent)]
+    SettingsError(#[from] SettingsError),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+    use crate::{
+        core::{tasks::Tasks, time::TargetFrameRate},
+        terrain::{
+            block::{registry::test_registry, BLOCK_AIR},
+            chunk::CHUNK_SIZE,
+            generation::GeneratorSettings,
+            load_area::{AreaShape, LoadArea},
+            position_types::ChunkPosition,
+            save::WorldSave,
+        },
+        util::size::Size3,
+    };
+
+    #[test]
+    fn commands() {
+        let block_registry = Arc::new(test_registry(&[
+            r#"
+                name = "stone"
+                model = { type = "full_block", textures = { all = "stone" } }
+            "#,
+            r#"
+                name = "sign"
+                block_entity = "sign"
+            "#,
+        ]));
+        let stone = block_registry.get_id("stone").unwrap();
+        let directory =
+            std::env::temp_dir().join(format!("voxels-commands-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        let world_save = Arc::new(
+            WorldSave::open_or_create(
+                &directory,
+                &block_registry,
+                7,
+                GeneratorSettings::Flat {
+                    layers: "stone".to_string(),
+                },
+            )
+            .unwrap(),
+        );
+        let generator = world_save
+            .generator_settings()
+            .build(world_save.seed(), &block_registry)
+            .unwrap();
+        let mut terrain = Terrain::new(block_registry, world_save, generator);
+        let mut tasks = Tasks::new(2);
+        let mut player = PlayerController::new(Vec3::new(0.5, 10.5, 0.5));
+
+        let mut load_area = LoadArea::new(
+            ChunkPosition::ZERO,
+            Size3::new(4, 2, 4),
+            AreaShape::Cylindrical,
+        );
+        load_area.set_center(player.camera.position / (CHUNK_SIZE as f32));
+        let load_area_index = terrain.load_areas_mut().insert(load_area);
+
+        let start = Instant::now();
+        while terrain
+            .get_block(load_area_index, &GlobalBlockPosition::new(6, 12, 6))
+            .is_none()
+        {
+            assert!(start.elapsed() < Duration::from_secs(20), "timed out");
+            terrain.update(
+                &mut tasks,
+                player.camera.position,
+                Duration::from_millis(10),
+            );
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        let time = Time::new(TargetFrameRate::UnlimitedOrVsync);
+        let mut held_block = BLOCK_AIR;
+        let mut culling_mode = ChunkCullingMode::VisibilitySearch;
+        let mut settings = Settings::default();
+        let mut path_search = None;
+        let mut context = CommandContext {
+            terrain: &mut terrain,
+            load_area_index,
+            player: &mut player,
+            time: &time,
+            held_block: &mut held_block,
+            culling_mode: &mut culling_mode,
+            settings: &mut settings,
+            tasks: &mut tasks,
+            path_search: &mut path_search,
+            client: None,
+        };
+        let registry = CommandRegistry::new();
+
+        assert_eq!(registry.execute("/seed", &mut context).unwrap(), "seed: 7");
+        assert!(matches!(
+            registry.execute("/players", &mut context),
+            Err(CommandError::NotConnected)
+        ));
+
+        // relative coordinates are relative to the block containing the player's eyes
+        registry
+            .execute("/setblock ~ ~1 ~1 stone", &mut context)
+            .unwrap();
+        assert_eq!(
+            context
+                .terrain
+                .get_block(load_area_index, &GlobalBlockPosition::new(0, 11, 1)),
+            Some(stone)
+        );
+
+        assert_eq!(
+            registry
+                .execute("fill 4 10 4 6 12 6 stone hollow", &mut context)
+                .unwrap(),
+            "changed 26 blocks"
+        );
+        assert_eq!(
+            context
+                .terrain
+                .get_block(load_area_index, &GlobalBlockPosition::new(5, 11, 5)),
+            Some(BLOCK_AIR)
+        );
+
+        assert_eq!(
+            registry
+                .execute("/fill 4 10 4 6 12 6 sign replace stone", &mut context)
+                .unwrap(),
+            "changed 26 blocks"
+        );
+        assert_eq!(
+            registry
+                .execute("/sphere 10 20 10 1 stone", &mut context)
+                .unwrap(),
+            "changed 7 blocks"
+        );
+        assert_eq!(
+            registry
+                .execute("/cylinder 14 20 14 1 2 stone walls", &mut context)
+                .unwrap(),
+            "changed 8 blocks"
+        );
+
+        registry
+            .execute("/setblock 2 11 2 sign", &mut context)
+            .unwrap();
+        assert_eq!(
+            registry.execute("/sign 2 11 2", &mut context).unwrap(),
+            "the sign says \"\""
+        );
+        registry
+            .execute("/sign 2 11 2 hello world", &mut context)
+            .unwrap();
+        assert_eq!(
+            registry.execute("/sign 2 11 2", &mut context).unwrap(),
+            "the sign says \"hello world\""
+        );
+        assert!(matches!(
+            registry.execute("/sign 0 11 1 hello", &mut context),
+            Err(CommandError::NoSign)
+        ));
+
+        assert_eq!(
+            registry.execute("/history", &mut context).unwrap(),
+            "6 edits can be undone and 0 redone, using 2 KiB"
+        );
+        registry.execute("/history clear", &mut context).unwrap();
+        assert_eq!(
+            registry.execute("/history", &mut context).unwrap(),
+            "0 edits can be undone and 0 redone, using 0 KiB"
+        );
+
+        // paths start from the cell containing the player's feet
+        registry.execute("/tp 0.5 3 0.5", &mut context).unwrap();
+        registry.execute("/path 3 1 0", &mut context).unwrap();
+        let path = context
+            .path_search
+            .take()
+            .unwrap()
+            .recv_timeout(Duration::from_secs(20))
+            .unwrap();
+        assert_eq!(describe_path(path.as_ref()), "found a path of 3 steps");
+
+        registry.execute("/tp ~1 20 ~", &mut context).unwrap();
+        assert_eq!(context.player.camera.position, Vec3::new(1.5, 20.0, 0.5));
+
+        registry.execute("/hold stone", &mut context).unwrap();
+        registry.execute("/cull frustum", &mut context).unwrap();
+        registry
+            .execute("/render_distance 3", &mut context)
+            .unwrap();
+        assert_eq!(context.settings.graphics.render_distance, 3);
+        assert_eq!(
+            registry
+                .execute("/set graphics.fov_degrees 90", &mut context)
+                .unwrap(),
+            "graphics.fov_degrees set to 90.0"
+        );
+        assert_eq!(
+            registry
+                .execute("/set performance.worker_threads 1", &mut context)
+                .unwrap(),
+            "performance.worker_threads set to 1, which takes effect after restarting"
+        );
+
+        assert!(matches!(
+            registry.execute("/render_distance 0", &mut context),
+            Err(CommandError::OutOfRange(..))
+        ));
+        assert!(matches!(
+            registry.execute("/setblock 0 0 0 dirt", &mut context),
+            Err(CommandError::UnknownBlock(_))
+        ));
+        assert!(matches!(
+            registry.execute("/setblock 0 x 0 stone", &mut context),
+            Err(CommandError::InvalidNumber(_))
+        ));
+        assert!(matches!(
+            registry.execute("/setblock 0 1000 0 stone", &mut context),
+            Err(CommandError::NotLoaded)
+        ));
+        assert!(matches!(
+            registry.execute("/fill 0 0 0 stone", &mut context),
+            Err(CommandError::Usage(_))
+        ));
+        assert!(matches!(
+            registry.execute(
+                "/fill -2147483648 0 0 2147483647 255 255 stone",
+                &mut context
+            ),
+            Err(CommandError::TooManyBlocks(..))
+        ));
+        assert!(matches!(
+            registry.execute("/sphere 0 0 0 1e30 stone", &mut context),
+            Err(CommandError::TooManyBlocks(..))
+        ));
+        assert!(matches!(
+            registry.execute("/sphere 0 0 0 -1 stone", &mut context),
+            Err(CommandError::InvalidNumber(_))
+        ));
+        assert!(matches!(
+            registry.execute("/fill 0 0 0 1 1 1 stone replace", &mut context),
+            Err(CommandError::Usage(_))
+        ));
+        assert!(matches!(
+            registry.execute("/set graphics.brightness 2", &mut context),
+            Err(CommandError::SettingsError(_))
+        ));
+        assert!(matches!(
+            registry.execute("/teleport 0 0 0", &mut context),
+            Err(CommandError::UnknownCommand(_))
+        ));
+
+        assert_eq!(held_block, stone);
+        assert_eq!(culling_mode, ChunkCullingMode::Frustum);
+        assert_eq!(settings.graphics.fov_degrees, 90.0);
+    }
+
+    #[test]
+    fn completions() {
+        let block_registry = test_registry(&[r#"
+            name = "stone"
+            model = { type = "full_block", textures = { all = "stone" } }
+        "#]);
+        let registry = CommandRegistry::new();
+
+        assert_eq!(
+            registry.completions("/se", &block_registry),
+            (
+                0,
+                vec![
+                    "/seed".to_string(),
+                    "/set".to_string(),
+                    "/setblock".to_string()
+                ]
+            )
+        );
+        assert_eq!(
+            registry.completions("help ren", &block_registry),
+            (5, vec!["render_distance".to_string()])
+        );
+        assert_eq!(
+            registry.completions("/fill 0 0 0 1 1 1 st", &block_registry),
+            (18, vec!["stone".to_string()])
+        );
+        assert_eq!(
+            registry.completions("/cull ", &block_registry),
+            (
+                6,
+                vec![
+                    "frustum".to_string(),
+                    "none".to_string(),
+                    "visibility".to_string()
+                ]
+            )
+        );
+        assert_eq!(
+            registry.completions("/tp 1", &block_registry),
+            (4, Vec::new())
+        );
+    }
+}
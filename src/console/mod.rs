@@ -0,0 +1,305 @@
+use std::{collections::VecDeque, fs, io, path::Path};
+
+use glam::Vec2;
+use winit::keyboard::KeyCode;
+
+use self::commands::{CommandContext, CommandRegistry};
+use crate::{
+    core::input::Input, renderer::overlay::Overlay, terrain::block::registry::BlockRegistry,
+};
+
+pub mod commands;
+
+/// Number of lines of output kept by the console
+const MAX_OUTPUT_LINES: usize = 200;
+
+/// Number of lines kept in the history of entered lines
+const MAX_HISTORY_LEN: usize = 100;
+
+/// Number of lines of output shown above the input line
+const VISIBLE_OUTPUT_LINES: usize = 12;
+
+const BACKGROUND_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.6];
+const OUTPUT_COLOR: [f32; 4] = [0.85, 0.85, 0.85, 1.0];
+const INPUT_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+/// Text console for running commands in game. While the console is open, typed text goes to its
+/// input line rather than controlling the player
+#[derive(Debug, Default)]
+pub struct Console {
+    is_open: bool,
+    input: String,
+    /// Lines entered, oldest first
+    history: Vec<String>,
+    /// Index in `history` of the line shown in the input while browsing the history
+    history_index: Option<usize>,
+    output: VecDeque<String>,
+}
+
+impl Console {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.is_open
+    }
+
+    /// Open the console with the given text already typed
+    pub fn open(&mut self, input: &str) {
+        self.is_open = true;
+        self.input = input.to_string();
+        self.history_index = None;
+    }
+
+    pub fn close(&mut self) {
+        self.is_open = false;
+        self.input.clear();
+    }
+
+    /// Add text to the output. Each line of the text is a line of output
+    pub fn print(&mut self, text: &str) {
+        for line in text.lines() {
+            if self.output.len() == MAX_OUTPUT_LINES {
+                self.output.pop_front();
+            }
+            self.output.push_back(line.to_string());
+        }
+    }
+
+    /// Handle the keys pressed and the text typed this frame while the console is open. Returns
+    /// the line entered, if one was
+    pub fn handle_input(
+        &mut self,
+        input: &Input,
+        commands: &CommandRegistry,
+        block_registry: &BlockRegistry,
+    ) -> Option<String> {
+        if input.is_key_just_pressed(KeyCode::Escape) {
+            self.close();
+            return None;
+        }
+
+        self.input.push_str(input.typed_text());
+
+        if input.is_key_pressed_or_repeated(KeyCode::Backspace) {
+            self.input.pop();
+        }
+        if input.is_key_pressed_or_repeated(KeyCode::ArrowUp) {
+            self.history_previous();
+        }
+        if input.is_key_pressed_or_repeated(KeyCode::ArrowDown) {
+            self.history_next();
+        }
+        if input.is_key_just_pressed(KeyCode::Tab) {
+            self.complete(commands, block_registry);
+        }
+
+        if input.is_key_just_pressed(KeyCode::Enter)
+            || input.is_key_just_pressed(KeyCode::NumpadEnter)
+        {
+            let line = std::mem::take(&mut self.input);
+            self.close();
+            self.add_to_history(&line);
+            return Some(line);
+        }
+
+        None
+    }
+
+    /// Run a command, printing it and its output
+    pub fn execute(
+        &mut self,
+        line: &str,
+        commands: &CommandRegistry,
+        context: &mut CommandContext,
+    ) {
+        if line.trim().is_empty() {
+            return;
+        }
+
+        self.print(&format!("> {}", line));
+        match commands.execute(line, context) {
+            Ok(output) => self.print(&output),
+            Err(e) => self.print(&e.to_string()),
+        }
+    }
+
+    /// Run each line of a file as a command. Empty lines and lines starting with `#` are skipped
+    pub fn run_script(
+        &mut self,
+        path: impl AsRef<Path>,
+        commands: &CommandRegistry,
+        context: &mut CommandContext,
+    ) -> io::Result<()> {
+        let script = fs::read_to_string(path)?;
+
+        for line in script.lines().map(str::trim) {
+            if !line.starts_with('#') {
+                self.execute(line, commands, context);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Draw the console at the bottom of the window, if it is open
+    pub fn draw(&self, overlay: &mut Overlay, window_size: Vec2) {
+        if !self.is_open {
+            return;
+        }
+
+        let line_height = Overlay::GLYPH_SIZE.y;
+        let margin = Overlay::GLYPH_SIZE.x;
+        let visible_lines = self.output.len().min(VISIBLE_OUTPUT_LINES);
+        let height = (visible_lines + 1) as f32 * line_height + 2.0 * margin;
+        let top = window_size.y - height;
+
+        overlay.draw_rect(
+            Vec2::new(0.0, top),
+            Vec2::new(window_size.x, height),
+            BACKGROUND_COLOR,
+        );
+
+        for (index, line) in self
+            .output
+            .iter()
+            .skip(self.output.len() - visible_lines)
+            .enumerate()
+        {
+            overlay.draw_text(
+                Vec2::new(margin, top + margin + index as f32 * line_height),
+                line,
+                OUTPUT_COLOR,
+            );
+        }
+
+        overlay.draw_text(
+            Vec2::new(margin, window_size.y - margin - line_height),
+            &format!("> {}_", self.input),
+            INPUT_COLOR,
+        );
+    }
+
+    fn add_to_history(&mut self, line: &str) {
+        if line.trim().is_empty() || self.history.last().is_some_and(|last| last == line) {
+            return;
+        }
+
+        if self.history.len() == MAX_HISTORY_LEN {
+            self.history.remove(0);
+        }
+        self.history.push(line.to_string());
+    }
+
+    /// Show the line entered before the one being shown
+    fn history_previous(&mut self) {
+        let index = match self.history_index {
+            Some(index) => index.saturating_sub(1),
+            None if self.history.is_empty() => return,
+            None => self.history.len() - 1,
+        };
+
+        self.history_index = Some(index);
+        self.input = self.history[index].clone();
+    }
+
+    /// Show the line entered after the one being shown, or an empty line after the last one
+    fn history_next(&mut self) {
+        let Some(index) = self.history_index else {
+            return;
+        };
+
+        if index + 1 < self.history.len() {
+            self.history_index = Some(index + 1);
+            self.input = self.history[index + 1].clone();
+        } else {
+            self.history_index = None;
+            self.input.clear();
+        }
+    }
+
+    /// Complete the word being typed. If there are several completions, the word is extended to
+    /// their common prefix and they are printed
+    fn complete(&mut self, commands: &CommandRegistry, block_registry: &BlockRegistry) {
+        let (word_start, candidates) = commands.completions(&self.input, block_registry);
+
+        match candidates.as_slice() {
+            [] => (),
+            [candidate] => {
+                self.input.truncate(word_start);
+                self.input.push_str(candidate);
+                self.input.push(' ');
+            }
+            [first, rest @ ..] => {
+                let common_len = rest.iter().fold(first.len(), |len, candidate| {
+                    first
+                        .bytes()
+                        .zip(candidate.bytes())
+                        .take(len)
+                        .take_while(|(a, b)| a == b)
+                        .count()
+                });
+
+                if common_len > self.input.len() - word_start {
+                    self.input.truncate(word_start);
+                    self.input.push_str(&first[..common_len]);
+                } else {
+                    self.print(&candidates.join("  "));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::block::registry::test_registry;
+
+    #[test]
+    fn history() {
+        let mut console = Console::new();
+        console.add_to_history("/seed");
+        console.add_to_history("/time");
+        console.add_to_history("/time");
+        console.add_to_history("  ");
+        assert_eq!(console.history, ["/seed", "/time"]);
+
+        console.open("/ti");
+        console.history_previous();
+        assert_eq!(console.input, "/time");
+        console.history_previous();
+        console.history_previous();
+        assert_eq!(console.input, "/seed");
+        console.history_next();
+        assert_eq!(console.input, "/time");
+        console.history_next();
+        assert_eq!(console.input, "");
+    }
+
+    #[test]
+    fn completion() {
+        let block_registry = test_registry(&[r#"
+            name = "stone"
+            model = { type = "full_block", textures = { all = "stone" } }
+        "#]);
+        let commands = CommandRegistry::new();
+        let mut console = Console::new();
+
+        console.open("/ho");
+        console.complete(&commands, &block_registry);
+        assert_eq!(console.input, "/hold ");
+        console.input.push('s');
+        console.complete(&commands, &block_registry);
+        assert_eq!(console.input, "/hold stone ");
+
+        // several completions are extended to their common prefix, then listed
+        console.open("/se");
+        console.complete(&commands, &block_registry);
+        assert_eq!(console.input, "/se");
+        console.complete(&commands, &block_registry);
+        assert_eq!(console.input, "/se");
+        assert_eq!(console.output.back().unwrap(), "/seed  /set  /setblock");
+    }
+}
@@ -12,6 +12,10 @@ pub struct Input {
     mouse_buttons_held: FxHashSet<MouseButton>,
     mouse_buttons_held_last_frame: FxHashSet<MouseButton>,
     mouse_delta: DVec2,
+    /// Keys pressed this frame, including the repeated presses of keys being held
+    keys_pressed: FxHashSet<KeyCode>,
+    /// Text typed this frame
+    typed_text: String,
 }
 
 impl Input {
@@ -22,6 +26,8 @@ impl Input {
             mouse_buttons_held: FxHashSet::default(),
             mouse_buttons_held_last_frame: FxHashSet::default(),
             mouse_delta: DVec2::ZERO,
+            keys_pressed: FxHashSet::default(),
+            typed_text: String::new(),
         }
     }
 
@@ -30,6 +36,8 @@ impl Input {
         self.keys_held_last_frame = self.keys_held.clone();
         self.mouse_buttons_held_last_frame = self.mouse_buttons_held.clone();
         self.mouse_delta = DVec2::ZERO;
+        self.keys_pressed.clear();
+        self.typed_text.clear();
     }
 
     /// Returns true if the event was "consumed"
@@ -40,6 +48,7 @@ impl Input {
                     KeyEvent {
                         physical_key: PhysicalKey::Code(key_code),
                         state,
+                        text,
                         ..
                     },
                 ..
@@ -47,6 +56,13 @@ impl Input {
                 match state {
                     ElementState::Pressed => {
                         self.keys_held.insert(*key_code);
+                        self.keys_pressed.insert(*key_code);
+
+                        // control characters such as backspace are handled as keys instead
+                        if let Some(text) = text {
+                            self.typed_text
+                                .extend(text.chars().filter(|c| !c.is_control()));
+                        }
                     }
                     ElementState::Released => {
                         self.keys_held.remove(key_code);
@@ -89,6 +105,12 @@ impl Input {
         self.keys_held.contains(&key_code) && !self.keys_held_last_frame.contains(&key_code)
     }
 
+    /// True if the key was pressed this frame, or is being held and was repeated by the
+    /// operating system, as when typing
+    pub fn is_key_pressed_or_repeated(&self, key_code: KeyCode) -> bool {
+        self.keys_pressed.contains(&key_code)
+    }
+
     pub fn is_key_just_released(&self, key_code: KeyCode) -> bool {
         self.keys_held_last_frame.contains(&key_code) && !self.keys_held.contains(&key_code)
     }
@@ -107,6 +129,11 @@ impl Input {
             && !self.mouse_buttons_held.contains(&button)
     }
 
+    /// Text typed this frame, without control characters
+    pub fn typed_text(&self) -> &str {
+        &self.typed_text
+    }
+
     pub fn mouse_delta(&self) -> DVec2 {
         self.mouse_delta
     }
@@ -40,6 +40,10 @@ impl Time {
         }
     }
 
+    pub fn set_target_frame_rate(&mut self, target_frame_rate: TargetFrameRate) {
+        self.target_frame_rate = target_frame_rate;
+    }
+
     /// This function is called at the beginning of each frame
     pub fn begin_frame(&mut self) {
         // update frame index
@@ -13,6 +13,7 @@ pub struct RenderPipelineBuilder<'a> {
     fragment_compilation_options: wgpu::PipelineCompilationOptions<'a>,
     targets: Vec<Option<wgpu::ColorTargetState>>,
     depth: Option<(wgpu::TextureFormat, wgpu::CompareFunction)>,
+    depth_write_enabled: bool,
     topology: wgpu::PrimitiveTopology,
     front_face: wgpu::FrontFace,
     cull_mode: Option<wgpu::Face>,
@@ -33,6 +34,7 @@ impl<'a> RenderPipelineBuilder<'a> {
             fragment_compilation_options: wgpu::PipelineCompilationOptions::default(),
             targets: Vec::new(),
             depth: None,
+            depth_write_enabled: true,
             topology: wgpu::PrimitiveTopology::TriangleList,
             front_face: wgpu::FrontFace::Ccw,
             cull_mode: Some(wgpu::Face::Back),
@@ -78,7 +80,7 @@ impl<'a> RenderPipelineBuilder<'a> {
                 .map(|(format, depth_compare)| wgpu::DepthStencilState {
                     format,
                     depth_compare,
-                    depth_write_enabled: true,
+                    depth_write_enabled: self.depth_write_enabled,
                     stencil: wgpu::StencilState::default(),
                     bias: wgpu::DepthBiasState::default(),
                 }),
@@ -178,6 +180,11 @@ impl<'a> RenderPipelineBuilder<'a> {
         self
     }
 
+    pub fn with_depth_write_enabled(mut self, depth_write_enabled: bool) -> Self {
+        self.depth_write_enabled = depth_write_enabled;
+        self
+    }
+
     pub fn with_topology(mut self, topology: wgpu::PrimitiveTopology) -> Self {
         self.topology = topology;
         self
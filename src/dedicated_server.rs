@@ -0,0 +1,406 @@
+use std::{
+    io::{self, BufRead},
+    net::ToSocketAddrs,
+    str::FromStr,
+    sync::mpsc::{self, Receiver, TryRecvError},
+    time::{Duration, Instant},
+};
+
+use generational_arena::Index;
+use glam::Vec3;
+use itertools::Itertools;
+
+use crate::{
+    core::{
+        tasks::Tasks,
+        time::{TargetFrameRate, Time},
+    },
+    net::server::Server,
+    settings::Settings,
+    terrain::{
+        chunk::CHUNK_SIZE,
+        load_area::{AreaShape, LoadArea},
+        position_types::{ChunkPosition, GlobalBlockPosition},
+        Terrain,
+    },
+    util::size::Size3,
+};
+
+/// Number of times the server updates per second. Matches the tick rate, so that each update
+/// runs about one tick
+const UPDATES_PER_SECOND: u32 = 20;
+
+/// Size of the load area around each connected player
+const PLAYER_LOAD_AREA_SIZE: Size3 = Size3::new(24, 12, 24);
+
+/// Size of the load area around each anchor point
+const ANCHOR_LOAD_AREA_SIZE: Size3 = Size3::new(8, 8, 8);
+
+/// Time between saves of the modified chunks
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(300);
+
+const COMMAND_HELP: &str = "\
+commands:
+    help                    show this message
+    list                    list the connected players
+    save                    save the world
+    anchor <x> <y> <z>      keep the chunks around a block position loaded
+    anchors                 list the anchor points
+    unanchor <index>        remove an anchor point
+    stop                    save the world and stop the server";
+
+/// Runs the terrain and serves it to clients without a window or renderer.
+/// Chunks are loaded around each connected player and around anchor points, which keep areas of
+/// the world ticking while no players are near them. Commands are read from stdin
+pub struct DedicatedServer {
+    terrain: Terrain,
+    tasks: Tasks,
+    server: Server,
+    anchors: Vec<Anchor>,
+    /// Lines read from stdin by the console thread
+    console_rx: Option<Receiver<String>>,
+    last_save: Instant,
+    stop_requested: bool,
+}
+
+/// A point the terrain is kept loaded around
+struct Anchor {
+    pos: GlobalBlockPosition,
+    load_area_index: Index,
+}
+
+impl DedicatedServer {
+    /// Start listening for clients on the given address, with load areas around the given anchor
+    /// points
+    pub fn new(
+        terrain: Terrain,
+        addr: impl ToSocketAddrs,
+        anchors: &[GlobalBlockPosition],
+        settings: &Settings,
+    ) -> io::Result<Self> {
+        let server = Server::bind(addr, &terrain, PLAYER_LOAD_AREA_SIZE)?;
+
+        let mut dedicated_server = Self {
+            terrain,
+            tasks: Tasks::new(settings.performance.worker_threads),
+            server,
+            anchors: Vec::new(),
+            console_rx: None,
+            last_save: Instant::now(),
+            stop_requested: false,
+        };
+        for &pos in anchors {
+            dedicated_server.add_anchor(pos);
+        }
+
+        Ok(dedicated_server)
+    }
+
+    /// Run the server until the `stop` command is given, then save the world
+    pub fn run(&mut self) {
+        self.spawn_console_thread();
+
+        log::info!(
+            "listening on {}",
+            self.server
+                .local_addr()
+                .map_or_else(|e| e.to_string(), |addr| addr.to_string())
+        );
+
+        let mut time = Time::new(TargetFrameRate::Limited(UPDATES_PER_SECOND));
+
+        while !self.stop_requested {
+            time.begin_frame();
+
+            self.run_console_commands();
+            self.update(time.delta());
+
+            if self.last_save.elapsed() >= AUTOSAVE_INTERVAL {
+                self.save();
+            }
+
+            time.wait_for_next_frame();
+            time.update_frame_count();
+        }
+
+        self.save();
+        log::info!("server stopped");
+    }
+
+    /// Run one update of the networking and the terrain
+    fn update(&mut self, delta: Duration) {
+        self.terrain.clear_events();
+
+        self.server.update(&mut self.terrain, &mut self.tasks);
+
+        // chunks nearest the first player are loaded first
+        let focus = self
+            .server
+            .player_positions()
+            .map(|(_, position)| position)
+            .next()
+            .unwrap_or(Vec3::ZERO);
+        self.terrain.update(&mut self.tasks, focus, delta);
+
+        self.server.send_updates(&self.terrain);
+    }
+
+    /// Save the modified chunks and the world metadata, waiting until they are written
+    fn save(&mut self) {
+        // wait for any chunks being decorated, so that their features are saved
+        self.tasks.block_until_finished();
+        self.terrain.save(&mut self.tasks);
+
+        if let Err(e) = self.terrain.world_save().save_metadata() {
+            log::error!("failed to save world metadata: {}", e);
+        }
+
+        self.tasks.block_until_finished();
+        self.last_save = Instant::now();
+    }
+
+    fn add_anchor(&mut self, pos: GlobalBlockPosition) {
+        let mut load_area =
+            LoadArea::new(ChunkPosition::ZERO, ANCHOR_LOAD_AREA_SIZE, AreaShape::Cubic);
+        load_area.set_center(pos.as_ivec3().as_vec3() / (CHUNK_SIZE as f32));
+
+        let load_area_index = self.terrain.load_areas_mut().insert(load_area);
+        self.anchors.push(Anchor {
+            pos,
+            load_area_index,
+        });
+    }
+
+    /// Start a thread which sends the lines typed on stdin to the server
+    fn spawn_console_thread(&mut self) {
+        let (console_tx, console_rx) = mpsc::channel();
+
+        std::thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if console_tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        self.console_rx = Some(console_rx);
+    }
+
+    fn run_console_commands(&mut self) {
+        loop {
+            let line = match self.console_rx.as_ref().map(Receiver::try_recv) {
+                Some(Ok(line)) => line,
+                // stdin was closed, so the server can only be stopped by killing it
+                Some(Err(TryRecvError::Disconnected)) => {
+                    self.console_rx = None;
+                    break;
+                }
+                Some(Err(TryRecvError::Empty)) | None => break,
+            };
+
+            match self.execute_command(&line) {
+                Ok(output) if output.is_empty() => (),
+                Ok(output) => println!("{}", output),
+                Err(e) => println!("{}", e),
+            }
+        }
+    }
+
+    /// Run an admin command, returning the text to show in response
+    fn execute_command(&mut self, line: &str) -> Result<String, CommandError> {
+        let args = line.split_whitespace().collect_vec();
+
+        match args.as_slice() {
+            [] => Ok(String::new()),
+            ["help"] => Ok(COMMAND_HELP.to_string()),
+            ["list"] => {
+                let players = self
+                    .server
+                    .player_positions()
+                    .sorted_by_key(|&(client_id, _)| client_id)
+                    .map(|(client_id, position)| {
+                        format!(
+                            "    client {} at {:.1} {:.1} {:.1}",
+                            client_id, position.x, position.y, position.z
+                        )
+                    })
+                    .join("\n");
+
+                Ok(format!(
+                    "{} players connected\n{}",
+                    self.server.client_count(),
+                    players
+                )
+                .trim_end()
+                .to_string())
+            }
+            ["save"] => {
+                self.save();
+                Ok("saved the world".to_string())
+            }
+            ["anchor", x, y, z] => {
+                let pos = GlobalBlockPosition::new(
+                    parse_coordinate(x)?,
+                    parse_coordinate(y)?,
+                    parse_coordinate(z)?,
+                );
+                self.add_anchor(pos);
+
+                Ok(format!("added anchor {}", self.anchors.len() - 1))
+            }
+            ["anchors"] => Ok(self
+                .anchors
+                .iter()
+                .enumerate()
+                .map(|(index, anchor)| {
+                    format!(
+                        "    {}: {} {} {}",
+                        index,
+                        anchor.pos.x(),
+                        anchor.pos.y(),
+                        anchor.pos.z()
+                    )
+                })
+                .join("\n")),
+            ["unanchor", index] => {
+                let index = usize::from_str(index)
+                    .ok()
+                    .filter(|&index| index < self.anchors.len())
+                    .ok_or(CommandError::InvalidAnchor(index.to_string()))?;
+
+                let anchor = self.anchors.remove(index);
+                self.terrain
+                    .remove_load_area(&mut self.tasks, anchor.load_area_index);
+
+                Ok(format!("removed anchor {}", index))
+            }
+            ["stop"] => {
+                self.stop_requested = true;
+                Ok("stopping".to_string())
+            }
+            [command, ..] => Err(CommandError::Unknown(command.to_string())),
+        }
+    }
+}
+
+fn parse_coordinate(coordinate: &str) -> Result<i32, CommandError> {
+    i32::from_str(coordinate).map_err(|_| CommandError::InvalidCoordinate(coordinate.to_string()))
+}
+
+/// Errors returned by admin commands
+#[derive(Debug, thiserror::Error)]
+pub enum CommandError {
+    #[error("unknown command or arguments for `{0}`, type `help` for a list of commands")]
+    Unknown(String),
+    #[error("invalid coordinate `{0}`")]
+    InvalidCoordinate(String),
+    #[error("no anchor `{0}`")]
+    InvalidAnchor(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::Arc};
+
+    use super::*;
+    use crate::terrain::{
+        block::registry::test_registry, generation::GeneratorSettings, save::WorldSave,
+    };
+
+    #[test]
+    fn console_commands() {
+        let block_registry = Arc::new(test_registry(&[r#"
+            name = "stone"
+            model = { type = "full_block", textures = { all = "stone" } }
+        "#]));
+        let directory = std::env::temp_dir().join(format!(
+            "voxels-dedicated-server-test-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&directory);
+        let world_save = Arc::new(
+            WorldSave::open_or_create(
+                &directory,
+                &block_registry,
+                0,
+                GeneratorSettings::Flat {
+                    layers: "stone".to_string(),
+                },
+            )
+            .unwrap(),
+        );
+        let generator = world_save
+            .generator_settings()
+            .build(world_save.seed(), &block_registry)
+            .unwrap();
+        let terrain = Terrain::new(block_registry, world_save, generator);
+
+        let mut server = DedicatedServer::new(
+            terrain,
+            "127.0.0.1:0",
+            &[GlobalBlockPosition::new(0, 0, 0)],
+            &Settings::default(),
+        )
+        .unwrap();
+        assert_eq!(server.terrain.load_areas().len(), 1);
+
+        // anchors load the chunks around them
+        assert_eq!(
+            server.execute_command("anchor 100 -5 3").unwrap(),
+            "added anchor 1"
+        );
+        let start = Instant::now();
+        while server
+            .terrain
+            .get_block(
+                server.anchors[1].load_area_index,
+                &GlobalBlockPosition::new(100, 0, 3),
+            )
+            .is_none()
+        {
+            assert!(start.elapsed() < Duration::from_secs(20), "timed out");
+            server.update(Duration::from_millis(10));
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        assert_eq!(
+            server.execute_command("anchors").unwrap(),
+            "    0: 0 0 0\n    1: 100 -5 3"
+        );
+        server.execute_command("unanchor 1").unwrap();
+        assert!(server.terrain.chunks().iter().all(|(_, chunk)| {
+            server.terrain.load_areas()[server.anchors[0].load_area_index]
+                .is_within_area(&chunk.position())
+        }));
+        server.execute_command("anchor 100 -5 3").unwrap();
+        server.execute_command("unanchor 0").unwrap();
+        assert_eq!(server.terrain.load_areas().len(), 1);
+        assert_eq!(
+            server.execute_command("anchors").unwrap(),
+            "    0: 100 -5 3"
+        );
+
+        assert!(matches!(
+            server.execute_command("unanchor 1"),
+            Err(CommandError::InvalidAnchor(_))
+        ));
+        assert!(matches!(
+            server.execute_command("anchor 1 x 2"),
+            Err(CommandError::InvalidCoordinate(_))
+        ));
+        assert!(matches!(
+            server.execute_command("teleport"),
+            Err(CommandError::Unknown(_))
+        ));
+        assert_eq!(
+            server.execute_command("list").unwrap(),
+            "0 players connected"
+        );
+
+        server.execute_command("stop").unwrap();
+        assert!(server.stop_requested);
+    }
+}
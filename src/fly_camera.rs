@@ -39,10 +39,7 @@ impl FlyCamera {
         let input_right = axis_input(input, self.key_right, self.key_left);
         let input_up = axis_input(input, self.key_up, self.key_down);
 
-        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
-
-        let dir_forward = Vec3::new(-sin_yaw, 0.0, -cos_yaw);
-        let dir_right = Vec3::new(cos_yaw, 0.0, -sin_yaw);
+        let (dir_forward, dir_right) = self.horizontal_directions();
         const DIR_UP: Vec3 = Vec3::new(0.0, 1.0, 0.0);
 
         let speed = self.speed * time.delta_seconds();
@@ -51,7 +48,11 @@ impl FlyCamera {
         self.position += dir_right * input_right * speed;
         self.position += DIR_UP * input_up * speed;
 
-        // rotation
+        self.update_rotation(input);
+    }
+
+    /// Turn the camera with the mouse, without moving it
+    pub fn update_rotation(&mut self, input: &Input) {
         let rotate_amount = input.mouse_delta_f32();
         self.yaw -= self.sensitivity * rotate_amount.x;
         self.pitch -= self.sensitivity * rotate_amount.y;
@@ -60,6 +61,16 @@ impl FlyCamera {
             .pitch
             .clamp(-std::f32::consts::FRAC_PI_2, std::f32::consts::FRAC_PI_2);
     }
+
+    /// Returns the horizontal directions the camera is facing forwards and to the right
+    pub fn horizontal_directions(&self) -> (Vec3, Vec3) {
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+
+        (
+            Vec3::new(-sin_yaw, 0.0, -cos_yaw),
+            Vec3::new(cos_yaw, 0.0, -sin_yaw),
+        )
+    }
 }
 
 impl Default for FlyCamera {
@@ -80,6 +91,6 @@ impl Default for FlyCamera {
     }
 }
 
-fn axis_input(input: &Input, key_pos: KeyCode, key_neg: KeyCode) -> f32 {
+pub fn axis_input(input: &Input, key_pos: KeyCode, key_neg: KeyCode) -> f32 {
     (input.is_key_down(key_pos) as i32 - input.is_key_down(key_neg) as i32) as f32
 }
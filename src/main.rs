@@ -1,22 +1,33 @@
-use core::{
-    input::Input,
-    tasks::Tasks,
-    time::{TargetFrameRate, Time},
-    wgpu_util::wgpu_context::WgpuContext,
+use core::{input::Input, tasks::Tasks, time::Time, wgpu_util::wgpu_context::WgpuContext};
+use std::{
+    env, io, process,
+    sync::{
+        mpsc::{Receiver, TryRecvError},
+        Arc,
+    },
 };
-use std::sync::Arc;
 
-use fly_camera::FlyCamera;
+use console::{
+    commands::{self, CommandContext, CommandRegistry},
+    Console,
+};
 use generational_arena::Index;
+use glam::{Vec2, Vec3};
+use itertools::Itertools;
+use net::client::Client;
+use player::PlayerController;
 use renderer::Renderer;
+use settings::Settings;
 use terrain::{
-    block::{BLOCK_AIR, BLOCK_DIRT, BLOCK_GRASS, BLOCK_LAMP_ORANGE},
+    block::{registry::BlockRegistry, state::BlockState, BlockId, BLOCK_AIR},
     chunk::CHUNK_SIZE,
+    generation::GeneratorSettings,
     load_area::{AreaShape, LoadArea},
+    pathfinding::Path,
     position_types::ChunkPosition,
+    save::WorldSave,
     Terrain,
 };
-use util::size::Size3;
 use winit::{
     application::ApplicationHandler,
     dpi::{LogicalPosition, PhysicalSize},
@@ -27,18 +38,37 @@ use winit::{
     window::{Window, WindowId},
 };
 
-use crate::terrain::{block::BLOCK_WOOD, position_types::GlobalBlockPosition};
+use crate::terrain::position_types::GlobalBlockPosition;
 
+mod cli;
+mod console;
 mod core;
+mod dedicated_server;
 mod fly_camera;
+mod net;
+mod player;
 mod renderer;
+mod settings;
 mod terrain;
 mod util;
 
 const WINDOW_TITLE: &'static str = "\"minecraft\"";
 
-/// Number of threads to use for task processing
-const TASKS_WORKER_THREAD_COUNT: usize = 4;
+/// Directory containing the block definition files
+const BLOCK_DEFINITIONS_PATH: &str = "assets/block";
+
+/// Directory containing the world save
+const WORLD_DIRECTORY_PATH: &str = "world";
+
+/// Directory containing the metadata of the worlds played on servers, in a directory for each
+/// server address
+const REMOTE_WORLDS_DIRECTORY_PATH: &str = "remote";
+
+/// File of console commands run when the game starts, if it exists
+const STARTUP_SCRIPT_PATH: &str = "startup_commands.txt";
+
+/// File the settings are read from at startup, and written to on exit if they were changed
+const SETTINGS_PATH: &str = "settings.toml";
 
 /// Priority value for chunk mesh generation tasks when an outdated mesh already exists
 const CHUNK_MESH_UPDATE_PRIORITY: i32 = 0;
@@ -49,8 +79,14 @@ const CHUNK_MESH_GENERATION_PRIORITY: i32 = 1;
 /// Priority value for chunk loading tasks
 const CHUNK_LOADING_PRIORITY: i32 = 2;
 
+/// Priority value for pathfinding tasks
+const PATHFINDING_PRIORITY: i32 = 3;
+
+/// Priority value for chunk saving tasks
+const CHUNK_SAVING_PRIORITY: i32 = 4;
+
 /// Priority value for chunk mesh generation tasks when an up-to-date mesh already exists
-const CHUNK_MESH_OPTIMIZATION_PRIORITY: i32 = 3;
+const CHUNK_MESH_OPTIMIZATION_PRIORITY: i32 = 5;
 
 struct State {
     window: Arc<Window>,
@@ -60,29 +96,70 @@ struct State {
     tasks: Tasks,
     terrain: Terrain,
     load_area_index: Index,
+    /// Connection to the server the game is playing on, or `None` when playing the local world
+    client: Option<Client>,
     renderer: Renderer,
-    fly_camera: FlyCamera,
-    fly_camera_active: bool,
+    player: PlayerController,
+    /// Block placed with the right mouse button, chosen with `/hold`
+    held_block: BlockId,
+    console: Console,
+    commands: CommandRegistry,
+    /// Path search started by `/path`, whose result is printed once it finishes
+    path_search: Option<Receiver<Option<Path>>>,
+    settings: Settings,
+    /// Settings as they were read from the settings file, or `None` if the file couldn't be read,
+    /// in which case it isn't overwritten
+    saved_settings: Option<Settings>,
     close_requested: bool,
 }
 
 impl State {
-    fn new(window: Arc<Window>) -> Self {
+    fn new(
+        window: Arc<Window>,
+        saved_settings: Option<Settings>,
+        remote: Option<(Client, Terrain)>,
+    ) -> Self {
+        let settings = saved_settings.clone().unwrap_or_default();
         let wgpu = WgpuContext::new(window.clone());
         let input = Input::new();
-        let time = Time::new(TargetFrameRate::UnlimitedOrVsync);
-        let tasks = Tasks::new(TASKS_WORKER_THREAD_COUNT);
-        let mut terrain = Terrain::new();
-        let fly_camera = FlyCamera::default();
+        let time = Time::new(settings.target_frame_rate());
+        let tasks = Tasks::new(settings.performance.worker_threads);
+        let (mut client, mut terrain) = match remote {
+            Some((client, terrain)) => (Some(client), terrain),
+            None => (None, open_terrain()),
+        };
+        let block_registry = terrain.block_registry().clone();
+        let mut player =
+            PlayerController::new(terrain.world_save().player_position().unwrap_or_default());
+        player.camera.sensitivity = settings.controls.mouse_sensitivity;
+        player.camera.speed = settings.controls.fly_speed;
 
         let load_area_index = terrain.load_areas_mut().insert(LoadArea::new(
             ChunkPosition::ZERO,
-            Size3::new(64, 16, 64),
+            settings.load_area_size(),
             AreaShape::Cylindrical,
         ));
-        let renderer = Renderer::new(&wgpu, terrain.load_areas().get(load_area_index).unwrap());
+        if let Some(client) = &mut client {
+            send_position(client, player.camera.position);
+        }
+        let renderer = Renderer::new(
+            &wgpu,
+            terrain.load_areas().get(load_area_index).unwrap(),
+            &block_registry,
+            &settings,
+        );
 
-        Self {
+        // hold the first block that isn't air, another state of a block or a flowing fluid
+        let held_block = block_registry
+            .iter()
+            .find(|(block_id, block)| {
+                *block_id != BLOCK_AIR
+                    && *block_id == block.default_state
+                    && block.fluid.is_none_or(|fluid| fluid.is_source())
+            })
+            .map_or(BLOCK_AIR, |(block_id, _)| block_id);
+
+        let mut state = Self {
             window,
             wgpu,
             input,
@@ -90,11 +167,27 @@ impl State {
             tasks,
             terrain,
             load_area_index,
+            client,
             renderer,
-            fly_camera,
-            fly_camera_active: true,
+            player,
+            held_block,
+            console: Console::new(),
+            commands: CommandRegistry::new(),
+            path_search: None,
+            settings,
+            saved_settings,
             close_requested: false,
-        }
+        };
+
+        state.with_command_context(|console, commands, context| {
+            match console.run_script(STARTUP_SCRIPT_PATH, commands, context) {
+                Ok(()) => log::info!("ran startup commands from {}", STARTUP_SCRIPT_PATH),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+                Err(e) => log::error!("failed to read {}: {}", STARTUP_SCRIPT_PATH, e),
+            }
+        });
+
+        state
     }
 
     fn frame(&mut self) {
@@ -105,6 +198,33 @@ impl State {
         self.time.wait_for_next_frame();
     }
 
+    /// Save the modified chunks and world metadata, waiting until everything is written
+    fn save(&mut self) {
+        // wait for any chunks being decorated, so that their features are saved
+        self.tasks.block_until_finished();
+        self.terrain.save(&mut self.tasks);
+
+        let world_save = self.terrain.world_save();
+        world_save.set_player_position(self.player.camera.position);
+        if let Err(e) = world_save.save_metadata() {
+            log::error!("failed to save world metadata: {}", e);
+        }
+
+        // write back the settings if they were changed while playing
+        if self
+            .saved_settings
+            .as_ref()
+            .is_some_and(|saved_settings| *saved_settings != self.settings)
+        {
+            match self.settings.save(SETTINGS_PATH) {
+                Ok(()) => self.saved_settings = Some(self.settings.clone()),
+                Err(e) => log::error!("failed to save settings: {}", e),
+            }
+        }
+
+        self.tasks.block_until_finished();
+    }
+
     fn resized(&mut self, new_size: PhysicalSize<u32>) {
         self.wgpu.resized(new_size);
         self.renderer.resized(&self.wgpu);
@@ -113,6 +233,14 @@ impl State {
     fn update(&mut self) {
         self.terrain.clear_events();
 
+        if let Some(client) = &mut self.client {
+            if !client.is_connected() {
+                log::error!("disconnected from the server");
+                self.close_requested = true;
+                return;
+            }
+        }
+
         // capture cursor
         if self.window.has_focus() {
             let window_size = self.window.inner_size();
@@ -124,93 +252,204 @@ impl State {
                 .unwrap();
         }
 
-        if self.input.is_key_just_pressed(KeyCode::KeyC) {
-            self.fly_camera.position.x *= 2.0;
-            log::info!("{}", self.fly_camera.position.x);
+        // open the console, with the `/` already typed if it was opened with the slash key
+        if !self.console.is_open() {
+            if self.input.is_key_just_pressed(KeyCode::Backquote) {
+                self.console.open("");
+            } else if self.input.is_key_just_pressed(KeyCode::Slash) {
+                self.console.open("/");
+            }
+        } else if let Some(line) =
+            self.console
+                .handle_input(&self.input, &self.commands, self.terrain.block_registry())
+        {
+            self.with_command_context(|console, commands, context| {
+                console.execute(&line, commands, context);
+            });
+        }
+
+        if let Some(path_search) = &self.path_search {
+            match path_search.try_recv() {
+                Ok(path) => {
+                    self.console.print(&commands::describe_path(path.as_ref()));
+                    self.path_search = None;
+                }
+                Err(TryRecvError::Empty) => (),
+                Err(TryRecvError::Disconnected) => self.path_search = None,
+            }
         }
 
-        // display framerate in window title
+        // display framerate and current biome in window title
+        let camera_block_pos =
+            GlobalBlockPosition::from(self.player.camera.position.floor().as_ivec3());
+        let biome_name = self
+            .terrain
+            .get_biome(self.load_area_index, &camera_block_pos)
+            .map_or("unloaded", |biome_id| biome_id.biome().name);
         self.window.set_title(&format!(
-            "{} ({} fps)",
+            "{} ({} fps, {})",
             WINDOW_TITLE,
-            self.time.get_frames_last_second()
+            self.time.get_frames_last_second(),
+            biome_name
         ));
 
-        // update flycam
-        if self.fly_camera_active {
-            self.fly_camera.update(&self.input, &self.time);
+        // update player, unless typing in the console
+        let previous_position = self.player.camera.position;
+        if !self.console.is_open() {
+            let terrain = &self.terrain;
+            let load_area_index = self.load_area_index;
+            self.player.update(
+                &self.input,
+                &self.time,
+                terrain.block_registry(),
+                |block_pos| terrain.get_block(load_area_index, block_pos),
+            );
+        }
+        self.renderer.camera_mut().transform = self.player.camera.get_transform();
+
+        // the server loads chunks around the player
+        if let Some(client) = &mut self.client {
+            if self.player.camera.position != previous_position {
+                send_position(client, self.player.camera.position);
+            }
         }
-        self.renderer.camera_mut().transform = self.fly_camera.get_transform();
 
         // block breaking and placing (TEMP)
         let destroy = self.input.is_mouse_button_just_pressed(MouseButton::Left);
-        let place_dirt = self.input.is_key_just_pressed(KeyCode::Digit1);
-        let place_grass = self.input.is_key_just_pressed(KeyCode::Digit2);
-        let place_wood = self.input.is_key_just_pressed(KeyCode::Digit3);
-        let place_lamp = self.input.is_key_just_pressed(KeyCode::Digit4);
-        if destroy || place_dirt || place_grass || place_wood || place_lamp {
+        let place = self.input.is_mouse_button_just_pressed(MouseButton::Right);
+        if (destroy || place) && !self.console.is_open() {
             let look_dir = self.renderer.camera().look_dir(); // bad coupling
 
-            let hit = self.terrain.raymarch(
+            let hit = self.terrain.raycast(
                 self.load_area_index,
-                self.fly_camera.position,
+                self.player.camera.position,
                 look_dir,
                 50.0,
+                |_, block| block.is_targetable(),
             );
 
             if let Some(hit) = hit {
+                // breaking and placing in the same frame is undone as one step
+                self.terrain.begin_edit_transaction();
                 if destroy {
-                    self.terrain
-                        .set_block(self.load_area_index, &hit.hit_pos, BLOCK_AIR);
+                    self.edit_block(hit.hit_pos, BLOCK_AIR);
                 }
-                if place_dirt {
-                    if let Some(hit_normal) = hit.hit_normal {
-                        self.terrain.set_block(
-                            self.load_area_index,
-                            &(hit.hit_pos + GlobalBlockPosition::from(hit_normal)),
-                            BLOCK_DIRT,
-                        );
-                    }
-                }
-                if place_grass {
-                    if let Some(hit_normal) = hit.hit_normal {
-                        self.terrain.set_block(
-                            self.load_area_index,
-                            &(hit.hit_pos + GlobalBlockPosition::from(hit_normal)),
-                            BLOCK_GRASS,
-                        );
-                    }
-                }
-                if place_wood {
-                    if let Some(hit_normal) = hit.hit_normal {
-                        self.terrain.set_block(
-                            self.load_area_index,
-                            &(hit.hit_pos + GlobalBlockPosition::from(hit_normal)),
-                            BLOCK_WOOD,
-                        );
-                    }
-                }
-                if place_lamp {
-                    if let Some(hit_normal) = hit.hit_normal {
-                        self.terrain.set_block(
-                            self.load_area_index,
-                            &(hit.hit_pos + GlobalBlockPosition::from(hit_normal)),
-                            BLOCK_LAMP_ORANGE,
-                        );
-                    }
+                if let (true, Some(hit_normal)) = (place, hit.hit_normal) {
+                    // orient the block based on the face it was placed against
+                    let block_id = self.terrain.block_registry().with_state(
+                        self.held_block,
+                        &BlockState::for_placement(hit_normal, look_dir),
+                    );
+
+                    self.edit_block(
+                        hit.hit_pos + GlobalBlockPosition::from(hit_normal),
+                        block_id,
+                    );
                 }
+                self.terrain.end_edit_transaction();
+            }
+        }
+
+        // undo and redo edits
+        if !self.console.is_open() && self.input.is_key_down(KeyCode::ControlLeft) {
+            if self.input.is_key_just_pressed(KeyCode::KeyZ) {
+                self.terrain.undo(&mut self.tasks);
+            }
+            if self.input.is_key_just_pressed(KeyCode::KeyY) {
+                self.terrain.redo(&mut self.tasks);
             }
         }
 
         self.terrain.load_areas_mut()[self.load_area_index]
-            .set_center(self.fly_camera.position / (CHUNK_SIZE as f32));
+            .set_center(self.player.camera.position / (CHUNK_SIZE as f32));
 
-        self.terrain
-            .update(&mut self.tasks, self.fly_camera.position);
+        if let Some(client) = &mut self.client {
+            client.update(&mut self.terrain, &mut self.tasks);
+        }
+        self.terrain.update(
+            &mut self.tasks,
+            self.player.camera.position,
+            self.time.delta(),
+        );
 
         self.input.reset();
     }
 
+    /// Change a block, or ask the server to change it when playing on one
+    fn edit_block(&mut self, pos: GlobalBlockPosition, block_id: BlockId) {
+        match &mut self.client {
+            Some(client) => {
+                if let Err(e) = client.request_set_block(pos, block_id) {
+                    log::error!("failed to send block edit to the server: {}", e);
+                }
+            }
+            None => {
+                self.terrain.set_block(self.load_area_index, &pos, block_id);
+            }
+        }
+    }
+
+    /// Call `f` with the console, the commands and the parts of the game they act on, then
+    /// apply the changes commands made to the renderer and the settings
+    fn with_command_context(
+        &mut self,
+        f: impl FnOnce(&mut Console, &CommandRegistry, &mut CommandContext),
+    ) {
+        let mut culling_mode = self.renderer.culling_mode();
+        let previous_settings = self.settings.clone();
+
+        f(
+            &mut self.console,
+            &self.commands,
+            &mut CommandContext {
+                terrain: &mut self.terrain,
+                load_area_index: self.load_area_index,
+                player: &mut self.player,
+                time: &self.time,
+                held_block: &mut self.held_block,
+                culling_mode: &mut culling_mode,
+                settings: &mut self.settings,
+                tasks: &mut self.tasks,
+                path_search: &mut self.path_search,
+                client: self.client.as_ref(),
+            },
+        );
+
+        self.renderer.set_culling_mode(culling_mode);
+        self.apply_settings(&previous_settings);
+    }
+
+    /// Apply the settings which have changed since `previous_settings`, other than those which
+    /// are only read at startup
+    fn apply_settings(&mut self, previous_settings: &Settings) {
+        let settings = &self.settings;
+        let load_area = &mut self.terrain.load_areas_mut()[self.load_area_index];
+
+        if settings.load_area_size() != previous_settings.load_area_size() {
+            load_area.set_size(settings.load_area_size());
+            load_area.set_center(self.player.camera.position / (CHUNK_SIZE as f32));
+            self.renderer
+                .load_area_resized(&self.wgpu, &mut self.tasks, load_area);
+        }
+
+        if settings.graphics.lod != previous_settings.graphics.lod {
+            self.renderer.set_lod_settings(
+                &self.wgpu,
+                &mut self.tasks,
+                load_area,
+                settings.graphics.lod,
+            );
+        }
+
+        self.renderer
+            .camera_mut()
+            .set_fov_y(settings.fov_y_radians());
+        self.time
+            .set_target_frame_rate(settings.target_frame_rate());
+        self.player.camera.sensitivity = settings.controls.mouse_sensitivity;
+        self.player.camera.speed = settings.controls.fly_speed;
+    }
+
     fn render(&mut self) {
         let Some(surface_texture) = self.wgpu.get_surface_texture() else {
             log::warn!("couldn't acquire surface texture");
@@ -221,6 +460,12 @@ impl State {
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
 
+        let window_size = Vec2::new(
+            self.wgpu.window_size.width as f32,
+            self.wgpu.window_size.height as f32,
+        );
+        self.console.draw(self.renderer.overlay_mut(), window_size);
+
         self.renderer.render(
             &self.wgpu,
             &output_view,
@@ -236,11 +481,19 @@ impl State {
 
 struct WinitApplicationHandler {
     state: Option<State>,
+    /// Settings read from the settings file, moved into the state once it is created
+    saved_settings: Option<Settings>,
+    /// Connection to a server and its terrain, moved into the state once it is created
+    remote: Option<(Client, Terrain)>,
 }
 
 impl WinitApplicationHandler {
-    fn new() -> Self {
-        Self { state: None }
+    fn new(saved_settings: Option<Settings>, remote: Option<(Client, Terrain)>) -> Self {
+        Self {
+            state: None,
+            saved_settings,
+            remote,
+        }
     }
 }
 
@@ -254,7 +507,11 @@ impl ApplicationHandler<()> for WinitApplicationHandler {
                     .expect("failed to create window"),
             );
 
-            self.state = Some(State::new(window));
+            self.state = Some(State::new(
+                window,
+                self.saved_settings.take(),
+                self.remote.take(),
+            ));
         }
     }
 
@@ -279,7 +536,9 @@ impl ApplicationHandler<()> for WinitApplicationHandler {
         match self.state.as_mut() {
             Some(state) => {
                 if state.close_requested {
+                    state.save();
                     event_loop.exit();
+                    return;
                 }
                 state.frame();
                 event_loop.set_control_flow(ControlFlow::Poll);
@@ -289,8 +548,63 @@ impl ApplicationHandler<()> for WinitApplicationHandler {
     }
 }
 
+/// Send the player's position to the server, which loads the chunks around it
+fn send_position(client: &mut Client, position: Vec3) {
+    if let Err(e) = client.send_position(position) {
+        log::error!("failed to send position to the server: {}", e);
+    }
+}
+
+fn load_block_registry() -> BlockRegistry {
+    BlockRegistry::load(BLOCK_DEFINITIONS_PATH).expect("failed to load block definitions")
+}
+
+/// Load the block definitions and open the world save, creating a new world if there isn't one
+fn open_terrain() -> Terrain {
+    let block_registry = Arc::new(load_block_registry());
+    let world_save = Arc::new(
+        WorldSave::open_or_create(
+            WORLD_DIRECTORY_PATH,
+            &block_registry,
+            rand::random(),
+            GeneratorSettings::default(),
+        )
+        .expect("failed to open world"),
+    );
+    let generator = world_save
+        .generator_settings()
+        .build(world_save.seed(), &block_registry)
+        .expect("failed to create terrain generator");
+
+    Terrain::new(block_registry, world_save, generator)
+}
+
 fn main() -> Result<(), EventLoopError> {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info,wgpu=warn"))
         .init();
-    EventLoop::new()?.run_app(&mut WinitApplicationHandler::new())
+
+    let saved_settings = Settings::load(SETTINGS_PATH)
+        .inspect_err(|e| log::error!("{}, using the default settings", e))
+        .ok();
+
+    // run a subcommand instead of the game if one is given
+    let args = env::args().skip(1).collect_vec();
+    if !args.is_empty() {
+        if let Err(e) = cli::run(&args, saved_settings) {
+            log::error!("{}", e);
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
+    run_game(saved_settings, None)
+}
+
+/// Open the game window, playing on the terrain of a server if `remote` is given and the local
+/// world otherwise
+fn run_game(
+    saved_settings: Option<Settings>,
+    remote: Option<(Client, Terrain)>,
+) -> Result<(), EventLoopError> {
+    EventLoop::new()?.run_app(&mut WinitApplicationHandler::new(saved_settings, remote))
 }
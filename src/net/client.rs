@@ -0,0 +1,219 @@
+use std::{
+    io::{BufReader, BufWriter},
+    net::{Shutdown, TcpStream, ToSocketAddrs},
+    sync::{
+        mpsc::{self, Receiver, TryRecvError},
+        Arc,
+    },
+};
+
+use glam::Vec3;
+use rustc_hash::FxHashMap;
+
+use super::{
+    protocol::{self, ClientId, ClientMessage, ServerMessage, PROTOCOL_VERSION},
+    NetError,
+};
+use crate::{
+    core::tasks::Tasks,
+    terrain::{
+        block::{registry::BlockRegistry, BlockId},
+        generation::GeneratorSettings,
+        position_types::GlobalBlockPosition,
+        save::{chunk_format::decode_chunk, BlockIdMap, WorldSave},
+        ChunkSource, Terrain,
+    },
+};
+
+/// Connection to a `Server`. The client's terrain receives its chunks from the server instead of
+/// loading them, and block edits are sent to the server, which sends back the changes it makes
+pub struct Client {
+    client_id: ClientId,
+    seed: u64,
+    generator: GeneratorSettings,
+    /// Conversion between this process's registry IDs and the server's network IDs
+    block_id_map: BlockIdMap,
+    writer: BufWriter<TcpStream>,
+    /// Messages read by the reader thread. Disconnected once the connection closes
+    messages_rx: Receiver<ServerMessage>,
+    other_players: FxHashMap<ClientId, Vec3>,
+    is_connected: bool,
+}
+
+impl Client {
+    /// Connect to a server, blocking until it has sent its welcome message
+    pub fn connect(
+        addr: impl ToSocketAddrs,
+        block_registry: &BlockRegistry,
+    ) -> Result<Self, NetError> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let ServerMessage::Welcome {
+            protocol_version,
+            client_id,
+            seed,
+            generator,
+            block_names,
+        } = protocol::read_server_message(&mut reader)?
+        else {
+            return Err(NetError::MissingWelcome);
+        };
+
+        if protocol_version != PROTOCOL_VERSION {
+            return Err(NetError::ProtocolVersionMismatch(protocol_version));
+        }
+
+        let block_id_map = BlockIdMap::from_block_names(&block_names, block_registry)
+            .map_err(NetError::InvalidBlockTable)?;
+
+        let (messages_tx, messages_rx) = mpsc::channel();
+        std::thread::spawn(move || loop {
+            match protocol::read_server_message(&mut reader) {
+                Ok(message) => {
+                    if messages_tx.send(message).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    log::debug!("connection to server closed: {}", e);
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            client_id,
+            seed,
+            generator,
+            block_id_map,
+            writer: BufWriter::new(stream),
+            messages_rx,
+            other_players: FxHashMap::default(),
+            is_connected: true,
+        })
+    }
+
+    /// Create a terrain which receives its chunks from the server. `world_save` is only used for
+    /// the world's metadata, since chunks from a server are never saved
+    pub fn create_terrain(
+        &self,
+        block_registry: Arc<BlockRegistry>,
+        world_save: Arc<WorldSave>,
+    ) -> Result<Terrain, NetError> {
+        let generator = self.generator.build(self.seed, &block_registry)?;
+
+        let mut terrain = Terrain::new(block_registry, world_save, generator);
+        terrain.set_chunk_source(ChunkSource::Remote);
+
+        Ok(terrain)
+    }
+
+    /// ID assigned to this client by the server
+    pub fn client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    /// Seed of the server's world
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Generator settings of the server's world
+    pub fn generator(&self) -> &GeneratorSettings {
+        &self.generator
+    }
+
+    /// False once the connection to the server has closed
+    pub fn is_connected(&self) -> bool {
+        self.is_connected
+    }
+
+    /// Positions of the other players connected to the server
+    pub fn other_players(&self) -> &FxHashMap<ClientId, Vec3> {
+        &self.other_players
+    }
+
+    /// Send the position of the player, which the server loads chunks around
+    pub fn send_position(&mut self, position: Vec3) -> Result<(), NetError> {
+        self.send(&ClientMessage::PlayerPosition(position))
+    }
+
+    /// Ask the server to change a block. The block changes once the server sends the change back
+    pub fn request_set_block(
+        &mut self,
+        pos: GlobalBlockPosition,
+        block_id: BlockId,
+    ) -> Result<(), NetError> {
+        let block_id = self.block_id_map.saved_id(block_id);
+
+        self.send(&ClientMessage::SetBlock { pos, block_id })
+    }
+
+    /// Apply the messages received from the server to the terrain. Called before
+    /// `Terrain::update`
+    pub fn update(&mut self, terrain: &mut Terrain, tasks: &mut Tasks) {
+        loop {
+            let message = match self.messages_rx.try_recv() {
+                Ok(message) => message,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.is_connected = false;
+                    break;
+                }
+            };
+
+            match message {
+                ServerMessage::Welcome { .. } => {
+                    log::warn!("received a second welcome message from the server");
+                }
+                ServerMessage::Chunk { chunk_pos, data } => {
+                    match decode_chunk(&data, &self.block_id_map) {
+                        Some(saved_chunk) => terrain.receive_chunk(tasks, chunk_pos, saved_chunk),
+                        None => log::warn!("received invalid data for chunk {:?}", chunk_pos),
+                    }
+                }
+                // the client's own load area unloads chunks once it moves away from them
+                ServerMessage::UnloadChunk(_) => (),
+                ServerMessage::BlockModified { pos, block_id } => {
+                    match self.block_id_map.registry_id(block_id) {
+                        Some(block_id) => {
+                            terrain.apply_remote_block(&pos, block_id);
+                        }
+                        None => log::warn!("received invalid block {}", block_id),
+                    }
+                }
+                ServerMessage::PlayerPosition {
+                    client_id,
+                    position,
+                } => {
+                    self.other_players.insert(client_id, position);
+                }
+                ServerMessage::PlayerLeft(client_id) => {
+                    self.other_players.remove(&client_id);
+                }
+                ServerMessage::BlockEntity { pos, block_entity } => {
+                    terrain.apply_remote_block_entity(&pos, block_entity);
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, message: &ClientMessage) -> Result<(), NetError> {
+        let result = protocol::write_client_message(&mut self.writer, message);
+        if result.is_err() {
+            self.is_connected = false;
+        }
+
+        Ok(result?)
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        // the reader thread has its own handle to the stream, so the connection is only closed
+        // once it is shut down
+        let _ = self.writer.get_ref().shutdown(Shutdown::Both);
+    }
+}
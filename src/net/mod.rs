@@ -0,0 +1,32 @@
+use std::io;
+
+use crate::terrain::{generation::GeneratorError, save::SaveError};
+
+pub mod client;
+pub mod protocol;
+pub mod server;
+
+/// Port used by servers when none is given
+pub const DEFAULT_PORT: u16 = 25570;
+
+/// Errors returned by the networking layer
+#[derive(Debug, thiserror::Error)]
+pub enum NetError {
+    #[error("io error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("invalid message")]
+    InvalidMessage,
+    #[error("message of {0} bytes is too long")]
+    MessageTooLong(usize),
+    #[error("expected a welcome message from the server")]
+    MissingWelcome,
+    #[error(
+        "server uses protocol version {0}, expected {}",
+        protocol::PROTOCOL_VERSION
+    )]
+    ProtocolVersionMismatch(u32),
+    #[error("invalid block table from server: {0}")]
+    InvalidBlockTable(SaveError),
+    #[error("error creating terrain generator: {0}")]
+    GeneratorError(#[from] GeneratorError),
+}
@@ -0,0 +1,400 @@
+use std::io::{self, Read, Write};
+
+use glam::Vec3;
+
+use super::NetError;
+use crate::terrain::{
+    block::entity::BlockEntity,
+    generation::GeneratorSettings,
+    position_types::{ChunkPosition, GlobalBlockPosition},
+    save::chunk_format::ByteReader,
+};
+
+/// Version of the protocol, which must match between the server and its clients
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Longest message that will be read, to stop a broken or malicious peer from making the reader
+/// allocate huge buffers
+const MAX_MESSAGE_LEN: usize = 1 << 24;
+
+const TAG_PLAYER_POSITION: u8 = 0;
+const TAG_SET_BLOCK: u8 = 1;
+
+const TAG_WELCOME: u8 = 0;
+const TAG_CHUNK: u8 = 1;
+const TAG_UNLOAD_CHUNK: u8 = 2;
+const TAG_BLOCK_MODIFIED: u8 = 3;
+const TAG_OTHER_PLAYER_POSITION: u8 = 4;
+const TAG_PLAYER_LEFT: u8 = 5;
+const TAG_BLOCK_ENTITY: u8 = 6;
+
+/// Identifies a client connected to a server
+pub type ClientId = u32;
+
+/// Messages sent from a client to the server.
+/// Block IDs are network IDs, which are indices into the table of block names sent in
+/// `ServerMessage::Welcome`
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClientMessage {
+    /// The position of the client's player, which its load area follows
+    PlayerPosition(Vec3),
+    /// Request to change a block. The server applies it if the block is loaded in the client's
+    /// load area and sends the change back as `ServerMessage::BlockModified`
+    SetBlock {
+        pos: GlobalBlockPosition,
+        block_id: u16,
+    },
+}
+
+/// Messages sent from the server to a client.
+/// Block IDs are network IDs, like in `ClientMessage`
+#[derive(Clone, Debug, PartialEq)]
+pub enum ServerMessage {
+    /// First message sent to each client, describing the world
+    Welcome {
+        protocol_version: u32,
+        client_id: ClientId,
+        seed: u64,
+        generator: GeneratorSettings,
+        /// Name of the block with each network ID
+        block_names: Vec<String>,
+    },
+    /// The contents of a chunk in the client's load area, encoded with `encode_chunk`
+    Chunk {
+        chunk_pos: ChunkPosition,
+        data: Vec<u8>,
+    },
+    /// A chunk left the client's load area, so it won't receive any more changes to it
+    UnloadChunk(ChunkPosition),
+    /// A block in a chunk sent to the client changed
+    BlockModified {
+        pos: GlobalBlockPosition,
+        block_id: u16,
+    },
+    /// The position of another player
+    PlayerPosition { client_id: ClientId, position: Vec3 },
+    /// Another player disconnected
+    PlayerLeft(ClientId),
+    /// The block entity of a block in a chunk sent to the client was created, changed or removed
+    BlockEntity {
+        pos: GlobalBlockPosition,
+        block_entity: Option<BlockEntity>,
+    },
+}
+
+impl ClientMessage {
+    fn encode(&self, data: &mut Vec<u8>) {
+        match self {
+            ClientMessage::PlayerPosition(position) => {
+                data.push(TAG_PLAYER_POSITION);
+                write_vec3(data, *position);
+            }
+            ClientMessage::SetBlock { pos, block_id } => {
+                data.push(TAG_SET_BLOCK);
+                write_block_pos(data, *pos);
+                data.extend_from_slice(&block_id.to_le_bytes());
+            }
+        }
+    }
+
+    fn decode(reader: &mut ByteReader) -> Option<Self> {
+        Some(match reader.read_u8()? {
+            TAG_PLAYER_POSITION => ClientMessage::PlayerPosition(read_vec3(reader)?),
+            TAG_SET_BLOCK => ClientMessage::SetBlock {
+                pos: read_block_pos(reader)?,
+                block_id: reader.read_u16()?,
+            },
+            _ => return None,
+        })
+    }
+}
+
+impl ServerMessage {
+    fn encode(&self, data: &mut Vec<u8>) {
+        match self {
+            ServerMessage::Welcome {
+                protocol_version,
+                client_id,
+                seed,
+                generator,
+                block_names,
+            } => {
+                data.push(TAG_WELCOME);
+                data.extend_from_slice(&protocol_version.to_le_bytes());
+                data.extend_from_slice(&client_id.to_le_bytes());
+                data.extend_from_slice(&seed.to_le_bytes());
+                write_bytes(
+                    data,
+                    serde_json::to_string(generator)
+                        .expect("generator settings should be serializable as JSON")
+                        .as_bytes(),
+                );
+                data.extend_from_slice(&(block_names.len() as u32).to_le_bytes());
+                for name in block_names {
+                    write_bytes(data, name.as_bytes());
+                }
+            }
+            ServerMessage::Chunk {
+                chunk_pos,
+                data: chunk_data,
+            } => {
+                data.push(TAG_CHUNK);
+                write_chunk_pos(data, *chunk_pos);
+                write_bytes(data, chunk_data);
+            }
+            ServerMessage::UnloadChunk(chunk_pos) => {
+                data.push(TAG_UNLOAD_CHUNK);
+                write_chunk_pos(data, *chunk_pos);
+            }
+            ServerMessage::BlockModified { pos, block_id } => {
+                data.push(TAG_BLOCK_MODIFIED);
+                write_block_pos(data, *pos);
+                data.extend_from_slice(&block_id.to_le_bytes());
+            }
+            ServerMessage::PlayerPosition {
+                client_id,
+                position,
+            } => {
+                data.push(TAG_OTHER_PLAYER_POSITION);
+                data.extend_from_slice(&client_id.to_le_bytes());
+                write_vec3(data, *position);
+            }
+            ServerMessage::PlayerLeft(client_id) => {
+                data.push(TAG_PLAYER_LEFT);
+                data.extend_from_slice(&client_id.to_le_bytes());
+            }
+            ServerMessage::BlockEntity { pos, block_entity } => {
+                data.push(TAG_BLOCK_ENTITY);
+                write_block_pos(data, *pos);
+                write_bytes(
+                    data,
+                    serde_json::to_string(block_entity)
+                        .expect("block entities should be serializable as JSON")
+                        .as_bytes(),
+                );
+            }
+        }
+    }
+
+    fn decode(reader: &mut ByteReader) -> Option<Self> {
+        Some(match reader.read_u8()? {
+            TAG_WELCOME => {
+                let protocol_version = reader.read_u32()?;
+                let client_id = reader.read_u32()?;
+                let seed = reader.read_u64()?;
+                let generator = serde_json::from_slice(read_bytes(reader)?).ok()?;
+
+                let block_name_count = reader.read_u32()?;
+                let block_names = (0..block_name_count)
+                    .map(|_| {
+                        std::str::from_utf8(read_bytes(reader)?)
+                            .ok()
+                            .map(str::to_string)
+                    })
+                    .collect::<Option<_>>()?;
+
+                ServerMessage::Welcome {
+                    protocol_version,
+                    client_id,
+                    seed,
+                    generator,
+                    block_names,
+                }
+            }
+            TAG_CHUNK => ServerMessage::Chunk {
+                chunk_pos: read_chunk_pos(reader)?,
+                data: read_bytes(reader)?.to_vec(),
+            },
+            TAG_UNLOAD_CHUNK => ServerMessage::UnloadChunk(read_chunk_pos(reader)?),
+            TAG_BLOCK_MODIFIED => ServerMessage::BlockModified {
+                pos: read_block_pos(reader)?,
+                block_id: reader.read_u16()?,
+            },
+            TAG_OTHER_PLAYER_POSITION => ServerMessage::PlayerPosition {
+                client_id: reader.read_u32()?,
+                position: read_vec3(reader)?,
+            },
+            TAG_PLAYER_LEFT => ServerMessage::PlayerLeft(reader.read_u32()?),
+            TAG_BLOCK_ENTITY => ServerMessage::BlockEntity {
+                pos: read_block_pos(reader)?,
+                block_entity: serde_json::from_slice(read_bytes(reader)?).ok()?,
+            },
+            _ => return None,
+        })
+    }
+}
+
+/// Write a client message to a stream, as its length followed by its data
+pub fn write_client_message(writer: &mut impl Write, message: &ClientMessage) -> io::Result<()> {
+    let mut data = Vec::new();
+    message.encode(&mut data);
+    write_frame(writer, &data)
+}
+
+/// Write a server message to a stream, as its length followed by its data
+pub fn write_server_message(writer: &mut impl Write, message: &ServerMessage) -> io::Result<()> {
+    let mut data = Vec::new();
+    message.encode(&mut data);
+    write_frame(writer, &data)
+}
+
+/// Read a message written with `write_client_message`, blocking until all of it has arrived
+pub fn read_client_message(reader: &mut impl Read) -> Result<ClientMessage, NetError> {
+    let data = read_frame(reader)?;
+    let mut reader = ByteReader(&data);
+
+    ClientMessage::decode(&mut reader)
+        .filter(|_| reader.0.is_empty())
+        .ok_or(NetError::InvalidMessage)
+}
+
+/// Read a message written with `write_server_message`, blocking until all of it has arrived
+pub fn read_server_message(reader: &mut impl Read) -> Result<ServerMessage, NetError> {
+    let data = read_frame(reader)?;
+    let mut reader = ByteReader(&data);
+
+    ServerMessage::decode(&mut reader)
+        .filter(|_| reader.0.is_empty())
+        .ok_or(NetError::InvalidMessage)
+}
+
+fn write_frame(writer: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_le_bytes())?;
+    writer.write_all(data)?;
+    writer.flush()
+}
+
+fn read_frame(reader: &mut impl Read) -> Result<Vec<u8>, NetError> {
+    let mut len_bytes = [0; 4];
+    reader.read_exact(&mut len_bytes)?;
+
+    let len = u32::from_le_bytes(len_bytes) as usize;
+    if len > MAX_MESSAGE_LEN {
+        return Err(NetError::MessageTooLong(len));
+    }
+
+    let mut data = vec![0; len];
+    reader.read_exact(&mut data)?;
+
+    Ok(data)
+}
+
+fn write_bytes(data: &mut Vec<u8>, bytes: &[u8]) {
+    data.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    data.extend_from_slice(bytes);
+}
+
+fn read_bytes<'a>(reader: &mut ByteReader<'a>) -> Option<&'a [u8]> {
+    let len = reader.read_u32()? as usize;
+    let (bytes, rest) = (reader.0.len() >= len).then(|| reader.0.split_at(len))?;
+    reader.0 = rest;
+
+    Some(bytes)
+}
+
+fn write_vec3(data: &mut Vec<u8>, vec: Vec3) {
+    for component in vec.to_array() {
+        data.extend_from_slice(&component.to_le_bytes());
+    }
+}
+
+fn read_vec3(reader: &mut ByteReader) -> Option<Vec3> {
+    Some(Vec3::new(
+        reader.read_f32()?,
+        reader.read_f32()?,
+        reader.read_f32()?,
+    ))
+}
+
+fn write_block_pos(data: &mut Vec<u8>, pos: GlobalBlockPosition) {
+    for component in pos.as_ivec3().to_array() {
+        data.extend_from_slice(&component.to_le_bytes());
+    }
+}
+
+fn read_block_pos(reader: &mut ByteReader) -> Option<GlobalBlockPosition> {
+    Some(GlobalBlockPosition::new(
+        reader.read_i32()?,
+        reader.read_i32()?,
+        reader.read_i32()?,
+    ))
+}
+
+fn write_chunk_pos(data: &mut Vec<u8>, chunk_pos: ChunkPosition) {
+    for component in chunk_pos.as_ivec3().to_array() {
+        data.extend_from_slice(&component.to_le_bytes());
+    }
+}
+
+fn read_chunk_pos(reader: &mut ByteReader) -> Option<ChunkPosition> {
+    Some(ChunkPosition::new(
+        reader.read_i32()?,
+        reader.read_i32()?,
+        reader.read_i32()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_messages() {
+        let server_messages = [
+            ServerMessage::Welcome {
+                protocol_version: PROTOCOL_VERSION,
+                client_id: 7,
+                seed: u64::MAX - 3,
+                generator: GeneratorSettings::Flat {
+                    layers: "3*dirt,grass".to_string(),
+                },
+                block_names: vec!["air".to_string(), "dirt".to_string()],
+            },
+            ServerMessage::Chunk {
+                chunk_pos: ChunkPosition::new(-1, 2, 3),
+                data: vec![1, 2, 3, 4],
+            },
+            ServerMessage::BlockModified {
+                pos: GlobalBlockPosition::new(-40, 5, 1000),
+                block_id: 12,
+            },
+            ServerMessage::PlayerLeft(3),
+            ServerMessage::BlockEntity {
+                pos: GlobalBlockPosition::new(2, -3, 4),
+                block_entity: Some(BlockEntity::Sign {
+                    text: "hello".to_string(),
+                }),
+            },
+            ServerMessage::BlockEntity {
+                pos: GlobalBlockPosition::new(2, -3, 4),
+                block_entity: None,
+            },
+        ];
+
+        let mut stream = Vec::new();
+        for message in &server_messages {
+            write_server_message(&mut stream, message).unwrap();
+        }
+        write_client_message(
+            &mut stream,
+            &ClientMessage::PlayerPosition(Vec3::new(1.5, -2.0, 3.25)),
+        )
+        .unwrap();
+
+        let mut reader = stream.as_slice();
+        for message in &server_messages {
+            assert_eq!(&read_server_message(&mut reader).unwrap(), message);
+        }
+        assert_eq!(
+            read_client_message(&mut reader).unwrap(),
+            ClientMessage::PlayerPosition(Vec3::new(1.5, -2.0, 3.25))
+        );
+
+        // a truncated message is an error rather than a panic
+        let mut reader = &stream[..stream.len() - 2];
+        for _ in &server_messages {
+            read_server_message(&mut reader).unwrap();
+        }
+        assert!(read_client_message(&mut reader).is_err());
+    }
+}
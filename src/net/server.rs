@@ -0,0 +1,706 @@
+use std::{
+    io::{self, BufReader, BufWriter},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::mpsc::{self, Receiver, Sender, TryRecvError},
+};
+
+use generational_arena::Index;
+use glam::Vec3;
+use itertools::Itertools;
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use super::protocol::{self, ClientId, ClientMessage, ServerMessage, PROTOCOL_VERSION};
+use crate::{
+    core::tasks::Tasks,
+    terrain::{
+        chunk::CHUNK_SIZE,
+        event::TerrainEvent,
+        generation::GeneratorSettings,
+        load_area::{AreaShape, LoadArea},
+        position_types::{ChunkPosition, GlobalBlockPosition, LocalBlockPosition},
+        save::{chunk_format::encode_chunk, BlockIdMap},
+        Terrain,
+    },
+    util::size::Size3,
+};
+
+/// Maximum number of chunks sent to each client per update, so that a client entering a new area
+/// doesn't hold up the others
+const MAX_CHUNKS_SENT_PER_UPDATE: usize = 32;
+
+/// Number of blocks modified in a chunk in one update above which the whole chunk is sent again
+/// rather than each block
+const CHUNK_RESEND_THRESHOLD: usize = 64;
+
+/// Serves the terrain to clients over TCP. Each client has its own load area in the terrain
+/// which follows its player, and is sent the chunks loaded in it and every change made to them.
+/// Clients' block edits are applied to the terrain, so the server's terrain is authoritative.
+/// Each connection has a thread reading its messages and a thread writing to it, so `update` and
+/// `send_updates` never block
+pub struct Server {
+    listener: TcpListener,
+    clients: FxHashMap<ClientId, ConnectedClient>,
+    next_client_id: ClientId,
+    /// Size of the load area created for each client
+    load_area_size: Size3,
+    /// Name of the block with each network ID, which are the server's registry IDs
+    block_names: Vec<String>,
+    block_id_map: BlockIdMap,
+    seed: u64,
+    generator: GeneratorSettings,
+}
+
+/// State the server keeps for each client
+struct ConnectedClient {
+    load_area_index: Index,
+    /// Messages read by the client's reader thread. Disconnected once the connection closes
+    messages_rx: Receiver<ClientMessage>,
+    /// Messages to be written by the client's writer thread
+    outgoing_tx: Sender<ServerMessage>,
+    position: Option<Vec3>,
+    /// True if the position changed since it was last sent to the other clients
+    position_changed: bool,
+    /// True if the load area moved since the chunks to send were last checked
+    area_moved: bool,
+    /// Chunks whose contents the client has been sent
+    sent_chunks: FxHashSet<ChunkPosition>,
+    /// Chunks waiting to be sent, or sent again
+    chunks_to_send: FxHashSet<ChunkPosition>,
+}
+
+impl ConnectedClient {
+    fn send(&self, message: ServerMessage) {
+        // the reader thread notices when the connection closes
+        let _ = self.outgoing_tx.send(message);
+    }
+}
+
+impl Server {
+    /// Start listening for clients on the given address. `load_area_size` is the size of the
+    /// area around each client in which chunks are loaded and sent to it
+    pub fn bind(
+        addr: impl ToSocketAddrs,
+        terrain: &Terrain,
+        load_area_size: Size3,
+    ) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+
+        let block_registry = terrain.block_registry();
+        let block_names = block_registry
+            .iter()
+            .map(|(_, block)| block.name.clone())
+            .collect_vec();
+        let block_id_map = BlockIdMap::from_block_names(&block_names, block_registry)
+            .expect("the registry's own block names should be valid");
+
+        Ok(Self {
+            listener,
+            clients: FxHashMap::default(),
+            next_client_id: 0,
+            load_area_size,
+            block_names,
+            block_id_map,
+            seed: terrain.world_save().seed(),
+            generator: terrain.world_save().generator_settings(),
+        })
+    }
+
+    /// The address the server is listening on
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Number of connected clients
+    pub fn client_count(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// Returns the position of each connected client's player, if it has sent one
+    pub fn player_positions(&self) -> impl Iterator<Item = (ClientId, Vec3)> + '_ {
+        self.clients.iter().filter_map(|(&client_id, client)| {
+            client.position.map(|position| (client_id, position))
+        })
+    }
+
+    /// Accept new clients and apply the messages received from every client. Called before
+    /// `Terrain::update`
+    pub fn update(&mut self, terrain: &mut Terrain, tasks: &mut Tasks) {
+        self.accept_clients(terrain);
+
+        let mut disconnected = Vec::new();
+
+        for (&client_id, client) in &mut self.clients {
+            loop {
+                let message = match client.messages_rx.try_recv() {
+                    Ok(message) => message,
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        disconnected.push(client_id);
+                        break;
+                    }
+                };
+
+                match message {
+                    ClientMessage::PlayerPosition(position) => {
+                        client.position = Some(position);
+                        client.position_changed = true;
+
+                        let load_area = &mut terrain.load_areas_mut()[client.load_area_index];
+                        let old_area_pos = load_area.position();
+                        load_area.set_center(position / (CHUNK_SIZE as f32));
+                        client.area_moved |= load_area.position() != old_area_pos;
+                    }
+                    ClientMessage::SetBlock { pos, block_id } => {
+                        // edits are only allowed in the client's own load area
+                        match self.block_id_map.registry_id(block_id) {
+                            Some(block_id) => {
+                                terrain.set_block(client.load_area_index, &pos, block_id);
+                            }
+                            None => log::warn!(
+                                "client {} tried to place invalid block {}",
+                                client_id,
+                                block_id
+                            ),
+                        }
+                    }
+                }
+            }
+        }
+
+        for client_id in disconnected {
+            let client = self.clients.remove(&client_id).unwrap();
+            terrain.remove_load_area(tasks, client.load_area_index);
+
+            for other_client in self.clients.values() {
+                other_client.send(ServerMessage::PlayerLeft(client_id));
+            }
+
+            log::info!("client {} disconnected", client_id);
+        }
+    }
+
+    /// Send each client the chunks loaded in its load area, the changes made to the chunks it has
+    /// and the positions of the other players. Called after `Terrain::update`, before the
+    /// terrain's events are cleared
+    pub fn send_updates(&mut self, terrain: &Terrain) {
+        let mut loaded_chunks = Vec::new();
+        let mut unloaded_chunks = Vec::new();
+        let mut modified_chunks = Vec::new();
+        let mut modified_blocks: FxHashMap<ChunkPosition, Vec<LocalBlockPosition>> =
+            FxHashMap::default();
+        let mut modified_block_entities: FxHashMap<ChunkPosition, Vec<LocalBlockPosition>> =
+            FxHashMap::default();
+
+        for event in terrain.events() {
+            match event {
+                TerrainEvent::ChunkLoaded(chunk_pos) => loaded_chunks.push(*chunk_pos),
+                TerrainEvent::ChunkUnloaded(chunk_pos) => unloaded_chunks.push(*chunk_pos),
+                TerrainEvent::ChunkModified(chunk_pos) => modified_chunks.push(*chunk_pos),
+                TerrainEvent::BlockModified(chunk_pos, local_block_pos) => modified_blocks
+                    .entry(*chunk_pos)
+                    .or_default()
+                    .push(*local_block_pos),
+                TerrainEvent::BlockEntityCreated(chunk_pos, local_block_pos)
+                | TerrainEvent::BlockEntityModified(chunk_pos, local_block_pos)
+                | TerrainEvent::BlockEntityRemoved(chunk_pos, local_block_pos) => {
+                    modified_block_entities
+                        .entry(*chunk_pos)
+                        .or_default()
+                        .push(*local_block_pos)
+                }
+                _ => (),
+            }
+        }
+
+        for client in self.clients.values_mut() {
+            let load_area = &terrain.load_areas()[client.load_area_index];
+
+            if client.area_moved {
+                client.area_moved = false;
+
+                // chunks that left the area won't be kept up to date
+                let left_area = client
+                    .sent_chunks
+                    .iter()
+                    .filter(|chunk_pos| !load_area.is_within_area(chunk_pos))
+                    .copied()
+                    .collect_vec();
+                for chunk_pos in left_area {
+                    client.sent_chunks.remove(&chunk_pos);
+                    client.send(ServerMessage::UnloadChunk(chunk_pos));
+                }
+                client
+                    .chunks_to_send
+                    .retain(|chunk_pos| load_area.is_within_area(chunk_pos));
+
+                // chunks that entered the area may already be loaded for another client
+                client
+                    .chunks_to_send
+                    .extend(load_area.iter_positions().filter(|chunk_pos| {
+                        load_area.is_loaded(chunk_pos) && !client.sent_chunks.contains(chunk_pos)
+                    }));
+            }
+
+            client.chunks_to_send.extend(
+                loaded_chunks
+                    .iter()
+                    .filter(|chunk_pos| load_area.is_within_area(chunk_pos)),
+            );
+
+            for chunk_pos in &unloaded_chunks {
+                client.sent_chunks.remove(chunk_pos);
+                client.chunks_to_send.remove(chunk_pos);
+            }
+
+            client.chunks_to_send.extend(
+                modified_chunks
+                    .iter()
+                    .filter(|chunk_pos| client.sent_chunks.contains(chunk_pos)),
+            );
+
+            for (chunk_pos, local_block_positions) in &modified_blocks {
+                if !client.sent_chunks.contains(chunk_pos) {
+                    continue;
+                }
+
+                if local_block_positions.len() > CHUNK_RESEND_THRESHOLD {
+                    client.chunks_to_send.insert(*chunk_pos);
+                    continue;
+                }
+
+                // the chunk may not be marked as loaded in the area while it is moving, in which
+                // case the whole chunk is sent again once it is
+                let Some(chunk) = terrain.get_chunk(client.load_area_index, chunk_pos) else {
+                    client.chunks_to_send.insert(*chunk_pos);
+                    continue;
+                };
+                for &local_block_pos in local_block_positions.iter().unique() {
+                    client.send(ServerMessage::BlockModified {
+                        pos: GlobalBlockPosition::from_local_and_chunk_pos(
+                            local_block_pos,
+                            *chunk_pos,
+                        ),
+                        block_id: self.block_id_map.saved_id(chunk.get_block(local_block_pos)),
+                    });
+                }
+            }
+
+            // sent after the blocks, since changing a block replaces its block entity
+            for (chunk_pos, local_block_positions) in &modified_block_entities {
+                if !client.sent_chunks.contains(chunk_pos) {
+                    continue;
+                }
+
+                let Some(chunk) = terrain.get_chunk(client.load_area_index, chunk_pos) else {
+                    client.chunks_to_send.insert(*chunk_pos);
+                    continue;
+                };
+                for &local_block_pos in local_block_positions.iter().unique() {
+                    client.send(ServerMessage::BlockEntity {
+                        pos: GlobalBlockPosition::from_local_and_chunk_pos(
+                            local_block_pos,
+                            *chunk_pos,
+                        ),
+                        block_entity: chunk.get_block_entity(local_block_pos).cloned(),
+                    });
+                }
+            }
+
+            // send the chunks nearest the player first
+            let area_center = load_area.center();
+            let chunks_to_send = client
+                .chunks_to_send
+                .iter()
+                .copied()
+                .sorted_by(|a, b| {
+                    a.as_vec3()
+                        .distance_squared(area_center)
+                        .total_cmp(&b.as_vec3().distance_squared(area_center))
+                })
+                .take(MAX_CHUNKS_SENT_PER_UPDATE)
+                .collect_vec();
+
+            for chunk_pos in chunks_to_send {
+                client.chunks_to_send.remove(&chunk_pos);
+
+                let Some(chunk) = terrain.get_chunk(client.load_area_index, &chunk_pos) else {
+                    continue;
+                };
+
+                client.sent_chunks.insert(chunk_pos);
+                client.send(ServerMessage::Chunk {
+                    chunk_pos,
+                    data: encode_chunk(&chunk.to_saved_chunk(), &self.block_id_map),
+                });
+            }
+        }
+
+        // share the positions of players that moved
+        let moved_players = self
+            .clients
+            .iter_mut()
+            .filter(|(_, client)| client.position_changed)
+            .filter_map(|(&client_id, client)| {
+                client.position_changed = false;
+                client.position.map(|position| (client_id, position))
+            })
+            .collect_vec();
+
+        for (client_id, position) in moved_players {
+            for (_, other_client) in self
+                .clients
+                .iter()
+                .filter(|(&other_client_id, _)| other_client_id != client_id)
+            {
+                other_client.send(ServerMessage::PlayerPosition {
+                    client_id,
+                    position,
+                });
+            }
+        }
+    }
+
+    /// Accept any clients waiting to connect, sending them the welcome message
+    fn accept_clients(&mut self, terrain: &mut Terrain) {
+        loop {
+            let (stream, addr) = match self.listener.accept() {
+                Ok(connection) => connection,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log::error!("failed to accept client: {}", e);
+                    break;
+                }
+            };
+
+            let client_id = self.next_client_id;
+            self.next_client_id += 1;
+
+            let (messages_rx, outgoing_tx) = match spawn_connection_threads(client_id, stream) {
+                Ok(channels) => channels,
+                Err(e) => {
+                    log::error!("failed to set up connection to {}: {}", addr, e);
+                    continue;
+                }
+            };
+
+            let load_area_index = terrain.load_areas_mut().insert(LoadArea::new(
+                ChunkPosition::ZERO,
+                self.load_area_size,
+                AreaShape::Cylindrical,
+            ));
+
+            let client = ConnectedClient {
+                load_area_index,
+                messages_rx,
+                outgoing_tx,
+                position: None,
+                position_changed: false,
+                area_moved: true,
+                sent_chunks: FxHashSet::default(),
+                chunks_to_send: FxHashSet::default(),
+            };
+
+            client.send(ServerMessage::Welcome {
+                protocol_version: PROTOCOL_VERSION,
+                client_id,
+                seed: self.seed,
+                generator: self.generator.clone(),
+                block_names: self.block_names.clone(),
+            });
+            for (other_client_id, position) in self.player_positions() {
+                client.send(ServerMessage::PlayerPosition {
+                    client_id: other_client_id,
+                    position,
+                });
+            }
+
+            self.clients.insert(client_id, client);
+
+            log::info!("client {} connected from {}", client_id, addr);
+        }
+    }
+}
+
+/// Start the threads reading from and writing to a client's connection, returning the channels
+/// they communicate through
+fn spawn_connection_threads(
+    client_id: ClientId,
+    stream: TcpStream,
+) -> io::Result<(Receiver<ClientMessage>, Sender<ServerMessage>)> {
+    stream.set_nonblocking(false)?;
+    stream.set_nodelay(true)?;
+
+    let (messages_tx, messages_rx) = mpsc::channel();
+    let (outgoing_tx, outgoing_rx) = mpsc::channel::<ServerMessage>();
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    std::thread::spawn(move || loop {
+        match protocol::read_client_message(&mut reader) {
+            Ok(message) => {
+                if messages_tx.send(message).is_err() {
+                    break;
+                }
+            }
+            Err(e) => {
+                log::debug!("connection to client {} closed: {}", client_id, e);
+                break;
+            }
+        }
+    });
+
+    let mut writer = BufWriter::new(stream);
+    std::thread::spawn(move || {
+        while let Ok(message) = outgoing_rx.recv() {
+            if let Err(e) = protocol::write_server_message(&mut writer, &message) {
+                log::debug!("failed to write to client {}: {}", client_id, e);
+                break;
+            }
+        }
+
+        // stop the reader thread too
+        let _ = writer.get_ref().shutdown(std::net::Shutdown::Both);
+    });
+
+    Ok((messages_rx, outgoing_tx))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+    use crate::{
+        net::client::Client,
+        terrain::{
+            block::{
+                registry::{test_registry, BlockRegistry},
+                BlockId, BLOCK_AIR,
+            },
+            save::WorldSave,
+        },
+    };
+
+    const TIMEOUT: Duration = Duration::from_secs(20);
+
+    fn test_block_registry() -> Arc<BlockRegistry> {
+        Arc::new(test_registry(&[
+            "name = \"stone\"\nmodel = { type = \"full_block\", textures = { all = \"stone\" } }",
+            "name = \"dirt\"\nmodel = { type = \"full_block\", textures = { all = \"dirt\" } }",
+        ]))
+    }
+
+    fn open_world_save(name: &str, block_registry: &BlockRegistry) -> Arc<WorldSave> {
+        let directory =
+            std::env::temp_dir().join(format!("voxels-net-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+
+        Arc::new(
+            WorldSave::open_or_create(
+                &directory,
+                block_registry,
+                5,
+                GeneratorSettings::Flat {
+                    layers: "3*stone,dirt".to_string(),
+                },
+            )
+            .unwrap(),
+        )
+    }
+
+    /// A client connected to the server with its own terrain, like a game process
+    struct TestClient {
+        client: Client,
+        terrain: Terrain,
+        load_area_index: Index,
+        position: Vec3,
+    }
+
+    impl TestClient {
+        fn get_block(&self, pos: GlobalBlockPosition) -> Option<BlockId> {
+            self.terrain.get_block(self.load_area_index, &pos)
+        }
+    }
+
+    /// Run the server and the clients until `condition` is true
+    fn run_until(
+        server: &mut Server,
+        server_terrain: &mut Terrain,
+        clients: &mut [&mut TestClient],
+        tasks: &mut Tasks,
+        condition: impl Fn(&[&mut TestClient]) -> bool,
+    ) {
+        let start = Instant::now();
+
+        while !condition(clients) {
+            assert!(start.elapsed() < TIMEOUT, "timed out");
+
+            server.update(server_terrain, tasks);
+            server_terrain.update(tasks, Vec3::ZERO, Duration::from_millis(10));
+            server.send_updates(server_terrain);
+            server_terrain.clear_events();
+
+            for client in clients.iter_mut() {
+                client.client.update(&mut client.terrain, tasks);
+                client
+                    .terrain
+                    .update(tasks, client.position, Duration::from_millis(10));
+                client.terrain.clear_events();
+            }
+
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    /// Connect a client to the server, updating the server while it waits for the welcome message
+    fn connect(
+        server: &mut Server,
+        server_terrain: &mut Terrain,
+        tasks: &mut Tasks,
+        block_registry: &Arc<BlockRegistry>,
+        name: &str,
+        position: Vec3,
+    ) -> TestClient {
+        let addr = server.local_addr().unwrap();
+        let connecting = std::thread::spawn({
+            let block_registry = block_registry.clone();
+            move || Client::connect(addr, &block_registry)
+        });
+
+        let start = Instant::now();
+        while !connecting.is_finished() {
+            assert!(start.elapsed() < TIMEOUT, "timed out");
+            server.update(server_terrain, tasks);
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        let mut client = connecting.join().unwrap().unwrap();
+
+        let mut terrain = client
+            .create_terrain(
+                block_registry.clone(),
+                open_world_save(name, block_registry),
+            )
+            .unwrap();
+        let mut load_area = LoadArea::new(
+            ChunkPosition::ZERO,
+            Size3::new(2, 2, 2),
+            AreaShape::Cylindrical,
+        );
+        load_area.set_center(position / (CHUNK_SIZE as f32));
+        let load_area_index = terrain.load_areas_mut().insert(load_area);
+        client.send_position(position).unwrap();
+
+        TestClient {
+            client,
+            terrain,
+            load_area_index,
+            position,
+        }
+    }
+
+    #[test]
+    fn loopback_server() {
+        let block_registry = test_block_registry();
+        let stone = block_registry.get_id("stone").unwrap();
+        let dirt = block_registry.get_id("dirt").unwrap();
+
+        let server_world_save = open_world_save("server", &block_registry);
+        let generator = server_world_save
+            .generator_settings()
+            .build(server_world_save.seed(), &block_registry)
+            .unwrap();
+        let mut server_terrain = Terrain::new(block_registry.clone(), server_world_save, generator);
+        let mut server = Server::bind("127.0.0.1:0", &server_terrain, Size3::new(4, 2, 4)).unwrap();
+        let mut tasks = Tasks::new(2);
+        let position = Vec3::new(16.0, 8.0, 16.0);
+        let mut a = connect(
+            &mut server,
+            &mut server_terrain,
+            &mut tasks,
+            &block_registry,
+            "a",
+            position,
+        );
+        let mut b = connect(
+            &mut server,
+            &mut server_terrain,
+            &mut tasks,
+            &block_registry,
+            "b",
+            position,
+        );
+        assert_eq!(server.client_count(), 2);
+        assert_eq!(a.client.seed(), 5);
+
+        // the chunk around the players is streamed to both clients
+        let surface = GlobalBlockPosition::new(16, 3, 16);
+        run_until(
+            &mut server,
+            &mut server_terrain,
+            &mut [&mut a, &mut b],
+            &mut tasks,
+            |clients| {
+                clients
+                    .iter()
+                    .all(|client| client.get_block(surface).is_some())
+            },
+        );
+        assert_eq!(a.get_block(surface), Some(dirt));
+        assert_eq!(b.get_block(GlobalBlockPosition::new(20, 0, 3)), Some(stone));
+        assert_eq!(
+            b.get_block(GlobalBlockPosition::new(20, 4, 3)),
+            Some(BLOCK_AIR)
+        );
+
+        // an edit by one client is applied by the server and sent to both clients
+        let above_surface = GlobalBlockPosition::new(16, 4, 16);
+        a.client.request_set_block(above_surface, stone).unwrap();
+        run_until(
+            &mut server,
+            &mut server_terrain,
+            &mut [&mut a, &mut b],
+            &mut tasks,
+            |clients| {
+                clients
+                    .iter()
+                    .all(|client| client.get_block(above_surface) == Some(stone))
+            },
+        );
+        assert_eq!(
+            server_terrain.get_block(server.clients[&0].load_area_index, &above_surface),
+            Some(stone)
+        );
+        assert_eq!(
+            b.client.other_players().get(&a.client.client_id()),
+            Some(&position)
+        );
+
+        // the other players are told when a client disconnects
+        let a_id = a.client.client_id();
+        drop(a);
+        run_until(
+            &mut server,
+            &mut server_terrain,
+            &mut [&mut b],
+            &mut tasks,
+            |clients| clients[0].client.other_players().is_empty(),
+        );
+        assert_eq!(server.client_count(), 1);
+        assert!(!server.clients.contains_key(&a_id));
+
+        // the chunks are unloaded once no client's area contains them
+        assert!(!server_terrain.chunks().is_empty());
+        drop(b);
+        let start = Instant::now();
+        while server.client_count() > 0 {
+            assert!(start.elapsed() < TIMEOUT, "timed out");
+            server.update(&mut server_terrain, &mut tasks);
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert!(server_terrain.chunks().is_empty());
+    }
+}
@@ -0,0 +1,354 @@
+use glam::{Vec3, Vec3Swizzles};
+use winit::keyboard::KeyCode;
+
+use crate::{
+    core::{input::Input, time::Time},
+    fly_camera::{self, FlyCamera},
+    terrain::{
+        block::{registry::BlockRegistry, BlockId},
+        collision::{self, Aabb},
+        position_types::GlobalBlockPosition,
+    },
+};
+
+/// Distance from the center of the body to each side
+pub const BODY_HALF_WIDTH: f32 = 0.3;
+pub const BODY_HEIGHT: f32 = 1.8;
+pub const EYE_HEIGHT: f32 = 1.62;
+pub const SNEAKING_EYE_HEIGHT: f32 = 1.27;
+
+/// Walking speed in blocks per second
+pub const WALK_SPEED: f32 = 4.3;
+/// Walking speed while sneaking in blocks per second
+pub const SNEAK_SPEED: f32 = 1.3;
+/// Downwards acceleration in blocks per second squared
+pub const GRAVITY: f32 = 32.0;
+/// Highest speed the player can fall at in blocks per second
+pub const TERMINAL_VELOCITY: f32 = 78.4;
+/// Upwards speed at the start of a jump in blocks per second, enough to jump onto a block
+pub const JUMP_VELOCITY: f32 = 9.0;
+/// Height of the tallest ledge that the player steps onto without jumping
+pub const STEP_HEIGHT: f32 = 1.0;
+/// Height of the tallest drop that a sneaking player will walk off
+pub const SNEAK_MAX_DROP: f32 = 0.5;
+
+/// Distance by which motion towards an edge is reduced at a time while sneaking, until the
+/// player would no longer walk off the edge
+const SNEAK_EDGE_STEP: f32 = 0.05;
+
+/// Longest time step simulated in one update, so that a long frame doesn't launch the player
+const MAX_TIME_STEP: f32 = 0.1;
+
+/// How the player moves
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MovementMode {
+    /// Walking with gravity, colliding with the terrain
+    Walk,
+    /// Flying freely through everything, like `FlyCamera`
+    Fly,
+}
+
+/// Moves the player through the world, either walking with an AABB body that collides with the
+/// terrain or flying through it
+#[derive(Clone, Debug)]
+pub struct PlayerController {
+    /// Camera at the player's eyes. It is turned with the mouse in both modes, and moved by
+    /// `FlyCamera::update` in fly mode
+    pub camera: FlyCamera,
+    pub mode: MovementMode,
+    /// Position of the center of the base of the body
+    pub position: Vec3,
+    /// Velocity in blocks per second
+    pub velocity: Vec3,
+    /// Whether the body was stopped by the ground in the last update
+    pub on_ground: bool,
+    pub sneaking: bool,
+    /// Key switching between walk and fly mode
+    pub key_toggle_mode: KeyCode,
+}
+
+impl PlayerController {
+    pub fn new(eye_position: Vec3) -> Self {
+        let mut player = Self {
+            camera: FlyCamera::default(),
+            mode: MovementMode::Fly,
+            position: Vec3::ZERO,
+            velocity: Vec3::ZERO,
+            on_ground: false,
+            sneaking: false,
+            key_toggle_mode: KeyCode::KeyF,
+        };
+        player.teleport(eye_position);
+        player
+    }
+
+    /// Move the player so that its eyes are at the given position, stopping it
+    pub fn teleport(&mut self, eye_position: Vec3) {
+        self.camera.position = eye_position;
+        self.position = eye_position - Vec3::Y * EYE_HEIGHT;
+        self.velocity = Vec3::ZERO;
+        self.on_ground = false;
+    }
+
+    /// Returns the position of the player's eyes
+    pub fn eye_position(&self) -> Vec3 {
+        let eye_height = if self.sneaking {
+            SNEAKING_EYE_HEIGHT
+        } else {
+            EYE_HEIGHT
+        };
+
+        self.position + Vec3::Y * eye_height
+    }
+
+    /// Returns the box occupied by the player's body
+    pub fn body(&self) -> Aabb {
+        Aabb::from_base(self.position, BODY_HALF_WIDTH, BODY_HEIGHT)
+    }
+
+    /// Switch between walk and fly mode
+    pub fn toggle_mode(&mut self) {
+        self.mode = match self.mode {
+            MovementMode::Walk => MovementMode::Fly,
+            MovementMode::Fly => MovementMode::Walk,
+        };
+        self.velocity = Vec3::ZERO;
+        self.on_ground = false;
+        self.sneaking = false;
+    }
+
+    /// Move the player according to the input. `get_block` returns the block at a position, or
+    /// None if it is not loaded (usually `Terrain::get_block`)
+    pub fn update(
+        &mut self,
+        input: &Input,
+        time: &Time,
+        block_registry: &BlockRegistry,
+        get_block: impl Fn(&GlobalBlockPosition) -> Option<BlockId>,
+    ) {
+        if input.is_key_just_pressed(self.key_toggle_mode) {
+            self.toggle_mode();
+        }
+
+        match self.mode {
+            MovementMode::Fly => {
+                self.camera.update(input, time);
+                self.position = self.camera.position - Vec3::Y * EYE_HEIGHT;
+            }
+            MovementMode::Walk => {
+                self.camera.update_rotation(input);
+                self.walk(input, time, block_registry, get_block);
+                self.camera.position = self.eye_position();
+            }
+        }
+    }
+
+    fn walk(
+        &mut self,
+        input: &Input,
+        time: &Time,
+        block_registry: &BlockRegistry,
+        get_block: impl Fn(&GlobalBlockPosition) -> Option<BlockId>,
+    ) {
+        let time_step = time.delta_seconds().min(MAX_TIME_STEP);
+
+        let input_forward =
+            fly_camera::axis_input(input, self.camera.key_forward, self.camera.key_backward);
+        let input_right =
+            fly_camera::axis_input(input, self.camera.key_right, self.camera.key_left);
+        self.sneaking = input.is_key_down(self.camera.key_down);
+
+        let (dir_forward, dir_right) = self.camera.horizontal_directions();
+        let walk_dir = (dir_forward * input_forward + dir_right * input_right).normalize_or_zero();
+        let speed = if self.sneaking {
+            SNEAK_SPEED
+        } else {
+            WALK_SPEED
+        };
+
+        self.velocity.x = walk_dir.x * speed;
+        self.velocity.z = walk_dir.z * speed;
+
+        if self.on_ground && input.is_key_down(self.camera.key_up) {
+            self.velocity.y = JUMP_VELOCITY;
+        }
+        self.velocity.y = (self.velocity.y - GRAVITY * time_step).max(-TERMINAL_VELOCITY);
+
+        self.move_body(self.velocity * time_step, block_registry, get_block);
+    }
+
+    /// Move the body by `motion`, colliding with the terrain. The body steps onto ledges up to
+    /// `STEP_HEIGHT` high while on the ground, and doesn't walk off edges while sneaking
+    pub fn move_body(
+        &mut self,
+        motion: Vec3,
+        block_registry: &BlockRegistry,
+        get_block: impl Fn(&GlobalBlockPosition) -> Option<BlockId>,
+    ) {
+        let body = self.body();
+
+        // find every obstacle that the body could run into, including when stepping up and
+        // checking for edges
+        let region = body
+            .expanded_towards(motion)
+            .expanded_towards(Vec3::Y * STEP_HEIGHT)
+            .expanded_towards(Vec3::NEG_Y * SNEAK_MAX_DROP);
+        let obstacles = collision::block_collision_boxes(&region, block_registry, get_block);
+
+        let mut motion = motion;
+        if self.sneaking && self.on_ground {
+            motion = stop_at_edges(&body, motion, &obstacles);
+        }
+
+        let mut moved = collision::sweep_aabb(&body, motion, &obstacles);
+
+        // if walking into a ledge, try moving over it instead. The body is raised, moved
+        // horizontally and lowered again, and this is used if it gets further
+        let blocked_horizontally = moved.xz() != motion.xz();
+        if self.on_ground && blocked_horizontally {
+            let up = collision::sweep_aabb(&body, Vec3::Y * STEP_HEIGHT, &obstacles);
+            let raised = body.translated(up);
+            let horizontal =
+                collision::sweep_aabb(&raised, Vec3::new(motion.x, 0.0, motion.z), &obstacles);
+            let down = collision::sweep_aabb(&raised.translated(horizontal), -up, &obstacles);
+
+            if horizontal.xz().length_squared() > moved.xz().length_squared() {
+                moved = up + horizontal + down;
+            }
+        }
+
+        self.on_ground = motion.y < 0.0 && moved.y > motion.y;
+
+        // stop moving on the axes where the body hit something
+        if moved.x != motion.x {
+            self.velocity.x = 0.0;
+        }
+        if moved.y != motion.y {
+            self.velocity.y = 0.0;
+        }
+        if moved.z != motion.z {
+            self.velocity.z = 0.0;
+        }
+
+        self.position += moved;
+    }
+}
+
+/// Reduce the horizontal motion of the body until it would still be standing on something, so
+/// that it doesn't walk off an edge higher than `SNEAK_MAX_DROP`
+fn stop_at_edges(body: &Aabb, motion: Vec3, obstacles: &[Aabb]) -> Vec3 {
+    let is_supported = |offset: Vec3| {
+        let moved = body.translated(offset);
+        let below = Aabb::new(
+            moved.min - Vec3::Y * SNEAK_MAX_DROP,
+            Vec3::new(moved.max.x, moved.min.y, moved.max.z),
+        );
+
+        obstacles.iter().any(|obstacle| obstacle.intersects(&below))
+    };
+    let approach_zero = |value: f32| {
+        if value.abs() <= SNEAK_EDGE_STEP {
+            0.0
+        } else {
+            value - SNEAK_EDGE_STEP * value.signum()
+        }
+    };
+
+    let mut motion = motion;
+
+    while motion.x != 0.0 && !is_supported(Vec3::new(motion.x, 0.0, 0.0)) {
+        motion.x = approach_zero(motion.x);
+    }
+    while motion.z != 0.0 && !is_supported(Vec3::new(0.0, 0.0, motion.z)) {
+        motion.z = approach_zero(motion.z);
+    }
+    while motion.x != 0.0 && motion.z != 0.0 && !is_supported(Vec3::new(motion.x, 0.0, motion.z)) {
+        motion.x = approach_zero(motion.x);
+        motion.z = approach_zero(motion.z);
+    }
+
+    motion
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::block::{registry::test_registry, BLOCK_AIR};
+
+    /// Registry with a stone block and a bottom stone slab
+    fn test_block_registry() -> BlockRegistry {
+        test_registry(&[
+                "name = \"stone\"\n[model]\ntype = \"full_block\"\ntextures = { all = \"stone\" }",
+                "name = \"stone_slab\"\n[model]\ntype = \"slab\"\nhalf = \"bottom\"\ntextures = { all = \"stone\" }",
+        ])
+    }
+
+    /// Returns a player standing at the given position
+    fn standing_player(position: Vec3) -> PlayerController {
+        let mut player = PlayerController::new(Vec3::ZERO);
+        player.mode = MovementMode::Walk;
+        player.position = position;
+        player.on_ground = true;
+        player
+    }
+
+    #[test]
+    fn stepping_and_slabs() {
+        let block_registry = test_block_registry();
+        let stone = block_registry.get_id("stone").unwrap();
+        let slab = block_registry.get_id("stone_slab").unwrap();
+
+        // a stone floor at y = 0 with a one block ledge at x >= 2 and a slab at x = -1
+        let get_block = |pos: &GlobalBlockPosition| {
+            Some(match (pos.x(), pos.y()) {
+                (_, y) if y < 0 => BLOCK_AIR,
+                (_, 0) => stone,
+                (x, 1) if x >= 2 => stone,
+                (-1, 1) => slab,
+                _ => BLOCK_AIR,
+            })
+        };
+
+        let mut player = standing_player(Vec3::new(1.5, 1.0, 0.5));
+        player.move_body(Vec3::new(0.5, -0.1, 0.0), &block_registry, get_block);
+        assert_eq!(player.position.y, 2.0);
+        assert!((player.position.x - 2.0).abs() < 1e-4);
+        assert!(player.on_ground);
+
+        // walking onto the slab only raises the body by half a block
+        let mut player = standing_player(Vec3::new(0.5, 1.0, 0.5));
+        player.move_body(Vec3::new(-0.5, -0.1, 0.0), &block_registry, get_block);
+        assert_eq!(player.position.y, 1.5);
+        assert_eq!(player.position.x, 0.0);
+    }
+
+    #[test]
+    fn sneaking_stops_at_edges() {
+        let block_registry = test_block_registry();
+        let stone = block_registry.get_id("stone").unwrap();
+
+        // a platform at x < 1 above a long drop
+        let get_block = |pos: &GlobalBlockPosition| {
+            Some(if pos.y() == 0 && pos.x() < 1 {
+                stone
+            } else {
+                BLOCK_AIR
+            })
+        };
+
+        let mut player = standing_player(Vec3::new(0.5, 1.0, 0.5));
+        player.sneaking = true;
+        for _ in 0..20 {
+            player.move_body(Vec3::new(0.2, -0.1, 0.0), &block_registry, get_block);
+        }
+        assert_eq!(player.position.y, 1.0);
+        assert!(player.position.x < 1.0 + BODY_HALF_WIDTH);
+
+        // without sneaking, the player walks off
+        player.sneaking = false;
+        for _ in 0..20 {
+            player.move_body(Vec3::new(0.2, -0.1, 0.0), &block_registry, get_block);
+        }
+        assert!(player.position.y < 1.0);
+    }
+}
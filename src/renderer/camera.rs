@@ -70,6 +70,13 @@ impl Camera {
         }
     }
 
+    /// Change the vertical field of view of a perspective projection
+    pub fn set_fov_y(&mut self, new_fov_y_radians: f32) {
+        if let Projection::Perspective { fov_y_radians, .. } = &mut self.projection {
+            *fov_y_radians = new_fov_y_radians;
+        }
+    }
+
     /// Returns the position of the camera in the world
     pub fn pos(&self) -> Vec3 {
         self.transform.translation
@@ -3,7 +3,8 @@ use generational_arena::Index;
 use self::{
     camera::{Camera, Projection},
     frustum_culling::FrustumCullingRegions,
-    terrain::{ChunkCullingMode, TerrainRenderer},
+    overlay::{Overlay, OverlayRenderer},
+    terrain::{lod::LodSettings, ChunkCullingMode, TerrainRenderer},
 };
 use crate::{
     core::{
@@ -15,12 +16,14 @@ use crate::{
             wgpu_context::WgpuContext,
         },
     },
-    terrain::{load_area::LoadArea, Terrain},
-    util::{size::Size3, transform::Transform, DEGREE},
+    settings::Settings,
+    terrain::{block::registry::BlockRegistry, load_area::LoadArea, Terrain},
+    util::{size::Size3, transform::Transform},
 };
 
 pub mod camera;
 pub mod frustum_culling;
+pub mod overlay;
 pub mod terrain;
 
 pub struct Renderer {
@@ -31,6 +34,8 @@ pub struct Renderer {
     terrain_renderer: TerrainRenderer,
     camera: Camera,
     frustum_culling_regions: FrustumCullingRegions,
+    overlay: Overlay,
+    overlay_renderer: OverlayRenderer,
 }
 
 impl Renderer {
@@ -38,7 +43,12 @@ impl Renderer {
     pub const DEPTH_COMPARE: wgpu::CompareFunction = wgpu::CompareFunction::Less;
     pub const FRUSTUM_CULLING_REGION_SIZE_CHUNKS: usize = 8;
 
-    pub fn new(wgpu: &WgpuContext, load_area: &LoadArea) -> Self {
+    pub fn new(
+        wgpu: &WgpuContext,
+        load_area: &LoadArea,
+        block_registry: &BlockRegistry,
+        settings: &Settings,
+    ) -> Self {
         let depth_texture = DepthTexture::new(
             &wgpu.device,
             wgpu.window_size,
@@ -73,23 +83,25 @@ impl Renderer {
             wgpu,
             &common_uniforms_bind_group_layout,
             load_area,
+            block_registry,
             ChunkCullingMode::VisibilitySearch,
+            settings.graphics.lod,
+            settings.graphics.mip_level_count,
         );
 
         let camera = Camera::new(
             Transform::IDENTITY,
             Projection::Perspective {
                 aspect_ratio: wgpu.window_size.width as f32 / wgpu.window_size.height as f32,
-                fov_y_radians: 80.0 * DEGREE,
+                fov_y_radians: settings.fov_y_radians(),
                 z_near: 0.01,
                 z_far: 1000.0,
             },
         );
 
-        let frustum_culling_region_size = Size3::splat(Self::FRUSTUM_CULLING_REGION_SIZE_CHUNKS);
-        let frustum_culling_grid_size = load_area.size() / frustum_culling_region_size + Size3::ONE;
-        let frustum_culling_regions =
-            FrustumCullingRegions::new(frustum_culling_grid_size, frustum_culling_region_size);
+        let frustum_culling_regions = Self::create_frustum_culling_regions(load_area);
+
+        let overlay_renderer = OverlayRenderer::new(wgpu);
 
         Self {
             depth_texture,
@@ -99,6 +111,8 @@ impl Renderer {
             terrain_renderer,
             camera,
             frustum_culling_regions,
+            overlay: Overlay::default(),
+            overlay_renderer,
         }
     }
 
@@ -149,6 +163,10 @@ impl Renderer {
             self.camera.pos(),
         );
 
+        self.overlay_renderer
+            .render(&mut render_encoder, output_view, wgpu, &self.overlay);
+        self.overlay.clear();
+
         let command_buffer = render_encoder.finish();
 
         wgpu.queue.submit(std::iter::once(command_buffer));
@@ -169,6 +187,44 @@ impl Renderer {
         self.camera.resized(wgpu.window_size);
     }
 
+    /// Called when the size of the load area being rendered changes
+    pub fn load_area_resized(
+        &mut self,
+        wgpu: &WgpuContext,
+        tasks: &mut Tasks,
+        load_area: &LoadArea,
+    ) {
+        self.terrain_renderer
+            .load_area_resized(wgpu, tasks, load_area);
+        self.frustum_culling_regions = Self::create_frustum_culling_regions(load_area);
+    }
+
+    /// Change the level of detail of the chunk meshes
+    pub fn set_lod_settings(
+        &mut self,
+        wgpu: &WgpuContext,
+        tasks: &mut Tasks,
+        load_area: &LoadArea,
+        lod_settings: LodSettings,
+    ) {
+        self.terrain_renderer
+            .set_lod_settings(wgpu, tasks, load_area, lod_settings);
+    }
+
+    /// Method used to choose which chunks to draw
+    pub fn culling_mode(&self) -> ChunkCullingMode {
+        self.terrain_renderer.culling_mode()
+    }
+
+    pub fn set_culling_mode(&mut self, culling_mode: ChunkCullingMode) {
+        self.terrain_renderer.set_culling_mode(culling_mode);
+    }
+
+    /// Returns the overlay drawn over the next frame, which is cleared after each frame
+    pub fn overlay_mut(&mut self) -> &mut Overlay {
+        &mut self.overlay
+    }
+
     /// Returns a shared reference to the camera used to render the world
     pub fn camera(&self) -> &Camera {
         &self.camera
@@ -178,6 +234,14 @@ impl Renderer {
     pub fn camera_mut(&mut self) -> &mut Camera {
         &mut self.camera
     }
+
+    /// Create the grid of frustum culling regions covering a load area
+    fn create_frustum_culling_regions(load_area: &LoadArea) -> FrustumCullingRegions {
+        let frustum_culling_region_size = Size3::splat(Self::FRUSTUM_CULLING_REGION_SIZE_CHUNKS);
+        let frustum_culling_grid_size = load_area.size() / frustum_culling_region_size + Size3::ONE;
+
+        FrustumCullingRegions::new(frustum_culling_grid_size, frustum_culling_region_size)
+    }
 }
 
 #[repr(C)]
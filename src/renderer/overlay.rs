@@ -0,0 +1,240 @@
+use glam::Vec2;
+use wgpu::util::DeviceExt;
+
+use crate::core::wgpu_util::{
+    bind_group_builder::BindGroupBuilder,
+    pipeline_builder::RenderPipelineBuilder,
+    texture::{ImageTexture, TextureConfig, TextureHolder},
+    vertex::Vertex,
+    wgpu_context::WgpuContext,
+};
+
+/// Image containing the glyphs of the printable ASCII characters from the space onwards, in rows
+/// of `FONT_COLUMNS` glyphs
+const FONT_TEXTURE_PATH: &str = "assets/image/font.png";
+
+/// Number of glyphs in each row of the font texture
+const FONT_COLUMNS: u32 = 16;
+
+/// Number of rows of glyphs in the font texture
+const FONT_ROWS: u32 = 6;
+
+/// Texture coordinates of the vertices of rectangles, which are drawn without the font texture
+const SOLID_UV: [f32; 2] = [-1.0, -1.0];
+
+/// Rectangles and text drawn over the rendered world, positioned in pixels from the top left
+/// corner of the window. The overlay is built each frame and cleared once it is drawn
+#[derive(Debug, Default)]
+pub struct Overlay {
+    /// Two triangles for each rectangle or glyph
+    vertices: Vec<OverlayVertex>,
+}
+
+impl Overlay {
+    /// Size of each glyph of text in pixels
+    pub const GLYPH_SIZE: Vec2 = Vec2::new(8.0, 16.0);
+
+    /// Draw a rectangle in a solid colour
+    pub fn draw_rect(&mut self, min: Vec2, size: Vec2, color: [f32; 4]) {
+        self.push_quad(min, size, SOLID_UV, SOLID_UV, color);
+    }
+
+    /// Draw a line of text with its top left corner at `min`. Characters which aren't printable
+    /// ASCII are drawn as `?`
+    pub fn draw_text(&mut self, min: Vec2, text: &str, color: [f32; 4]) {
+        let glyph_uv_size = Vec2::new((FONT_COLUMNS as f32).recip(), (FONT_ROWS as f32).recip());
+
+        for (index, c) in text.chars().enumerate() {
+            if c == ' ' {
+                continue;
+            }
+
+            let glyph_index = match c {
+                ' '..='~' => c as u32 - ' ' as u32,
+                _ => '?' as u32 - ' ' as u32,
+            };
+            let uv_min = Vec2::new(
+                (glyph_index % FONT_COLUMNS) as f32,
+                (glyph_index / FONT_COLUMNS) as f32,
+            ) * glyph_uv_size;
+
+            self.push_quad(
+                min + Vec2::X * (index as f32 * Self::GLYPH_SIZE.x),
+                Self::GLYPH_SIZE,
+                uv_min.to_array(),
+                (uv_min + glyph_uv_size).to_array(),
+                color,
+            );
+        }
+    }
+
+    /// Remove everything drawn on the overlay
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    fn push_quad(
+        &mut self,
+        min: Vec2,
+        size: Vec2,
+        uv_min: [f32; 2],
+        uv_max: [f32; 2],
+        color: [f32; 4],
+    ) {
+        let max = min + size;
+        let corners = [
+            ([min.x, min.y], [uv_min[0], uv_min[1]]),
+            ([min.x, max.y], [uv_min[0], uv_max[1]]),
+            ([max.x, max.y], [uv_max[0], uv_max[1]]),
+            ([max.x, min.y], [uv_max[0], uv_min[1]]),
+        ];
+
+        for corner_index in [0, 1, 2, 0, 2, 3] {
+            let (position, uv) = corners[corner_index];
+            self.vertices.push(OverlayVertex {
+                position,
+                uv,
+                color,
+            });
+        }
+    }
+}
+
+/// Draws an `Overlay` on top of the rendered frame
+pub struct OverlayRenderer {
+    pipeline: wgpu::RenderPipeline,
+    font_bind_group: wgpu::BindGroup,
+}
+
+impl OverlayRenderer {
+    pub fn new(wgpu: &WgpuContext) -> Self {
+        let font_texture = ImageTexture::from_file(
+            &wgpu.device,
+            &wgpu.queue,
+            FONT_TEXTURE_PATH,
+            &TextureConfig {
+                label: Some("Font Texture"),
+                ..Default::default()
+            },
+        )
+        .expect("failed to load font texture")
+        .with_view_and_sampler(&wgpu.device, wgpu::SamplerDescriptor::default());
+
+        let (font_bind_group, font_bind_group_layout) = BindGroupBuilder::new()
+            .with_label("Font Bind Group")
+            .with_texture_view(
+                font_texture.view(),
+                wgpu::TextureViewDimension::D2,
+                wgpu::TextureSampleType::Float { filterable: true },
+                wgpu::ShaderStages::FRAGMENT,
+            )
+            .with_sampler(
+                font_texture.sampler(),
+                wgpu::SamplerBindingType::Filtering,
+                wgpu::ShaderStages::FRAGMENT,
+            )
+            .build(&wgpu.device);
+
+        let overlay_shader = wgpu
+            .device
+            .create_shader_module(wgpu::include_wgsl!("../../assets/shader/overlay.wgsl"));
+
+        let (pipeline, _) = RenderPipelineBuilder::new()
+            .with_label("Overlay Pipeline")
+            .with_bind_group_layout(&font_bind_group_layout)
+            .with_vertex::<OverlayVertex>()
+            .with_vertex_shader(&overlay_shader, "vs_main")
+            .with_fragment_shader(&overlay_shader, "fs_main")
+            .with_color_target(
+                wgpu.surface_config.format,
+                Some(wgpu::BlendState::ALPHA_BLENDING),
+                wgpu::ColorWrites::all(),
+            )
+            .with_cull_mode(None)
+            .build(&wgpu.device);
+
+        Self {
+            pipeline,
+            font_bind_group,
+        }
+    }
+
+    /// Draw the overlay over the contents of `output_view`
+    pub fn render(
+        &self,
+        render_encoder: &mut wgpu::CommandEncoder,
+        output_view: &wgpu::TextureView,
+        wgpu: &WgpuContext,
+        overlay: &Overlay,
+    ) {
+        if overlay.vertices.is_empty() {
+            return;
+        }
+
+        // convert from pixels to normalized device coordinates
+        let window_size = Vec2::new(
+            wgpu.window_size.width as f32,
+            wgpu.window_size.height as f32,
+        );
+        let vertices = overlay
+            .vertices
+            .iter()
+            .map(|vertex| {
+                let position = Vec2::from(vertex.position) / window_size * 2.0 - 1.0;
+                OverlayVertex {
+                    position: [position.x, -position.y],
+                    ..*vertex
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let vertex_buffer = wgpu
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Overlay Vertex Buffer"),
+                contents: bytemuck::cast_slice(&vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+
+        let mut render_pass = render_encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Overlay Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.font_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+        render_pass.draw(0..vertices.len() as u32, 0..1);
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct OverlayVertex {
+    position: [f32; 2],
+    uv: [f32; 2],
+    color: [f32; 4],
+}
+
+impl Vertex for OverlayVertex {
+    fn vertex_buffer_layout() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 3] =
+            wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2, 2 => Float32x4];
+
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
@@ -9,7 +9,8 @@ use itertools::Itertools;
 use wgpu::util::DeviceExt;
 
 use super::{
-    meshing::{self, ChunkMeshInput},
+    lod::{self, ChunkMeshLod, LodSettings},
+    meshing::{self, ChunkMeshInput, ChunkMeshVertices},
     vertex::TerrainVertex,
     ChunkMeshData, ChunkMeshStatus,
 };
@@ -61,10 +62,11 @@ pub struct ChunkBatch {
     vertex_buffer_needs_updating: bool,
     /// Current position of this batch in the grid of chunk batches
     position: IVec3,
-    /// Combined vertex buffer for all chunks in this batch
-    vertex_buffer: Option<wgpu::Buffer>,
-    /// Number of vertices in `vertex_buffer`
-    vertex_count: usize,
+    /// Combined vertex buffer for the opaque geometry of all chunks in this batch
+    opaque_vertex_buffer: BatchVertexBuffer,
+    /// Combined vertex buffer for the translucent geometry of all chunks in this batch, drawn
+    /// separately after the opaque geometry of every batch
+    translucent_vertex_buffer: BatchVertexBuffer,
     /// Mesh data for each chunk in the batch
     chunk_mesh_data: [Option<ChunkMeshData>; CHUNK_BATCH_SIZE_CUBED],
     /// Mesh status for each chunk in the batch
@@ -112,8 +114,8 @@ impl ChunkBatch {
         Self {
             vertex_buffer_needs_updating: false,
             position: pos,
-            vertex_buffer: None,
-            vertex_count: 0,
+            opaque_vertex_buffer: BatchVertexBuffer::default(),
+            translucent_vertex_buffer: BatchVertexBuffer::default(),
             chunk_mesh_data,
             chunk_mesh_status,
             uniform_buffer,
@@ -125,7 +127,8 @@ impl ChunkBatch {
     pub fn reset(&mut self, wgpu: &WgpuContext, pos: IVec3) {
         self.vertex_buffer_needs_updating = false;
         self.position = pos;
-        self.vertex_count = 0;
+        self.opaque_vertex_buffer.vertex_count = 0;
+        self.translucent_vertex_buffer.vertex_count = 0;
         self.chunk_mesh_data = array_init::array_init(|_| None);
         self.chunk_mesh_status = array_init::array_init(|_| ChunkMeshStatus::Missing);
 
@@ -198,80 +201,132 @@ impl ChunkBatch {
         }
     }
 
-    /// Update the vertex_buffer for this batch
-    pub fn update_vertex_buffer(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+    /// Update the vertex buffers for this batch
+    pub fn update_vertex_buffers(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
         self.vertex_buffer_needs_updating = false;
 
-        // calculate the total number of vertices for the combined mesh
-        self.vertex_count = self
+        let chunk_vertices = self
             .chunk_mesh_data
             .iter()
             .filter_map(|mesh_data_opt| mesh_data_opt.as_ref())
-            .map(|mesh_data| mesh_data.vertices.len())
-            .sum();
+            .map(|mesh_data| &mesh_data.vertices)
+            .collect_vec();
+
+        self.opaque_vertex_buffer.update(
+            device,
+            queue,
+            chunk_vertices.iter().map(|vertices| &vertices.opaque),
+        );
+        self.translucent_vertex_buffer.update(
+            device,
+            queue,
+            chunk_vertices.iter().map(|vertices| &vertices.translucent),
+        );
+    }
+
+    /// Returns the level of detail of the stored mesh for the given chunk, if there is one
+    pub fn get_chunk_mesh_lod(&self, chunk_pos_in_batch: &UVec3) -> Option<ChunkMeshLod> {
+        let index = Self::get_index_for_chunk(chunk_pos_in_batch);
+        self.chunk_mesh_data[index]
+            .as_ref()
+            .map(|mesh_data| mesh_data.lod)
+    }
+
+    /// Returns the status of the given chunk in the batch
+    pub fn get_chunk_mesh_status(&self, chunk_pos_in_batch: &UVec3) -> ChunkMeshStatus {
+        let index = Self::get_index_for_chunk(chunk_pos_in_batch);
+        self.chunk_mesh_status[index]
+    }
+
+    /// Returns the vertex buffer for the opaque geometry of this batch
+    pub fn opaque_vertex_buffer(&self) -> &BatchVertexBuffer {
+        &self.opaque_vertex_buffer
+    }
+
+    /// Returns the vertex buffer for the translucent geometry of this batch
+    pub fn translucent_vertex_buffer(&self) -> &BatchVertexBuffer {
+        &self.translucent_vertex_buffer
+    }
+
+    /// Returns the bind group for this batch's uniforms
+    pub fn uniform_bind_group(&self) -> &wgpu::BindGroup {
+        &self.uniform_bind_group
+    }
+
+    /// Returns the largest number of vertices in either of this batch's vertex buffers
+    pub fn max_vertex_count(&self) -> usize {
+        self.opaque_vertex_buffer
+            .vertex_count()
+            .max(self.translucent_vertex_buffer.vertex_count())
+    }
+
+    /// Returns the index in `self.vertices_for_chunk` for the chunk with the given position in the
+    /// group
+    fn get_index_for_chunk(pos: &UVec3) -> usize {
+        CHUNK_BATCH_SIZE_SQUARED * pos.z as usize
+            + CHUNK_BATCH_SIZE * pos.y as usize
+            + pos.x as usize
+    }
+}
+
+/// Vertex buffer holding the concatenated vertices of the chunks in a batch
+#[derive(Debug, Default)]
+pub struct BatchVertexBuffer {
+    buffer: Option<wgpu::Buffer>,
+    /// Number of vertices in `buffer`
+    vertex_count: usize,
+}
+
+impl BatchVertexBuffer {
+    /// Replace the contents of the buffer with the concatenation of each chunk's vertices
+    fn update<'a>(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        chunk_vertices: impl Iterator<Item = &'a Vec<TerrainVertex>> + Clone,
+    ) {
+        // calculate the total number of vertices for the combined mesh
+        self.vertex_count = chunk_vertices.clone().map(|vertices| vertices.len()).sum();
 
         if self.vertex_count == 0 {
-            self.vertex_buffer = None;
+            self.buffer = None;
             return;
         }
 
         // concatenate each chunk's vertices
         let mut vertices = Vec::with_capacity(self.vertex_count);
-        self.chunk_mesh_data
-            .iter()
-            .filter_map(|mesh_data_opt| mesh_data_opt.as_ref())
-            .for_each(|mesh_data| vertices.extend_from_slice(&mesh_data.vertices));
+        chunk_vertices.for_each(|chunk_vertices| vertices.extend_from_slice(chunk_vertices));
 
         // see if we can reuse the existing vertex buffer
-        if let Some(old_vertex_buffer) = self.vertex_buffer.as_ref().filter(|old_vertex_buffer| {
-            self.vertex_count * std::mem::size_of::<TerrainVertex>()
-                <= old_vertex_buffer.size() as usize
+        if let Some(old_buffer) = self.buffer.as_ref().filter(|old_buffer| {
+            self.vertex_count * std::mem::size_of::<TerrainVertex>() <= old_buffer.size() as usize
         }) {
-            queue.write_buffer(&old_vertex_buffer, 0, bytemuck::cast_slice(&vertices));
+            queue.write_buffer(old_buffer, 0, bytemuck::cast_slice(&vertices));
         } else {
-            self.vertex_buffer = Some(device.create_buffer_init(
-                &wgpu::util::BufferInitDescriptor {
+            self.buffer = Some(
+                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                     label: None,
                     contents: bytemuck::cast_slice(&vertices),
                     usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-                },
-            ));
+                }),
+            );
         }
     }
 
-    /// Returns the status of the given chunk in the batch
-    pub fn get_chunk_mesh_status(&self, chunk_pos_in_batch: &UVec3) -> ChunkMeshStatus {
-        let index = Self::get_index_for_chunk(chunk_pos_in_batch);
-        self.chunk_mesh_status[index]
-    }
-
-    /// Returns the vertex buffer for this batch, if it has one
-    pub fn vertex_buffer(&self) -> Option<&wgpu::Buffer> {
-        self.vertex_buffer.as_ref()
-    }
-
-    /// Returns the bind group for this batch's uniforms
-    pub fn uniform_bind_group(&self) -> &wgpu::BindGroup {
-        &self.uniform_bind_group
+    /// Returns the vertex buffer, if there are any vertices
+    pub fn buffer(&self) -> Option<&wgpu::Buffer> {
+        self.buffer.as_ref().filter(|_| self.vertex_count > 0)
     }
 
-    /// Returns the number of vertices in this batch
+    /// Returns the number of vertices in the buffer
     pub fn vertex_count(&self) -> usize {
         self.vertex_count
     }
 
-    /// Returns the number of indices required to draw this batch
+    /// Returns the number of indices required to draw the buffer
     pub fn index_count(&self) -> usize {
         self.vertex_count * 3 / 2
     }
-
-    /// Returns the index in `self.vertices_for_chunk` for the chunk with the given position in the
-    /// group
-    fn get_index_for_chunk(pos: &UVec3) -> usize {
-        CHUNK_BATCH_SIZE_SQUARED * pos.z as usize
-            + CHUNK_BATCH_SIZE * pos.y as usize
-            + pos.x as usize
-    }
 }
 
 #[repr(C)]
@@ -296,6 +351,8 @@ pub struct ChunkBatches {
     uniform_bind_group_layout: wgpu::BindGroupLayout,
     /// Shared index buffer for rendering chunk batches
     shared_index_buffer: SharedIndexBuffer,
+    /// Settings deciding the level of detail of each chunk's mesh
+    lod_settings: LodSettings,
 }
 
 impl ChunkBatches {
@@ -303,6 +360,7 @@ impl ChunkBatches {
         wgpu: &WgpuContext,
         load_area: &LoadArea,
         uniform_bind_group_layout: wgpu::BindGroupLayout,
+        lod_settings: LodSettings,
     ) -> Self {
         let batch_grid_size = Self::compute_batch_grid_size(load_area);
 
@@ -329,6 +387,7 @@ impl ChunkBatches {
             finished_mesh_rx,
             uniform_bind_group_layout,
             shared_index_buffer,
+            lod_settings,
         }
     }
 
@@ -367,8 +426,8 @@ impl ChunkBatches {
         let mut highest_vertex_count = self.shared_index_buffer.vertex_count;
         for batch in &mut self.batches {
             if batch.vertex_buffer_needs_updating {
-                batch.update_vertex_buffer(&wgpu.device, &wgpu.queue);
-                highest_vertex_count = highest_vertex_count.max(batch.vertex_count());
+                batch.update_vertex_buffers(&wgpu.device, &wgpu.queue);
+                highest_vertex_count = highest_vertex_count.max(batch.max_vertex_count());
             }
         }
 
@@ -435,7 +494,24 @@ impl ChunkBatches {
         batch
     }
 
-    /// Spawn a new task to generate a chunk's mesh
+    /// Settings deciding the level of detail of each chunk's mesh
+    pub fn lod_settings(&self) -> &LodSettings {
+        &self.lod_settings
+    }
+
+    /// Change the level of detail settings. Chunk meshes with the wrong level of detail are
+    /// generated again by `TerrainRenderer::request_mesh_updates_for_chunk`
+    pub fn set_lod_settings(&mut self, lod_settings: LodSettings) {
+        self.lod_settings = lod_settings;
+    }
+
+    /// Returns the level of detail that the mesh of the chunk at the given position should have
+    pub fn get_desired_mesh_lod(&self, chunk_pos: ChunkPosition, camera_pos: Vec3) -> ChunkMeshLod {
+        self.lod_settings.mesh_lod_for_chunk(chunk_pos, camera_pos)
+    }
+
+    /// Spawn a new task to generate a chunk's mesh, at the level of detail for its distance from
+    /// the camera
     /// If this function is called multiple times for the same chunk before the mesh generation
     /// finishes, the mesh from the latest call is used
     pub fn queue_chunk_for_meshing(
@@ -448,6 +524,7 @@ impl ChunkBatches {
         priority: i32,
     ) {
         let queued_instant = Instant::now();
+        let lod = self.get_desired_mesh_lod(chunk.position(), camera_pos);
 
         // skip meshing air chunks
         if let ChunkBlockStore::Uniform(block_id) = chunk.block_store() {
@@ -455,14 +532,16 @@ impl ChunkBatches {
                 let _ = self.finished_mesh_tx.send((
                     chunk.position(),
                     ChunkMeshData {
-                        vertices: Vec::new(),
+                        vertices: ChunkMeshVertices::default(),
                         queued_instant,
+                        lod,
                     },
                 ));
             }
         }
 
         let finished_mesh_tx = self.finished_mesh_tx.clone();
+        let downsample_mode = self.lod_settings.downsample_mode;
 
         let (batch_pos, chunk_pos_in_batch) =
             Self::get_batch_pos_and_chunk_pos_in_batch(&chunk.position());
@@ -481,6 +560,7 @@ impl ChunkBatches {
         let chunk_pos = chunk.position();
         let block_store = chunk.block_store().clone();
         let light_store = chunk.light_store().clone();
+        let block_registry = terrain.block_registry().clone();
         let surrounding_sides_faces =
             ChunkSideFaces::get_surrounding_sides(chunk_pos, terrain, load_area_index);
         let surrounding_sides_light =
@@ -504,17 +584,24 @@ impl ChunkBatches {
                     .rem_euclid(IVec3::splat(CHUNK_BATCH_SIZE as i32))
                     * CHUNK_SIZE_I32;
 
-                let vertices = meshing::mesh_greedy(ChunkMeshInput {
-                    blocks: &blocks,
-                    light: &light_store,
-                    translation: translation.as_vec3(),
-                    surrounding_sides_faces: &surrounding_sides_faces,
-                    surrounding_sides_light: &surrounding_sides_light,
-                });
+                let vertices = lod::mesh_greedy_lod(
+                    ChunkMeshInput {
+                        blocks: &blocks,
+                        light: &light_store,
+                        translation: translation.as_vec3(),
+                        surrounding_sides_faces: &surrounding_sides_faces,
+                        surrounding_sides_light: &surrounding_sides_light,
+                        block_registry: &block_registry,
+                        cell_range: meshing::FULL_CELL_RANGE,
+                    },
+                    lod,
+                    downsample_mode,
+                );
 
                 if let Err(e) = finished_mesh_tx.send((chunk_pos, ChunkMeshData {
                     vertices,
                     queued_instant,
+                    lod,
                 })) {
                     log::trace!(
                         "sending chunk vertices from meshing thread to main thread returned error: {}",
@@ -526,6 +613,32 @@ impl ChunkBatches {
         batch.mark_generating(&chunk_pos_in_batch, task_id);
     }
 
+    /// Replace the batches with a grid of empty batches fitting the load area, after the load
+    /// area has been resized. The meshes of the loaded chunks are generated again as they are
+    /// drawn
+    pub fn resize(&mut self, wgpu: &WgpuContext, tasks: &mut Tasks, load_area: &LoadArea) {
+        // cancel any mesh generation tasks queued for the old batches
+        for batch in &self.batches {
+            for chunk_mesh_status in batch.chunk_mesh_status {
+                if let ChunkMeshStatus::Generating(task_id) = chunk_mesh_status {
+                    tasks.cancel_if_pending(task_id);
+                }
+            }
+        }
+
+        self.batch_grid_size = Self::compute_batch_grid_size(load_area);
+        self.batches = itertools::iproduct!(
+            (0..self.batch_grid_size.x),
+            (0..self.batch_grid_size.y),
+            (0..self.batch_grid_size.z),
+        )
+        .map(|(x, y, z)| {
+            let pos = Size3::new(x, y, z).as_ivec3();
+            ChunkBatch::new(pos, wgpu, &self.uniform_bind_group_layout)
+        })
+        .collect_vec();
+    }
+
     /// Size of the grid of chunk batches
     pub fn size(&self) -> Size3 {
         self.batch_grid_size
@@ -0,0 +1,643 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use generational_arena::Index;
+use glam::{Vec3, Vec4};
+use serde_json::json;
+
+use super::{
+    meshing::{self, ChunkMeshInput, ChunkMeshVertices},
+    vertex::TerrainVertex,
+};
+use crate::terrain::{
+    chunk::{
+        side::{ChunkSideFaces, ChunkSideLight},
+        CHUNK_SIZE_I32,
+    },
+    position_types::ChunkPosition,
+    Terrain,
+};
+
+/// Model file formats that terrain meshes can be exported to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MeshExportFormat {
+    /// Wavefront OBJ with an MTL material library. The textures are copied to a directory next to
+    /// the OBJ file
+    Obj,
+    /// Binary glTF 2.0, with the textures embedded
+    Glb,
+}
+
+impl MeshExportFormat {
+    /// Returns the format with the given file extension, if there is one
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "obj" => Some(Self::Obj),
+            "glb" => Some(Self::Glb),
+            _ => None,
+        }
+    }
+}
+
+/// Material of a group of quads in a `TerrainMesh`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct MeshMaterial {
+    texture_index: u32,
+    /// True for the faces of translucent blocks, which are alpha blended rather than alpha tested
+    translucent: bool,
+}
+
+/// Mesh of a box of chunks generated by `mesh_greedy` on the CPU, for writing to model files for
+/// use in other programs.
+/// The quads are grouped by material, with one material per block texture. UVs are in texels of
+/// the block texture divided by the texture size, so merged faces repeat their texture
+#[derive(Clone, Debug, Default)]
+pub struct TerrainMesh {
+    /// Quads of four vertices, wound anticlockwise, for each material
+    groups: BTreeMap<MeshMaterial, Vec<TerrainVertex>>,
+}
+
+impl TerrainMesh {
+    /// Mesh the chunks in the box between the given corners, which are both inside the box.
+    /// The minimum corner of the box is at the origin of the mesh.
+    /// Faces against loaded chunks outside of the box are culled as they are in game
+    pub fn from_terrain(
+        terrain: &Terrain,
+        load_area_index: Index,
+        a: ChunkPosition,
+        b: ChunkPosition,
+    ) -> Result<Self, MeshExportError> {
+        let min = a.as_ivec3().min(b.as_ivec3());
+        let max = a.as_ivec3().max(b.as_ivec3());
+        let mut mesh = Self::default();
+
+        for (x, y, z) in itertools::iproduct!(min.x..=max.x, min.y..=max.y, min.z..=max.z) {
+            let chunk_pos = ChunkPosition::new(x, y, z);
+            let chunk = terrain
+                .get_chunk(load_area_index, &chunk_pos)
+                .ok_or(MeshExportError::ChunkNotLoaded(chunk_pos))?;
+
+            let blocks = chunk.block_store().as_block_array();
+            let surrounding_sides_faces =
+                ChunkSideFaces::get_surrounding_sides(chunk_pos, terrain, load_area_index);
+            let surrounding_sides_light =
+                ChunkSideLight::get_surrounding_sides(chunk_pos, terrain, load_area_index);
+
+            let vertices = meshing::mesh_greedy(ChunkMeshInput {
+                blocks: &blocks,
+                light: chunk.light_store(),
+                translation: ((chunk_pos.as_ivec3() - min) * CHUNK_SIZE_I32).as_vec3(),
+                surrounding_sides_faces: &surrounding_sides_faces,
+                surrounding_sides_light: &surrounding_sides_light,
+                block_registry: terrain.block_registry(),
+                cell_range: meshing::FULL_CELL_RANGE,
+            });
+
+            mesh.add_vertices(&vertices);
+        }
+
+        Ok(mesh)
+    }
+
+    /// Add the quads of a chunk mesh
+    pub fn add_vertices(&mut self, vertices: &ChunkMeshVertices) {
+        for (layer_vertices, translucent) in
+            [(&vertices.opaque, false), (&vertices.translucent, true)]
+        {
+            for quad in layer_vertices.chunks_exact(4) {
+                let material = MeshMaterial {
+                    texture_index: quad[0].texture_index,
+                    translucent,
+                };
+
+                self.groups
+                    .entry(material)
+                    .or_default()
+                    .extend_from_slice(quad);
+            }
+        }
+    }
+
+    /// Number of quads in the mesh
+    pub fn quad_count(&self) -> usize {
+        self.groups
+            .values()
+            .map(|vertices| vertices.len() / 4)
+            .sum()
+    }
+
+    /// Indices of the block textures used by the mesh, in ascending order
+    pub fn used_texture_indices(&self) -> Vec<u32> {
+        let mut texture_indices = self
+            .groups
+            .keys()
+            .map(|material| material.texture_index)
+            .collect::<Vec<_>>();
+        texture_indices.dedup();
+        texture_indices
+    }
+
+    /// Write the mesh to a file in the given format. The block textures are read from
+    /// `texture_dir`, where `texture_names` are the registry's texture names. If `vertex_colors`
+    /// is true, the light at each vertex is written as its colour
+    pub fn save(
+        &self,
+        path: impl AsRef<Path>,
+        format: MeshExportFormat,
+        texture_dir: impl AsRef<Path>,
+        texture_names: &[String],
+        vertex_colors: bool,
+    ) -> Result<(), MeshExportError> {
+        let path = path.as_ref();
+        let texture_path = |texture_index: u32| {
+            texture_dir
+                .as_ref()
+                .join(&texture_names[texture_index as usize])
+                .with_extension("png")
+        };
+
+        match format {
+            MeshExportFormat::Obj => {
+                let stem = path
+                    .file_stem()
+                    .map_or("terrain".into(), |stem| stem.to_string_lossy());
+                let mtl_file_name = format!("{}.mtl", stem);
+                let texture_dir_name = format!("{}_textures", stem);
+
+                let (obj, mtl) = self.to_obj(
+                    &mtl_file_name,
+                    &texture_dir_name,
+                    texture_names,
+                    vertex_colors,
+                );
+
+                // copy the used textures next to the model, so that it can be moved as a whole
+                let output_dir = path.parent().unwrap_or(Path::new(""));
+                let output_texture_dir = output_dir.join(&texture_dir_name);
+                fs::create_dir_all(&output_texture_dir)
+                    .map_err(|e| MeshExportError::IoError(output_texture_dir.clone(), e))?;
+
+                for texture_index in self.used_texture_indices() {
+                    let source = texture_path(texture_index);
+                    let destination = output_texture_dir
+                        .join(&texture_names[texture_index as usize])
+                        .with_extension("png");
+
+                    fs::copy(&source, &destination)
+                        .map_err(|e| MeshExportError::IoError(source.clone(), e))?;
+                }
+
+                let mtl_path = output_dir.join(&mtl_file_name);
+                fs::write(&mtl_path, mtl).map_err(|e| MeshExportError::IoError(mtl_path, e))?;
+                fs::write(path, obj).map_err(|e| MeshExportError::IoError(path.to_path_buf(), e))
+            }
+            MeshExportFormat::Glb => {
+                let mut textures = vec![Vec::new(); texture_names.len()];
+                for texture_index in self.used_texture_indices() {
+                    let source = texture_path(texture_index);
+                    textures[texture_index as usize] =
+                        fs::read(&source).map_err(|e| MeshExportError::IoError(source, e))?;
+                }
+
+                let glb = self.to_glb(texture_names, &textures, vertex_colors);
+                fs::write(path, glb).map_err(|e| MeshExportError::IoError(path.to_path_buf(), e))
+            }
+        }
+    }
+
+    /// Returns the source of an OBJ file and its MTL material library, named `mtl_file_name`.
+    /// The materials reference the block textures as PNG files in `texture_dir_name`, relative to
+    /// the MTL file. Vertex colours use the common `v x y z r g b` extension
+    pub fn to_obj(
+        &self,
+        mtl_file_name: &str,
+        texture_dir_name: &str,
+        texture_names: &[String],
+        vertex_colors: bool,
+    ) -> (String, String) {
+        let mut obj = String::new();
+        let mut mtl = String::new();
+
+        writeln!(obj, "mtllib {}", mtl_file_name).unwrap();
+
+        // vertices, texture coordinates and normals are numbered from 1 across the whole file
+        let mut vertex_number = 1;
+        let mut normal_number = 1;
+
+        for (material, vertices) in &self.groups {
+            let material_name = material_name(material, texture_names);
+            let texture_path = format!(
+                "{}/{}.png",
+                texture_dir_name, texture_names[material.texture_index as usize]
+            );
+
+            writeln!(mtl, "newmtl {}", material_name).unwrap();
+            writeln!(mtl, "Kd 1 1 1").unwrap();
+            writeln!(mtl, "map_Kd {}", texture_path).unwrap();
+            if material.translucent {
+                writeln!(mtl, "map_d {}", texture_path).unwrap();
+            }
+            writeln!(mtl).unwrap();
+
+            writeln!(obj, "usemtl {}", material_name).unwrap();
+
+            for quad in vertices.chunks_exact(4) {
+                for vertex in quad {
+                    let [x, y, z] = vertex.position;
+                    write!(obj, "v {} {} {}", x, y, z).unwrap();
+                    if vertex_colors {
+                        let [r, g, b] = light_color(vertex).to_array();
+                        write!(obj, " {} {} {}", r, g, b).unwrap();
+                    }
+                    writeln!(obj).unwrap();
+
+                    // OBJ texture coordinates start at the bottom of the image
+                    let [u, v] = vertex.uv;
+                    writeln!(obj, "vt {} {}", u, 1.0 - v).unwrap();
+                }
+
+                let [nx, ny, nz] = quad_normal(quad).to_array();
+                writeln!(obj, "vn {} {} {}", nx, ny, nz).unwrap();
+
+                write!(obj, "f").unwrap();
+                for i in vertex_number..vertex_number + 4 {
+                    write!(obj, " {}/{}/{}", i, i, normal_number).unwrap();
+                }
+                writeln!(obj).unwrap();
+
+                vertex_number += 4;
+                normal_number += 1;
+            }
+        }
+
+        (obj, mtl)
+    }
+
+    /// Returns the mesh as a binary glTF 2.0 file, with one primitive per material.
+    /// `textures` holds the PNG data of each block texture, indexed by texture index; only the
+    /// textures used by the mesh are needed
+    pub fn to_glb(
+        &self,
+        texture_names: &[String],
+        textures: &[Vec<u8>],
+        vertex_colors: bool,
+    ) -> Vec<u8> {
+        let mut buffer = GlbBuffer::default();
+        let mut images = Vec::new();
+        let mut materials = Vec::new();
+        let mut primitives = Vec::new();
+        let mut image_indices = BTreeMap::new();
+
+        for (material, vertices) in &self.groups {
+            let image_index = *image_indices
+                .entry(material.texture_index)
+                .or_insert_with(|| {
+                    let buffer_view =
+                        buffer.push_view(&textures[material.texture_index as usize], None);
+                    images.push(json!({
+                        "name": texture_names[material.texture_index as usize],
+                        "bufferView": buffer_view,
+                        "mimeType": "image/png",
+                    }));
+                    images.len() - 1
+                });
+
+            materials.push(json!({
+                "name": material_name(material, texture_names),
+                "pbrMetallicRoughness": {
+                    "baseColorTexture": { "index": image_index },
+                    "metallicFactor": 0.0,
+                    "roughnessFactor": 1.0,
+                },
+                "alphaMode": if material.translucent { "BLEND" } else { "MASK" },
+            }));
+
+            // one normal per vertex, shared by the vertices of each quad
+            let normals = vertices
+                .chunks_exact(4)
+                .flat_map(|quad| [quad_normal(quad).to_array(); 4])
+                .collect::<Vec<_>>();
+            let positions = vertices
+                .iter()
+                .map(|vertex| vertex.position)
+                .collect::<Vec<_>>();
+            let uvs = vertices.iter().map(|vertex| vertex.uv).collect::<Vec<_>>();
+            let indices = meshing::generate_indices(vertices.len());
+
+            let (position_min, position_max) = positions.iter().fold(
+                (Vec3::INFINITY, Vec3::NEG_INFINITY),
+                |(min, max), &position| (min.min(position.into()), max.max(position.into())),
+            );
+
+            let mut attributes = json!({
+                "POSITION": buffer.push_accessor(
+                    bytemuck::cast_slice(&positions),
+                    positions.len(),
+                    "VEC3",
+                    FLOAT,
+                    Some((position_min.to_array(), position_max.to_array())),
+                ),
+                "NORMAL": buffer.push_accessor(
+                    bytemuck::cast_slice(&normals),
+                    normals.len(),
+                    "VEC3",
+                    FLOAT,
+                    None,
+                ),
+                "TEXCOORD_0": buffer.push_accessor(
+                    bytemuck::cast_slice(&uvs),
+                    uvs.len(),
+                    "VEC2",
+                    FLOAT,
+                    None,
+                ),
+            });
+            if vertex_colors {
+                let colors = vertices
+                    .iter()
+                    .map(|vertex| light_color(vertex).to_array())
+                    .collect::<Vec<_>>();
+
+                attributes["COLOR_0"] = buffer
+                    .push_accessor(
+                        bytemuck::cast_slice(&colors),
+                        colors.len(),
+                        "VEC3",
+                        FLOAT,
+                        None,
+                    )
+                    .into();
+            }
+
+            primitives.push(json!({
+                "attributes": attributes,
+                "indices": buffer.push_index_accessor(&indices),
+                "material": materials.len() - 1,
+            }));
+        }
+
+        let textures = (0..images.len())
+            .map(|image_index| json!({ "source": image_index, "sampler": 0 }))
+            .collect::<Vec<_>>();
+
+        let document = json!({
+            "asset": { "version": "2.0", "generator": "voxels" },
+            "scene": 0,
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [{ "name": "terrain", "mesh": 0 }],
+            "meshes": [{ "name": "terrain", "primitives": primitives }],
+            "materials": materials,
+            "textures": textures,
+            "images": images,
+            // pixelated textures which repeat across merged faces
+            "samplers": [{
+                "magFilter": NEAREST,
+                "minFilter": NEAREST,
+                "wrapS": REPEAT,
+                "wrapT": REPEAT,
+            }],
+            "buffers": [{ "byteLength": buffer.data.len() }],
+            "bufferViews": buffer.views,
+            "accessors": buffer.accessors,
+        });
+
+        write_glb(&document.to_string(), &buffer.data)
+    }
+}
+
+/// glTF component type of 32-bit floats
+const FLOAT: u32 = 5126;
+
+/// glTF component type of 32-bit unsigned integers
+const UNSIGNED_INT: u32 = 5125;
+
+/// glTF buffer view target for vertex attributes
+const ARRAY_BUFFER: u32 = 34962;
+
+/// glTF buffer view target for indices
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+/// glTF sampler filter without interpolation
+const NEAREST: u32 = 9728;
+
+/// glTF sampler wrapping mode that repeats the texture
+const REPEAT: u32 = 10497;
+
+/// Binary buffer of a glTF file, with the buffer views and accessors describing its contents
+#[derive(Default)]
+struct GlbBuffer {
+    data: Vec<u8>,
+    views: Vec<serde_json::Value>,
+    accessors: Vec<serde_json::Value>,
+}
+
+impl GlbBuffer {
+    /// Append a buffer view, returning its index. Views are aligned to 4 bytes
+    fn push_view(&mut self, bytes: &[u8], target: Option<u32>) -> usize {
+        self.data.resize(self.data.len().next_multiple_of(4), 0);
+
+        let mut view = json!({
+            "buffer": 0,
+            "byteOffset": self.data.len(),
+            "byteLength": bytes.len(),
+        });
+        if let Some(target) = target {
+            view["target"] = target.into();
+        }
+
+        self.data.extend_from_slice(bytes);
+        self.views.push(view);
+        self.views.len() - 1
+    }
+
+    /// Append an accessor for vertex attributes with its own buffer view, returning its index
+    fn push_accessor(
+        &mut self,
+        bytes: &[u8],
+        count: usize,
+        accessor_type: &str,
+        component_type: u32,
+        min_max: Option<([f32; 3], [f32; 3])>,
+    ) -> usize {
+        let buffer_view = self.push_view(bytes, Some(ARRAY_BUFFER));
+
+        let mut accessor = json!({
+            "bufferView": buffer_view,
+            "componentType": component_type,
+            "count": count,
+            "type": accessor_type,
+        });
+        if let Some((min, max)) = min_max {
+            accessor["min"] = json!(min);
+            accessor["max"] = json!(max);
+        }
+
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+
+    /// Append an accessor for triangle indices with its own buffer view, returning its index
+    fn push_index_accessor(&mut self, indices: &[u32]) -> usize {
+        let buffer_view = self.push_view(bytemuck::cast_slice(indices), Some(ELEMENT_ARRAY_BUFFER));
+
+        self.accessors.push(json!({
+            "bufferView": buffer_view,
+            "componentType": UNSIGNED_INT,
+            "count": indices.len(),
+            "type": "SCALAR",
+        }));
+        self.accessors.len() - 1
+    }
+}
+
+/// Assemble a binary glTF file from its JSON document and binary buffer
+fn write_glb(json: &str, buffer: &[u8]) -> Vec<u8> {
+    const MAGIC: &[u8; 4] = b"glTF";
+    const VERSION: u32 = 2;
+    const JSON_CHUNK_TYPE: &[u8; 4] = b"JSON";
+    const BIN_CHUNK_TYPE: &[u8; 4] = b"BIN\0";
+
+    // chunks are padded to 4 bytes, the JSON chunk with spaces
+    let mut json = json.as_bytes().to_vec();
+    json.resize(json.len().next_multiple_of(4), b' ');
+    let mut buffer = buffer.to_vec();
+    buffer.resize(buffer.len().next_multiple_of(4), 0);
+
+    let total_len = 12 + 8 + json.len() + 8 + buffer.len();
+    let mut glb = Vec::with_capacity(total_len);
+
+    glb.extend_from_slice(MAGIC);
+    glb.extend_from_slice(&VERSION.to_le_bytes());
+    glb.extend_from_slice(&(total_len as u32).to_le_bytes());
+
+    for (chunk_type, data) in [(JSON_CHUNK_TYPE, &json), (BIN_CHUNK_TYPE, &buffer)] {
+        glb.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        glb.extend_from_slice(chunk_type);
+        glb.extend_from_slice(data);
+    }
+
+    glb
+}
+
+/// Returns the name of a material, which is the name of its texture
+fn material_name(material: &MeshMaterial, texture_names: &[String]) -> String {
+    let texture_name = &texture_names[material.texture_index as usize];
+
+    if material.translucent {
+        format!("{}_translucent", texture_name)
+    } else {
+        texture_name.clone()
+    }
+}
+
+/// Returns the colour of the light at a vertex, combined in the same way as the terrain shader
+fn light_color(vertex: &TerrainVertex) -> Vec3 {
+    let light = Vec4::from(vertex.light);
+
+    (light.truncate() + light.w).min(Vec3::ONE)
+}
+
+/// Returns the normal of a quad wound anticlockwise
+fn quad_normal(quad: &[TerrainVertex]) -> Vec3 {
+    let [a, b, c] = [0, 1, 2].map(|i| Vec3::from(quad[i].position));
+
+    (b - a).cross(c - a).normalize_or_zero()
+}
+
+/// Errors returned when exporting a terrain mesh
+#[derive(Debug, thiserror::Error)]
+pub enum MeshExportError {
+    #[error("io error accessing {0}: {1}")]
+    IoError(PathBuf, io::Error),
+    #[error("chunk {0:?} is not loaded")]
+    ChunkNotLoaded(ChunkPosition),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quad(texture_index: u32, y: f32) -> [TerrainVertex; 4] {
+        [[0.0, 0.0], [2.0, 0.0], [2.0, 1.0], [0.0, 1.0]].map(|[x, z]| TerrainVertex {
+            position: [x, y, z],
+            texture_index,
+            light: [0.25, 0.5, 0.0, 0.5],
+            uv: [x, z],
+        })
+    }
+
+    fn test_mesh() -> TerrainMesh {
+        let mut mesh = TerrainMesh::default();
+        mesh.add_vertices(&ChunkMeshVertices {
+            opaque: [quad(1, 0.0), quad(0, 1.0), quad(1, 2.0)].concat(),
+            translucent: quad(1, 3.0).to_vec(),
+        });
+        mesh
+    }
+
+    #[test]
+    fn obj() {
+        let texture_names = ["dirt".to_string(), "stone".to_string()];
+        let (obj, mtl) = test_mesh().to_obj("terrain.mtl", "textures", &texture_names, true);
+
+        assert_eq!(
+            obj.lines().filter(|line| line.starts_with("v ")).count(),
+            16
+        );
+        assert_eq!(obj.lines().filter(|line| line.starts_with("f ")).count(), 4);
+        assert!(obj.contains("v 2 0 1 0.75 1 0.5\n"));
+        assert!(obj.contains("f 13/13/4 14/14/4 15/15/4 16/16/4\n"));
+        assert!(obj.contains("vn 0 -1 0\n") || obj.contains("vn 0 1 0\n"));
+
+        // materials are in order of texture, then translucency
+        let materials = mtl
+            .lines()
+            .filter_map(|line| line.strip_prefix("newmtl "))
+            .collect::<Vec<_>>();
+        assert_eq!(materials, vec!["dirt", "stone", "stone_translucent"]);
+        assert!(mtl.contains("map_Kd textures/stone.png"));
+    }
+
+    #[test]
+    fn glb() {
+        let mesh = test_mesh();
+        assert_eq!(mesh.quad_count(), 4);
+        assert_eq!(mesh.used_texture_indices(), vec![0, 1]);
+
+        let texture_names = ["dirt".to_string(), "stone".to_string()];
+        let textures = vec![vec![1, 2, 3], vec![4, 5, 6, 7, 8]];
+        let glb = mesh.to_glb(&texture_names, &textures, true);
+
+        assert_eq!(&glb[0..4], b"glTF");
+        assert_eq!(glb.len() % 4, 0);
+        assert_eq!(
+            u32::from_le_bytes(glb[8..12].try_into().unwrap()) as usize,
+            glb.len()
+        );
+
+        let json_len = u32::from_le_bytes(glb[12..16].try_into().unwrap()) as usize;
+        assert_eq!(&glb[16..20], b"JSON");
+        let document: serde_json::Value = serde_json::from_slice(&glb[20..20 + json_len]).unwrap();
+
+        let primitives = document["meshes"][0]["primitives"].as_array().unwrap();
+        assert_eq!(primitives.len(), 3);
+        assert_eq!(document["images"].as_array().unwrap().len(), 2);
+        assert_eq!(document["materials"][2]["alphaMode"], "BLEND");
+
+        // the second primitive holds the two opaque stone quads
+        let position_accessor = &document["accessors"]
+            [primitives[1]["attributes"]["POSITION"].as_u64().unwrap() as usize];
+        assert_eq!(position_accessor["count"], 8);
+        assert_eq!(position_accessor["max"], json!([2.0, 2.0, 1.0]));
+        let index_accessor =
+            &document["accessors"][primitives[1]["indices"].as_u64().unwrap() as usize];
+        assert_eq!(index_accessor["count"], 12);
+
+        let bin_header = 20 + json_len;
+        assert_eq!(&glb[bin_header + 4..bin_header + 8], b"BIN\0");
+    }
+}
@@ -0,0 +1,430 @@
+use glam::{IVec3, UVec3, Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+
+use super::meshing::{self, ChunkMeshInput, ChunkMeshVertices};
+use crate::{
+    terrain::{
+        block::{model::BlockModel, registry::BlockRegistry, BlockId, BLOCK_AIR},
+        chunk::{
+            light_store::ChunkLightStore,
+            side::{ChunkSideFaces, ChunkSideLight},
+            CHUNK_SIZE, CHUNK_SIZE_CUBED, CHUNK_SIZE_I32, CHUNK_SIZE_U32,
+        },
+        lighting::{emitted_light::EmittedLight, skylight::Skylight},
+        position_types::{ChunkPosition, LocalBlockPosition},
+    },
+    util::face::{FACE_BITANGENTS, FACE_NORMALS, FACE_TANGENTS},
+};
+
+/// Number of levels of detail, including full resolution
+pub const LOD_LEVEL_COUNT: usize = 4;
+
+/// Level of detail of a chunk mesh. Each level halves the resolution of the previous level, so
+/// one cell of the mesh covers `scale()` blocks on each axis
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LodLevel(pub u8);
+
+impl LodLevel {
+    /// Full resolution, where each cell of the mesh is one block
+    pub const FULL: LodLevel = LodLevel(0);
+
+    /// Number of blocks covered by one cell of the mesh on each axis
+    pub fn scale(self) -> u32 {
+        1 << self.0
+    }
+}
+
+/// How the block of each cell of a downsampled chunk is chosen from the blocks it covers
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DownsampleMode {
+    /// The most common block, if at least half of the blocks are filled
+    Majority,
+    /// The highest block, if any of the blocks are filled. This keeps thin layers on the surface
+    /// of the terrain such as snow, but fills in small caves and overhangs
+    TopSurface,
+}
+
+/// Settings deciding the level of detail of the chunk meshes
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LodSettings {
+    /// Distance from the camera in chunks beyond which chunks are meshed at each level of detail
+    /// after full resolution. Must be in increasing order
+    pub ring_distances: [f32; LOD_LEVEL_COUNT - 1],
+    /// How the blocks of downsampled chunks are chosen
+    pub downsample_mode: DownsampleMode,
+}
+
+impl LodSettings {
+    /// Returns the level of detail for the chunk at the given position
+    pub fn level_for_chunk(&self, chunk_pos: ChunkPosition, camera_pos: Vec3) -> LodLevel {
+        let chunk_center = (chunk_pos.as_vec3() + 0.5) * (CHUNK_SIZE as f32);
+        let distance = chunk_center.distance(camera_pos) / (CHUNK_SIZE as f32);
+
+        let level = self
+            .ring_distances
+            .iter()
+            .take_while(|&&ring_distance| distance >= ring_distance)
+            .count();
+
+        LodLevel(level as u8)
+    }
+
+    /// Returns the level of detail and skirts of the mesh for the chunk at the given position
+    pub fn mesh_lod_for_chunk(&self, chunk_pos: ChunkPosition, camera_pos: Vec3) -> ChunkMeshLod {
+        let level = self.level_for_chunk(chunk_pos, camera_pos);
+
+        // downsampled chunks always have skirts on every side
+        if level != LodLevel::FULL {
+            return ChunkMeshLod {
+                level,
+                skirts: ChunkMeshLod::ALL_SIDES,
+            };
+        }
+
+        let skirts = FACE_NORMALS
+            .iter()
+            .enumerate()
+            .filter(|(_, &normal)| {
+                let neighbour_pos = chunk_pos + ChunkPosition::from(normal);
+                self.level_for_chunk(neighbour_pos, camera_pos) != level
+            })
+            .fold(0, |skirts, (face_index, _)| skirts | (1 << face_index));
+
+        ChunkMeshLod { level, skirts }
+    }
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self {
+            ring_distances: [8.0, 16.0, 24.0],
+            downsample_mode: DownsampleMode::Majority,
+        }
+    }
+}
+
+/// Level of detail of a chunk mesh, along with the sides of the chunk with skirts.
+/// A skirt is drawn by treating the cells outside that side of the chunk as empty, so that every
+/// face on that side is drawn. This hides the gaps that would otherwise appear between chunks
+/// meshed at different levels of detail, whose surfaces do not line up
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkMeshLod {
+    pub level: LodLevel,
+    /// Bit flags for the sides of the chunk with skirts, indexed by `FaceIndex`
+    pub skirts: u8,
+}
+
+impl ChunkMeshLod {
+    /// Bit flags for every side of the chunk
+    pub const ALL_SIDES: u8 = 0b111111;
+}
+
+/// Creates the vertices for a chunk mesh at the given level of detail, using `mesh_greedy`.
+/// The blocks of downsampled chunks are chosen according to `downsample_mode`, and the light of
+/// each cell is the brightest light among the blocks it covers
+pub fn mesh_greedy_lod(
+    input: ChunkMeshInput,
+    lod: ChunkMeshLod,
+    downsample_mode: DownsampleMode,
+) -> ChunkMeshVertices {
+    if lod.level == LodLevel::FULL {
+        let surrounding_sides_faces: Vec<Option<ChunkSideFaces>> = input
+            .surrounding_sides_faces
+            .iter()
+            .enumerate()
+            .map(|(face_index, side)| side.clone().filter(|_| lod.skirts & (1 << face_index) == 0))
+            .collect();
+
+        return meshing::mesh_greedy(ChunkMeshInput {
+            surrounding_sides_faces: &surrounding_sides_faces,
+            ..input
+        });
+    }
+
+    let downsampled = DownsampledChunk::new(input, lod.level, downsample_mode);
+    let no_sides: [Option<ChunkSideFaces>; 6] = Default::default();
+    let no_light: [Option<ChunkSideLight>; 6] = Default::default();
+
+    let mut vertices = meshing::mesh_greedy(ChunkMeshInput {
+        blocks: &downsampled.blocks,
+        light: &downsampled.light,
+        translation: Vec3::ZERO,
+        surrounding_sides_faces: &no_sides,
+        surrounding_sides_light: &no_light,
+        block_registry: input.block_registry,
+        cell_range: DownsampledChunk::cell_range(lod.level),
+    });
+
+    // scale the mesh back up to the size of the chunk. The texture coordinates are scaled too, so
+    // that the textures are the same size as at full resolution
+    let scale = lod.level.scale() as f32;
+    for vertex in vertices
+        .opaque
+        .iter_mut()
+        .chain(vertices.translucent.iter_mut())
+    {
+        let position = (Vec3::from(vertex.position) - Vec3::ONE) * scale + input.translation;
+
+        vertex.position = position.to_array();
+        vertex.uv = (Vec2::from(vertex.uv) * scale).to_array();
+    }
+
+    vertices
+}
+
+/// Blocks and light of a chunk downsampled for meshing at a lower level of detail.
+/// The cells are stored in chunk-sized arrays, one cell away from the minimum corner, so that
+/// they are surrounded by a layer of cells on every side. The surrounding cells are empty, giving
+/// the mesh skirts on every side, and are lit by the sides of the neighbouring chunks
+struct DownsampledChunk {
+    blocks: Box<[BlockId]>,
+    light: ChunkLightStore,
+}
+
+impl DownsampledChunk {
+    fn new(input: ChunkMeshInput, level: LodLevel, downsample_mode: DownsampleMode) -> Self {
+        let scale = level.scale();
+        let (min, max) = Self::cell_range(level);
+
+        let mut blocks = vec![BLOCK_AIR; CHUNK_SIZE_CUBED].into_boxed_slice();
+        let mut light = ChunkLightStore::new();
+
+        for (x, y, z) in itertools::iproduct!(0..=max, 0..=max, 0..=max) {
+            let cell_pos = UVec3::new(x, y, z);
+            let cell_index = LocalBlockPosition::from(cell_pos).get_array_index();
+            let in_range = cell_pos.min_element() >= min && cell_pos.max_element() < max;
+
+            let (emitted, sky) = if in_range {
+                let origin = (cell_pos - min) * scale;
+
+                blocks[cell_index] = downsample_cell(
+                    input.blocks,
+                    input.block_registry,
+                    origin,
+                    scale,
+                    downsample_mode,
+                );
+
+                Self::cell_light(input.light, origin, scale)
+            } else {
+                Self::surrounding_cell_light(
+                    input.surrounding_sides_light,
+                    cell_pos,
+                    min,
+                    max,
+                    scale,
+                )
+            };
+
+            light.set_emitted_light(LocalBlockPosition::from(cell_pos), emitted);
+            light.set_skylight(LocalBlockPosition::from(cell_pos), sky);
+        }
+
+        Self { blocks, light }
+    }
+
+    /// Range of the coordinates of the downsampled cells on each axis
+    fn cell_range(level: LodLevel) -> (u32, u32) {
+        (1, 1 + CHUNK_SIZE_U32 / level.scale())
+    }
+
+    /// Returns the brightest light among the blocks covered by the cell with the given origin
+    fn cell_light(light: &ChunkLightStore, origin: UVec3, scale: u32) -> (EmittedLight, Skylight) {
+        cell_block_positions(origin, scale).fold(
+            (EmittedLight::ZERO, Skylight::ZERO),
+            |(emitted, sky), pos| {
+                (
+                    EmittedLight::max(emitted, light.get_emitted_light(pos)),
+                    sky.max(light.get_skylight(pos)),
+                )
+            },
+        )
+    }
+
+    /// Returns the brightest light among the tiles of the neighbouring chunk's side next to a cell
+    /// surrounding the downsampled cells. Cells on the edges and corners use the side of the first
+    /// axis they are outside of
+    fn surrounding_cell_light(
+        surrounding_sides_light: &[Option<ChunkSideLight>],
+        cell_pos: UVec3,
+        min: u32,
+        max: u32,
+        scale: u32,
+    ) -> (EmittedLight, Skylight) {
+        let cell_pos = cell_pos.as_ivec3();
+        let (min, max) = (min as i32, max as i32);
+
+        let outside_axis = (0..3)
+            .find(|&axis| cell_pos[axis] < min || cell_pos[axis] >= max)
+            .expect("cell should be outside the range");
+        let side_index = if cell_pos[outside_axis] < min {
+            outside_axis + 3
+        } else {
+            outside_axis
+        };
+
+        let Some(side) = &surrounding_sides_light[side_index] else {
+            return (EmittedLight::ZERO, Skylight::ZERO);
+        };
+
+        // position of the first block covered by the cell, and the number of blocks it covers on
+        // each axis, clamped to the chunk
+        let origin =
+            ((cell_pos - min) * scale as i32).clamp(IVec3::ZERO, IVec3::splat(CHUNK_SIZE_I32 - 1));
+        let extent = IVec3::select(
+            cell_pos.cmplt(IVec3::splat(min)) | cell_pos.cmpge(IVec3::splat(max)),
+            IVec3::ONE,
+            IVec3::splat(scale as i32),
+        );
+
+        itertools::iproduct!(0..extent.x, 0..extent.y, 0..extent.z)
+            .map(|(x, y, z)| {
+                let pos = origin + IVec3::new(x, y, z);
+                let u = pos.dot(FACE_TANGENTS[side_index].abs());
+                let v = pos.dot(FACE_BITANGENTS[side_index].abs());
+
+                (u + v * CHUNK_SIZE_I32) as usize
+            })
+            .fold(
+                (EmittedLight::ZERO, Skylight::ZERO),
+                |(emitted, sky), index_in_side| {
+                    (
+                        EmittedLight::max(emitted, side.emitted[index_in_side]),
+                        sky.max(side.sky[index_in_side]),
+                    )
+                },
+            )
+    }
+}
+
+/// Choose the block for the downsampled cell covering the blocks from `origin` to
+/// `origin + scale` on each axis
+fn downsample_cell(
+    blocks: &[BlockId],
+    block_registry: &BlockRegistry,
+    origin: UVec3,
+    scale: u32,
+    downsample_mode: DownsampleMode,
+) -> BlockId {
+    let filled_blocks = cell_block_positions(origin, scale)
+        .map(|pos| (pos, blocks[pos.get_array_index()]))
+        .filter(|&(_, block_id)| is_downsampled(block_registry, block_id));
+
+    match downsample_mode {
+        DownsampleMode::Majority => {
+            // count each block in the cell, keeping them in the order they were found
+            let mut counts: Vec<(BlockId, u32)> = Vec::new();
+            for (_, block_id) in filled_blocks {
+                if let Some((_, count)) = counts.iter_mut().find(|(id, _)| *id == block_id) {
+                    *count += 1;
+                } else {
+                    counts.push((block_id, 1));
+                }
+            }
+
+            let filled_count: u32 = counts.iter().map(|(_, count)| count).sum();
+            if 2 * filled_count < scale * scale * scale {
+                return BLOCK_AIR;
+            }
+
+            counts
+                .iter()
+                .rev()
+                .max_by_key(|(_, count)| *count)
+                .map_or(BLOCK_AIR, |(block_id, _)| *block_id)
+        }
+        DownsampleMode::TopSurface => filled_blocks
+            .rev()
+            .max_by_key(|(pos, _)| pos.y())
+            .map_or(BLOCK_AIR, |(_, block_id)| block_id),
+    }
+}
+
+/// True if the block counts towards filling a downsampled cell. Plants are left out, since they
+/// would otherwise become solid blocks the size of the cell
+fn is_downsampled(block_registry: &BlockRegistry, block_id: BlockId) -> bool {
+    !matches!(
+        block_registry[block_id].model,
+        BlockModel::Empty | BlockModel::Cross(_)
+    )
+}
+
+/// Returns the positions of the blocks from `origin` to `origin + scale` on each axis, ordered
+/// by y, then z, then x
+fn cell_block_positions(
+    origin: UVec3,
+    scale: u32,
+) -> impl DoubleEndedIterator<Item = LocalBlockPosition> {
+    itertools::iproduct!(0..scale, 0..scale, 0..scale)
+        .map(move |(y, z, x)| LocalBlockPosition::from(origin + UVec3::new(x, y, z)))
+        .collect::<Vec<_>>()
+        .into_iter()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::block::registry::test_registry;
+
+    fn test_block_registry() -> BlockRegistry {
+        test_registry(&[
+            "name = \"snow\"\n[model]\ntype = \"full_block\"\ntextures = { all = \"snow\" }",
+            "name = \"stone\"\n[model]\ntype = \"full_block\"\ntextures = { all = \"stone\" }",
+            "name = \"tall_grass\"\n[model]\ntype = \"cross\"\ntexture = \"tall_grass\"",
+        ])
+    }
+
+    #[test]
+    fn downsampling() {
+        let block_registry = test_block_registry();
+        let snow = block_registry.get_id("snow").unwrap();
+        let stone = block_registry.get_id("stone").unwrap();
+        let tall_grass = block_registry.get_id("tall_grass").unwrap();
+
+        // a 2x2x2 cell with stone on the bottom, snow on top of one column and a plant beside it
+        let mut blocks = vec![BLOCK_AIR; CHUNK_SIZE_CUBED];
+        for (x, z) in itertools::iproduct!(0..2, 0..2) {
+            blocks[LocalBlockPosition::new(x, 0, z).get_array_index()] = stone;
+        }
+        blocks[LocalBlockPosition::new(0, 1, 0).get_array_index()] = snow;
+        blocks[LocalBlockPosition::new(1, 1, 0).get_array_index()] = tall_grass;
+
+        let downsample = |mode| downsample_cell(&blocks, &block_registry, UVec3::ZERO, 2, mode);
+        assert_eq!(downsample(DownsampleMode::Majority), stone);
+        assert_eq!(downsample(DownsampleMode::TopSurface), snow);
+
+        // only the snow is in the top half of the 2x2x2 cell above
+        let above = |mode| downsample_cell(&blocks, &block_registry, UVec3::Y, 2, mode);
+        assert_eq!(above(DownsampleMode::Majority), BLOCK_AIR);
+        assert_eq!(above(DownsampleMode::TopSurface), snow);
+    }
+
+    #[test]
+    fn lod_rings() {
+        let lod_settings = LodSettings::default();
+        let camera_pos = Vec3::splat(16.0);
+
+        assert_eq!(
+            lod_settings.level_for_chunk(ChunkPosition::ZERO, camera_pos),
+            LodLevel::FULL
+        );
+        assert_eq!(
+            lod_settings.level_for_chunk(ChunkPosition::new(10, 0, 0), camera_pos),
+            LodLevel(1)
+        );
+        assert_eq!(
+            lod_settings.level_for_chunk(ChunkPosition::new(0, 0, -100), camera_pos),
+            LodLevel(3)
+        );
+
+        // full resolution chunks have skirts only on the sides facing the next ring
+        let lod = lod_settings.mesh_lod_for_chunk(ChunkPosition::new(7, 0, 0), camera_pos);
+        assert_eq!(lod.level, LodLevel::FULL);
+        assert_eq!(lod.skirts, 1 << 0);
+
+        let lod = lod_settings.mesh_lod_for_chunk(ChunkPosition::new(12, 0, 0), camera_pos);
+        assert_eq!(lod.skirts, ChunkMeshLod::ALL_SIDES);
+    }
+}
@@ -4,7 +4,11 @@ use self::face_dir::*;
 use super::vertex::TerrainVertex;
 use crate::{
     terrain::{
-        block::{model::BlockFace, BlockId, BLOCKS},
+        block::{
+            model::{fluid_height, BlockBox, BlockFace, BlockModel},
+            registry::BlockRegistry,
+            Block, BlockId, BLOCK_AIR,
+        },
         chunk::{
             light_store::ChunkLightStore,
             side::{ChunkSideFaces, ChunkSideLight},
@@ -31,6 +35,24 @@ pub struct ChunkMeshInput<'a> {
     pub surrounding_sides_faces: &'a [Option<ChunkSideFaces>],
     /// Light data on the sides of the surrounding chunks
     pub surrounding_sides_light: &'a [Option<ChunkSideLight>],
+    /// Registry used to look up the models of the blocks
+    pub block_registry: &'a BlockRegistry,
+    /// Range of the coordinates of the cells to create faces for on each axis, where the end is
+    /// exclusive. Cells outside the range are only used to decide which faces are visible and
+    /// to sample light
+    pub cell_range: (u32, u32),
+}
+
+/// Cell range covering the whole chunk
+pub const FULL_CELL_RANGE: (u32, u32) = (0, CHUNK_SIZE_U32);
+
+/// Vertices of a chunk mesh, split by how they must be drawn
+#[derive(Clone, Debug, Default)]
+pub struct ChunkMeshVertices {
+    /// Vertices of opaque and cutout blocks, which can be drawn in any order
+    pub opaque: Vec<TerrainVertex>,
+    /// Vertices of translucent blocks, which must be drawn after the opaque geometry
+    pub translucent: Vec<TerrainVertex>,
 }
 
 /// Creates the vertices for a chunk mesh where faces inside the volume are skipped but no
@@ -39,15 +61,18 @@ pub struct ChunkMeshInput<'a> {
 /// Compared to `mesh_greedy`, meshing is much faster but the resulting meshes
 /// are more complex and therefore slower to render
 #[allow(unused)]
-pub fn mesh_culled(input: ChunkMeshInput) -> Vec<TerrainVertex> {
-    let mut vertices = Vec::new();
+pub fn mesh_culled(input: ChunkMeshInput) -> ChunkMeshVertices {
+    let mut vertices = ChunkMeshVertices::default();
 
-    add_visible_faces::<PosX>(&mut vertices, input);
-    add_visible_faces::<PosY>(&mut vertices, input);
-    add_visible_faces::<PosZ>(&mut vertices, input);
-    add_visible_faces::<NegX>(&mut vertices, input);
-    add_visible_faces::<NegY>(&mut vertices, input);
-    add_visible_faces::<NegZ>(&mut vertices, input);
+    add_visible_faces::<PosX>(&mut vertices.opaque, input);
+    add_visible_faces::<PosY>(&mut vertices.opaque, input);
+    add_visible_faces::<PosZ>(&mut vertices.opaque, input);
+    add_visible_faces::<NegX>(&mut vertices.opaque, input);
+    add_visible_faces::<NegY>(&mut vertices.opaque, input);
+    add_visible_faces::<NegZ>(&mut vertices.opaque, input);
+
+    add_partial_geometry(&mut vertices, input);
+    add_translucent_faces(&mut vertices.translucent, input);
 
     vertices
 }
@@ -57,19 +82,36 @@ pub fn mesh_culled(input: ChunkMeshInput) -> Vec<TerrainVertex> {
 /// Compared to `culled`, meshing is much slower but the resulting meshes
 /// are simpler and therefore faster to render
 #[allow(unused)]
-pub fn mesh_greedy(input: ChunkMeshInput) -> Vec<TerrainVertex> {
-    let mut vertices = Vec::new();
+pub fn mesh_greedy(input: ChunkMeshInput) -> ChunkMeshVertices {
+    let mut vertices = ChunkMeshVertices::default();
+
+    add_greedy_merged_faces::<PosX>(&mut vertices.opaque, input);
+    add_greedy_merged_faces::<PosY>(&mut vertices.opaque, input);
+    add_greedy_merged_faces::<PosZ>(&mut vertices.opaque, input);
+    add_greedy_merged_faces::<NegX>(&mut vertices.opaque, input);
+    add_greedy_merged_faces::<NegY>(&mut vertices.opaque, input);
+    add_greedy_merged_faces::<NegZ>(&mut vertices.opaque, input);
+
+    // partial faces are added separately so that they are never merged with whole faces
+    add_partial_geometry(&mut vertices, input);
 
-    add_greedy_merged_faces::<PosX>(&mut vertices, input);
-    add_greedy_merged_faces::<PosY>(&mut vertices, input);
-    add_greedy_merged_faces::<PosZ>(&mut vertices, input);
-    add_greedy_merged_faces::<NegX>(&mut vertices, input);
-    add_greedy_merged_faces::<NegY>(&mut vertices, input);
-    add_greedy_merged_faces::<NegZ>(&mut vertices, input);
+    // translucent faces are never merged, as they are few and merging them would make the faces
+    // between identical translucent blocks harder to cull
+    add_translucent_faces(&mut vertices.translucent, input);
 
     vertices
 }
 
+/// Returns the face of the block in the given direction which is drawn with the opaque geometry.
+/// The faces of translucent blocks are added separately by `add_translucent_faces`
+fn opaque_geometry_face(block: &Block, face_index: FaceIndex) -> Option<BlockFace> {
+    if block.is_translucent() {
+        None
+    } else {
+        block.model.face(face_index)
+    }
+}
+
 /// Decides whether the two faces can be merged
 fn can_merge_faces<Dir>(first: Option<BlockFace>, second: Option<BlockFace>) -> bool
 where
@@ -86,7 +128,7 @@ fn add_face<Dir>(
     vertices: &mut Vec<TerrainVertex>,
     origin: Vec3,
     size: Vec2,
-    texture_index: usize,
+    face: BlockFace,
     light_data: FaceLightData,
 ) where
     Dir: FaceDir,
@@ -112,42 +154,376 @@ fn add_face<Dir>(
             })
             .map(|i| TerrainVertex {
                 position: (origin + vertex_offsets[i]).to_array(),
-                uv: uvs[i],
-                texture_index: texture_index as u32,
+                uv: face.rotate_uv(Vec2::from(uvs[i])).to_array(),
+                texture_index: face.texture_index as u32,
                 light: (Dir::SHADING * light_data.0[Dir::LIGHT_INDICES[i]]).to_array(),
             }),
     );
 }
 
+/// Add the geometry of blocks with partial models (see `BlockModel::has_partial_geometry`).
+/// Faces which cover a whole side of the cell are skipped, since they are added with the other
+/// whole faces by `add_visible_faces`, `add_greedy_merged_faces` or `add_translucent_faces`
+fn add_partial_geometry(mesh_vertices: &mut ChunkMeshVertices, input: ChunkMeshInput) {
+    for (block_index, &block_id) in input.blocks.iter().enumerate() {
+        let block = &input.block_registry[block_id];
+        let block_model = &block.model;
+        if !block_model.has_partial_geometry() {
+            continue;
+        }
+
+        let block_pos = LocalBlockPosition::from_array_index(block_index);
+        if !is_in_cell_range(input, block_pos) {
+            continue;
+        }
+        let vertices = if block.is_translucent() {
+            &mut mesh_vertices.translucent
+        } else {
+            &mut mesh_vertices.opaque
+        };
+
+        match block_model {
+            BlockModel::Cross(face) => {
+                add_cross_faces(vertices, input, block_pos, face.texture_index);
+                continue;
+            }
+            BlockModel::Fluid { level, face } => {
+                add_fluid_geometry(vertices, input, block_pos, block_id, *level, *face);
+                continue;
+            }
+            _ => (),
+        }
+
+        for block_box in block_model.boxes() {
+            add_box_face::<PosX>(vertices, input, block_pos, block_id, block_box);
+            add_box_face::<PosY>(vertices, input, block_pos, block_id, block_box);
+            add_box_face::<PosZ>(vertices, input, block_pos, block_id, block_box);
+            add_box_face::<NegX>(vertices, input, block_pos, block_id, block_box);
+            add_box_face::<NegY>(vertices, input, block_pos, block_id, block_box);
+            add_box_face::<NegZ>(vertices, input, block_pos, block_id, block_box);
+        }
+    }
+}
+
+/// Add the face of a box within a partial block model in the given direction, unless it is
+/// hidden by the neighbouring block or covers the whole side of the cell
+fn add_box_face<Dir>(
+    vertices: &mut Vec<TerrainVertex>,
+    input: ChunkMeshInput,
+    block_pos: LocalBlockPosition,
+    block_id: BlockId,
+    block_box: &BlockBox,
+) where
+    Dir: FaceDir,
+{
+    let block_model = &input.block_registry[block_id].model;
+    let Some(face) = block_model.box_face(Dir::FACE_INDEX) else {
+        return;
+    };
+
+    let is_on_cell_side = is_box_face_on_cell_side::<Dir>(block_box);
+
+    if is_on_cell_side
+        && (block_model.face(Dir::FACE_INDEX).is_some()
+            || !is_side_visible::<Dir>(input, block_pos, block_id))
+    {
+        return;
+    }
+
+    add_box_quad::<Dir>(vertices, input, block_pos, block_box, face, is_on_cell_side);
+}
+
+/// True if the face of the box in the given direction lies on the side of the cell
+fn is_box_face_on_cell_side<Dir>(block_box: &BlockBox) -> bool
+where
+    Dir: FaceDir,
+{
+    let axis = Dir::rotate_vec3(Vec3::Z);
+
+    if Dir::NEGATIVE {
+        block_box.min.dot(axis) == 0.0
+    } else {
+        block_box.max.dot(axis) == 1.0
+    }
+}
+
+/// Add the face of a box in the given direction to the mesh
+fn add_box_quad<Dir>(
+    vertices: &mut Vec<TerrainVertex>,
+    input: ChunkMeshInput,
+    block_pos: LocalBlockPosition,
+    block_box: &BlockBox,
+    face: BlockFace,
+    is_on_cell_side: bool,
+) where
+    Dir: FaceDir,
+{
+    let axis = Dir::rotate_vec3(Vec3::Z);
+    let axis_u = Dir::rotate_vec3(Vec3::X);
+    let axis_v = Dir::rotate_vec3(Vec3::Y);
+
+    let extent = block_box.max - block_box.min;
+    let size = Vec2::new(extent.dot(axis_u), extent.dot(axis_v));
+
+    // `Dir::vertices` places the face at 0 on the axis for negative directions and 1 for positive
+    // directions
+    let depth = if Dir::NEGATIVE {
+        block_box.min.dot(axis)
+    } else {
+        block_box.max.dot(axis) - 1.0
+    };
+    let origin = block_box.min * (Vec3::ONE - axis) + axis * depth;
+
+    // offset the texture coordinates so that the texture lines up with whole faces
+    let uv_offset = Vec2::new(block_box.min.dot(axis_u), 1.0 - block_box.max.dot(axis_v));
+
+    // faces inside the cell are lit by the light in the cell itself
+    let light_data = if is_on_cell_side {
+        interpolate_light_for_face::<Dir>(input, block_pos)
+    } else {
+        FaceLightData([sample_light_at(input, block_pos, IVec3::ZERO, Dir::FACE_INDEX, None); 4])
+    };
+
+    let vertex_offsets = Dir::vertices(size);
+    let uvs = [[0.0, size.y], [size.x, size.y], [size.x, 0.0], [0.0, 0.0]];
+    let flipped = should_flip_quad(&light_data);
+
+    vertices.extend(
+        (0..4)
+            .map(|i| if flipped { (i + 1) & 3 } else { i })
+            .map(|i| TerrainVertex {
+                position: (block_pos.as_uvec3().as_vec3()
+                    + origin
+                    + vertex_offsets[i]
+                    + input.translation)
+                    .to_array(),
+                uv: face.rotate_uv(Vec2::from(uvs[i]) + uv_offset).to_array(),
+                texture_index: face.texture_index as u32,
+                light: (Dir::SHADING * light_data.0[Dir::LIGHT_INDICES[i]]).to_array(),
+            }),
+    );
+}
+
+/// Add the two diagonal quads of a cross model. Each quad is added once for each side, since
+/// both sides are visible
+fn add_cross_faces(
+    vertices: &mut Vec<TerrainVertex>,
+    input: ChunkMeshInput,
+    block_pos: LocalBlockPosition,
+    texture_index: usize,
+) {
+    const QUADS: [[Vec3; 4]; 2] = [
+        [
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 1.0),
+            Vec3::new(1.0, 1.0, 1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        ],
+        [
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 1.0, 1.0),
+            Vec3::new(1.0, 1.0, 0.0),
+        ],
+    ];
+    const UVS: [[f32; 2]; 4] = [[0.0, 1.0], [1.0, 1.0], [1.0, 0.0], [0.0, 0.0]];
+
+    let light = sample_light_at(input, block_pos, IVec3::ZERO, FaceIndex::POS_Y, None);
+    let origin = block_pos.as_uvec3().as_vec3() + input.translation;
+
+    for quad in QUADS {
+        // front side, then back side with the opposite winding
+        for order in [[0, 1, 2, 3], [1, 0, 3, 2]] {
+            vertices.extend(order.map(|i| TerrainVertex {
+                position: (origin + quad[i]).to_array(),
+                uv: UVS[i],
+                texture_index: texture_index as u32,
+                light: light.to_array(),
+            }));
+        }
+    }
+}
+
+/// Add the faces of a fluid block. The surface of the fluid is lowered according to its level,
+/// unless the same fluid is above it. Faces against the same fluid are skipped where the other
+/// fluid is at least as high
+fn add_fluid_geometry(
+    vertices: &mut Vec<TerrainVertex>,
+    input: ChunkMeshInput,
+    block_pos: LocalBlockPosition,
+    block_id: BlockId,
+    level: u8,
+    face: BlockFace,
+) {
+    let block = &input.block_registry[block_id];
+    let is_same_fluid = |neighbour_id: Option<BlockId>| {
+        neighbour_id
+            .is_some_and(|neighbour_id| block.is_same_fluid(&input.block_registry[neighbour_id]))
+    };
+
+    let height = if is_same_fluid(get_neighbour::<PosY>(input, block_pos)) {
+        1.0
+    } else {
+        fluid_height(level)
+    };
+    let block_box = BlockBox::new(Vec3::ZERO, Vec3::new(1.0, height, 1.0));
+
+    macro_rules! add_fluid_face {
+        ($dir:ty) => {
+            let is_on_cell_side = is_box_face_on_cell_side::<$dir>(&block_box);
+            let neighbour_id = get_neighbour::<$dir>(input, block_pos);
+
+            // a side face is hidden by the same fluid if the fluid is at least as high there
+            let hidden_by_fluid =
+                match &input.block_registry[neighbour_id.unwrap_or(BLOCK_AIR)].model {
+                    BlockModel::Fluid {
+                        level: neighbour_level,
+                        ..
+                    } if is_same_fluid(neighbour_id) => {
+                        <$dir>::NORMAL.y != 0 || fluid_height(*neighbour_level) >= height
+                    }
+                    _ => false,
+                };
+
+            if !is_on_cell_side
+                || (is_side_visible::<$dir>(input, block_pos, block_id) && !hidden_by_fluid)
+            {
+                add_box_quad::<$dir>(
+                    vertices,
+                    input,
+                    block_pos,
+                    &block_box,
+                    face,
+                    is_on_cell_side,
+                );
+            }
+        };
+    }
+
+    add_fluid_face!(PosX);
+    add_fluid_face!(PosY);
+    add_fluid_face!(PosZ);
+    add_fluid_face!(NegX);
+    add_fluid_face!(NegY);
+    add_fluid_face!(NegZ);
+}
+
+/// Returns the ID of the block neighbouring the given position in the given direction, or None
+/// if it is in a chunk which is not loaded
+fn get_neighbour<Dir>(input: ChunkMeshInput, block_pos: LocalBlockPosition) -> Option<BlockId>
+where
+    Dir: FaceDir,
+{
+    if let Some(neighbour_pos) = block_pos.try_add(Dir::NORMAL) {
+        Some(input.blocks[neighbour_pos.get_array_index()])
+    } else {
+        let pos = block_pos.as_uvec3();
+        let index_in_layer = (CHUNK_SIZE_U32 * pos.dot(Dir::rotate_uvec3(UVec3::Y))
+            + pos.dot(Dir::rotate_uvec3(UVec3::X))) as usize;
+
+        input.surrounding_sides_faces[Dir::FACE_INDEX.as_usize()]
+            .as_ref()
+            .map(|side| side.blocks[index_in_layer])
+    }
+}
+
+/// True if the face of the block at the given position in the given direction is not hidden by a
+/// whole face of the neighbouring block, or by an identical translucent block
+fn is_side_visible<Dir>(
+    input: ChunkMeshInput,
+    block_pos: LocalBlockPosition,
+    block_id: BlockId,
+) -> bool
+where
+    Dir: FaceDir,
+{
+    let Some(neighbour_id) = get_neighbour::<Dir>(input, block_pos) else {
+        return true;
+    };
+
+    let neighbour_visible = input.block_registry[neighbour_id]
+        .occluding_face(Dir::OPPOSITE_FACE_INDEX)
+        .is_none();
+
+    neighbour_visible
+        && !(neighbour_id == block_id && input.block_registry[block_id].is_translucent())
+}
+
+/// Add the whole faces of translucent blocks which are not hidden by their neighbours
+fn add_translucent_faces(vertices: &mut Vec<TerrainVertex>, input: ChunkMeshInput) {
+    for (block_index, &block_id) in input.blocks.iter().enumerate() {
+        if !input.block_registry[block_id].is_translucent() {
+            continue;
+        }
+
+        let block_pos = LocalBlockPosition::from_array_index(block_index);
+        if !is_in_cell_range(input, block_pos) {
+            continue;
+        }
+
+        add_translucent_face::<PosX>(vertices, input, block_pos, block_id);
+        add_translucent_face::<PosY>(vertices, input, block_pos, block_id);
+        add_translucent_face::<PosZ>(vertices, input, block_pos, block_id);
+        add_translucent_face::<NegX>(vertices, input, block_pos, block_id);
+        add_translucent_face::<NegY>(vertices, input, block_pos, block_id);
+        add_translucent_face::<NegZ>(vertices, input, block_pos, block_id);
+    }
+}
+
+/// Add the whole face of a translucent block in the given direction, if it is visible
+fn add_translucent_face<Dir>(
+    vertices: &mut Vec<TerrainVertex>,
+    input: ChunkMeshInput,
+    block_pos: LocalBlockPosition,
+    block_id: BlockId,
+) where
+    Dir: FaceDir,
+{
+    let Some(face) = input.block_registry[block_id].model.face(Dir::FACE_INDEX) else {
+        return;
+    };
+    if !is_side_visible::<Dir>(input, block_pos, block_id) {
+        return;
+    }
+
+    add_face::<Dir>(
+        vertices,
+        block_pos.as_uvec3().as_vec3() + input.translation,
+        Vec2::ONE,
+        face,
+        interpolate_light_for_face::<Dir>(input, block_pos),
+    );
+}
+
 /// Add all visible faces for the given face direction
 fn add_visible_faces<Dir>(vertices: &mut Vec<TerrainVertex>, input: ChunkMeshInput)
 where
     Dir: FaceDir,
 {
-    for pos_parallel_x in 0..CHUNK_SIZE_U32 {
-        for pos_parallel_y in 0..CHUNK_SIZE_U32 {
+    let (min, max) = input.cell_range;
+    let first_layer_visible = first_layer_visibility::<Dir>(input);
+
+    for pos_parallel_x in min..max {
+        for pos_parallel_y in min..max {
             let index_in_layer = (CHUNK_SIZE_U32 * pos_parallel_y + pos_parallel_x) as usize;
-            let mut visible = input.surrounding_sides_faces[Dir::FACE_INDEX.as_usize()]
-                .as_ref()
-                .map(|side| side.faces[index_in_layer])
-                .unwrap_or(true);
+            let mut visible = first_layer_visible[index_in_layer];
 
-            for pos_perpendicular in 0..CHUNK_SIZE_U32 {
+            for pos_perpendicular in 0..(max - min) {
                 let pos_in_chunk = Dir::rotate_uvec3(UVec3::new(
                     pos_parallel_x,
                     pos_parallel_y,
                     // iterate backwards through the chunk
                     if Dir::NEGATIVE {
-                        pos_perpendicular
+                        min + pos_perpendicular
                     } else {
-                        (CHUNK_SIZE_U32 - 1) - pos_perpendicular
+                        (max - 1) - pos_perpendicular
                     },
                 ));
 
                 let block_id = input.blocks[uvec3_to_chunk_index(pos_in_chunk)];
-                let block_model = &BLOCKS[block_id.0 as usize].model;
+                let block = &input.block_registry[block_id];
 
-                let face = block_model.face(Dir::FACE_INDEX);
+                let face = opaque_geometry_face(block, Dir::FACE_INDEX);
                 if let Some(face) = face {
                     if visible {
                         let light_data = interpolate_light_for_face::<Dir>(
@@ -159,18 +535,64 @@ where
                             vertices,
                             pos_in_chunk.as_vec3() + input.translation,
                             Vec2::ONE,
-                            face.texture_index,
+                            face,
                             light_data,
                         );
                     }
                 }
 
-                visible = block_model.face(Dir::OPPOSITE_FACE_INDEX).is_none();
+                visible = block.occluding_face(Dir::OPPOSITE_FACE_INDEX).is_none();
             }
         }
     }
 }
 
+/// Returns whether each face in the first layer of faces with the given direction is visible,
+/// indexed by `CHUNK_SIZE * V + U`. This depends on the layer of cells in front of it, which is
+/// either in the neighbouring chunk or outside of the cell range
+fn first_layer_visibility<Dir>(input: ChunkMeshInput) -> [bool; CHUNK_SIZE_SQUARED]
+where
+    Dir: FaceDir,
+{
+    let (min, max) = input.cell_range;
+
+    let layer_in_front = if Dir::NEGATIVE {
+        min.checked_sub(1)
+    } else {
+        Some(max).filter(|&max| max < CHUNK_SIZE_U32)
+    };
+
+    let Some(layer_in_front) = layer_in_front else {
+        return if let Some(side) = &input.surrounding_sides_faces[Dir::FACE_INDEX.as_usize()] {
+            *side.faces
+        } else {
+            [true; CHUNK_SIZE_SQUARED]
+        };
+    };
+
+    let mut visible = [true; CHUNK_SIZE_SQUARED];
+
+    for v in min..max {
+        for u in min..max {
+            let pos = Dir::rotate_uvec3(UVec3::new(u, v, layer_in_front));
+            let block = &input.block_registry[input.blocks[uvec3_to_chunk_index(pos)]];
+
+            visible[(CHUNK_SIZE_U32 * v + u) as usize] =
+                block.occluding_face(Dir::OPPOSITE_FACE_INDEX).is_none();
+        }
+    }
+
+    visible
+}
+
+/// True if the cell at the given position is in the range of cells to create faces for
+fn is_in_cell_range(input: ChunkMeshInput, block_pos: LocalBlockPosition) -> bool {
+    let (min, max) = input.cell_range;
+    let pos = block_pos.as_uvec3();
+
+    pos.min_element() >= min && pos.max_element() < max
+}
+
 /// Greedily merge visible faces with the given direction and add them to the mesh
 fn add_greedy_merged_faces<Dir>(vertices: &mut Vec<TerrainVertex>, input: ChunkMeshInput)
 where
@@ -187,21 +609,18 @@ where
     // this will track whether each face in the next layer is visible
     // a face is visible if the block in the previous layer had no face in
     // the opposite direction
-    let mut visible: [bool; CHUNK_SIZE_SQUARED] =
-        if let Some(side) = &input.surrounding_sides_faces[Dir::FACE_INDEX.as_usize()] {
-            *side.faces
-        } else {
-            [true; CHUNK_SIZE_SQUARED]
-        };
+    let mut visible = first_layer_visibility::<Dir>(input);
+
+    let (min, max) = input.cell_range;
 
     // iterate over each layer of faces we will create
-    for layer_index in 0..CHUNK_SIZE_U32 {
+    for layer_index in 0..(max - min) {
         // position of this layer, moving backwards through the chunk with respect to the face
         // direction
         let layer_pos = if Dir::NEGATIVE {
-            layer_index
+            min + layer_index
         } else {
-            (CHUNK_SIZE_U32 - 1) - layer_index
+            (max - 1) - layer_index
         };
 
         // this will track which faces have already been merged with another
@@ -213,8 +632,8 @@ where
         let mut interpolated_light_cache = [None; CHUNK_SIZE_SQUARED];
 
         // iterate over each block in the layer
-        for original_v in 0..CHUNK_SIZE_U32 {
-            for original_u in 0..CHUNK_SIZE_U32 {
+        for original_v in min..max {
+            for original_u in min..max {
                 // index of this block in the current layer
                 let original_index = (original_v * CHUNK_SIZE_U32 + original_u) as usize;
 
@@ -227,8 +646,8 @@ where
                 let original_pos = Dir::rotate_uvec3(UVec3::new(original_u, original_v, layer_pos));
 
                 let original_id = input.blocks[uvec3_to_chunk_index(original_pos) as usize];
-                let original_model = &BLOCKS[original_id.0 as usize].model;
-                let original_face = original_model.face(Dir::FACE_INDEX);
+                let original_block = &input.block_registry[original_id];
+                let original_face = opaque_geometry_face(original_block, Dir::FACE_INDEX);
                 let original_visible = visible[original_index];
 
                 let original_light_data = if let Some(cached_light_data) =
@@ -242,7 +661,9 @@ where
                 };
 
                 // update `visible` for the next layer
-                visible[original_index] = original_model.face(Dir::OPPOSITE_FACE_INDEX).is_none();
+                visible[original_index] = original_block
+                    .occluding_face(Dir::OPPOSITE_FACE_INDEX)
+                    .is_none();
 
                 // skip if there is no face or the face is invisible
                 if original_face.is_none() || !original_visible {
@@ -252,7 +673,7 @@ where
 
                 // march to see how many faces can be merged in the U direction
                 let mut face_size = UVec2::ONE;
-                for merge_candidate_u in (original_u + 1)..CHUNK_SIZE_U32 {
+                for merge_candidate_u in (original_u + 1)..max {
                     let (can_merge, next_visible) = consider_merge_candidate::<Dir>(
                         input,
                         &visible,
@@ -284,7 +705,7 @@ where
                 }
 
                 // march to see how many faces can be merged in the V direction
-                'v: for merge_candidate_v in (original_v + 1)..CHUNK_SIZE_U32 {
+                'v: for merge_candidate_v in (original_v + 1)..max {
                     // bit flags for whether the block adjacent to a block being considered for
                     // merging will be visible
                     // this avoids having to check the model again once it has been decided
@@ -336,7 +757,7 @@ where
                     vertices,
                     original_pos.as_vec3() + input.translation,
                     face_size.as_vec2(),
-                    original_face.texture_index,
+                    original_face,
                     original_light_data,
                 );
             }
@@ -367,12 +788,12 @@ where
     let merge_candidate_index = (CHUNK_SIZE_U32 * merge_candidate_v + merge_candidate_u) as usize;
 
     let merge_candidate_id = input.blocks[uvec3_to_chunk_index(merge_candidate_pos) as usize];
-    let merge_candidate_model = &BLOCKS[merge_candidate_id.0 as usize].model;
-    let merge_candidate_face = merge_candidate_model.face(Dir::FACE_INDEX);
+    let merge_candidate_block = &input.block_registry[merge_candidate_id];
+    let merge_candidate_face = opaque_geometry_face(merge_candidate_block, Dir::FACE_INDEX);
     let merge_candidate_visible = visible[merge_candidate_index];
 
-    let next_visible = merge_candidate_model
-        .face(Dir::OPPOSITE_FACE_INDEX)
+    let next_visible = merge_candidate_block
+        .occluding_face(Dir::OPPOSITE_FACE_INDEX)
         .is_none();
 
     let can_merge = can_merge_faces::<Dir>(Some(original_face), merge_candidate_face)
@@ -539,9 +960,9 @@ fn sample_light_at(
     let (emitted_light, skylight) = if let Some(block_pos) = block_pos.try_add(block_offset) {
         opaque.map(|p| {
             let block_id = input.blocks[block_pos.get_array_index()];
-            let block = &BLOCKS[block_id.as_usize()];
+            let block = &input.block_registry[block_id];
 
-            *p = block.model.is_opaque();
+            *p = block.is_opaque();
         });
 
         (
@@ -867,3 +1288,131 @@ mod face_dir {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::{block::registry::test_registry, chunk::CHUNK_SIZE_CUBED};
+
+    fn test_block_registry() -> BlockRegistry {
+        test_registry(&[
+            r#"
+                    name = "stone"
+                    model = { type = "full_block", textures = { all = "stone" } }
+                "#,
+            r#"
+                    name = "glass"
+                    render_layer = "translucent"
+                    model = { type = "full_block", textures = { all = "glass" } }
+                "#,
+            r#"
+                    name = "slab"
+                    model = { type = "slab", half = "bottom", textures = { all = "stone" } }
+                "#,
+            r#"
+                    name = "stairs"
+                    model = { type = "stairs", facing = "neg_x", textures = { all = "stone" } }
+                "#,
+        ])
+    }
+
+    /// Mesh a chunk of air containing the given blocks with `mesh_culled`, returning the number of
+    /// opaque and translucent faces
+    fn count_faces(block_registry: &BlockRegistry, blocks: &[(UVec3, &str)]) -> (usize, usize) {
+        let mut chunk_blocks = vec![BLOCK_AIR; CHUNK_SIZE_CUBED];
+        for &(pos, name) in blocks {
+            chunk_blocks[uvec3_to_chunk_index(pos)] = block_registry.get_id(name).unwrap();
+        }
+
+        let light = ChunkLightStore::new();
+        let no_sides: [Option<ChunkSideFaces>; 6] = Default::default();
+        let no_light: [Option<ChunkSideLight>; 6] = Default::default();
+        let vertices = mesh_culled(ChunkMeshInput {
+            blocks: &chunk_blocks,
+            light: &light,
+            translation: Vec3::ZERO,
+            surrounding_sides_faces: &no_sides,
+            surrounding_sides_light: &no_light,
+            block_registry,
+            cell_range: FULL_CELL_RANGE,
+        });
+
+        (vertices.opaque.len() / 4, vertices.translucent.len() / 4)
+    }
+
+    const POS: UVec3 = UVec3::new(5, 5, 5);
+
+    #[test]
+    fn translucent_culling() {
+        let block_registry = test_block_registry();
+
+        assert_eq!(count_faces(&block_registry, &[(POS, "glass")]), (0, 6));
+
+        // the faces between identical translucent blocks are hidden
+        assert_eq!(
+            count_faces(
+                &block_registry,
+                &[(POS, "glass"), (POS + UVec3::X, "glass")]
+            ),
+            (0, 10)
+        );
+
+        // stone is seen through glass, but hides the face of the glass against it
+        assert_eq!(
+            count_faces(
+                &block_registry,
+                &[(POS, "glass"), (POS + UVec3::X, "stone")]
+            ),
+            (6, 5)
+        );
+        assert_eq!(
+            count_faces(
+                &block_registry,
+                &[(POS, "glass"), (POS + UVec3::Y, "stone")]
+            ),
+            (6, 5)
+        );
+    }
+
+    #[test]
+    fn partial_geometry_culling() {
+        let block_registry = test_block_registry();
+
+        assert_eq!(count_faces(&block_registry, &[(POS, "slab")]), (6, 0));
+
+        // the half-height side of the slab is hidden by the stone, but doesn't hide the stone
+        assert_eq!(
+            count_faces(&block_registry, &[(POS, "slab"), (POS + UVec3::X, "stone")]),
+            (11, 0)
+        );
+
+        // the top of a slab is inside the cell, so neither it nor the stone above it is hidden
+        assert_eq!(
+            count_faces(&block_registry, &[(POS, "slab"), (POS + UVec3::Y, "stone")]),
+            (12, 0)
+        );
+
+        // the whole bottom face of the slab and the top of the stone below hide each other
+        assert_eq!(
+            count_faces(&block_registry, &[(POS, "slab"), (POS - UVec3::Y, "stone")]),
+            (10, 0)
+        );
+
+        // the back of the stairs is a whole face, unlike the front
+        let (stairs_faces, _) = count_faces(&block_registry, &[(POS, "stairs")]);
+        assert_eq!(
+            count_faces(
+                &block_registry,
+                &[(POS, "stairs"), (POS - UVec3::X, "stone")]
+            ),
+            (stairs_faces + 4, 0)
+        );
+        assert_eq!(
+            count_faces(
+                &block_registry,
+                &[(POS, "stairs"), (POS + UVec3::X, "stone")]
+            ),
+            (stairs_faces + 5, 0)
+        );
+    }
+}
@@ -1,10 +1,16 @@
-use std::time::Instant;
+use std::{path::Path, time::Instant};
 
 use generational_arena::Index;
-use glam::Vec3;
+use glam::{IVec3, Vec3};
 use itertools::Itertools;
 
-use self::{batching::ChunkBatches, vertex::TerrainVertex, visibility_search::visibility_search};
+use self::{
+    batching::{BatchVertexBuffer, ChunkBatches, CHUNK_BATCH_TOTAL_SIZE},
+    lod::{ChunkMeshLod, LodSettings},
+    meshing::ChunkMeshVertices,
+    vertex::TerrainVertex,
+    visibility_search::visibility_search,
+};
 use super::{frustum_culling::FrustumCullingRegions, Renderer};
 use crate::{
     core::{
@@ -19,6 +25,7 @@ use crate::{
         },
     },
     terrain::{
+        block::registry::BlockRegistry,
         chunk::Chunk,
         event::TerrainEvent,
         load_area::LoadArea,
@@ -29,6 +36,8 @@ use crate::{
 };
 
 mod batching;
+pub mod export;
+pub mod lod;
 mod meshing;
 mod vertex;
 mod visibility_search;
@@ -42,32 +51,42 @@ pub struct TerrainRenderer {
     frame_last_drawn: Vec<usize>,
     culling_mode: ChunkCullingMode,
     terrain_pipeline: wgpu::RenderPipeline,
+    translucent_terrain_pipeline: wgpu::RenderPipeline,
     texture_bind_group: wgpu::BindGroup,
 }
 
 impl TerrainRenderer {
-    pub const MIP_LEVEL_COUNT: u32 = 4;
+    /// Directory containing the block textures referenced by the block registry
+    pub const BLOCK_TEXTURE_PATH: &str = "assets/image/block";
 
     pub fn new(
         wgpu: &WgpuContext,
         common_uniforms_bind_group_layout: &wgpu::BindGroupLayout,
         load_area: &LoadArea,
+        block_registry: &BlockRegistry,
         cull_mode: ChunkCullingMode,
+        lod_settings: LodSettings,
+        mip_level_count: u32,
     ) -> Self {
         // TODO load texture and shader using proper asset system rather than doing it here
+        // the layers of the texture array are ordered by `BlockFace::texture_index`
+        let texture_paths = block_registry
+            .texture_names()
+            .iter()
+            .map(|texture_name| {
+                Path::new(Self::BLOCK_TEXTURE_PATH)
+                    .join(texture_name)
+                    .with_extension("png")
+            })
+            .collect_vec();
+
         let texture_array = ArrayTexture::from_files(
             &wgpu.device,
             &wgpu.queue,
-            &[
-                "assets/image/block/dirt.png",
-                "assets/image/block/grass_side.png",
-                "assets/image/block/grass_top.png",
-                "assets/image/block/wood.png",
-                "assets/image/block/lamp_orange.png",
-            ],
+            &texture_paths,
             image::ImageFormat::Png,
             &TextureConfig {
-                mip_level_count: Self::MIP_LEVEL_COUNT,
+                mip_level_count,
                 ..Default::default()
             },
         )
@@ -92,7 +111,7 @@ impl TerrainRenderer {
             &wgpu.device,
             texture_array.texture(),
             texture_array.size().z,
-            Self::MIP_LEVEL_COUNT,
+            mip_level_count,
         );
         wgpu.queue.submit(std::iter::once(mip_encoder.finish()));
 
@@ -148,7 +167,27 @@ impl TerrainRenderer {
             //.with_polygon_mode(wgpu::PolygonMode::Line)
             .build(&wgpu.device);
 
-        let chunk_batches = ChunkBatches::new(wgpu, load_area, batch_bind_group_layout);
+        // translucent geometry is blended over the opaque geometry and doesn't write depth, so
+        // that translucent faces behind other translucent faces are still visible
+        let (translucent_terrain_pipeline, _) = RenderPipelineBuilder::new()
+            .with_label("Translucent Terrain Pipeline")
+            .with_bind_group_layout(&texture_bind_group_layout)
+            .with_bind_group_layout(common_uniforms_bind_group_layout)
+            .with_bind_group_layout(&batch_bind_group_layout)
+            .with_vertex::<TerrainVertex>()
+            .with_vertex_shader(&terrain_shader, "vs_main")
+            .with_fragment_shader(&terrain_shader, "fs_translucent")
+            .with_color_target(
+                wgpu.surface_config.format,
+                Some(wgpu::BlendState::ALPHA_BLENDING),
+                wgpu::ColorWrites::all(),
+            )
+            .with_depth(Renderer::DEPTH_FORMAT, Renderer::DEPTH_COMPARE)
+            .with_depth_write_enabled(false)
+            .build(&wgpu.device);
+
+        let chunk_batches =
+            ChunkBatches::new(wgpu, load_area, batch_bind_group_layout, lod_settings);
 
         let frame_last_drawn = vec![0; chunk_batches.size().product()];
 
@@ -157,10 +196,52 @@ impl TerrainRenderer {
             frame_last_drawn,
             culling_mode: cull_mode,
             terrain_pipeline,
+            translucent_terrain_pipeline,
             texture_bind_group,
         }
     }
 
+    /// Method used to choose which chunks to draw
+    pub fn culling_mode(&self) -> ChunkCullingMode {
+        self.culling_mode
+    }
+
+    pub fn set_culling_mode(&mut self, culling_mode: ChunkCullingMode) {
+        self.culling_mode = culling_mode;
+    }
+
+    /// Change the level of detail of the chunk meshes. Meshes are generated again at their new
+    /// level of detail as they are drawn
+    pub fn set_lod_settings(
+        &mut self,
+        wgpu: &WgpuContext,
+        tasks: &mut Tasks,
+        load_area: &LoadArea,
+        lod_settings: LodSettings,
+    ) {
+        let downsample_mode_changed =
+            self.chunk_batches.lod_settings().downsample_mode != lod_settings.downsample_mode;
+        self.chunk_batches.set_lod_settings(lod_settings);
+
+        // meshes are only generated again when their level of detail changes, so start again
+        // with empty batches to downsample the existing meshes differently
+        if downsample_mode_changed {
+            self.load_area_resized(wgpu, tasks, load_area);
+        }
+    }
+
+    /// Called when the size of the load area being rendered changes, to fit the chunk batches to
+    /// its new size
+    pub fn load_area_resized(
+        &mut self,
+        wgpu: &WgpuContext,
+        tasks: &mut Tasks,
+        load_area: &LoadArea,
+    ) {
+        self.chunk_batches.resize(wgpu, tasks, load_area);
+        self.frame_last_drawn = vec![0; self.chunk_batches.size().product()];
+    }
+
     /// Called once per frame to render the terrain
     pub fn render(
         &mut self,
@@ -184,7 +265,12 @@ impl TerrainRenderer {
                 TerrainEvent::BlockModified(chunk_pos, local_block_pos) => {
                     self.block_modified(chunk_pos, local_block_pos)
                 }
-                TerrainEvent::ChunkLightUpdate(chunk_pos) => self.chunk_modified(chunk_pos),
+                TerrainEvent::ChunkModified(chunk_pos)
+                | TerrainEvent::ChunkLightUpdate(chunk_pos) => self.chunk_modified(chunk_pos),
+                // block entities don't affect the chunk meshes
+                TerrainEvent::BlockEntityCreated(..)
+                | TerrainEvent::BlockEntityModified(..)
+                | TerrainEvent::BlockEntityRemoved(..) => (),
             }
         }
 
@@ -252,14 +338,8 @@ impl TerrainRenderer {
             timestamp_writes: None,
         });
 
-        render_pass.set_pipeline(&self.terrain_pipeline);
-        render_pass.set_bind_group(0, &self.texture_bind_group, &[]);
-        render_pass.set_bind_group(1, &common_uniforms_bind_group, &[]);
-        render_pass.set_index_buffer(
-            self.chunk_batches.shared_index_buffer().slice(..),
-            wgpu::IndexFormat::Uint32,
-        );
-
+        // get the list of chunk batches to be drawn in the same order as the chunks
+        let mut batch_queue = Vec::new();
         for chunk in &render_queue {
             let (batch_pos, _) =
                 ChunkBatches::get_batch_pos_and_chunk_pos_in_batch(&chunk.position());
@@ -269,24 +349,73 @@ impl TerrainRenderer {
                 // don't draw the same chunk batch twice
                 continue;
             }
-            let Some(batch) = self.chunk_batches.get_batch(&batch_pos) else {
-                continue;
-            };
-            if batch.vertex_count() == 0 {
+            if self.chunk_batches.get_batch(&batch_pos).is_none() {
                 continue;
             }
-            let Some(vertex_buffer) = batch.vertex_buffer() else {
+
+            self.frame_last_drawn[batch_index] = time.frame_index();
+            batch_queue.push(batch_pos);
+        }
+
+        render_pass.set_bind_group(0, &self.texture_bind_group, &[]);
+        render_pass.set_bind_group(1, &common_uniforms_bind_group, &[]);
+        render_pass.set_index_buffer(
+            self.chunk_batches.shared_index_buffer().slice(..),
+            wgpu::IndexFormat::Uint32,
+        );
+
+        // draw the opaque geometry front-to-back
+        render_pass.set_pipeline(&self.terrain_pipeline);
+        for batch_pos in &batch_queue {
+            let Some(batch) = self.chunk_batches.get_batch(batch_pos) else {
                 continue;
             };
 
-            self.frame_last_drawn[batch_index] = time.frame_index();
+            Self::draw_batch_vertex_buffer(
+                &mut render_pass,
+                batch.uniform_bind_group(),
+                batch.opaque_vertex_buffer(),
+            );
+        }
+
+        // draw the translucent geometry back-to-front, so that it blends over everything behind it.
+        // Only the visibility search finds the chunks in order of distance, so the batches are
+        // sorted by the distance to their centres
+        let batch_distance_squared = |batch_pos: &IVec3| {
+            let batch_center = (batch_pos.as_vec3() + 0.5) * CHUNK_BATCH_TOTAL_SIZE as f32;
+            batch_center.distance_squared(camera_pos)
+        };
+        batch_queue.sort_by(|a, b| batch_distance_squared(b).total_cmp(&batch_distance_squared(a)));
 
-            render_pass.set_bind_group(2, batch.uniform_bind_group(), &[]);
-            render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
-            render_pass.draw_indexed(0..(batch.index_count() as u32), 0, 0..1);
+        render_pass.set_pipeline(&self.translucent_terrain_pipeline);
+        for batch_pos in &batch_queue {
+            let Some(batch) = self.chunk_batches.get_batch(batch_pos) else {
+                continue;
+            };
+
+            Self::draw_batch_vertex_buffer(
+                &mut render_pass,
+                batch.uniform_bind_group(),
+                batch.translucent_vertex_buffer(),
+            );
         }
     }
 
+    /// Draw one of the vertex buffers of a chunk batch, if it has any vertices
+    fn draw_batch_vertex_buffer<'pass>(
+        render_pass: &mut wgpu::RenderPass<'pass>,
+        uniform_bind_group: &'pass wgpu::BindGroup,
+        vertex_buffer: &'pass BatchVertexBuffer,
+    ) {
+        let Some(buffer) = vertex_buffer.buffer() else {
+            return;
+        };
+
+        render_pass.set_bind_group(2, uniform_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, buffer.slice(..));
+        render_pass.draw_indexed(0..(vertex_buffer.index_count() as u32), 0, 0..1);
+    }
+
     /// Request any necessary mesh updates for the given chunk
     pub fn request_mesh_updates_for_chunk(
         &mut self,
@@ -300,11 +429,24 @@ impl TerrainRenderer {
         let (batch_pos, chunk_pos_in_batch) =
             ChunkBatches::get_batch_pos_and_chunk_pos_in_batch(&chunk.position());
 
+        self.chunk_batches
+            .get_or_repurpose_batch(wgpu, tasks, &batch_pos);
+
+        // swap the level of detail of the mesh when the camera has moved to another ring. The
+        // existing mesh is kept until the new one is ready
+        let lod = self
+            .chunk_batches
+            .get_desired_mesh_lod(chunk.position(), camera_pos);
         let batch = self
             .chunk_batches
-            .get_or_repurpose_batch(wgpu, tasks, &batch_pos);
+            .get_batch(&batch_pos)
+            .expect("batch should exist");
+        let lod_changed = batch
+            .get_chunk_mesh_lod(&chunk_pos_in_batch)
+            .is_some_and(|mesh_lod| mesh_lod != lod);
 
         let remeshing_priority = match batch.get_chunk_mesh_status(&chunk_pos_in_batch) {
+            ChunkMeshStatus::Good if lod_changed => Some(CHUNK_MESH_UPDATE_PRIORITY),
             ChunkMeshStatus::Good | ChunkMeshStatus::Generating(_) => None,
             ChunkMeshStatus::Missing => Some(CHUNK_MESH_GENERATION_PRIORITY),
             ChunkMeshStatus::Outdated => Some(CHUNK_MESH_UPDATE_PRIORITY),
@@ -388,11 +530,13 @@ enum ChunkMeshStatus {
 
 #[derive(Debug)]
 struct ChunkMeshData {
-    pub vertices: Vec<TerrainVertex>,
+    pub vertices: ChunkMeshVertices,
     pub queued_instant: Instant,
+    /// Level of detail the mesh was generated at
+    pub lod: ChunkMeshLod,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ChunkCullingMode {
     CullNone,
     Frustum,
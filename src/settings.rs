@@ -0,0 +1,394 @@
+use std::{
+    cmp::Ordering,
+    fmt::Display,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    core::time::TargetFrameRate,
+    fly_camera,
+    renderer::terrain::lod::LodSettings,
+    util::{size::Size3, DEGREE},
+};
+
+/// Largest horizontal render distance in chunks
+pub const MAX_RENDER_DISTANCE: usize = 64;
+
+/// Largest vertical render distance in chunks
+pub const MAX_VERTICAL_RENDER_DISTANCE: usize = 32;
+
+/// Largest number of worker threads
+pub const MAX_WORKER_THREADS: usize = 64;
+
+/// Largest number of mip levels, which is enough to shrink the 16x16 block textures to one pixel
+pub const MAX_MIP_LEVEL_COUNT: u32 = 5;
+
+/// Settings which are only read at startup, so changing them has no effect until the game is
+/// restarted
+const RESTART_REQUIRED: [&str; 2] = ["graphics.mip_level_count", "performance.worker_threads"];
+
+/// Settings chosen by the player, read from a TOML file at startup.
+/// Missing settings take their default values, and values out of range are clamped
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub graphics: GraphicsSettings,
+    pub controls: ControlSettings,
+    pub performance: PerformanceSettings,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GraphicsSettings {
+    /// Horizontal distance in chunks that terrain is loaded and drawn around the player
+    pub render_distance: usize,
+    /// Vertical distance in chunks that terrain is loaded and drawn around the player
+    pub vertical_render_distance: usize,
+    /// Vertical field of view in degrees
+    pub fov_degrees: f32,
+    /// Most frames drawn each second, or 0 for no limit other than vsync
+    pub max_frame_rate: u32,
+    /// Number of mip levels of the block textures
+    pub mip_level_count: u32,
+    pub lod: LodSettings,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ControlSettings {
+    /// Radians turned for each unit of mouse movement
+    pub mouse_sensitivity: f32,
+    /// Flying speed in blocks per second
+    pub fly_speed: f32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PerformanceSettings {
+    /// Number of threads used for loading, generating and meshing chunks
+    pub worker_threads: usize,
+}
+
+impl Settings {
+    /// Read the settings from a file, using the default settings if it doesn't exist
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SettingsError> {
+        let path = path.as_ref();
+
+        let mut settings: Settings = match fs::read_to_string(path) {
+            Ok(source) => toml::from_str(&source)
+                .map_err(|e| SettingsError::ParseError(path.to_path_buf(), Box::new(e)))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Settings::default(),
+            Err(e) => return Err(SettingsError::IoError(path.to_path_buf(), e)),
+        };
+        settings.clamp();
+
+        Ok(settings)
+    }
+
+    /// Write the settings to a file
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SettingsError> {
+        let path = path.as_ref();
+        let source = toml::to_string(self).map_err(SettingsError::SerializeError)?;
+
+        fs::write(path, source).map_err(|e| SettingsError::IoError(path.to_path_buf(), e))
+    }
+
+    /// Names of every setting, made of the section and the key, such as `graphics.fov_degrees`
+    pub fn names() -> Vec<String> {
+        fn add_names(table: &toml::Table, prefix: &str, names: &mut Vec<String>) {
+            for (key, value) in table {
+                let name = format!("{}{}", prefix, key);
+                match value {
+                    toml::Value::Table(table) => add_names(table, &format!("{}.", name), names),
+                    _ => names.push(name),
+                }
+            }
+        }
+
+        let mut names = Vec::new();
+        add_names(&Settings::default().to_table(), "", &mut names);
+        names
+    }
+
+    /// Returns the value of a setting, formatted as TOML
+    pub fn get(&self, name: &str) -> Option<String> {
+        name.split('.')
+            .try_fold(&toml::Value::Table(self.to_table()), |value, key| {
+                value.as_table()?.get(key)
+            })
+            .filter(|value| !value.is_table())
+            .map(toml::Value::to_string)
+    }
+
+    /// Change a setting to a value written as TOML. Strings don't need to be quoted.
+    /// The value is clamped if it is out of range
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), SettingsError> {
+        let unknown = || SettingsError::UnknownSetting(name.to_string());
+
+        // parse the value as the right hand side of a TOML key, falling back to a string so
+        // that the names of enum variants can be given without quotes
+        let value = toml::from_str::<toml::Table>(&format!("value = {}", value))
+            .ok()
+            .and_then(|mut table| table.remove("value"))
+            .unwrap_or_else(|| toml::Value::String(value.to_string()));
+
+        let mut table = toml::Value::Table(self.to_table());
+        let entry = name
+            .split('.')
+            .try_fold(&mut table, |value, key| value.as_table_mut()?.get_mut(key))
+            .filter(|entry| !entry.is_table())
+            .ok_or_else(unknown)?;
+        *entry = value;
+
+        let mut settings: Settings = table
+            .try_into()
+            .map_err(|e| SettingsError::InvalidValue(name.to_string(), e))?;
+        settings.clamp();
+        *self = settings;
+
+        Ok(())
+    }
+
+    /// Returns whether changes to a setting only take effect once the game is restarted
+    pub fn requires_restart(name: &str) -> bool {
+        RESTART_REQUIRED.contains(&name)
+    }
+
+    /// Size of the load area around the player
+    pub fn load_area_size(&self) -> Size3 {
+        Size3::new(
+            2 * self.graphics.render_distance,
+            2 * self.graphics.vertical_render_distance,
+            2 * self.graphics.render_distance,
+        )
+    }
+
+    /// Vertical field of view in radians
+    pub fn fov_y_radians(&self) -> f32 {
+        self.graphics.fov_degrees * DEGREE
+    }
+
+    pub fn target_frame_rate(&self) -> TargetFrameRate {
+        match self.graphics.max_frame_rate {
+            0 => TargetFrameRate::UnlimitedOrVsync,
+            frame_rate => TargetFrameRate::Limited(frame_rate),
+        }
+    }
+
+    /// Clamp every setting to its allowed range, warning about the values which were changed
+    fn clamp(&mut self) {
+        let graphics = &mut self.graphics;
+        clamp_setting(
+            "graphics.render_distance",
+            &mut graphics.render_distance,
+            1,
+            MAX_RENDER_DISTANCE,
+        );
+        clamp_setting(
+            "graphics.vertical_render_distance",
+            &mut graphics.vertical_render_distance,
+            1,
+            MAX_VERTICAL_RENDER_DISTANCE,
+        );
+        clamp_setting(
+            "graphics.fov_degrees",
+            &mut graphics.fov_degrees,
+            30.0,
+            120.0,
+        );
+        if graphics.max_frame_rate != 0 {
+            clamp_setting(
+                "graphics.max_frame_rate",
+                &mut graphics.max_frame_rate,
+                10,
+                1000,
+            );
+        }
+        clamp_setting(
+            "graphics.mip_level_count",
+            &mut graphics.mip_level_count,
+            1,
+            MAX_MIP_LEVEL_COUNT,
+        );
+
+        // each level of detail must start further away than the one before it
+        let mut min_distance = 1.0;
+        for ring_distance in &mut graphics.lod.ring_distances {
+            clamp_setting(
+                "graphics.lod.ring_distances",
+                ring_distance,
+                min_distance,
+                f32::MAX,
+            );
+            min_distance = *ring_distance;
+        }
+
+        let controls = &mut self.controls;
+        clamp_setting(
+            "controls.mouse_sensitivity",
+            &mut controls.mouse_sensitivity,
+            0.0001,
+            0.1,
+        );
+        clamp_setting("controls.fly_speed", &mut controls.fly_speed, 1.0, 500.0);
+
+        clamp_setting(
+            "performance.worker_threads",
+            &mut self.performance.worker_threads,
+            1,
+            MAX_WORKER_THREADS,
+        );
+    }
+
+    fn to_table(&self) -> toml::Table {
+        toml::Table::try_from(self).expect("settings should be serializable as TOML")
+    }
+}
+
+impl Default for GraphicsSettings {
+    fn default() -> Self {
+        Self {
+            render_distance: 32,
+            vertical_render_distance: 8,
+            fov_degrees: 80.0,
+            max_frame_rate: 0,
+            mip_level_count: 4,
+            lod: LodSettings::default(),
+        }
+    }
+}
+
+impl Default for ControlSettings {
+    fn default() -> Self {
+        Self {
+            mouse_sensitivity: fly_camera::DEFAULT_SENSITIVITY,
+            fly_speed: fly_camera::DEFAULT_SPEED,
+        }
+    }
+}
+
+impl Default for PerformanceSettings {
+    fn default() -> Self {
+        Self { worker_threads: 4 }
+    }
+}
+
+/// Clamp a setting to the range from `min` to `max`, warning if it was out of range. Values which
+/// can't be compared, such as NaN, are set to `min`
+fn clamp_setting<T: PartialOrd + Copy + Display>(name: &str, value: &mut T, min: T, max: T) {
+    let clamped = match ((*value).partial_cmp(&min), (*value).partial_cmp(&max)) {
+        (Some(Ordering::Less) | None, _) => min,
+        (_, Some(Ordering::Greater)) => max,
+        _ => return,
+    };
+
+    log::warn!(
+        "setting {} = {} is out of range, using {}",
+        name,
+        value,
+        clamped
+    );
+    *value = clamped;
+}
+
+/// Errors returned when reading, writing or changing settings
+#[derive(Debug, thiserror::Error)]
+pub enum SettingsError {
+    #[error("io error accessing {0}: {1}")]
+    IoError(PathBuf, io::Error),
+    #[error("error parsing settings {0}: {1}")]
+    ParseError(PathBuf, Box<toml::de::Error>),
+    #[error("error serializing settings: {0}")]
+    SerializeError(toml::ser::Error),
+    #[error("unknown setting `{0}`")]
+    UnknownSetting(String),
+    #[error("invalid value for {0}: {1}")]
+    InvalidValue(String, toml::de::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::terrain::lod::DownsampleMode;
+
+    #[test]
+    fn parse_and_clamp() {
+        let directory =
+            std::env::temp_dir().join(format!("voxels-settings-test-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("settings.toml");
+
+        // a missing file gives the default settings
+        let _ = fs::remove_file(&path);
+        assert_eq!(Settings::load(&path).unwrap(), Settings::default());
+
+        fs::write(
+            &path,
+            r#"
+                [graphics]
+                render_distance = 1000
+                fov_degrees = 90.0
+
+                [graphics.lod]
+                ring_distances = [4.0, 2.0, 12.0]
+                downsample_mode = "top_surface"
+            "#,
+        )
+        .unwrap();
+        let settings = Settings::load(&path).unwrap();
+        assert_eq!(settings.graphics.render_distance, MAX_RENDER_DISTANCE);
+        assert_eq!(settings.graphics.fov_degrees, 90.0);
+        assert_eq!(settings.graphics.lod.ring_distances, [4.0, 4.0, 12.0]);
+        assert_eq!(
+            settings.graphics.lod.downsample_mode,
+            DownsampleMode::TopSurface
+        );
+        assert_eq!(settings.controls, ControlSettings::default());
+
+        // saved settings are read back unchanged
+        settings.save(&path).unwrap();
+        assert_eq!(Settings::load(&path).unwrap(), settings);
+
+        fs::write(&path, "[graphics]\nfov_degrees = \"wide\"").unwrap();
+        assert!(matches!(
+            Settings::load(&path),
+            Err(SettingsError::ParseError(..))
+        ));
+    }
+
+    #[test]
+    fn get_and_set() {
+        let mut settings = Settings::default();
+        assert!(Settings::names().contains(&"graphics.lod.downsample_mode".to_string()));
+        assert_eq!(
+            settings.get("graphics.render_distance").as_deref(),
+            Some("32")
+        );
+        assert_eq!(settings.get("graphics"), None);
+
+        settings.set("graphics.render_distance", "12").unwrap();
+        assert_eq!(settings.graphics.render_distance, 12);
+        settings.set("controls.fly_speed", "10000").unwrap();
+        assert_eq!(settings.controls.fly_speed, 500.0);
+        settings
+            .set("graphics.lod.downsample_mode", "top_surface")
+            .unwrap();
+        assert_eq!(
+            settings.graphics.lod.downsample_mode,
+            DownsampleMode::TopSurface
+        );
+
+        assert!(matches!(
+            settings.set("graphics.brightness", "1"),
+            Err(SettingsError::UnknownSetting(_))
+        ));
+        assert!(matches!(
+            settings.set("graphics.render_distance", "far"),
+            Err(SettingsError::InvalidValue(..))
+        ));
+        assert_eq!(settings.graphics.render_distance, 12);
+    }
+}
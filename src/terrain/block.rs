@@ -1,10 +1,21 @@
 use glam::IVec3;
 
-use self::model::{BlockFace, BlockModel};
+use self::{
+    entity::BlockEntityKind,
+    model::{BlockFace, BlockModel, RenderLayer},
+    state::{BlockProperty, BlockState},
+};
+use crate::util::face::FaceIndex;
 
+pub mod entity;
 pub mod model;
+pub mod registry;
+pub mod state;
 
-/// Numeric identifier for a `Block`
+/// ID of the air block, which is always registered first
+pub const BLOCK_AIR: BlockId = BlockId(0);
+
+/// Numeric identifier for a `Block`. Each state of a block type has its own ID
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct BlockId(pub u16);
 
@@ -17,72 +28,95 @@ impl BlockId {
 /// Represents a kind of block in the world
 #[derive(Clone, Debug)]
 pub struct Block {
+    pub name: String,
     pub model: BlockModel,
     pub emission: IVec3,
+    pub replaceable: bool,
+    pub render_layer: RenderLayer,
+    /// The fluid this block is part of, if it is a fluid
+    pub fluid: Option<BlockFluid>,
+    /// Kind of block entity created when the block is placed, if it has one
+    pub block_entity: Option<BlockEntityKind>,
+    /// Values of the properties of this state of the block type
+    pub state: BlockState,
+    /// Properties which vary between the states of the block type
+    pub properties: Vec<BlockProperty>,
+    /// ID of the default state of the block type
+    pub default_state: BlockId,
 }
 
-// ----------------------------------------------------------------------------
-// temporary block registry
-// TODO: replace this with a proper system for registering block types
-pub const BLOCK_AIR: BlockId = BlockId(0);
-pub const BLOCK_DIRT: BlockId = BlockId(1);
-pub const BLOCK_GRASS: BlockId = BlockId(2);
-pub const BLOCK_WOOD: BlockId = BlockId(3);
-pub const BLOCK_LAMP_ORANGE: BlockId = BlockId(4);
-pub const BLOCK_COUNT: usize = 5;
-
-pub const BLOCKS: [Block; BLOCK_COUNT] = [
-    // Air
-    Block {
-        model: BlockModel::Empty,
-        emission: IVec3::ZERO,
-    },
-    // Dirt
-    Block {
-        model: BlockModel::FullBlock([
-            BlockFace { texture_index: 0 },
-            BlockFace { texture_index: 0 },
-            BlockFace { texture_index: 0 },
-            BlockFace { texture_index: 0 },
-            BlockFace { texture_index: 0 },
-            BlockFace { texture_index: 0 },
-        ]),
-        emission: IVec3::ZERO,
-    },
-    // Grass
-    Block {
-        model: BlockModel::FullBlock([
-            BlockFace { texture_index: 1 },
-            BlockFace { texture_index: 2 },
-            BlockFace { texture_index: 1 },
-            BlockFace { texture_index: 1 },
-            BlockFace { texture_index: 0 },
-            BlockFace { texture_index: 1 },
-        ]),
-        emission: IVec3::ZERO,
-    },
-    // Wood
-    Block {
-        model: BlockModel::FullBlock([
-            BlockFace { texture_index: 3 },
-            BlockFace { texture_index: 3 },
-            BlockFace { texture_index: 3 },
-            BlockFace { texture_index: 3 },
-            BlockFace { texture_index: 3 },
-            BlockFace { texture_index: 3 },
-        ]),
-        emission: IVec3::ZERO,
-    },
-    // Orange lamp
-    Block {
-        model: BlockModel::FullBlock([
-            BlockFace { texture_index: 4 },
-            BlockFace { texture_index: 4 },
-            BlockFace { texture_index: 4 },
-            BlockFace { texture_index: 4 },
-            BlockFace { texture_index: 4 },
-            BlockFace { texture_index: 4 },
-        ]),
-        emission: IVec3::new(15, 10, 5),
-    },
-];
+impl Block {
+    /// Returns the face of the block covering the whole side of the cell in the given direction,
+    /// if it hides the faces of the neighbouring block behind it.
+    /// See-through blocks never hide their neighbours
+    pub fn occluding_face(&self, face_index: FaceIndex) -> Option<BlockFace> {
+        if self.render_layer.is_see_through() {
+            None
+        } else {
+            self.model.face(face_index)
+        }
+    }
+
+    /// True if both blocks are the same fluid, at any level
+    pub fn is_same_fluid(&self, other: &Block) -> bool {
+        match (self.fluid, other.fluid) {
+            (Some(fluid), Some(other_fluid)) => fluid.source == other_fluid.source,
+            _ => false,
+        }
+    }
+
+    /// True if the block can be targeted to break it or place blocks against it. Fluids can't be
+    /// targeted so that blocks can be placed through them
+    pub fn is_targetable(&self) -> bool {
+        !matches!(self.model, BlockModel::Empty | BlockModel::Fluid { .. })
+    }
+
+    /// True if the block is drawn with the translucent geometry
+    pub fn is_translucent(&self) -> bool {
+        self.render_layer == RenderLayer::Translucent
+    }
+
+    /// True if any face of the block is opaque, i.e. light cannot pass through that face
+    pub fn is_opaque(&self) -> bool {
+        !self.render_layer.is_see_through() && self.model.is_opaque()
+    }
+
+    /// True if every face of the block is opaque
+    pub fn is_fully_opaque(&self) -> bool {
+        !self.render_layer.is_see_through() && self.model.is_fully_opaque()
+    }
+
+    /// True if light can pass through the given face of the block
+    pub fn is_transparent_in_direction(&self, face_index: FaceIndex) -> bool {
+        self.render_layer.is_see_through() || self.model.is_transparent_in_direction(face_index)
+    }
+
+    /// True if light inside the block's cell can leave through the given face
+    pub fn can_light_exit(&self, face_index: FaceIndex) -> bool {
+        self.render_layer.is_see_through() || self.model.can_light_exit(face_index)
+    }
+}
+
+/// Identifies the fluid a block is part of and the level of the fluid.
+/// Each level of a fluid is registered as a separate block, so that the level is saved and
+/// modified like any other block
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockFluid {
+    /// ID of the source block of the fluid, which has level 0. The block for each following level
+    /// is registered directly after it
+    pub source: BlockId,
+    /// 0 for source blocks, increasing by one for each block the fluid has flowed away from its
+    /// source
+    pub level: u8,
+}
+
+impl BlockFluid {
+    /// Returns the ID of the block for this fluid with the given level
+    pub fn with_level(&self, level: u8) -> BlockId {
+        BlockId(self.source.0 + level as u16)
+    }
+
+    pub fn is_source(&self) -> bool {
+        self.level == 0
+    }
+}
@@ -0,0 +1,55 @@
+use serde::{Deserialize, Serialize};
+
+/// Kind of block entity attached to every block of a type, as written in the block definition
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockEntityKind {
+    Container,
+    Sign,
+    Furnace,
+}
+
+impl BlockEntityKind {
+    /// Returns the block entity given to a newly placed block
+    pub fn create(self) -> BlockEntity {
+        match self {
+            Self::Container => BlockEntity::Container { items: Vec::new() },
+            Self::Sign => BlockEntity::Sign {
+                text: String::new(),
+            },
+            Self::Furnace => BlockEntity::Furnace {
+                burn_ticks: 0,
+                cook_ticks: 0,
+            },
+        }
+    }
+}
+
+/// Extra state stored for a single block, for blocks whose state doesn't fit in a `BlockId`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BlockEntity {
+    Container {
+        /// The occupied slots of the container
+        items: Vec<ItemStack>,
+    },
+    Sign {
+        text: String,
+    },
+    Furnace {
+        /// Ticks until the fuel runs out
+        burn_ticks: u32,
+        /// Ticks spent cooking the current item
+        cook_ticks: u32,
+    },
+}
+
+/// A stack of identical items in a slot of a container
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ItemStack {
+    /// Index of the slot in the container
+    pub slot: u32,
+    /// Name of the item
+    pub name: String,
+    pub count: u32,
+}
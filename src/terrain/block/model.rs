@@ -1,41 +1,591 @@
-use crate::util::face::FaceIndex;
+use glam::{IVec3, Vec2, Vec3};
+use serde::Deserialize;
+
+use super::state::{rotate_face_y, Axis, BlockState, HORIZONTAL_FACES};
+use crate::util::face::{FaceIndex, FACE_NORMALS};
 
 #[derive(Clone, Debug)]
 pub enum BlockModel {
     Empty,
     FullBlock([BlockFace; 6]),
+    /// Fills either the top or bottom half of the cell
+    Slab {
+        half: SlabHalf,
+        faces: [BlockFace; 6],
+    },
+    /// A slab filling `half` of the cell with a step filling the other half of the cell on the
+    /// `facing` side. `facing` is always horizontal
+    Stairs {
+        facing: FaceIndex,
+        half: SlabHalf,
+        faces: [BlockFace; 6],
+    },
+    /// A thin panel against the `side` of the cell, used for doors. `side` is always horizontal
+    Panel {
+        side: FaceIndex,
+        faces: [BlockFace; 6],
+    },
+    /// Two diagonal quads crossing in the middle of the cell, used for plants
+    Cross(BlockFace),
+    /// A fluid whose surface is lower the further the fluid has flowed from its source.
+    /// `level` is in the range 0..FLUID_LEVEL_COUNT, where 0 is a source block
+    Fluid {
+        level: u8,
+        face: BlockFace,
+    },
+}
+
+/// Number of levels a fluid can have. Fluids flow at most `FLUID_LEVEL_COUNT - 1` blocks
+/// sideways from their source
+pub const FLUID_LEVEL_COUNT: u8 = 8;
+
+/// Returns the height of the surface of a fluid with the given level within its cell
+pub const fn fluid_height(level: u8) -> f32 {
+    (FLUID_LEVEL_COUNT - level) as f32 / (FLUID_LEVEL_COUNT + 1) as f32
 }
 
 impl BlockModel {
+    /// Returns the face of the block covering the whole side of the cell in the given direction,
+    /// if there is one. Faces which only partly cover the side are not included
     pub fn face(&self, face_index: FaceIndex) -> Option<BlockFace> {
         match self {
-            BlockModel::Empty => None,
+            BlockModel::Empty | BlockModel::Cross(_) | BlockModel::Fluid { .. } => None,
             BlockModel::FullBlock(faces) => Some(faces[face_index.as_usize()]),
+            BlockModel::Slab { half, faces } => {
+                (face_index == half.covered_face()).then(|| faces[face_index.as_usize()])
+            }
+            BlockModel::Stairs {
+                facing,
+                half,
+                faces,
+            } => (face_index == half.covered_face() || face_index == *facing)
+                .then(|| faces[face_index.as_usize()]),
+            BlockModel::Panel { side, faces } => {
+                (face_index == *side).then(|| faces[face_index.as_usize()])
+            }
+        }
+    }
+
+    /// Returns the texture used for faces of `boxes()` pointing in the given direction
+    pub fn box_face(&self, face_index: FaceIndex) -> Option<BlockFace> {
+        match self {
+            BlockModel::Empty | BlockModel::Cross(_) => None,
+            BlockModel::Fluid { face, .. } => Some(*face),
+            BlockModel::FullBlock(faces)
+            | BlockModel::Slab { faces, .. }
+            | BlockModel::Stairs { faces, .. }
+            | BlockModel::Panel { faces, .. } => Some(faces[face_index.as_usize()]),
+        }
+    }
+
+    /// Boxes making up the shape of the block, in the range 0..1 within the cell
+    pub fn boxes(&self) -> &'static [BlockBox] {
+        match self {
+            BlockModel::Empty | BlockModel::Cross(_) => &[],
+            BlockModel::FullBlock(_) => &[BlockBox::FULL],
+            BlockModel::Slab {
+                half: SlabHalf::Bottom,
+                ..
+            } => &[BlockBox::BOTTOM_HALF],
+            BlockModel::Slab {
+                half: SlabHalf::Top,
+                ..
+            } => &[BlockBox::TOP_HALF],
+            BlockModel::Fluid { level, .. } => &FLUID_BOXES[*level as usize],
+            BlockModel::Stairs { facing, half, .. } => {
+                let half_index = match half {
+                    SlabHalf::Bottom => 0,
+                    SlabHalf::Top => 1,
+                };
+
+                &STAIRS_BOXES[half_index][horizontal_index(*facing)]
+            }
+            BlockModel::Panel { side, .. } => &PANEL_BOXES[horizontal_index(*side)],
+        }
+    }
+
+    /// Boxes that bodies collide with, in the range 0..1 within the cell. Plants and fluids can be
+    /// walked through, so they have no collision boxes
+    pub fn collision_boxes(&self) -> &'static [BlockBox] {
+        match self {
+            BlockModel::Cross(_) | BlockModel::Fluid { .. } => &[],
+            _ => self.boxes(),
+        }
+    }
+
+    /// Returns the model for the given state of a block type with this model (see
+    /// `BlockProperty` for how each property changes the model)
+    pub fn for_state(&self, state: &BlockState) -> Self {
+        let mut model = self.clone();
+
+        if state.half == SlabHalf::Top {
+            model = model.upside_down();
+        }
+        if state.open {
+            model = model.opened();
+        }
+
+        model
+            .aligned_to_axis(state.axis)
+            .rotated_y(state.facing_quarter_turns())
+    }
+
+    /// Returns the model turned about the vertical axis by the given number of quarter turns, in
+    /// the same direction as the order of `HORIZONTAL_FACES`
+    pub fn rotated_y(self, quarter_turns: u8) -> Self {
+        match self {
+            BlockModel::FullBlock(faces) => {
+                BlockModel::FullBlock(rotate_faces_y(faces, quarter_turns))
+            }
+            BlockModel::Slab { half, faces } => BlockModel::Slab {
+                half,
+                faces: rotate_faces_y(faces, quarter_turns),
+            },
+            BlockModel::Stairs {
+                facing,
+                half,
+                faces,
+            } => BlockModel::Stairs {
+                facing: rotate_face_y(facing, quarter_turns),
+                half,
+                faces: rotate_faces_y(faces, quarter_turns),
+            },
+            BlockModel::Panel { side, faces } => BlockModel::Panel {
+                side: rotate_face_y(side, quarter_turns),
+                faces: rotate_faces_y(faces, quarter_turns),
+            },
+            BlockModel::Empty | BlockModel::Cross(_) | BlockModel::Fluid { .. } => self,
+        }
+    }
+
+    /// Returns the model with its top and bottom faces moved to the ends of the given axis. Only
+    /// full blocks are affected
+    pub fn aligned_to_axis(self, axis: Axis) -> Self {
+        let BlockModel::FullBlock(faces) = self else {
+            return self;
+        };
+        let face = |face_index: FaceIndex| faces[face_index.as_usize()];
+
+        // the side faces are rotated so that the texture runs along the axis
+        let mut aligned = faces;
+        match axis {
+            Axis::X => {
+                aligned[FaceIndex::POS_X.as_usize()] = face(FaceIndex::POS_Y);
+                aligned[FaceIndex::NEG_X.as_usize()] = face(FaceIndex::NEG_Y);
+                aligned[FaceIndex::POS_Y.as_usize()] = face(FaceIndex::NEG_X);
+                aligned[FaceIndex::NEG_Y.as_usize()] = face(FaceIndex::POS_X);
+                aligned[FaceIndex::POS_Z.as_usize()] = face(FaceIndex::POS_Z).rotated(1);
+                aligned[FaceIndex::NEG_Z.as_usize()] = face(FaceIndex::NEG_Z).rotated(1);
+            }
+            Axis::Y => (),
+            Axis::Z => {
+                aligned[FaceIndex::POS_Z.as_usize()] = face(FaceIndex::POS_Y);
+                aligned[FaceIndex::NEG_Z.as_usize()] = face(FaceIndex::NEG_Y);
+                aligned[FaceIndex::POS_Y.as_usize()] = face(FaceIndex::NEG_Z).rotated(1);
+                aligned[FaceIndex::NEG_Y.as_usize()] = face(FaceIndex::POS_Z).rotated(1);
+                aligned[FaceIndex::POS_X.as_usize()] = face(FaceIndex::POS_X).rotated(1);
+                aligned[FaceIndex::NEG_X.as_usize()] = face(FaceIndex::NEG_X).rotated(1);
+            }
+        }
+
+        BlockModel::FullBlock(aligned)
+    }
+
+    /// Returns the model flipped upside down. Only slabs and stairs are affected
+    pub fn upside_down(self) -> Self {
+        match self {
+            BlockModel::Slab { half, faces } => BlockModel::Slab {
+                half: half.opposite(),
+                faces,
+            },
+            BlockModel::Stairs {
+                facing,
+                half,
+                faces,
+            } => BlockModel::Stairs {
+                facing,
+                half: half.opposite(),
+                faces,
+            },
+            _ => self,
+        }
+    }
+
+    /// Returns the model of an open door, which swings the panel a quarter turn. Only panels are
+    /// affected
+    pub fn opened(self) -> Self {
+        match self {
+            BlockModel::Panel { .. } => self.rotated_y(1),
+            _ => self,
         }
     }
 
+    /// True if the block has geometry which is not made of whole faces of the cell, and must be
+    /// meshed separately from the greedily merged faces
+    pub fn has_partial_geometry(&self) -> bool {
+        matches!(
+            self,
+            BlockModel::Slab { .. }
+                | BlockModel::Stairs { .. }
+                | BlockModel::Panel { .. }
+                | BlockModel::Cross(_)
+                | BlockModel::Fluid { .. }
+        )
+    }
+
     // Returns a bitmask of the faces of the block that are opaque, i.e. light
     // cannot pass through that face
+    // A face is only opaque if it covers the whole side of the cell
     // If the result is all zeroes, the block is completely transparent
     pub fn opaque_faces_mask(&self) -> u8 {
-        match self {
-            BlockModel::Empty => 0b000000,
-            BlockModel::FullBlock(_) => 0b111111,
-        }
+        (0..6)
+            .filter(|&face_index| self.face(FaceIndex(face_index)).is_some())
+            .fold(0, |mask, face_index| mask | (1 << face_index))
     }
 
     pub fn is_opaque(&self) -> bool {
         self.opaque_faces_mask() != 0
     }
 
+    // True if every face of the block is opaque
+    pub fn is_fully_opaque(&self) -> bool {
+        self.opaque_faces_mask() == 0b111111
+    }
+
     // True if light can pass through the given face of the block
     pub fn is_transparent_in_direction(&self, face_index: FaceIndex) -> bool {
         self.opaque_faces_mask() & (1 << face_index.as_usize()) == 0
     }
+
+    // True if light inside the block's cell can leave through the given face.
+    // Light inside a full block can only come from the block's own emission, which leaves
+    // through every face
+    pub fn can_light_exit(&self, face_index: FaceIndex) -> bool {
+        matches!(self, BlockModel::FullBlock(_)) || self.is_transparent_in_direction(face_index)
+    }
+
+    /// Intersects a ray with the shape of the block. `ray_origin` is relative to the cell.
+    /// Returns the distance along the ray to the intersection and the normal of the face hit, or
+    /// None for the normal if the ray starts inside the block or hits a cross model
+    pub fn intersect_ray(
+        &self,
+        ray_origin: Vec3,
+        ray_direction: Vec3,
+    ) -> Option<(f32, Option<IVec3>)> {
+        match self {
+            BlockModel::Cross(_) => intersect_cross(ray_origin, ray_direction).map(|t| (t, None)),
+            _ => self
+                .boxes()
+                .iter()
+                .filter_map(|block_box| block_box.intersect_ray(ray_origin, ray_direction))
+                .min_by(|(a, _), (b, _)| a.total_cmp(b)),
+        }
+    }
+}
+
+/// Which half of the cell a slab fills
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SlabHalf {
+    Bottom,
+    Top,
+}
+
+impl SlabHalf {
+    pub fn opposite(self) -> Self {
+        match self {
+            SlabHalf::Bottom => SlabHalf::Top,
+            SlabHalf::Top => SlabHalf::Bottom,
+        }
+    }
+
+    /// Returns the side of the cell covered by a slab in this half
+    pub fn covered_face(self) -> FaceIndex {
+        match self {
+            SlabHalf::Bottom => FaceIndex::NEG_Y,
+            SlabHalf::Top => FaceIndex::POS_Y,
+        }
+    }
+}
+
+/// How the faces of a block are drawn
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RenderLayer {
+    /// Faces hide everything behind them
+    #[default]
+    Opaque,
+    /// Faces are drawn with the opaque geometry, but fully transparent parts of the texture are
+    /// discarded, e.g. leaves and plants
+    Cutout,
+    /// Faces are blended with the geometry behind them, e.g. water and stained glass
+    Translucent,
+}
+
+impl RenderLayer {
+    /// True if the block can be seen through, so that it hides neither the faces of its
+    /// neighbours nor the light behind it
+    pub fn is_see_through(&self) -> bool {
+        *self != RenderLayer::Opaque
+    }
 }
 
 /// represents one axis-aligned face of a block model
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct BlockFace {
     pub texture_index: usize,
+    /// Number of quarter turns the texture is rotated by, in 0..4
+    pub rotation: u8,
+}
+
+impl BlockFace {
+    pub fn new(texture_index: usize) -> Self {
+        Self {
+            texture_index,
+            rotation: 0,
+        }
+    }
+
+    /// Returns the face with its texture rotated by a further number of quarter turns
+    pub fn rotated(self, quarter_turns: u8) -> Self {
+        Self {
+            rotation: (self.rotation + quarter_turns) % 4,
+            ..self
+        }
+    }
+
+    /// Rotates texture coordinates by the rotation of the face. Whole faces of the cell map onto
+    /// themselves, so the textures of neighbouring faces still line up
+    pub fn rotate_uv(&self, uv: Vec2) -> Vec2 {
+        match self.rotation {
+            0 => uv,
+            1 => Vec2::new(uv.y, 1.0 - uv.x),
+            2 => Vec2::new(1.0 - uv.x, 1.0 - uv.y),
+            _ => Vec2::new(1.0 - uv.y, uv.x),
+        }
+    }
+}
+
+/// Axis-aligned box within a cell, in the range 0..1
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BlockBox {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl BlockBox {
+    pub const FULL: Self = Self::new(Vec3::ZERO, Vec3::ONE);
+    pub const BOTTOM_HALF: Self = Self::new(Vec3::ZERO, Vec3::new(1.0, 0.5, 1.0));
+    pub const TOP_HALF: Self = Self::new(Vec3::new(0.0, 0.5, 0.0), Vec3::ONE);
+
+    pub const fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    /// Intersects a ray with the box, returning the distance along the ray to the intersection and
+    /// the normal of the face hit (None if the ray starts inside the box)
+    pub fn intersect_ray(
+        &self,
+        ray_origin: Vec3,
+        ray_direction: Vec3,
+    ) -> Option<(f32, Option<IVec3>)> {
+        let dir_recip = ray_direction.recip();
+        let t0 = (self.min - ray_origin) * dir_recip;
+        let t1 = (self.max - ray_origin) * dir_recip;
+        let t_near = t0.min(t1);
+        let t_far = t0.max(t1);
+
+        let t_enter = t_near.max_element();
+        let t_exit = t_far.min_element();
+
+        if t_exit < t_enter.max(0.0) {
+            return None;
+        }
+
+        if t_enter < 0.0 {
+            return Some((0.0, None));
+        }
+
+        // the face hit is on the axis where the ray entered the box last
+        let axis = if t_enter == t_near.x {
+            0
+        } else if t_enter == t_near.y {
+            1
+        } else {
+            2
+        };
+        let normal = -FACE_NORMALS[axis] * ray_direction[axis].signum() as i32;
+
+        Some((t_enter, Some(normal)))
+    }
+}
+
+/// Thickness of a panel within its cell
+const PANEL_THICKNESS: f32 = 3.0 / 16.0;
+
+/// Returns the box filling the part of the cell within `depth` of the given horizontal side,
+/// between the heights `min_y` and `max_y`
+const fn side_box(side: FaceIndex, depth: f32, min_y: f32, max_y: f32) -> BlockBox {
+    match side {
+        FaceIndex::POS_X => BlockBox::new(
+            Vec3::new(1.0 - depth, min_y, 0.0),
+            Vec3::new(1.0, max_y, 1.0),
+        ),
+        FaceIndex::POS_Z => BlockBox::new(
+            Vec3::new(0.0, min_y, 1.0 - depth),
+            Vec3::new(1.0, max_y, 1.0),
+        ),
+        FaceIndex::NEG_X => BlockBox::new(Vec3::new(0.0, min_y, 0.0), Vec3::new(depth, max_y, 1.0)),
+        _ => BlockBox::new(Vec3::new(0.0, min_y, 0.0), Vec3::new(1.0, max_y, depth)),
+    }
+}
+
+/// Returns the position of a horizontal face in `HORIZONTAL_FACES`
+fn horizontal_index(face_index: FaceIndex) -> usize {
+    HORIZONTAL_FACES
+        .iter()
+        .position(|&face| face == face_index)
+        .unwrap_or_else(|| panic!("{:?} is not horizontal", face_index))
+}
+
+/// Boxes of stairs, indexed by half (bottom, then top) and by the facing direction in the order
+/// of `HORIZONTAL_FACES`
+const STAIRS_BOXES: [[[BlockBox; 2]; 4]; 2] = {
+    let mut boxes = [[[BlockBox::FULL; 2]; 4]; 2];
+    let mut i = 0;
+    while i < 4 {
+        let facing = HORIZONTAL_FACES[i];
+        boxes[0][i] = [BlockBox::BOTTOM_HALF, side_box(facing, 0.5, 0.5, 1.0)];
+        boxes[1][i] = [BlockBox::TOP_HALF, side_box(facing, 0.5, 0.0, 0.5)];
+        i += 1;
+    }
+    boxes
+};
+
+/// Boxes of panels, indexed by side in the order of `HORIZONTAL_FACES`
+const PANEL_BOXES: [[BlockBox; 1]; 4] = {
+    let mut boxes = [[BlockBox::FULL]; 4];
+    let mut i = 0;
+    while i < 4 {
+        boxes[i] = [side_box(HORIZONTAL_FACES[i], PANEL_THICKNESS, 0.0, 1.0)];
+        i += 1;
+    }
+    boxes
+};
+
+/// Moves each face of a block to the face a given number of quarter turns around the vertical
+/// axis, rotating the textures of the top and bottom faces to match
+fn rotate_faces_y(faces: [BlockFace; 6], quarter_turns: u8) -> [BlockFace; 6] {
+    let mut rotated = faces;
+
+    for (face_index, face) in faces.into_iter().enumerate() {
+        let face_index = FaceIndex(face_index);
+        let is_vertical = face_index == FaceIndex::POS_Y || face_index == FaceIndex::NEG_Y;
+
+        rotated[rotate_face_y(face_index, quarter_turns).as_usize()] = if is_vertical {
+            face.rotated(quarter_turns)
+        } else {
+            face
+        };
+    }
+
+    rotated
+}
+
+const FLUID_BOXES: [[BlockBox; 1]; FLUID_LEVEL_COUNT as usize] = {
+    let mut boxes = [[BlockBox::FULL]; FLUID_LEVEL_COUNT as usize];
+    let mut level = 0;
+    while level < FLUID_LEVEL_COUNT {
+        boxes[level as usize] = [BlockBox::new(
+            Vec3::ZERO,
+            Vec3::new(1.0, fluid_height(level), 1.0),
+        )];
+        level += 1;
+    }
+    boxes
+};
+
+/// Intersects a ray with the two diagonal quads of a cross model, returning the distance along the
+/// ray to the nearest intersection
+fn intersect_cross(ray_origin: Vec3, ray_direction: Vec3) -> Option<f32> {
+    // the quads lie on the planes x - z = 0 and x + z = 1
+    [
+        (Vec3::new(1.0, 0.0, -1.0), 0.0),
+        (Vec3::new(1.0, 0.0, 1.0), 1.0),
+    ]
+    .into_iter()
+    .filter_map(|(normal, distance)| {
+        let t = (distance - normal.dot(ray_origin)) / normal.dot(ray_direction);
+        let hit_pos = ray_origin + ray_direction * t;
+
+        (t >= 0.0 && hit_pos.cmpge(Vec3::ZERO).all() && hit_pos.cmple(Vec3::ONE).all()).then_some(t)
+    })
+    .min_by(f32::total_cmp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FACES: [BlockFace; 6] = [BlockFace {
+        texture_index: 0,
+        rotation: 0,
+    }; 6];
+
+    #[test]
+    fn partial_opacity() {
+        let bottom_slab = BlockModel::Slab {
+            half: SlabHalf::Bottom,
+            faces: FACES,
+        };
+        assert_eq!(
+            bottom_slab.opaque_faces_mask(),
+            1 << FaceIndex::NEG_Y.as_usize()
+        );
+        assert!(bottom_slab.is_transparent_in_direction(FaceIndex::POS_X));
+        assert!(!bottom_slab.can_light_exit(FaceIndex::NEG_Y));
+
+        let stairs = BlockModel::Stairs {
+            facing: FaceIndex::NEG_Z,
+            half: SlabHalf::Bottom,
+            faces: FACES,
+        };
+        assert_eq!(
+            stairs.opaque_faces_mask(),
+            (1 << FaceIndex::NEG_Y.as_usize()) | (1 << FaceIndex::NEG_Z.as_usize())
+        );
+
+        let cross = BlockModel::Cross(FACES[0]);
+        assert_eq!(cross.opaque_faces_mask(), 0);
+        assert!(BlockModel::FullBlock(FACES).can_light_exit(FaceIndex::POS_Y));
+    }
+
+    #[test]
+    fn ray_intersection() {
+        let bottom_slab = BlockModel::Slab {
+            half: SlabHalf::Bottom,
+            faces: FACES,
+        };
+
+        // straight down onto the top of the slab
+        let hit = bottom_slab.intersect_ray(Vec3::new(0.5, 1.0, 0.5), Vec3::NEG_Y);
+        assert_eq!(hit, Some((0.5, Some(IVec3::Y))));
+
+        // horizontally through the empty upper half
+        let miss = bottom_slab.intersect_ray(Vec3::new(0.0, 0.75, 0.5), Vec3::X);
+        assert_eq!(miss, None);
+
+        let stairs = BlockModel::Stairs {
+            facing: FaceIndex::POS_X,
+            half: SlabHalf::Bottom,
+            faces: FACES,
+        };
+        let hit = stairs.intersect_ray(Vec3::new(0.0, 0.75, 0.5), Vec3::X);
+        assert_eq!(hit, Some((0.5, Some(IVec3::NEG_X))));
+
+        let cross = BlockModel::Cross(FACES[0]);
+        let hit = cross.intersect_ray(Vec3::new(0.0, 0.5, 0.5), Vec3::X);
+        assert_eq!(hit.map(|(t, _)| t), Some(0.5));
+        assert_eq!(cross.intersect_ray(Vec3::new(0.0, 0.5, 0.5), Vec3::Y), None);
+    }
 }
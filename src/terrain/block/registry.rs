@@ -0,0 +1,625 @@
+use std::{
+    fs,
+    ops::Index,
+    path::{Path, PathBuf},
+};
+
+use glam::IVec3;
+use itertools::Itertools;
+use rustc_hash::FxHashMap;
+use serde::Deserialize;
+
+use super::{
+    entity::BlockEntityKind,
+    model::{BlockFace, BlockModel, RenderLayer, SlabHalf, FLUID_LEVEL_COUNT},
+    state::{BlockProperty, BlockState},
+    Block, BlockFluid, BlockId, BLOCK_AIR,
+};
+use crate::util::face::FaceIndex;
+
+/// File extension of block definition files
+pub const BLOCK_DEFINITION_EXTENSION: &str = "toml";
+
+/// Name of the built-in air block
+pub const AIR_NAME: &str = "air";
+
+/// Holds every kind of block that can exist in the world, indexed by `BlockId`.
+/// Block types are loaded from a directory of definition files (one block per file), so that
+/// adding a block never requires changes to the code.
+/// Air is built in and always has ID 0. The remaining blocks are assigned IDs in order of name,
+/// so the IDs are stable as long as the set of definitions doesn't change.
+/// Each state of a block type (see `BlockState`) is registered as a separate block with its own
+/// ID, so a `BlockId` identifies both the type and the state of a block
+#[derive(Clone, Debug)]
+pub struct BlockRegistry {
+    /// Registered blocks, indexed by ID
+    blocks: Vec<Block>,
+    /// Block ID for each block name
+    id_lookup: FxHashMap<String, BlockId>,
+    /// Names of the textures referenced by the blocks, indexed by `BlockFace::texture_index`
+    texture_names: Vec<String>,
+    /// Texture index for each texture name
+    texture_index_lookup: FxHashMap<String, usize>,
+}
+
+impl BlockRegistry {
+    /// Load the block definitions from every file in `dir` with the extension
+    /// `BLOCK_DEFINITION_EXTENSION`
+    pub fn load(dir: impl AsRef<Path>) -> Result<Self, BlockRegistryError> {
+        let paths = fs::read_dir(dir.as_ref())
+            .map_err(|e| BlockRegistryError::IoError(dir.as_ref().to_path_buf(), e))?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == BLOCK_DEFINITION_EXTENSION)
+            })
+            .collect_vec();
+
+        let mut definitions = Vec::with_capacity(paths.len());
+        for path in paths {
+            let source = fs::read_to_string(&path)
+                .map_err(|e| BlockRegistryError::IoError(path.clone(), e))?;
+
+            definitions.push(
+                BlockDefinition::parse(&source)
+                    .map_err(|e| BlockRegistryError::ParseError(path.clone(), Box::new(e)))?,
+            );
+        }
+
+        Self::from_definitions(definitions)
+    }
+
+    /// Create a registry from a list of block definitions
+    pub fn from_definitions(
+        mut definitions: Vec<BlockDefinition>,
+    ) -> Result<Self, BlockRegistryError> {
+        definitions.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut registry = Self {
+            blocks: Vec::with_capacity(definitions.len() + 1),
+            id_lookup: FxHashMap::default(),
+            texture_names: Vec::new(),
+            texture_index_lookup: FxHashMap::default(),
+        };
+
+        let air_id = registry.register(BlockDefinition {
+            name: AIR_NAME.to_string(),
+            model: ModelDefinition::Empty,
+            emission: [0; 3],
+            replaceable: true,
+            render_layer: RenderLayer::default(),
+            block_entity: None,
+            properties: Vec::new(),
+        })?;
+        debug_assert!(air_id == BLOCK_AIR);
+
+        for definition in definitions {
+            registry.register(definition)?;
+        }
+
+        Ok(registry)
+    }
+
+    /// Returns the ID of the block with the given name, if it is registered
+    pub fn get_id(&self, name: &str) -> Option<BlockId> {
+        self.id_lookup.get(name).copied()
+    }
+
+    /// Returns the ID of the given state of the same block type as the block with the given ID.
+    /// Properties the block type doesn't declare are ignored.
+    /// Panics if no block is registered with the given ID
+    pub fn with_state(&self, block_id: BlockId, state: &BlockState) -> BlockId {
+        let block = &self[block_id];
+
+        BlockId(block.default_state.0 + state.index(&block.properties) as u16)
+    }
+
+    /// Iterator over all registered blocks and their IDs, in order of ID
+    pub fn iter(&self) -> impl Iterator<Item = (BlockId, &Block)> {
+        self.blocks
+            .iter()
+            .enumerate()
+            .map(|(index, block)| (BlockId(index as u16), block))
+    }
+
+    /// Number of registered blocks, including air
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Names of the textures referenced by the registered blocks, in order of texture index
+    pub fn texture_names(&self) -> &[String] {
+        &self.texture_names
+    }
+
+    /// Add a block to the registry, returning its newly assigned ID.
+    /// Blocks with properties are registered as one block for each state, named as described in
+    /// `BlockState::name`. The ID of the default state is returned.
+    /// Fluids are registered as one block for each level, named `<name>_<level>` apart from the
+    /// source block. The ID of the source block is returned
+    fn register(&mut self, definition: BlockDefinition) -> Result<BlockId, BlockRegistryError> {
+        if !definition.properties.iter().all_unique() {
+            return Err(BlockRegistryError::DuplicateProperty(definition.name));
+        }
+        if !definition
+            .emission
+            .iter()
+            .all(|component| (0..16).contains(component))
+        {
+            return Err(BlockRegistryError::InvalidEmission(definition.name));
+        }
+
+        let model = match &definition.model {
+            ModelDefinition::Empty => BlockModel::Empty,
+            ModelDefinition::FullBlock { textures } => {
+                BlockModel::FullBlock(self.get_faces(&definition.name, textures)?)
+            }
+            ModelDefinition::Slab { half, textures } => BlockModel::Slab {
+                half: *half,
+                faces: self.get_faces(&definition.name, textures)?,
+            },
+            ModelDefinition::Stairs { facing, textures } => BlockModel::Stairs {
+                facing: facing.face_index(),
+                half: SlabHalf::Bottom,
+                faces: self.get_faces(&definition.name, textures)?,
+            },
+            ModelDefinition::Door { textures } => BlockModel::Panel {
+                side: FaceIndex::NEG_X,
+                faces: self.get_faces(&definition.name, textures)?,
+            },
+            ModelDefinition::Cross { texture } => {
+                BlockModel::Cross(BlockFace::new(self.get_or_add_texture_index(texture)))
+            }
+            ModelDefinition::Fluid { texture } => {
+                if !definition.properties.is_empty() {
+                    return Err(BlockRegistryError::FluidWithProperties(definition.name));
+                }
+
+                let face = BlockFace::new(self.get_or_add_texture_index(texture));
+                let source = BlockId(self.blocks.len() as u16);
+
+                for level in 0..FLUID_LEVEL_COUNT {
+                    let name = if level == 0 {
+                        definition.name.clone()
+                    } else {
+                        format!("{}_{}", definition.name, level)
+                    };
+
+                    self.add_block(Block {
+                        name,
+                        model: BlockModel::Fluid { level, face },
+                        emission: IVec3::from_array(definition.emission),
+                        replaceable: definition.replaceable,
+                        render_layer: definition.render_layer,
+                        fluid: Some(BlockFluid { source, level }),
+                        block_entity: definition.block_entity,
+                        state: BlockState::default(),
+                        properties: Vec::new(),
+                        default_state: BlockId(self.blocks.len() as u16),
+                    })?;
+                }
+
+                return Ok(source);
+            }
+        };
+
+        let default_state = BlockId(self.blocks.len() as u16);
+
+        for state_index in 0..BlockState::count(&definition.properties) {
+            let state = BlockState::from_index(&definition.properties, state_index);
+
+            self.add_block(Block {
+                name: state.name(&definition.name, &definition.properties),
+                model: model.for_state(&state),
+                emission: IVec3::from_array(definition.emission),
+                replaceable: definition.replaceable,
+                render_layer: definition.render_layer,
+                fluid: None,
+                block_entity: definition.block_entity,
+                state,
+                properties: definition.properties.clone(),
+                default_state,
+            })?;
+        }
+
+        Ok(default_state)
+    }
+
+    /// Add a block with a unique name, returning its newly assigned ID
+    fn add_block(&mut self, block: Block) -> Result<BlockId, BlockRegistryError> {
+        if self.id_lookup.contains_key(&block.name) {
+            return Err(BlockRegistryError::DuplicateName(block.name));
+        }
+        if self.blocks.len() > u16::MAX as usize {
+            return Err(BlockRegistryError::TooManyBlocks);
+        }
+
+        let block_id = BlockId(self.blocks.len() as u16);
+
+        self.id_lookup.insert(block.name.clone(), block_id);
+        self.blocks.push(block);
+
+        Ok(block_id)
+    }
+
+    /// Returns the face for each side of a block with the given textures
+    fn get_faces(
+        &mut self,
+        block_name: &str,
+        textures: &FaceTextures,
+    ) -> Result<[BlockFace; 6], BlockRegistryError> {
+        array_init::try_array_init(|face_index| {
+            let texture_name = textures
+                .texture_for_face(FaceIndex(face_index))
+                .ok_or_else(|| {
+                    BlockRegistryError::MissingTexture(
+                        block_name.to_string(),
+                        FaceIndex(face_index),
+                    )
+                })?;
+
+            Ok(BlockFace::new(self.get_or_add_texture_index(texture_name)))
+        })
+    }
+
+    /// If the texture is already referenced by another block, returns its index.
+    /// Otherwise assigns the texture a new index
+    fn get_or_add_texture_index(&mut self, texture_name: &str) -> usize {
+        if let Some(&texture_index) = self.texture_index_lookup.get(texture_name) {
+            texture_index
+        } else {
+            let texture_index = self.texture_names.len();
+
+            self.texture_names.push(texture_name.to_string());
+            self.texture_index_lookup
+                .insert(texture_name.to_string(), texture_index);
+
+            texture_index
+        }
+    }
+}
+
+impl Index<BlockId> for BlockRegistry {
+    type Output = Block;
+
+    /// Panics if no block is registered with the given ID
+    fn index(&self, block_id: BlockId) -> &Self::Output {
+        &self.blocks[block_id.as_usize()]
+    }
+}
+
+/// Contents of a block definition file
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BlockDefinition {
+    /// Unique name of the block
+    pub name: String,
+    /// Shape and textures of the block
+    #[serde(default)]
+    pub model: ModelDefinition,
+    /// Light emitted by the block as RGB values in 0..16
+    #[serde(default)]
+    pub emission: [i32; 3],
+    /// Whether blocks placed by terrain features may replace this block
+    #[serde(default)]
+    pub replaceable: bool,
+    /// How the faces of the block are drawn
+    #[serde(default)]
+    pub render_layer: RenderLayer,
+    /// Kind of block entity created when the block is placed
+    #[serde(default)]
+    pub block_entity: Option<BlockEntityKind>,
+    /// Properties which vary between the states of the block
+    #[serde(default)]
+    pub properties: Vec<BlockProperty>,
+}
+
+impl BlockDefinition {
+    /// Parse a block definition from the contents of a definition file
+    pub fn parse(source: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(source)
+    }
+}
+
+/// Block model as written in a block definition file
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ModelDefinition {
+    #[default]
+    Empty,
+    FullBlock {
+        textures: FaceTextures,
+    },
+    Slab {
+        half: SlabHalf,
+        textures: FaceTextures,
+    },
+    Stairs {
+        facing: HorizontalDirection,
+        textures: FaceTextures,
+    },
+    /// A thin panel against the `neg_x` side of the cell, which swings a quarter turn when the
+    /// `open` property is true
+    Door {
+        textures: FaceTextures,
+    },
+    Cross {
+        texture: String,
+    },
+    /// Registered as one block for each level of the fluid
+    Fluid {
+        texture: String,
+    },
+}
+
+/// Horizontal direction as written in a block definition file
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HorizontalDirection {
+    PosX,
+    PosZ,
+    NegX,
+    NegZ,
+}
+
+impl HorizontalDirection {
+    pub fn face_index(self) -> FaceIndex {
+        match self {
+            HorizontalDirection::PosX => FaceIndex::POS_X,
+            HorizontalDirection::PosZ => FaceIndex::POS_Z,
+            HorizontalDirection::NegX => FaceIndex::NEG_X,
+            HorizontalDirection::NegZ => FaceIndex::NEG_Z,
+        }
+    }
+}
+
+/// Texture names for each face of a block.
+/// More specific entries take precedence, so a face uses the first texture given out of its own
+/// entry (e.g. `pos_x`), then `top`/`bottom`/`side`, then `all`
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FaceTextures {
+    pub all: Option<String>,
+    pub side: Option<String>,
+    pub top: Option<String>,
+    pub bottom: Option<String>,
+    pub pos_x: Option<String>,
+    pub pos_y: Option<String>,
+    pub pos_z: Option<String>,
+    pub neg_x: Option<String>,
+    pub neg_y: Option<String>,
+    pub neg_z: Option<String>,
+}
+
+impl FaceTextures {
+    /// Returns the name of the texture to use for the given face, if one was specified
+    pub fn texture_for_face(&self, face_index: FaceIndex) -> Option<&str> {
+        let (face, group) = match face_index {
+            FaceIndex::POS_X => (&self.pos_x, &self.side),
+            FaceIndex::POS_Y => (&self.pos_y, &self.top),
+            FaceIndex::POS_Z => (&self.pos_z, &self.side),
+            FaceIndex::NEG_X => (&self.neg_x, &self.side),
+            FaceIndex::NEG_Y => (&self.neg_y, &self.bottom),
+            FaceIndex::NEG_Z => (&self.neg_z, &self.side),
+            _ => panic!("invalid face index {:?}", face_index),
+        };
+
+        face.as_ref()
+            .or(group.as_ref())
+            .or(self.all.as_ref())
+            .map(String::as_str)
+    }
+}
+
+/// Errors returned when building a `BlockRegistry`
+#[derive(Debug, thiserror::Error)]
+pub enum BlockRegistryError {
+    #[error("io error reading {0}: {1}")]
+    IoError(PathBuf, std::io::Error),
+    #[error("error parsing block definition {0}: {1}")]
+    ParseError(PathBuf, Box<toml::de::Error>),
+    #[error("more than one block is named `{0}`")]
+    DuplicateName(String),
+    #[error("block `{0}` has no texture for face {1:?}")]
+    MissingTexture(String, FaceIndex),
+    #[error("block `{0}` has emission components outside of 0..16")]
+    InvalidEmission(String),
+    #[error("block `{0}` declares the same property more than once")]
+    DuplicateProperty(String),
+    #[error("fluid `{0}` cannot have properties")]
+    FluidWithProperties(String),
+    #[error("too many blocks registered")]
+    TooManyBlocks,
+}
+
+/// Create a registry from the sources of block definitions, panicking if any is invalid
+#[cfg(test)]
+pub fn test_registry(sources: &[&str]) -> BlockRegistry {
+    BlockRegistry::from_definitions(
+        sources
+            .iter()
+            .map(|source| BlockDefinition::parse(source).unwrap())
+            .collect(),
+    )
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::block::state::Axis;
+
+    fn parse_all(sources: &[&str]) -> Result<BlockRegistry, BlockRegistryError> {
+        BlockRegistry::from_definitions(
+            sources
+                .iter()
+                .map(|source| BlockDefinition::parse(source).unwrap())
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn block_ids_and_textures() {
+        let registry = test_registry(&[
+            r#"
+                name = "grass"
+                model = { type = "full_block", textures = { side = "grass_side", top = "grass_top", bottom = "dirt" } }
+            "#,
+            r#"
+                name = "dirt"
+                model = { type = "full_block", textures = { all = "dirt" } }
+            "#,
+            r#"
+                name = "lamp"
+                emission = [15, 10, 5]
+                model = { type = "full_block", textures = { all = "lamp" } }
+            "#,
+        ]);
+
+        assert_eq!(registry.len(), 4);
+        assert_eq!(registry.get_id("air"), Some(BLOCK_AIR));
+        assert_eq!(registry.get_id("dirt"), Some(BlockId(1)));
+        assert_eq!(registry.get_id("grass"), Some(BlockId(2)));
+        assert_eq!(registry.get_id("lamp"), Some(BlockId(3)));
+        assert_eq!(registry.get_id("stone"), None);
+
+        assert_eq!(
+            registry.texture_names(),
+            &["dirt", "grass_side", "grass_top", "lamp"]
+        );
+
+        let grass = &registry[BlockId(2)];
+        let texture_name = |face_index| {
+            registry.texture_names()[grass.model.face(face_index).unwrap().texture_index].as_str()
+        };
+        assert_eq!(texture_name(FaceIndex::POS_X), "grass_side");
+        assert_eq!(texture_name(FaceIndex::POS_Y), "grass_top");
+        assert_eq!(texture_name(FaceIndex::NEG_Y), "dirt");
+        assert_eq!(texture_name(FaceIndex::NEG_Z), "grass_side");
+
+        assert_eq!(registry[BlockId(3)].emission, IVec3::new(15, 10, 5));
+        assert!(!registry[BLOCK_AIR].model.is_opaque());
+    }
+
+    #[test]
+    fn see_through_blocks() {
+        let registry = test_registry(&[
+            r#"
+                name = "glass"
+                render_layer = "translucent"
+                model = { type = "full_block", textures = { all = "glass" } }
+            "#,
+            r#"
+                name = "stone"
+                model = { type = "full_block", textures = { all = "stone" } }
+            "#,
+        ]);
+
+        let glass = &registry[registry.get_id("glass").unwrap()];
+        assert_eq!(glass.render_layer, RenderLayer::Translucent);
+        assert!(glass.model.face(FaceIndex::POS_X).is_some());
+        assert!(glass.occluding_face(FaceIndex::POS_X).is_none());
+        assert!(glass.is_transparent_in_direction(FaceIndex::POS_X));
+        assert!(!glass.is_fully_opaque());
+
+        let stone = &registry[registry.get_id("stone").unwrap()];
+        assert_eq!(stone.render_layer, RenderLayer::Opaque);
+        assert!(stone.occluding_face(FaceIndex::POS_X).is_some());
+        assert!(stone.is_fully_opaque());
+    }
+
+    #[test]
+    fn block_states() {
+        let registry = test_registry(&[
+            r#"
+                name = "stairs"
+                properties = ["facing", "half"]
+                model = { type = "stairs", facing = "neg_x", textures = { all = "stone" } }
+            "#,
+            r#"
+                name = "wood"
+                properties = ["axis"]
+                model = { type = "full_block", textures = { side = "bark", top = "rings", bottom = "rings" } }
+            "#,
+        ]);
+
+        // air, 8 states of stairs and 3 states of wood
+        assert_eq!(registry.len(), 12);
+
+        let stairs = registry.get_id("stairs").unwrap();
+        let top_stairs = registry.with_state(
+            stairs,
+            &BlockState {
+                facing: FaceIndex::NEG_Z,
+                half: SlabHalf::Top,
+                ..Default::default()
+            },
+        );
+        assert_eq!(registry[stairs].default_state, stairs);
+        assert_eq!(registry[top_stairs].default_state, stairs);
+        assert_eq!(registry[top_stairs].name, "stairs[facing=neg_z,half=top]");
+        assert_eq!(
+            registry.get_id("stairs[facing=neg_z,half=top]"),
+            Some(top_stairs)
+        );
+
+        // the full side of the stairs turns with the block, and moves to the top when upside down
+        assert!(registry[stairs].occluding_face(FaceIndex::NEG_X).is_some());
+        assert!(registry[stairs].occluding_face(FaceIndex::NEG_Y).is_some());
+        assert!(registry[top_stairs]
+            .occluding_face(FaceIndex::POS_Z)
+            .is_some());
+        assert!(registry[top_stairs]
+            .occluding_face(FaceIndex::POS_Y)
+            .is_some());
+        assert!(registry[top_stairs]
+            .occluding_face(FaceIndex::NEG_Y)
+            .is_none());
+
+        // the rings of the wood move to the ends of its axis
+        let wood = registry.get_id("wood").unwrap();
+        let wood_x = registry.with_state(
+            wood,
+            &BlockState {
+                axis: Axis::X,
+                ..Default::default()
+            },
+        );
+        let texture_name = |block_id, face_index| {
+            let face = registry[block_id].model.face(face_index).unwrap();
+            registry.texture_names()[face.texture_index].as_str()
+        };
+        assert_eq!(texture_name(wood, FaceIndex::POS_Y), "rings");
+        assert_eq!(texture_name(wood_x, FaceIndex::POS_X), "rings");
+        assert_eq!(texture_name(wood_x, FaceIndex::NEG_X), "rings");
+        assert_eq!(texture_name(wood_x, FaceIndex::POS_Y), "bark");
+    }
+
+    #[test]
+    fn invalid_definitions() {
+        let duplicate = parse_all(&[r#"name = "dirt""#, r#"name = "dirt""#]);
+        assert!(matches!(
+            duplicate,
+            Err(BlockRegistryError::DuplicateName(_))
+        ));
+
+        let missing_texture = parse_all(&[r#"
+                name = "grass"
+                model = { type = "full_block", textures = { side = "grass_side" } }
+            "#]);
+        assert!(matches!(
+            missing_texture,
+            Err(BlockRegistryError::MissingTexture(_, FaceIndex::POS_Y))
+        ));
+
+        let invalid_emission = parse_all(&[r#"
+            name = "lamp"
+            emission = [16, 0, 0]
+        "#]);
+        assert!(matches!(
+            invalid_emission,
+            Err(BlockRegistryError::InvalidEmission(_))
+        ));
+    }
+}
@@ -0,0 +1,284 @@
+use glam::{IVec3, Vec3};
+use serde::Deserialize;
+
+use super::model::SlabHalf;
+use crate::util::face::FaceIndex;
+
+/// Horizontal directions in the order of the values of the `facing` property. Each direction is a
+/// quarter turn from the previous one
+pub const HORIZONTAL_FACES: [FaceIndex; 4] = [
+    FaceIndex::POS_X,
+    FaceIndex::POS_Z,
+    FaceIndex::NEG_X,
+    FaceIndex::NEG_Z,
+];
+
+/// A property whose value varies between the states of a block type.
+/// The first value of each property is its default, which leaves the model as it is written in the
+/// block definition
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockProperty {
+    /// Axis the block is aligned with, e.g. logs. The top and bottom faces are moved to the ends
+    /// of the axis
+    Axis,
+    /// Horizontal direction the front of the block points in. The model is turned about the
+    /// vertical axis, where the model in the block definition faces `pos_x`
+    Facing,
+    /// Whether the block is in the bottom or top half of the cell. Slabs and stairs are flipped
+    /// upside down in the top half
+    Half,
+    /// Whether the block is open, e.g. doors
+    Open,
+}
+
+impl BlockProperty {
+    /// Returns the name of the property as written in block definitions and state names
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Axis => "axis",
+            Self::Facing => "facing",
+            Self::Half => "half",
+            Self::Open => "open",
+        }
+    }
+
+    /// Returns the names of the values of the property, in order of value
+    pub fn value_names(self) -> &'static [&'static str] {
+        match self {
+            Self::Axis => &["y", "x", "z"],
+            Self::Facing => &["pos_x", "pos_z", "neg_x", "neg_z"],
+            Self::Half => &["bottom", "top"],
+            Self::Open => &["false", "true"],
+        }
+    }
+
+    /// Returns the number of values the property can take
+    pub fn value_count(self) -> usize {
+        self.value_names().len()
+    }
+}
+
+/// Axis a block is aligned with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    #[default]
+    Y,
+    Z,
+}
+
+impl Axis {
+    /// Returns the axis along which the given vector is longest
+    pub fn from_dir(dir: IVec3) -> Self {
+        let abs = dir.abs();
+
+        if abs.x >= abs.y && abs.x >= abs.z {
+            Self::X
+        } else if abs.y >= abs.z {
+            Self::Y
+        } else {
+            Self::Z
+        }
+    }
+}
+
+/// Values of the properties of one state of a block type. Properties the block type doesn't
+/// declare keep their default values
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockState {
+    pub axis: Axis,
+    /// Always horizontal
+    pub facing: FaceIndex,
+    pub half: SlabHalf,
+    pub open: bool,
+}
+
+impl Default for BlockState {
+    fn default() -> Self {
+        Self {
+            axis: Axis::Y,
+            facing: FaceIndex::POS_X,
+            half: SlabHalf::Bottom,
+            open: false,
+        }
+    }
+}
+
+impl BlockState {
+    /// Returns the state with the given index out of the states of a block type with the given
+    /// properties. The value of the first property changes fastest as the index increases
+    pub fn from_index(properties: &[BlockProperty], mut index: usize) -> Self {
+        let mut state = Self::default();
+
+        for &property in properties {
+            state = state.with_value(property, index % property.value_count());
+            index /= property.value_count();
+        }
+
+        state
+    }
+
+    /// Returns the index of this state out of the states of a block type with the given
+    /// properties (the inverse of `from_index`)
+    pub fn index(&self, properties: &[BlockProperty]) -> usize {
+        properties.iter().rev().fold(0, |index, &property| {
+            index * property.value_count() + self.value(property)
+        })
+    }
+
+    /// Returns the number of states of a block type with the given properties
+    pub fn count(properties: &[BlockProperty]) -> usize {
+        properties
+            .iter()
+            .map(|property| property.value_count())
+            .product()
+    }
+
+    /// Returns the value of the given property, in 0..property.value_count()
+    pub fn value(&self, property: BlockProperty) -> usize {
+        match property {
+            BlockProperty::Axis => match self.axis {
+                Axis::Y => 0,
+                Axis::X => 1,
+                Axis::Z => 2,
+            },
+            BlockProperty::Facing => HORIZONTAL_FACES
+                .iter()
+                .position(|&face_index| face_index == self.facing)
+                .expect("facing should be horizontal"),
+            BlockProperty::Half => match self.half {
+                SlabHalf::Bottom => 0,
+                SlabHalf::Top => 1,
+            },
+            BlockProperty::Open => self.open as usize,
+        }
+    }
+
+    /// Returns a copy of the state with the given property set to the given value.
+    /// Panics if the value is out of range
+    pub fn with_value(mut self, property: BlockProperty, value: usize) -> Self {
+        match property {
+            BlockProperty::Axis => self.axis = [Axis::Y, Axis::X, Axis::Z][value],
+            BlockProperty::Facing => self.facing = HORIZONTAL_FACES[value],
+            BlockProperty::Half => self.half = [SlabHalf::Bottom, SlabHalf::Top][value],
+            BlockProperty::Open => self.open = [false, true][value],
+        }
+
+        self
+    }
+
+    /// Returns the name of the state of the block type with the given name and properties: the
+    /// name of the block type for the default state, otherwise the name of the block type followed
+    /// by the values of its properties, e.g. `stairs[facing=neg_x,half=top]`
+    pub fn name(&self, block_name: &str, properties: &[BlockProperty]) -> String {
+        if self.index(properties) == 0 {
+            return block_name.to_string();
+        }
+
+        let values = properties
+            .iter()
+            .map(|&property| {
+                format!(
+                    "{}={}",
+                    property.name(),
+                    property.value_names()[self.value(property)]
+                )
+            })
+            .collect::<Vec<_>>();
+
+        format!("{}[{}]", block_name, values.join(","))
+    }
+
+    /// Returns the state for a block placed against the face of another block with the given
+    /// normal, by a player looking in the given direction.
+    /// The block is aligned with the normal, faces back towards the player and is placed in the
+    /// top half of the cell when placed against the underside of a block
+    pub fn for_placement(hit_normal: IVec3, look_dir: Vec3) -> Self {
+        let facing = if look_dir.x.abs() > look_dir.z.abs() {
+            if look_dir.x > 0.0 {
+                FaceIndex::NEG_X
+            } else {
+                FaceIndex::POS_X
+            }
+        } else if look_dir.z > 0.0 {
+            FaceIndex::NEG_Z
+        } else {
+            FaceIndex::POS_Z
+        };
+
+        Self {
+            axis: Axis::from_dir(hit_normal),
+            facing,
+            half: if hit_normal == IVec3::NEG_Y {
+                SlabHalf::Top
+            } else {
+                SlabHalf::Bottom
+            },
+            open: false,
+        }
+    }
+
+    /// Returns the number of quarter turns from `pos_x` to the facing direction, in the same
+    /// direction as the order of `HORIZONTAL_FACES`
+    pub fn facing_quarter_turns(&self) -> u8 {
+        self.value(BlockProperty::Facing) as u8
+    }
+}
+
+/// Rotate a horizontal face index by the given number of quarter turns in the same direction as
+/// the order of `HORIZONTAL_FACES`. Vertical faces are unchanged
+pub fn rotate_face_y(face_index: FaceIndex, quarter_turns: u8) -> FaceIndex {
+    match HORIZONTAL_FACES.iter().position(|&face| face == face_index) {
+        Some(position) => HORIZONTAL_FACES[(position + quarter_turns as usize) % 4],
+        None => face_index,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn state_indices_and_names() {
+        let properties = [BlockProperty::Facing, BlockProperty::Half];
+        assert_eq!(BlockState::count(&properties), 8);
+
+        for index in 0..BlockState::count(&properties) {
+            assert_eq!(
+                BlockState::from_index(&properties, index).index(&properties),
+                index
+            );
+        }
+
+        assert_eq!(
+            BlockState::from_index(&properties, 0),
+            BlockState::default()
+        );
+        assert_eq!(BlockState::default().name("stairs", &properties), "stairs");
+
+        let state = BlockState {
+            facing: FaceIndex::NEG_Z,
+            half: SlabHalf::Top,
+            ..Default::default()
+        };
+        assert_eq!(state.index(&properties), 7);
+        assert_eq!(
+            state.name("stairs", &properties),
+            "stairs[facing=neg_z,half=top]"
+        );
+    }
+
+    #[test]
+    fn placement() {
+        let state = BlockState::for_placement(IVec3::NEG_Y, Vec3::new(0.2, 0.5, -0.8));
+        assert_eq!(state.axis, Axis::Y);
+        assert_eq!(state.facing, FaceIndex::POS_Z);
+        assert_eq!(state.half, SlabHalf::Top);
+
+        let state = BlockState::for_placement(IVec3::X, Vec3::new(-1.0, 0.0, 0.1));
+        assert_eq!(state.axis, Axis::X);
+        assert_eq!(state.facing, FaceIndex::POS_X);
+        assert_eq!(state.half, SlabHalf::Bottom);
+    }
+}
@@ -80,6 +80,48 @@ impl ChunkBlockStore {
         };
     }
 
+    /// Set many blocks at once, in order. Each layer containing a changed block is rebuilt once,
+    /// which is much faster than calling `set_block` for every block and keeps the palettes
+    /// compact.
+    /// Panics if any position is out of bounds
+    pub fn set_blocks(&mut self, changes: &[(LocalBlockPosition, BlockId)]) {
+        // the blocks of each layer with a change, ordered by z then x
+        let mut changed_layers: Vec<Option<Vec<BlockId>>> = vec![None; CHUNK_SIZE];
+
+        for &(pos, new_id) in changes {
+            let layer_blocks = changed_layers[pos.y() as usize].get_or_insert_with(|| match self {
+                Self::Uniform(block_id) => vec![*block_id; CHUNK_SIZE_SQUARED],
+                Self::Layered(layers) => layers[pos.y() as usize].iter().collect(),
+            });
+
+            layer_blocks[CHUNK_SIZE * pos.z() as usize + pos.x() as usize] = new_id;
+        }
+
+        let mut layers = match self {
+            Self::Uniform(block_id) => array_init::array_init(|_| BlockLayer::new(&[*block_id])),
+            Self::Layered(layers) => layers.clone(),
+        };
+
+        for (layer, layer_blocks) in layers.iter_mut().zip(changed_layers) {
+            if let Some(layer_blocks) = layer_blocks {
+                *layer = BlockLayer::new(&layer_blocks);
+            }
+        }
+
+        // the chunk may now be made of a single block
+        *self = match layers
+            .iter()
+            .map(|layer| match layer.palette() {
+                [block_id] => Some(*block_id),
+                _ => None,
+            })
+            .all_equal_value()
+        {
+            Ok(Some(block_id)) => Self::Uniform(block_id),
+            _ => Self::Layered(layers),
+        };
+    }
+
     /// If the chunk comprises of a single block type, returns it
     /// Otherwise returns `None`
     pub fn get_single_block(&self) -> Option<BlockId> {
@@ -92,7 +134,8 @@ impl ChunkBlockStore {
 
 /// Represents a horizontal layer of blocks in a chunk.
 /// Layers are compressed using a block palette, where each block is encoded as
-/// the index in the palette of that block.
+/// the index in the palette of that block. Since every state of a block type has its
+/// own `BlockId`, the palette holds states rather than block types.
 /// The length of each index is the smallest power of 2 number of bits needed to
 /// store the highest index.
 /// The length of the indices is confined to be a power of two so that each index
@@ -122,6 +165,68 @@ impl BlockLayer {
         }
     }
 
+    /// Reassemble a layer from the parts returned by `palette`, `element_size_bits` and
+    /// `segments`, returning None if they don't describe a valid layer
+    pub fn from_raw_parts(
+        palette: Vec<BlockId>,
+        element_size_bits: usize,
+        segments: Box<[usize]>,
+    ) -> Option<Self> {
+        let expected_segment_count = if element_size_bits == 0 {
+            0
+        } else if element_size_bits.is_power_of_two() && element_size_bits <= 16 {
+            div_pow2(CHUNK_SIZE_SQUARED * element_size_bits, usize::BITS as usize)
+        } else {
+            return None;
+        };
+
+        if palette.is_empty() || segments.len() != expected_segment_count {
+            return None;
+        }
+
+        let layer = Self {
+            segments,
+            element_size_bits,
+            palette: BlockPalette::from_block_lookup(palette),
+        };
+
+        // make sure every palette index is in range
+        if element_size_bits != 0 {
+            for y in 0..CHUNK_SIZE as u32 {
+                for x in 0..CHUNK_SIZE as u32 {
+                    let (segment_index, bit_index_in_segment) =
+                        layer.get_segment_index_and_bit_index_in_segment(x, y);
+
+                    let palette_index = mod_pow2(
+                        layer.segments[segment_index] >> bit_index_in_segment,
+                        1 << element_size_bits,
+                    );
+
+                    if palette_index >= layer.palette.len() {
+                        return None;
+                    }
+                }
+            }
+        }
+
+        Some(layer)
+    }
+
+    /// Blocks in the layer's palette, in order of palette index
+    pub fn palette(&self) -> &[BlockId] {
+        &self.palette.block_lookup
+    }
+
+    /// Number of bits used to store each palette index
+    pub fn element_size_bits(&self) -> usize {
+        self.element_size_bits
+    }
+
+    /// Packed palette indices for each block in the layer
+    pub fn segments(&self) -> &[usize] {
+        &self.segments
+    }
+
     pub fn get(&self, x: u32, y: u32) -> BlockId {
         if self.element_size_bits == 0 {
             self.palette.get_block_for_index(0)
@@ -285,6 +390,21 @@ impl BlockPalette {
         }
     }
 
+    /// Create a `BlockPalette` with the given blocks in order of index. The blocks need not be
+    /// unique, in which case the first index of each block is used for lookups
+    fn from_block_lookup(block_lookup: Vec<BlockId>) -> Self {
+        let mut index_lookup = FxHashMap::default();
+
+        for (index, block_id) in block_lookup.iter().copied().enumerate() {
+            index_lookup.entry(block_id).or_insert(index);
+        }
+
+        Self {
+            block_lookup,
+            index_lookup,
+        }
+    }
+
     /// If the block palette has an entry for the given block, returns the
     /// index of the block in the palette.
     /// Otherwise adds the given block to the palette and returns its new index.
@@ -340,6 +460,36 @@ const fn mod_pow2(a: usize, b: usize) -> usize {
 mod tests {
     use super::*;
 
+    #[test]
+    fn set_blocks() {
+        let mut block_store = ChunkBlockStore::Uniform(BlockId(0));
+
+        block_store.set_blocks(&[
+            (LocalBlockPosition::new(1, 2, 3), BlockId(1)),
+            (LocalBlockPosition::new(4, 2, 3), BlockId(2)),
+            (LocalBlockPosition::new(1, 2, 3), BlockId(3)),
+        ]);
+        assert_eq!(
+            block_store.get_block(LocalBlockPosition::new(1, 2, 3)),
+            BlockId(3)
+        );
+        assert_eq!(
+            block_store.get_block(LocalBlockPosition::new(4, 2, 3)),
+            BlockId(2)
+        );
+        assert_eq!(
+            block_store.get_block(LocalBlockPosition::new(4, 3, 3)),
+            BlockId(0)
+        );
+
+        // filling every block makes the chunk uniform again
+        let changes = (0..CHUNK_SIZE_CUBED)
+            .map(|index| (LocalBlockPosition::from_array_index(index), BlockId(5)))
+            .collect_vec();
+        block_store.set_blocks(&changes);
+        assert_eq!(block_store.get_single_block(), Some(BlockId(5)));
+    }
+
     #[test]
     fn block_layer() {
         {
@@ -3,7 +3,7 @@ use std::collections::VecDeque;
 use glam::UVec3;
 
 use super::super::{
-    block::{BlockId, BLOCKS},
+    block::{registry::BlockRegistry, BlockId},
     chunk::{CHUNK_SIZE_CUBED, CHUNK_SIZE_U32},
     position_types::LocalBlockPosition,
 };
@@ -16,7 +16,7 @@ pub struct ChunkConnections(u16);
 
 impl ChunkConnections {
     /// Compute the connections for the given block array
-    pub fn compute(blocks: &[BlockId]) -> Self {
+    pub fn compute(blocks: &[BlockId], block_registry: &BlockRegistry) -> Self {
         let mut connection_bits: u16 = 0;
         let mut explored = [false; CHUNK_SIZE_CUBED];
         let mut frontier = VecDeque::new();
@@ -44,11 +44,7 @@ impl ChunkConnections {
                     }
 
                     // skip opaque blocks
-                    if BLOCKS[blocks[array_index].0 as usize]
-                        .model
-                        .opaque_faces_mask()
-                        != 0
-                    {
+                    if block_registry[blocks[array_index]].is_fully_opaque() {
                         continue;
                     }
 
@@ -70,11 +66,7 @@ impl ChunkConnections {
                                 if explored[array_index] {
                                     continue;
                                 }
-                                if BLOCKS[blocks[array_index].0 as usize]
-                                    .model
-                                    .opaque_faces_mask()
-                                    != 0
-                                {
+                                if block_registry[blocks[array_index]].is_fully_opaque() {
                                     continue;
                                 }
                                 frontier.push_back(neighbour_pos);
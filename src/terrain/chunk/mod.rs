@@ -1,27 +1,34 @@
-use glam::{IVec3, Vec3};
 use itertools::Itertools;
+use rustc_hash::FxHashMap;
 
 use self::{
     block_store::ChunkBlockStore, connections::ChunkConnections, light_store::ChunkLightStore,
     side::ChunkSideLight,
 };
 use super::{
-    block::{BlockId, BLOCKS, BLOCK_AIR},
+    block::{entity::BlockEntity, registry::BlockRegistry, BlockId, BLOCK_AIR},
+    generation::{
+        biome::{BiomeId, ChunkBiomes},
+        feature::merge_feature_block,
+    },
     lighting::{
         emitted_light::{
             get_initial_emitted_light_queue, propagate_emitted_light,
             propagate_emitted_light_shadow, EmittedLight,
         },
-        skylight::{get_initial_skylight_queue, propagate_skylight, Skylight},
+        skylight::{
+            get_initial_skylight_queue, propagate_skylight, propagate_skylight_shadow, Skylight,
+        },
         LightPropagationQueue, LightUpdate, LightUpdatesOutsideChunk, ShadowPropagationQueue,
         ShadowPropagationStep,
     },
     position_types::{ChunkPosition, LocalBlockPosition},
+    save::SavedChunk,
+    tick::ScheduledTick,
 };
 use crate::util::{
     face::FaceIndex,
     size::{Size2, Size3},
-    vector_map::VectorMapExt,
 };
 
 pub mod block_store;
@@ -37,7 +44,6 @@ pub const CHUNK_SIZE_SQUARED: usize = (CHUNK_SIZE_U32 * CHUNK_SIZE_U32) as usize
 pub const CHUNK_SIZE_CUBED: usize = (CHUNK_SIZE_U32 * CHUNK_SIZE_U32 * CHUNK_SIZE_U32) as usize;
 pub const CHUNK_SIZE_U32: u32 = CHUNK_SIZE as u32;
 pub const CHUNK_SIZE_I32: i32 = CHUNK_SIZE as i32;
-pub const CHUNK_SIZE_RECIP: f32 = 1.0 / (CHUNK_SIZE as f32);
 
 #[derive(Clone, Debug)]
 pub struct Chunk {
@@ -46,20 +52,77 @@ pub struct Chunk {
     emitted_light_queue: LightPropagationQueue<EmittedLight>,
     emitted_light_shadow_queue: ShadowPropagationQueue,
     skylight_queue: LightPropagationQueue<Skylight>,
+    skylight_shadow_queue: ShadowPropagationQueue,
     position: ChunkPosition,
     connections: ChunkConnections,
+    /// Biome of each column of the chunk
+    biomes: ChunkBiomes,
+    /// True if the chunk has been modified since it was last saved
+    is_modified: bool,
+    /// True if the features belonging to the chunk have been placed
+    is_decorated: bool,
+    /// Block updates requested for future ticks
+    scheduled_ticks: Vec<ScheduledTick>,
+    /// Extra state for the blocks which have it
+    block_entities: FxHashMap<LocalBlockPosition, BlockEntity>,
 }
 
 impl Chunk {
-    pub fn new(position: ChunkPosition, blocks: &[BlockId]) -> Self {
+    pub fn new(
+        position: ChunkPosition,
+        blocks: &[BlockId],
+        biomes: ChunkBiomes,
+        block_registry: &BlockRegistry,
+    ) -> Self {
+        Self::from_block_store(
+            position,
+            ChunkBlockStore::new(blocks),
+            blocks,
+            biomes,
+            false,
+            block_registry,
+        )
+    }
+
+    /// Create a chunk from data loaded from a save
+    pub fn from_saved_chunk(
+        position: ChunkPosition,
+        saved_chunk: SavedChunk,
+        biomes: ChunkBiomes,
+        block_registry: &BlockRegistry,
+    ) -> Self {
+        let blocks = saved_chunk.block_store.as_block_array();
+
+        let mut chunk = Self::from_block_store(
+            position,
+            saved_chunk.block_store,
+            &blocks,
+            biomes,
+            saved_chunk.is_decorated,
+            block_registry,
+        );
+        chunk.scheduled_ticks = saved_chunk.scheduled_ticks;
+        chunk.block_entities = saved_chunk.block_entities;
+
+        chunk
+    }
+
+    fn from_block_store(
+        position: ChunkPosition,
+        block_store: ChunkBlockStore,
+        blocks: &[BlockId],
+        biomes: ChunkBiomes,
+        is_decorated: bool,
+        block_registry: &BlockRegistry,
+    ) -> Self {
         // this function is called from a parallel thread so it's OK to perform intensive tasks
         // here
-        let block_store = ChunkBlockStore::new(blocks);
-        let connections = ChunkConnections::compute(blocks);
+        let connections = ChunkConnections::compute(blocks, block_registry);
         let light_store = ChunkLightStore::new();
         let emitted_light_queue = LightPropagationQueue::new();
         let emitted_light_shadow_queue = ShadowPropagationQueue::new();
         let skylight_queue = LightPropagationQueue::new();
+        let skylight_shadow_queue = ShadowPropagationQueue::new();
 
         Self {
             block_store,
@@ -67,8 +130,24 @@ impl Chunk {
             emitted_light_queue,
             emitted_light_shadow_queue,
             skylight_queue,
+            skylight_shadow_queue,
             position,
             connections,
+            biomes,
+            is_modified: false,
+            is_decorated,
+            scheduled_ticks: Vec::new(),
+            block_entities: FxHashMap::default(),
+        }
+    }
+
+    /// Returns the data to be written to the world save for this chunk
+    pub fn to_saved_chunk(&self) -> SavedChunk {
+        SavedChunk {
+            block_store: self.block_store.clone(),
+            is_decorated: self.is_decorated,
+            scheduled_ticks: self.scheduled_ticks.clone(),
+            block_entities: self.block_entities.clone(),
         }
     }
 
@@ -88,6 +167,12 @@ impl Chunk {
         self.block_store.get_block(pos)
     }
 
+    /// Returns the biome ID of the column containing the given position
+    /// Panics if the position is out of bounds
+    pub fn get_biome(&self, pos: LocalBlockPosition) -> BiomeId {
+        self.biomes.get(pos.x(), pos.z())
+    }
+
     /// Update the block ID at the given position and perform light updates
     /// Panics if the position is out of bounds
     pub fn set_block(&mut self, pos: LocalBlockPosition, new_id: BlockId) {
@@ -97,6 +182,7 @@ impl Chunk {
         }
 
         self.block_store.set_block(pos, new_id);
+        self.is_modified = true;
 
         // Update emitted light shadow propagation queue
         self.emitted_light_shadow_queue
@@ -104,71 +190,161 @@ impl Chunk {
                 position: pos,
                 depth: EmittedLight::MAX_VALUE,
             });
+
+        // Update skylight shadow propagation queue
+        self.skylight_shadow_queue.push_back(ShadowPropagationStep {
+            position: pos,
+            depth: Skylight::MAX_VALUE as u32,
+        });
     }
 
-    /// Returns this chunk's position
-    pub fn position(&self) -> ChunkPosition {
-        self.position
+    /// Update many blocks at once, in order, and perform light updates for the blocks which
+    /// changed. This is much faster than calling `set_block` for each block.
+    /// Panics if any position is out of bounds
+    pub fn set_blocks(&mut self, changes: &[(LocalBlockPosition, BlockId)]) {
+        let changes = changes
+            .iter()
+            .filter(|&&(pos, new_id)| self.block_store.get_block(pos) != new_id)
+            .copied()
+            .collect_vec();
+        if changes.is_empty() {
+            return;
+        }
+
+        self.block_store.set_blocks(&changes);
+        self.is_modified = true;
+
+        for (pos, _) in changes {
+            self.emitted_light_shadow_queue
+                .push_back(ShadowPropagationStep {
+                    position: pos,
+                    depth: EmittedLight::MAX_VALUE,
+                });
+            self.skylight_shadow_queue.push_back(ShadowPropagationStep {
+                position: pos,
+                depth: Skylight::MAX_VALUE as u32,
+            });
+        }
     }
 
-    /// Returns the computed connections for this chunk
-    pub fn connections(&self) -> ChunkConnections {
-        self.connections
+    /// True if the chunk has been modified since it was loaded or last saved
+    pub fn is_modified(&self) -> bool {
+        self.is_modified
     }
 
-    /// Marches through the chunk along the ray with the given origin and direction, using the DDA
-    /// algorithm
-    /// If a block was hit, returns the position of that block in the chunk and face index of the
-    /// hit face
-    pub fn raymarch(
-        &self,
-        ray_origin: Vec3,
-        ray_direction: Vec3,
-        previous_chunk_pos: Option<ChunkPosition>,
-        maximum_distance: f32,
-    ) -> Option<ChunkHit> {
-        pub const EPS: f32 = 1e-3;
-
-        let dir_step = ray_direction.map(|component| if component >= 0.0 { 1.0 } else { 0.0 });
-        let dir_recip = ray_direction.recip();
-
-        let mut t = 0.0;
-        let mut previous_block_pos: Option<LocalBlockPosition> = None;
-
-        while t < maximum_distance {
-            let ray_pos = ray_origin + ray_direction * t;
-            let block_pos = ray_pos.floor().as_ivec3();
-
-            if !Size3::splat(CHUNK_SIZE).contains_ivec3(block_pos) {
-                // escaped chunk; no intersection
-                return None;
-            }
+    /// Record that the chunk's current blocks have been saved
+    pub fn mark_saved(&mut self) {
+        self.is_modified = false;
+    }
 
-            let block_pos = LocalBlockPosition::from(block_pos.as_uvec3());
-            if self.get_block(block_pos) != BLOCK_AIR {
-                // hit a block
-                return Some(ChunkHit {
-                    local_hit_pos: block_pos,
-                    hit_normal: previous_block_pos
-                        .map(|previous_block_pos| {
-                            previous_block_pos.as_ivec3() - block_pos.as_ivec3()
-                        })
-                        .or_else(|| {
-                            previous_chunk_pos.map(|previous_chunk_pos| {
-                                previous_chunk_pos.as_ivec3() - self.position().as_ivec3()
-                            })
-                        }),
-                });
-            }
+    /// True if the features belonging to the chunk have been placed
+    pub fn is_decorated(&self) -> bool {
+        self.is_decorated
+    }
+
+    /// Record that the features belonging to the chunk have been placed
+    pub fn mark_decorated(&mut self) {
+        self.is_decorated = true;
+        self.is_modified = true;
+    }
 
-            // advance to the next block position
-            let deltas = (dir_step - ray_pos.fract_gl()) * dir_recip;
-            t += deltas.min_element().max(EPS);
+    /// Returns the block entity at the given position, if there is one
+    pub fn get_block_entity(&self, pos: LocalBlockPosition) -> Option<&BlockEntity> {
+        self.block_entities.get(&pos)
+    }
+
+    /// Returns the block entity at the given position for modification, if there is one. The
+    /// chunk is marked as modified
+    pub fn get_block_entity_mut(&mut self, pos: LocalBlockPosition) -> Option<&mut BlockEntity> {
+        let block_entity = self.block_entities.get_mut(&pos)?;
+        self.is_modified = true;
+
+        Some(block_entity)
+    }
+
+    /// Set or remove the block entity at the given position, returning the previous block entity
+    pub fn set_block_entity(
+        &mut self,
+        pos: LocalBlockPosition,
+        block_entity: Option<BlockEntity>,
+    ) -> Option<BlockEntity> {
+        let old_block_entity = match block_entity {
+            Some(block_entity) => self.block_entities.insert(pos, block_entity),
+            None => self.block_entities.remove(&pos),
+        };
+        self.is_modified = true;
+
+        old_block_entity
+    }
+
+    /// Request an update of the block at the given position on a future tick
+    pub fn schedule_tick(&mut self, scheduled_tick: ScheduledTick) {
+        if !self.scheduled_ticks.contains(&scheduled_tick) {
+            self.scheduled_ticks.push(scheduled_tick);
+            self.is_modified = true;
+        }
+    }
+
+    /// Remove the scheduled ticks which are due on or before the tick with the given index,
+    /// returning the positions to update
+    pub fn take_due_ticks(&mut self, tick_index: u64) -> Vec<LocalBlockPosition> {
+        if self
+            .scheduled_ticks
+            .iter()
+            .all(|scheduled_tick| scheduled_tick.tick_index > tick_index)
+        {
+            return Vec::new();
+        }
+
+        let (due_ticks, pending_ticks) = self
+            .scheduled_ticks
+            .iter()
+            .partition::<Vec<_>, _>(|scheduled_tick| scheduled_tick.tick_index <= tick_index);
+        self.scheduled_ticks = pending_ticks;
+        self.is_modified = true;
+
+        due_ticks
+            .into_iter()
+            .map(|scheduled_tick| scheduled_tick.pos)
+            .collect()
+    }
 
-            previous_block_pos = Some(block_pos);
+    /// Place feature blocks in a newly loaded chunk, before its lighting is initialized.
+    /// Each block is merged with the existing block using `merge_feature_block`, replacing the
+    /// block entity of the existing block with a new one if the block has one
+    pub fn place_features(
+        &mut self,
+        placements: &[(LocalBlockPosition, BlockId)],
+        block_registry: &BlockRegistry,
+    ) {
+        for &(pos, block_id) in placements {
+            let old_id = self.block_store.get_block(pos);
+            let new_id = merge_feature_block(old_id, block_id, block_registry);
+
+            if new_id != old_id {
+                self.block_store.set_block(pos, new_id);
+                self.set_block_entity(
+                    pos,
+                    block_registry[new_id]
+                        .block_entity
+                        .map(|kind| kind.create()),
+                );
+                self.is_modified = true;
+            }
         }
 
-        None
+        self.connections =
+            ChunkConnections::compute(&self.block_store.as_block_array(), block_registry);
+    }
+
+    /// Returns this chunk's position
+    pub fn position(&self) -> ChunkPosition {
+        self.position
+    }
+
+    /// Returns the computed connections for this chunk
+    pub fn connections(&self) -> ChunkConnections {
+        self.connections
     }
 
     /// True if the chunk has pending light updates
@@ -177,16 +353,22 @@ impl Chunk {
             self.emitted_light_queue.len(),
             self.emitted_light_shadow_queue.len(),
             self.skylight_queue.len(),
+            self.skylight_shadow_queue.len(),
         ]
         .into_iter()
         .any(|len| len == 0)
     }
 
     /// Setup the light propagation queues for a newly loaded chunk
-    pub fn initialize_lighting(&mut self, surrounding_sides_light: &[Option<ChunkSideLight>]) {
+    pub fn initialize_lighting(
+        &mut self,
+        surrounding_sides_light: &[Option<ChunkSideLight>],
+        block_registry: &BlockRegistry,
+    ) {
         self.emitted_light_queue = get_initial_emitted_light_queue(
             &self.block_store.as_block_array(),
             surrounding_sides_light,
+            block_registry,
         );
 
         // don't bother computing skylight for air chunks at the top of the world
@@ -209,6 +391,7 @@ impl Chunk {
             self.skylight_queue = get_initial_skylight_queue(
                 &self.block_store.as_block_array(),
                 surrounding_sides_light,
+                block_registry,
             );
         }
     }
@@ -219,6 +402,7 @@ impl Chunk {
         &mut self,
         light_update: LightUpdate,
         neighbour_index: FaceIndex,
+        block_registry: &BlockRegistry,
     ) {
         match light_update {
             LightUpdate::EmittedLight(step) => {
@@ -227,10 +411,9 @@ impl Chunk {
                     EmittedLight::less(existing_light_value, step.light) != 0;
 
                 let block_id = self.block_store.get_block(step.position);
-                let block = &BLOCKS[block_id.as_usize()];
-                let can_pass_into_chunk = block
-                    .model
-                    .is_transparent_in_direction(neighbour_index.opposite());
+                let block = &block_registry[block_id];
+                let can_pass_into_chunk =
+                    block.is_transparent_in_direction(neighbour_index.opposite());
 
                 if would_increase_light && can_pass_into_chunk {
                     self.emitted_light_queue.push_back(step)
@@ -244,21 +427,23 @@ impl Chunk {
                 let would_increase_light = existing_light_value < step.light;
 
                 let block_id = self.block_store.get_block(step.position);
-                let block = &BLOCKS[block_id.as_usize()];
-                let can_pass_into_chunk = block
-                    .model
-                    .is_transparent_in_direction(neighbour_index.opposite());
+                let block = &block_registry[block_id];
+                let can_pass_into_chunk =
+                    block.is_transparent_in_direction(neighbour_index.opposite());
 
                 if would_increase_light && can_pass_into_chunk {
                     self.skylight_queue.push_back(step)
                 }
             }
+            LightUpdate::SkylightShadow(step) => {
+                self.skylight_shadow_queue.push_back(step);
+            }
         }
     }
 
     /// Propagate light and shadow within the chunk, returning the light updates to be applied
     /// outside of the chunk
-    pub fn update_lighting(&mut self) -> LightUpdatesOutsideChunk {
+    pub fn update_lighting(&mut self, block_registry: &BlockRegistry) -> LightUpdatesOutsideChunk {
         let mut light_updates_outside_chunk = LightUpdatesOutsideChunk::new();
 
         propagate_emitted_light_shadow(
@@ -267,6 +452,7 @@ impl Chunk {
             &mut self.emitted_light_queue,
             &mut light_updates_outside_chunk,
             &self.block_store,
+            block_registry,
         );
 
         propagate_emitted_light(
@@ -274,6 +460,14 @@ impl Chunk {
             &mut self.emitted_light_queue,
             &mut light_updates_outside_chunk,
             &self.block_store,
+            block_registry,
+        );
+
+        propagate_skylight_shadow(
+            &mut self.light_store,
+            &mut self.skylight_shadow_queue,
+            &mut self.skylight_queue,
+            &mut light_updates_outside_chunk,
         );
 
         propagate_skylight(
@@ -281,14 +475,9 @@ impl Chunk {
             &mut self.skylight_queue,
             &mut light_updates_outside_chunk,
             &self.block_store,
+            block_registry,
         );
 
         light_updates_outside_chunk
     }
 }
-
-/// Returned by `Chunk::raymarch` if a block was hit
-pub struct ChunkHit {
-    pub local_hit_pos: LocalBlockPosition,
-    pub hit_normal: Option<IVec3>,
-}
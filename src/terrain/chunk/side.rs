@@ -4,7 +4,7 @@ use generational_arena::Index;
 
 use super::{
     super::{
-        block::BLOCKS,
+        block::{registry::BlockRegistry, BlockId, BLOCK_AIR},
         position_types::{ChunkPosition, LocalBlockPosition},
         Terrain,
     },
@@ -23,20 +23,24 @@ use crate::{
 #[derive(Clone, Debug)]
 pub struct ChunkSideFaces {
     pub faces: Arc<[bool; CHUNK_SIZE_SQUARED]>,
+    /// ID of the block at each tile, used to cull the faces between identical translucent blocks
+    pub blocks: Arc<[BlockId; CHUNK_SIZE_SQUARED]>,
 }
 
 impl ChunkSideFaces {
-    pub fn px(chunk: &Chunk) -> Self {
+    pub fn px(chunk: &Chunk, block_registry: &BlockRegistry) -> Self {
         let mut index = 0;
         let mut faces = [false; CHUNK_SIZE_SQUARED];
+        let mut blocks = [BLOCK_AIR; CHUNK_SIZE_SQUARED];
 
         for v in 0..CHUNK_SIZE_U32 {
             for u in 0..CHUNK_SIZE_U32 {
                 let pos_in_chunk = LocalBlockPosition::new(CHUNK_SIZE_U32 - 1, v, u);
 
                 let block_id = chunk.get_block(pos_in_chunk);
-                let block = &BLOCKS[block_id.0 as usize];
-                faces[index] = block.model.face(FaceIndex::POS_X).is_none();
+                let block = &block_registry[block_id];
+                faces[index] = block.occluding_face(FaceIndex::POS_X).is_none();
+                blocks[index] = block_id;
 
                 index += 1;
             }
@@ -44,101 +48,117 @@ impl ChunkSideFaces {
 
         Self {
             faces: faces.into(),
+            blocks: blocks.into(),
         }
     }
 
-    pub fn py(chunk: &Chunk) -> Self {
+    pub fn py(chunk: &Chunk, block_registry: &BlockRegistry) -> Self {
         let mut faces = [false; CHUNK_SIZE_SQUARED];
+        let mut blocks = [BLOCK_AIR; CHUNK_SIZE_SQUARED];
         let mut index = 0;
 
         for v in 0..CHUNK_SIZE_U32 {
             for u in 0..CHUNK_SIZE_U32 {
                 let pos_in_chunk = LocalBlockPosition::new(v, CHUNK_SIZE_U32 - 1, u);
                 let block_id = chunk.get_block(pos_in_chunk);
-                let block = &BLOCKS[block_id.0 as usize];
-                faces[index] = block.model.face(FaceIndex::POS_Y).is_none();
+                let block = &block_registry[block_id];
+                faces[index] = block.occluding_face(FaceIndex::POS_Y).is_none();
+                blocks[index] = block_id;
                 index += 1;
             }
         }
 
         Self {
             faces: faces.into(),
+            blocks: blocks.into(),
         }
     }
 
-    pub fn pz(chunk: &Chunk) -> Self {
+    pub fn pz(chunk: &Chunk, block_registry: &BlockRegistry) -> Self {
         let mut faces = [false; CHUNK_SIZE_SQUARED];
+        let mut blocks = [BLOCK_AIR; CHUNK_SIZE_SQUARED];
         let mut index = 0;
 
         for v in 0..CHUNK_SIZE_U32 {
             for u in 0..CHUNK_SIZE_U32 {
                 let pos_in_chunk = LocalBlockPosition::new(u, v, CHUNK_SIZE_U32 - 1);
                 let block_id = chunk.get_block(pos_in_chunk);
-                let block = &BLOCKS[block_id.0 as usize];
-                faces[index] = block.model.face(FaceIndex::POS_Z).is_none();
+                let block = &block_registry[block_id];
+                faces[index] = block.occluding_face(FaceIndex::POS_Z).is_none();
+                blocks[index] = block_id;
                 index += 1;
             }
         }
 
         Self {
             faces: faces.into(),
+            blocks: blocks.into(),
         }
     }
 
-    pub fn nx(chunk: &Chunk) -> Self {
+    pub fn nx(chunk: &Chunk, block_registry: &BlockRegistry) -> Self {
         let mut faces = [false; CHUNK_SIZE_SQUARED];
+        let mut blocks = [BLOCK_AIR; CHUNK_SIZE_SQUARED];
         let mut index = 0;
 
         for v in 0..CHUNK_SIZE_U32 {
             for u in 0..CHUNK_SIZE_U32 {
                 let pos_in_chunk = LocalBlockPosition::new(0, v, u);
                 let block_id = chunk.get_block(pos_in_chunk);
-                let block = &BLOCKS[block_id.0 as usize];
-                faces[index] = block.model.face(FaceIndex::NEG_X).is_none();
+                let block = &block_registry[block_id];
+                faces[index] = block.occluding_face(FaceIndex::NEG_X).is_none();
+                blocks[index] = block_id;
                 index += 1;
             }
         }
 
         Self {
             faces: faces.into(),
+            blocks: blocks.into(),
         }
     }
 
-    pub fn ny(chunk: &Chunk) -> Self {
+    pub fn ny(chunk: &Chunk, block_registry: &BlockRegistry) -> Self {
         let mut faces = [false; CHUNK_SIZE_SQUARED];
+        let mut blocks = [BLOCK_AIR; CHUNK_SIZE_SQUARED];
         let mut index = 0;
 
         for v in 0..CHUNK_SIZE_U32 {
             for u in 0..CHUNK_SIZE_U32 {
                 let pos_in_chunk = LocalBlockPosition::new(v, 0, u);
                 let block_id = chunk.get_block(pos_in_chunk);
-                let block = &BLOCKS[block_id.0 as usize];
-                faces[index] = block.model.face(FaceIndex::NEG_Y).is_none();
+                let block = &block_registry[block_id];
+                faces[index] = block.occluding_face(FaceIndex::NEG_Y).is_none();
+                blocks[index] = block_id;
                 index += 1;
             }
         }
 
         Self {
             faces: faces.into(),
+            blocks: blocks.into(),
         }
     }
 
-    pub fn nz(chunk: &Chunk) -> Self {
+    pub fn nz(chunk: &Chunk, block_registry: &BlockRegistry) -> Self {
         let mut faces = [false; CHUNK_SIZE_SQUARED];
+        let mut blocks = [BLOCK_AIR; CHUNK_SIZE_SQUARED];
         let mut index = 0;
 
         for v in 0..CHUNK_SIZE_U32 {
             for u in 0..CHUNK_SIZE_U32 {
                 let pos_in_chunk = LocalBlockPosition::new(u, v, 0);
                 let block_id = chunk.get_block(pos_in_chunk);
-                let block = &BLOCKS[block_id.0 as usize];
-                faces[index] = block.model.face(FaceIndex::NEG_Z).is_none();
+                let block = &block_registry[block_id];
+                faces[index] = block.occluding_face(FaceIndex::NEG_Z).is_none();
+                blocks[index] = block_id;
                 index += 1;
             }
         }
 
         Self {
             faces: faces.into(),
+            blocks: blocks.into(),
         }
     }
 
@@ -150,31 +170,31 @@ impl ChunkSideFaces {
     ) -> Vec<Option<ChunkSideFaces>> {
         let side_px = terrain
             .get_chunk(load_area_index, &(center_pos + ChunkPosition::new(1, 0, 0)))
-            .map(ChunkSideFaces::nx);
+            .map(|chunk| ChunkSideFaces::nx(chunk, terrain.block_registry()));
         let side_py = terrain
             .get_chunk(load_area_index, &(center_pos + ChunkPosition::new(0, 1, 0)))
-            .map(ChunkSideFaces::ny);
+            .map(|chunk| ChunkSideFaces::ny(chunk, terrain.block_registry()));
         let side_pz = terrain
             .get_chunk(load_area_index, &(center_pos + ChunkPosition::new(0, 0, 1)))
-            .map(ChunkSideFaces::nz);
+            .map(|chunk| ChunkSideFaces::nz(chunk, terrain.block_registry()));
         let side_nx = terrain
             .get_chunk(
                 load_area_index,
                 &(center_pos + ChunkPosition::new(-1, 0, 0)),
             )
-            .map(ChunkSideFaces::px);
+            .map(|chunk| ChunkSideFaces::px(chunk, terrain.block_registry()));
         let side_ny = terrain
             .get_chunk(
                 load_area_index,
                 &(center_pos + ChunkPosition::new(0, -1, 0)),
             )
-            .map(ChunkSideFaces::py);
+            .map(|chunk| ChunkSideFaces::py(chunk, terrain.block_registry()));
         let side_nz = terrain
             .get_chunk(
                 load_area_index,
                 &(center_pos + ChunkPosition::new(0, 0, -1)),
             )
-            .map(ChunkSideFaces::pz);
+            .map(|chunk| ChunkSideFaces::pz(chunk, terrain.block_registry()));
 
         vec![side_px, side_py, side_pz, side_nx, side_ny, side_nz]
     }
@@ -0,0 +1,153 @@
+use glam::Vec3;
+
+use super::{
+    block::{model::BlockBox, registry::BlockRegistry, BlockId},
+    position_types::GlobalBlockPosition,
+};
+
+/// Distance within which two boxes are considered to be touching, to avoid boxes getting stuck
+/// in each other due to floating point error
+const TOUCHING_EPS: f32 = 1e-4;
+
+/// Axis-aligned bounding box in world space
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub const fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    /// Returns the box with the given width and depth and height, whose base is centered on
+    /// `base_center`
+    pub fn from_base(base_center: Vec3, half_width: f32, height: f32) -> Self {
+        Self {
+            min: base_center - Vec3::new(half_width, 0.0, half_width),
+            max: base_center + Vec3::new(half_width, height, half_width),
+        }
+    }
+
+    /// Returns the box moved by `offset`
+    pub fn translated(&self, offset: Vec3) -> Self {
+        Self {
+            min: self.min + offset,
+            max: self.max + offset,
+        }
+    }
+
+    /// Returns the box grown to cover everything it passes through when moved by `motion`
+    pub fn expanded_towards(&self, motion: Vec3) -> Self {
+        Self {
+            min: self.min + motion.min(Vec3::ZERO),
+            max: self.max + motion.max(Vec3::ZERO),
+        }
+    }
+
+    /// True if the boxes overlap by more than touching
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        (self.min + TOUCHING_EPS).cmplt(other.max).all()
+            && (other.min + TOUCHING_EPS).cmplt(self.max).all()
+    }
+
+    /// Returns how far this box can move along the given axis, up to `motion`, before it runs
+    /// into `other`
+    pub fn clip_motion(&self, other: &Aabb, axis: usize, motion: f32) -> f32 {
+        // the boxes can only collide if they overlap on the other two axes
+        let overlaps = (0..3)
+            .filter(|&other_axis| other_axis != axis)
+            .all(|other_axis| {
+                self.min[other_axis] + TOUCHING_EPS < other.max[other_axis]
+                    && other.min[other_axis] + TOUCHING_EPS < self.max[other_axis]
+            });
+        if !overlaps {
+            return motion;
+        }
+
+        if motion > 0.0 && self.max[axis] <= other.min[axis] + TOUCHING_EPS {
+            motion.min(other.min[axis] - self.max[axis]).max(0.0)
+        } else if motion < 0.0 && self.min[axis] >= other.max[axis] - TOUCHING_EPS {
+            motion.max(other.max[axis] - self.min[axis]).min(0.0)
+        } else {
+            motion
+        }
+    }
+}
+
+/// Returns the collision boxes of the blocks in the cells overlapping `region`, in world space.
+/// The boxes themselves do not necessarily overlap `region`.
+/// Cells where `get_block` returns None, such as cells in chunks which are not loaded, are solid
+/// so that nothing can move into them
+pub fn block_collision_boxes(
+    region: &Aabb,
+    block_registry: &BlockRegistry,
+    get_block: impl Fn(&GlobalBlockPosition) -> Option<BlockId>,
+) -> Vec<Aabb> {
+    let min = region.min.floor().as_ivec3();
+    let max = region.max.ceil().as_ivec3();
+
+    itertools::iproduct!(min.x..max.x, min.y..max.y, min.z..max.z)
+        .flat_map(|(x, y, z)| {
+            let block_pos = GlobalBlockPosition::new(x, y, z);
+            let cell_min = block_pos.as_ivec3().as_vec3();
+
+            let boxes = match get_block(&block_pos) {
+                Some(block_id) => block_registry[block_id].model.collision_boxes(),
+                None => &[BlockBox::FULL],
+            };
+
+            boxes
+                .iter()
+                .map(move |block_box| Aabb::new(cell_min + block_box.min, cell_min + block_box.max))
+        })
+        .collect()
+}
+
+/// Moves the box by `motion`, stopping at the first obstacle on each axis. The vertical axis is
+/// resolved first, so that a body falling onto the ground lands before sliding along it.
+/// Returns the motion that could be made
+pub fn sweep_aabb(aabb: &Aabb, motion: Vec3, obstacles: &[Aabb]) -> Vec3 {
+    let mut aabb = *aabb;
+    let mut result = Vec3::ZERO;
+
+    for axis in [1, 0, 2] {
+        let axis_motion = obstacles
+            .iter()
+            .fold(motion[axis], |axis_motion, obstacle| {
+                aabb.clip_motion(obstacle, axis, axis_motion)
+            });
+
+        result[axis] = axis_motion;
+
+        let mut offset = Vec3::ZERO;
+        offset[axis] = axis_motion;
+        aabb = aabb.translated(offset);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sweeping() {
+        let body = Aabb::from_base(Vec3::new(0.5, 1.0, 0.5), 0.3, 1.8);
+        let floor = Aabb::new(Vec3::new(-5.0, 0.0, -5.0), Vec3::new(5.0, 1.0, 5.0));
+        let wall = Aabb::new(Vec3::new(2.0, 1.0, -5.0), Vec3::new(3.0, 3.0, 5.0));
+        let obstacles = [floor, wall];
+
+        // falling onto the floor while moving into the wall
+        let motion = sweep_aabb(&body, Vec3::new(3.0, -0.5, 0.5), &obstacles);
+        assert_eq!(motion.y, 0.0);
+        assert!((motion.x - 1.2).abs() < 1e-4);
+        assert_eq!(motion.z, 0.5);
+
+        // moving away from the wall is unaffected
+        let motion = sweep_aabb(&body, Vec3::new(-1.0, 0.0, 0.0), &obstacles);
+        assert_eq!(motion, Vec3::new(-1.0, 0.0, 0.0));
+    }
+}
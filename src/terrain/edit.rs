@@ -0,0 +1,274 @@
+use glam::{IVec3, UVec3, Vec3Swizzles};
+
+use super::{
+    block::{BlockId, BLOCK_AIR},
+    position_types::GlobalBlockPosition,
+};
+use crate::util::{face::FACE_NORMALS, size::Size3};
+
+/// Region of the terrain changed by a bulk edit
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EditShape {
+    /// Axis-aligned box between two corners, which are both inside the box
+    Cuboid {
+        min: GlobalBlockPosition,
+        max: GlobalBlockPosition,
+    },
+    /// Blocks whose centres are within `radius` of the centre of the block at `center`
+    Sphere {
+        center: GlobalBlockPosition,
+        radius: f32,
+    },
+    /// Vertical cylinder `height` blocks tall whose bottom layer is centred on the block at `base`
+    Cylinder {
+        base: GlobalBlockPosition,
+        radius: f32,
+        height: u32,
+    },
+}
+
+impl EditShape {
+    /// Create a cuboid from any two opposite corners
+    pub fn cuboid(a: GlobalBlockPosition, b: GlobalBlockPosition) -> Self {
+        Self::Cuboid {
+            min: a.as_ivec3().min(b.as_ivec3()).into(),
+            max: a.as_ivec3().max(b.as_ivec3()).into(),
+        }
+    }
+
+    /// Returns the corners of the smallest box containing the shape, which are both inside the
+    /// box
+    pub fn bounds(&self) -> (GlobalBlockPosition, GlobalBlockPosition) {
+        match *self {
+            Self::Cuboid { min, max } => (min, max),
+            Self::Sphere { center, radius } => {
+                let extent = IVec3::splat(radius.max(0.0) as i32);
+                (center - extent.into(), center + extent.into())
+            }
+            Self::Cylinder {
+                base,
+                radius,
+                height,
+            } => {
+                let extent = radius.max(0.0) as i32;
+                (
+                    base - IVec3::new(extent, 0, extent).into(),
+                    base + IVec3::new(extent, height.max(1) as i32 - 1, extent).into(),
+                )
+            }
+        }
+    }
+
+    /// True if the block at the given position is inside the shape
+    pub fn contains(&self, pos: GlobalBlockPosition) -> bool {
+        match *self {
+            Self::Cuboid { min, max } => {
+                pos.as_ivec3().cmpge(min.as_ivec3()).all()
+                    && pos.as_ivec3().cmple(max.as_ivec3()).all()
+            }
+            Self::Sphere { center, radius } => {
+                (pos - center).as_ivec3().as_vec3().length_squared() <= radius * radius
+            }
+            Self::Cylinder {
+                base,
+                radius,
+                height,
+            } => {
+                let offset = (pos - base).as_ivec3();
+
+                offset.y >= 0
+                    && offset.y < height as i32
+                    && offset.xz().as_vec2().length_squared() <= radius * radius
+            }
+        }
+    }
+
+    /// True if the block at the given position is inside the shape and next to a block outside
+    /// of it
+    pub fn is_on_surface(&self, pos: GlobalBlockPosition) -> bool {
+        self.contains(pos)
+            && FACE_NORMALS
+                .iter()
+                .any(|&normal| !self.contains(pos + normal.into()))
+    }
+
+    /// True if the block at the given position is inside the shape and horizontally next to a
+    /// block outside of it
+    pub fn is_on_walls(&self, pos: GlobalBlockPosition) -> bool {
+        self.contains(pos)
+            && [IVec3::X, IVec3::Z, IVec3::NEG_X, IVec3::NEG_Z]
+                .iter()
+                .any(|&offset| !self.contains(pos + offset.into()))
+    }
+}
+
+/// What a bulk edit does to the blocks inside its shape
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EditOperation {
+    /// Set every block
+    Fill(BlockId),
+    /// Replace every block of one kind with another
+    Replace { from: BlockId, to: BlockId },
+    /// Set the blocks on the surface of the shape and clear the blocks inside it to air
+    Hollow(BlockId),
+    /// Set the blocks on the vertical sides of the shape, leaving the rest unchanged
+    Walls(BlockId),
+}
+
+impl EditOperation {
+    /// Returns the block which should replace the block with ID `old_id` at the given position, or
+    /// None if it should stay the same
+    pub fn apply(
+        &self,
+        shape: &EditShape,
+        pos: GlobalBlockPosition,
+        old_id: BlockId,
+    ) -> Option<BlockId> {
+        if !shape.contains(pos) {
+            return None;
+        }
+
+        let new_id = match *self {
+            Self::Fill(block_id) => block_id,
+            Self::Replace { from, to } => {
+                if old_id != from {
+                    return None;
+                }
+                to
+            }
+            Self::Hollow(block_id) => {
+                if shape.is_on_surface(pos) {
+                    block_id
+                } else {
+                    BLOCK_AIR
+                }
+            }
+            Self::Walls(block_id) => {
+                if !shape.is_on_walls(pos) {
+                    return None;
+                }
+                block_id
+            }
+        };
+
+        (new_id != old_id).then_some(new_id)
+    }
+}
+
+/// Blocks copied from a box in the terrain, which can be pasted elsewhere
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Clipboard {
+    size: Size3,
+    /// Blocks ordered by z, then y, then x (see `Size3::flatten`)
+    blocks: Vec<BlockId>,
+}
+
+impl Clipboard {
+    /// Create a clipboard from blocks ordered by z, then y, then x.
+    /// Panics if the number of blocks doesn't match the size
+    pub fn new(size: Size3, blocks: Vec<BlockId>) -> Self {
+        assert_eq!(
+            blocks.len(),
+            size.product(),
+            "number of blocks should match the size of the clipboard"
+        );
+
+        Self { size, blocks }
+    }
+
+    /// Size of the copied box
+    pub fn size(&self) -> Size3 {
+        self.size
+    }
+
+    /// Returns the block at the given position relative to the minimum corner of the copied box.
+    /// Panics if the position is out of bounds
+    pub fn get(&self, pos: UVec3) -> BlockId {
+        debug_assert!(self.size.contains_uvec3(pos));
+
+        self.blocks[self.size.flatten(pos)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shapes() {
+        let cuboid = EditShape::cuboid(
+            GlobalBlockPosition::new(2, 0, 2),
+            GlobalBlockPosition::new(-2, 4, -2),
+        );
+        assert_eq!(
+            cuboid.bounds(),
+            (
+                GlobalBlockPosition::new(-2, 0, -2),
+                GlobalBlockPosition::new(2, 4, 2)
+            )
+        );
+        assert!(cuboid.is_on_surface(GlobalBlockPosition::new(0, 0, 0)));
+        assert!(!cuboid.is_on_surface(GlobalBlockPosition::new(0, 2, 0)));
+        assert!(!cuboid.is_on_walls(GlobalBlockPosition::new(0, 0, 0)));
+        assert!(cuboid.is_on_walls(GlobalBlockPosition::new(2, 0, 0)));
+
+        let sphere = EditShape::Sphere {
+            center: GlobalBlockPosition::new(10, 10, 10),
+            radius: 2.5,
+        };
+        assert!(sphere.contains(GlobalBlockPosition::new(12, 10, 10)));
+        assert!(sphere.contains(GlobalBlockPosition::new(11, 11, 11)));
+        assert!(!sphere.contains(GlobalBlockPosition::new(12, 12, 10)));
+        assert!(!sphere.contains(GlobalBlockPosition::new(13, 10, 10)));
+
+        let cylinder = EditShape::Cylinder {
+            base: GlobalBlockPosition::ZERO,
+            radius: 1.0,
+            height: 3,
+        };
+        assert!(cylinder.contains(GlobalBlockPosition::new(1, 2, 0)));
+        assert!(!cylinder.contains(GlobalBlockPosition::new(1, 3, 0)));
+        assert!(!cylinder.contains(GlobalBlockPosition::new(1, 0, 1)));
+    }
+
+    #[test]
+    fn operations() {
+        let shape = EditShape::cuboid(GlobalBlockPosition::ZERO, GlobalBlockPosition::new(2, 2, 2));
+        let stone = BlockId(1);
+        let dirt = BlockId(2);
+        let centre = GlobalBlockPosition::new(1, 1, 1);
+        let corner = GlobalBlockPosition::ZERO;
+        let outside = GlobalBlockPosition::new(3, 0, 0);
+
+        assert_eq!(
+            EditOperation::Fill(stone).apply(&shape, centre, dirt),
+            Some(stone)
+        );
+        assert_eq!(
+            EditOperation::Fill(stone).apply(&shape, centre, stone),
+            None
+        );
+        assert_eq!(
+            EditOperation::Fill(stone).apply(&shape, outside, dirt),
+            None
+        );
+
+        let replace = EditOperation::Replace {
+            from: dirt,
+            to: stone,
+        };
+        assert_eq!(replace.apply(&shape, corner, dirt), Some(stone));
+        assert_eq!(replace.apply(&shape, corner, BLOCK_AIR), None);
+
+        let hollow = EditOperation::Hollow(stone);
+        assert_eq!(hollow.apply(&shape, corner, dirt), Some(stone));
+        assert_eq!(hollow.apply(&shape, centre, dirt), Some(BLOCK_AIR));
+
+        let walls = EditOperation::Walls(stone);
+        assert_eq!(walls.apply(&shape, corner, dirt), Some(stone));
+        assert_eq!(
+            walls.apply(&shape, GlobalBlockPosition::new(1, 0, 1), dirt),
+            None
+        );
+    }
+}
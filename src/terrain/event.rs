@@ -5,5 +5,10 @@ pub enum TerrainEvent {
     ChunkLoaded(ChunkPosition),
     ChunkUnloaded(ChunkPosition),
     BlockModified(ChunkPosition, LocalBlockPosition),
+    /// Many blocks in the chunk were modified at once
+    ChunkModified(ChunkPosition),
     ChunkLightUpdate(ChunkPosition),
+    BlockEntityCreated(ChunkPosition, LocalBlockPosition),
+    BlockEntityModified(ChunkPosition, LocalBlockPosition),
+    BlockEntityRemoved(ChunkPosition, LocalBlockPosition),
 }
@@ -0,0 +1,274 @@
+use std::collections::VecDeque;
+
+use glam::IVec3;
+use rustc_hash::FxHashSet;
+
+use super::{
+    block::{model::FLUID_LEVEL_COUNT, registry::BlockRegistry, BlockFluid, BlockId, BLOCK_AIR},
+    chunk::block_store::ChunkBlockStore,
+    position_types::{ChunkPosition, GlobalBlockPosition, LocalBlockPosition},
+};
+use crate::util::face::FACE_NORMALS;
+
+/// Simulates fluids flowing through the terrain.
+/// Fluids flow downwards, and sideways when they can't flow downwards, gaining one level for each
+/// block they flow away from their source. A fluid block which is no longer fed by a source
+/// drains away.
+/// Positions where the fluid may change are queued whenever a block changes, and processed in
+/// bounded batches on each fluid tick, so that fluids flow at a fixed speed
+#[derive(Debug, Default)]
+pub struct FluidSimulation {
+    /// Positions to update, in the order they were queued
+    queue: VecDeque<GlobalBlockPosition>,
+    /// Positions in `queue`, to avoid queueing the same position twice
+    queued: FxHashSet<GlobalBlockPosition>,
+}
+
+impl FluidSimulation {
+    /// Maximum number of positions updated in one fluid tick. Any remaining positions are updated
+    /// on the following ticks
+    pub const MAX_UPDATES_PER_TICK: usize = 1024;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue the position to be updated on the next fluid tick
+    pub fn queue(&mut self, pos: GlobalBlockPosition) {
+        if self.queued.insert(pos) {
+            self.queue.push_back(pos);
+        }
+    }
+
+    /// Called when a block changes to queue it and its neighbours, whose fluid may now flow
+    /// differently
+    pub fn block_changed(&mut self, pos: GlobalBlockPosition) {
+        self.queue(pos);
+
+        for normal in FACE_NORMALS {
+            self.queue(pos + GlobalBlockPosition::from(normal));
+        }
+    }
+
+    /// Number of positions waiting to be updated
+    pub fn queued_count(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Update a batch of the queued positions, returning the blocks which should be set to make
+    /// the fluids flow. The changes are computed before any are applied, so the fluids flow at
+    /// most one block per tick.
+    /// `get_block` returns None for positions in chunks which aren't loaded. Positions which
+    /// depend on blocks in unloaded chunks are dropped from the queue
+    pub fn tick(
+        &mut self,
+        get_block: impl Fn(GlobalBlockPosition) -> Option<BlockId>,
+        block_registry: &BlockRegistry,
+    ) -> Vec<(GlobalBlockPosition, BlockId)> {
+        let update_count = self.queue.len().min(Self::MAX_UPDATES_PER_TICK);
+
+        self.queue
+            .drain(..update_count)
+            .collect::<Vec<_>>()
+            .into_iter()
+            .filter_map(|pos| {
+                self.queued.remove(&pos);
+
+                flow(pos, &get_block, block_registry).map(|new_id| (pos, new_id))
+            })
+            .collect()
+    }
+}
+
+/// Returns the positions in a newly loaded chunk where fluid may be able to flow, so that fluids
+/// which were still flowing when the chunk was saved continue to flow
+pub fn find_unsettled_fluids(
+    chunk_pos: ChunkPosition,
+    block_store: &ChunkBlockStore,
+    block_registry: &BlockRegistry,
+) -> Vec<GlobalBlockPosition> {
+    if block_store
+        .get_single_block()
+        .is_some_and(|block_id| block_registry[block_id].fluid.is_none())
+    {
+        return Vec::new();
+    }
+
+    let blocks = block_store.as_block_array();
+    let mut positions = Vec::new();
+
+    for (block_index, &block_id) in blocks.iter().enumerate() {
+        let Some(fluid) = block_registry[block_id].fluid else {
+            continue;
+        };
+
+        let local_pos = LocalBlockPosition::from_array_index(block_index);
+        let global_pos = GlobalBlockPosition::from_local_and_chunk_pos(local_pos, chunk_pos);
+
+        if !fluid.is_source() {
+            positions.push(global_pos);
+        }
+
+        // air next to the fluid may be flowed into. Neighbours in other chunks are always
+        // included, since we can't tell what they contain
+        for normal in FACE_NORMALS {
+            let is_air = local_pos
+                .try_add(normal)
+                .is_none_or(|neighbour_pos| blocks[neighbour_pos.get_array_index()] == BLOCK_AIR);
+
+            if is_air {
+                positions.push(global_pos + GlobalBlockPosition::from(normal));
+            }
+        }
+    }
+
+    positions
+}
+
+/// Returns the block that should be at the given position according to the fluids around it, or
+/// None if it should not change or depends on a block in a chunk which isn't loaded.
+/// Fluids only flow into air and other fluid blocks which aren't sources
+fn flow(
+    pos: GlobalBlockPosition,
+    get_block: impl Fn(GlobalBlockPosition) -> Option<BlockId>,
+    block_registry: &BlockRegistry,
+) -> Option<BlockId> {
+    let current_id = get_block(pos)?;
+
+    match block_registry[current_id].fluid {
+        Some(fluid) if fluid.is_source() => return None,
+        Some(_) => (),
+        None if current_id == BLOCK_AIR => (),
+        None => return None,
+    }
+
+    let above_id = get_block(pos + GlobalBlockPosition::from(IVec3::Y))?;
+
+    let new_id = if let Some(fluid) = block_registry[above_id].fluid {
+        // fluid falling from above
+        fluid.with_level(1)
+    } else {
+        // fluid spreading sideways from the neighbour with the lowest level
+        let mut feeding_fluid: Option<BlockFluid> = None;
+
+        for offset in [IVec3::X, IVec3::Z, IVec3::NEG_X, IVec3::NEG_Z] {
+            let neighbour_pos = pos + GlobalBlockPosition::from(offset);
+            let Some(fluid) = block_registry[get_block(neighbour_pos)?].fluid else {
+                continue;
+            };
+            if fluid.level + 1 >= FLUID_LEVEL_COUNT {
+                continue;
+            }
+
+            // fluid only spreads sideways if it can't flow downwards
+            let below_id = get_block(neighbour_pos + GlobalBlockPosition::from(IVec3::NEG_Y))?;
+            let can_flow_down = below_id == BLOCK_AIR
+                || block_registry[below_id]
+                    .fluid
+                    .is_some_and(|below_fluid| !below_fluid.is_source());
+            if can_flow_down {
+                continue;
+            }
+
+            if feeding_fluid.is_none_or(|feeding_fluid| fluid.level < feeding_fluid.level) {
+                feeding_fluid = Some(fluid);
+            }
+        }
+
+        feeding_fluid
+            .map(|fluid| fluid.with_level(fluid.level + 1))
+            .unwrap_or(BLOCK_AIR)
+    };
+
+    (new_id != current_id).then_some(new_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_hash::FxHashMap;
+
+    use super::*;
+    use crate::terrain::block::registry::test_registry;
+
+    /// Run fluid ticks on a small world until nothing changes
+    fn settle(
+        fluids: &mut FluidSimulation,
+        world: &mut FxHashMap<GlobalBlockPosition, BlockId>,
+        block_registry: &BlockRegistry,
+    ) {
+        for _ in 0..100 {
+            let changes = fluids.tick(|pos| world.get(&pos).copied(), block_registry);
+            if changes.is_empty() && fluids.queued_count() == 0 {
+                return;
+            }
+
+            for (pos, block_id) in changes {
+                world.insert(pos, block_id);
+                fluids.block_changed(pos);
+            }
+        }
+        panic!("fluid did not settle");
+    }
+
+    #[test]
+    fn spreads_and_drains() {
+        let block_registry = test_registry(&[
+            "name = \"stone\"",
+            "name = \"water\"\nmodel = { type = \"fluid\", texture = \"water\" }",
+        ]);
+        let stone = block_registry.get_id("stone").unwrap();
+        let water = block_registry.get_id("water").unwrap();
+        let water_fluid = block_registry[water].fluid.unwrap();
+
+        // a stone floor at y = 0 with air above it, walled in at x = 5
+        let mut world = FxHashMap::default();
+        for x in -12..=12 {
+            for z in -1..=1 {
+                for y in 0..4 {
+                    let block_id = if y == 0 || x == 5 { stone } else { BLOCK_AIR };
+                    world.insert(GlobalBlockPosition::new(x, y, z), block_id);
+                }
+            }
+        }
+
+        let mut fluids = FluidSimulation::new();
+        let source_pos = GlobalBlockPosition::new(0, 2, 0);
+        world.insert(source_pos, water);
+        fluids.block_changed(source_pos);
+        settle(&mut fluids, &mut world, &block_registry);
+
+        // falls onto the floor, then spreads until it runs out of levels or reaches the wall
+        assert_eq!(
+            world[&GlobalBlockPosition::new(0, 1, 0)],
+            water_fluid.with_level(1)
+        );
+        assert_eq!(
+            world[&GlobalBlockPosition::new(-6, 1, 0)],
+            water_fluid.with_level(7)
+        );
+        assert_eq!(world[&GlobalBlockPosition::new(-7, 1, 0)], BLOCK_AIR);
+        assert_eq!(
+            world[&GlobalBlockPosition::new(4, 1, 0)],
+            water_fluid.with_level(5)
+        );
+        assert_eq!(world[&GlobalBlockPosition::new(5, 1, 0)], stone);
+        assert_eq!(world[&GlobalBlockPosition::new(-1, 2, 0)], BLOCK_AIR);
+
+        // removing the wall lets the water flow further
+        world.insert(GlobalBlockPosition::new(5, 1, 0), BLOCK_AIR);
+        fluids.block_changed(GlobalBlockPosition::new(5, 1, 0));
+        settle(&mut fluids, &mut world, &block_registry);
+        assert_eq!(
+            world[&GlobalBlockPosition::new(6, 1, 0)],
+            water_fluid.with_level(7)
+        );
+
+        // removing the source drains all of the water
+        world.insert(source_pos, BLOCK_AIR);
+        fluids.block_changed(source_pos);
+        settle(&mut fluids, &mut world, &block_registry);
+        assert!(world
+            .values()
+            .all(|&block_id| block_registry[block_id].fluid.is_none()));
+    }
+}
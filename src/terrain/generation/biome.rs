@@ -0,0 +1,330 @@
+use bracket_noise::prelude::*;
+
+use super::{require_block, GeneratorError};
+use crate::terrain::{
+    block::{registry::BlockRegistry, BlockId},
+    chunk::{CHUNK_SIZE, CHUNK_SIZE_SQUARED},
+};
+
+/// Index of a biome in `BIOMES`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BiomeId(pub u8);
+
+impl BiomeId {
+    /// Biome used by generators which don't generate biomes
+    pub const DEFAULT: Self = Self(0);
+
+    pub fn as_usize(&self) -> usize {
+        self.0 as usize
+    }
+
+    /// Returns the definition of this biome
+    pub fn biome(&self) -> &'static Biome {
+        &BIOMES[self.as_usize()]
+    }
+}
+
+/// Climate at a column of the world. Each component is roughly in the range -1..1
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Climate {
+    pub temperature: f32,
+    pub humidity: f32,
+    /// How far inland the column is. Low values give lowlands and high values give mountains
+    pub continentalness: f32,
+}
+
+impl Climate {
+    fn distance_squared(&self, other: &Climate) -> f32 {
+        (self.temperature - other.temperature).powi(2)
+            + (self.humidity - other.humidity).powi(2)
+            + (self.continentalness - other.continentalness).powi(2)
+    }
+}
+
+/// Properties of a biome
+#[derive(Clone, Debug)]
+pub struct Biome {
+    pub name: &'static str,
+    /// Climate at the centre of the biome. Each column uses the biome with the closest climate
+    pub climate: Climate,
+    /// Name of the block on top of the terrain
+    pub surface_block: &'static str,
+    /// Name of the block below the surface
+    pub subsurface_block: &'static str,
+    /// Height of the terrain at the middle of its range
+    pub height_offset: f32,
+    /// Multiplier for the range of heights of the terrain
+    pub height_scale: f32,
+    /// Chance of each feature cell containing a tree
+    pub tree_chance: f32,
+    /// Chance of each feature cell containing a boulder
+    pub boulder_chance: f32,
+}
+
+/// Every biome, indexed by `BiomeId`
+pub const BIOMES: [Biome; 6] = [
+    Biome {
+        name: "plains",
+        climate: Climate {
+            temperature: 0.0,
+            humidity: 0.0,
+            continentalness: 0.0,
+        },
+        surface_block: "grass",
+        subsurface_block: "dirt",
+        height_offset: 0.0,
+        height_scale: 1.0,
+        tree_chance: 0.05,
+        boulder_chance: 0.02,
+    },
+    Biome {
+        name: "forest",
+        climate: Climate {
+            temperature: 0.1,
+            humidity: 0.4,
+            continentalness: 0.1,
+        },
+        surface_block: "grass",
+        subsurface_block: "dirt",
+        height_offset: 5.0,
+        height_scale: 1.0,
+        tree_chance: 0.6,
+        boulder_chance: 0.03,
+    },
+    Biome {
+        name: "desert",
+        climate: Climate {
+            temperature: 0.5,
+            humidity: -0.4,
+            continentalness: 0.0,
+        },
+        surface_block: "sand",
+        subsurface_block: "sand",
+        height_offset: 0.0,
+        height_scale: 0.5,
+        tree_chance: 0.0,
+        boulder_chance: 0.05,
+    },
+    Biome {
+        name: "mountains",
+        climate: Climate {
+            temperature: -0.1,
+            humidity: 0.0,
+            continentalness: 0.5,
+        },
+        surface_block: "stone",
+        subsurface_block: "stone",
+        height_offset: 40.0,
+        height_scale: 2.5,
+        tree_chance: 0.02,
+        boulder_chance: 0.15,
+    },
+    Biome {
+        name: "tundra",
+        climate: Climate {
+            temperature: -0.5,
+            humidity: 0.0,
+            continentalness: 0.1,
+        },
+        surface_block: "snow",
+        subsurface_block: "dirt",
+        height_offset: 10.0,
+        height_scale: 0.8,
+        tree_chance: 0.05,
+        boulder_chance: 0.05,
+    },
+    Biome {
+        name: "lowlands",
+        climate: Climate {
+            temperature: 0.1,
+            humidity: 0.2,
+            continentalness: -0.5,
+        },
+        surface_block: "grass",
+        subsurface_block: "dirt",
+        height_offset: -20.0,
+        height_scale: 0.4,
+        tree_chance: 0.15,
+        boulder_chance: 0.0,
+    },
+];
+
+/// Biome IDs for each column of a chunk, ordered by z, then x
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ChunkBiomes {
+    Uniform(BiomeId),
+    Columns(Box<[BiomeId; CHUNK_SIZE_SQUARED]>),
+}
+
+impl ChunkBiomes {
+    /// Create from the biome IDs of each column, ordered by z, then x
+    pub fn new(biomes: &[BiomeId]) -> Self {
+        if let Some(first) = biomes.first() {
+            if biomes.iter().all(|biome_id| biome_id == first) {
+                return Self::Uniform(*first);
+            }
+        }
+
+        Self::Columns(Box::new(
+            biomes
+                .try_into()
+                .expect("there should be `CHUNK_SIZE_SQUARED` biomes"),
+        ))
+    }
+
+    /// Returns the biome ID of the column at the given position in the chunk.
+    /// Panics if the position is out of bounds
+    pub fn get(&self, x: u32, z: u32) -> BiomeId {
+        match self {
+            Self::Uniform(biome_id) => *biome_id,
+            Self::Columns(biomes) => biomes[z as usize * CHUNK_SIZE + x as usize],
+        }
+    }
+}
+
+impl Default for ChunkBiomes {
+    fn default() -> Self {
+        Self::Uniform(BiomeId::DEFAULT)
+    }
+}
+
+/// Blocks used by a biome, looked up in the `BlockRegistry`
+#[derive(Clone, Copy, Debug)]
+pub struct BiomeBlocks {
+    pub surface: BlockId,
+    pub subsurface: BlockId,
+}
+
+/// Height parameters of a column, blended between the biomes with similar climates
+#[derive(Clone, Copy, Debug)]
+pub struct ColumnInfo {
+    pub biome_id: BiomeId,
+    pub height_offset: f32,
+    pub height_scale: f32,
+}
+
+/// Samples the climate noise to decide the biome and terrain height parameters of each column
+pub struct BiomeSource {
+    temperature_noise: FastNoise,
+    humidity_noise: FastNoise,
+    continentalness_noise: FastNoise,
+    blocks: Vec<BiomeBlocks>,
+}
+
+impl BiomeSource {
+    /// Differences in climate distance smaller than this are blended between biomes, so that the
+    /// terrain height changes smoothly across biome borders
+    const BLEND_WIDTH: f32 = 0.4;
+
+    pub fn new(seed: u64, block_registry: &BlockRegistry) -> Result<Self, GeneratorError> {
+        let climate_noise = |seed_offset: u64, frequency: f32| {
+            let mut noise = FastNoise::seeded(seed.wrapping_add(seed_offset));
+            noise.set_noise_type(NoiseType::SimplexFractal);
+            noise.set_fractal_octaves(3);
+            noise.set_frequency(frequency);
+            noise
+        };
+
+        let blocks = BIOMES
+            .iter()
+            .map(|biome| {
+                Ok(BiomeBlocks {
+                    surface: require_block(block_registry, biome.surface_block)?,
+                    subsurface: require_block(block_registry, biome.subsurface_block)?,
+                })
+            })
+            .collect::<Result<_, GeneratorError>>()?;
+
+        Ok(Self {
+            temperature_noise: climate_noise(2, 0.0015),
+            humidity_noise: climate_noise(3, 0.0015),
+            continentalness_noise: climate_noise(4, 0.001),
+            blocks,
+        })
+    }
+
+    /// Returns the climate at the given column
+    pub fn climate(&self, x: f32, z: f32) -> Climate {
+        // fractal simplex noise rarely leaves -0.5..0.5, so stretch it to cover the biomes
+        Climate {
+            temperature: self.temperature_noise.get_noise(x, z) * 2.0,
+            humidity: self.humidity_noise.get_noise(x, z) * 2.0,
+            continentalness: self.continentalness_noise.get_noise(x, z) * 2.0,
+        }
+    }
+
+    /// Returns the biome and blended height parameters at the given column
+    pub fn column(&self, x: f32, z: f32) -> ColumnInfo {
+        let climate = self.climate(x, z);
+        let distances = BIOMES.map(|biome| biome.climate.distance_squared(&climate).sqrt());
+
+        let (nearest_index, nearest_distance) = distances
+            .iter()
+            .copied()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .expect("there should be at least one biome");
+
+        // each biome whose climate is nearly as close as the nearest biome contributes to the
+        // height. The weights change continuously with the climate, so there are no seams at
+        // biome borders
+        let mut total_weight = 0.0;
+        let mut height_offset = 0.0;
+        let mut height_scale = 0.0;
+
+        for (biome, distance) in BIOMES.iter().zip(distances) {
+            let weight = (Self::BLEND_WIDTH - (distance - nearest_distance))
+                .max(0.0)
+                .powi(2);
+
+            total_weight += weight;
+            height_offset += biome.height_offset * weight;
+            height_scale += biome.height_scale * weight;
+        }
+
+        ColumnInfo {
+            biome_id: BiomeId(nearest_index as u8),
+            height_offset: height_offset / total_weight,
+            height_scale: height_scale / total_weight,
+        }
+    }
+
+    /// Returns the blocks used by the given biome
+    pub fn blocks(&self, biome_id: BiomeId) -> BiomeBlocks {
+        self.blocks[biome_id.as_usize()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::block::registry::test_registry;
+
+    #[test]
+    fn height_is_blended_across_borders() {
+        let block_registry = test_registry(&[
+            "name = \"dirt\"",
+            "name = \"grass\"",
+            "name = \"sand\"",
+            "name = \"snow\"",
+            "name = \"stone\"",
+        ]);
+        let biome_source = BiomeSource::new(7, &block_registry).unwrap();
+
+        let mut biome_counts = [0; BIOMES.len()];
+        let mut previous = biome_source.column(0.0, 0.0);
+
+        for x in 1..50_000 {
+            let column = biome_source.column(x as f32, 0.0);
+            biome_counts[column.biome_id.as_usize()] += 1;
+
+            // the height should never jump between neighbouring columns
+            assert!((column.height_offset - previous.height_offset).abs() < 2.0);
+            assert!((column.height_scale - previous.height_scale).abs() < 0.1);
+
+            previous = column;
+        }
+
+        assert!(biome_counts.iter().filter(|&&count| count > 0).count() > 1);
+    }
+}
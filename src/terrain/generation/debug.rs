@@ -0,0 +1,78 @@
+use super::TerrainGenerator;
+use crate::terrain::{
+    block::{registry::BlockRegistry, BlockId, BLOCK_AIR},
+    chunk::{CHUNK_SIZE_CUBED, CHUNK_SIZE_U32},
+    position_types::{ChunkPosition, GlobalBlockPosition, LocalBlockPosition},
+};
+
+/// Distance between neighbouring blocks in the grid
+const GRID_SPACING: i32 = 2;
+
+/// Generates every registered block (except air) laid out in a square grid at y = 0, starting from
+/// the origin and extending towards +x and +z
+#[derive(Clone, Debug)]
+pub struct DebugGenerator {
+    /// Blocks to lay out, in order of ID
+    blocks: Vec<BlockId>,
+    /// Number of blocks in each row of the grid
+    row_length: usize,
+}
+
+impl DebugGenerator {
+    pub fn new(block_registry: &BlockRegistry) -> Self {
+        let blocks: Vec<BlockId> = block_registry
+            .iter()
+            .map(|(block_id, _)| block_id)
+            .filter(|&block_id| block_id != BLOCK_AIR)
+            .collect();
+        let row_length = (blocks.len() as f64).sqrt().ceil().max(1.0) as usize;
+
+        Self { blocks, row_length }
+    }
+
+    /// Returns the block at the given position in the world
+    fn block_at(&self, pos: GlobalBlockPosition) -> BlockId {
+        let on_grid = pos.y() == 0
+            && pos.x() >= 0
+            && pos.z() >= 0
+            && pos.x() % GRID_SPACING == 0
+            && pos.z() % GRID_SPACING == 0;
+        if !on_grid {
+            return BLOCK_AIR;
+        }
+
+        let column = (pos.x() / GRID_SPACING) as usize;
+        let row = (pos.z() / GRID_SPACING) as usize;
+        if column >= self.row_length {
+            return BLOCK_AIR;
+        }
+
+        self.blocks
+            .get(row * self.row_length + column)
+            .copied()
+            .unwrap_or(BLOCK_AIR)
+    }
+}
+
+impl TerrainGenerator for DebugGenerator {
+    fn generate_chunk(&self, chunk_pos: ChunkPosition) -> Vec<BlockId> {
+        let mut blocks = vec![BLOCK_AIR; CHUNK_SIZE_CUBED];
+
+        // the grid is only in the chunks at y = 0
+        if chunk_pos.y() != 0 {
+            return blocks;
+        }
+
+        for z in 0..CHUNK_SIZE_U32 {
+            for x in 0..CHUNK_SIZE_U32 {
+                let local_pos = LocalBlockPosition::new(x, 0, z);
+                let global_pos =
+                    GlobalBlockPosition::from_local_and_chunk_pos(local_pos, chunk_pos);
+
+                blocks[local_pos.get_array_index()] = self.block_at(global_pos);
+            }
+        }
+
+        blocks
+    }
+}
@@ -0,0 +1,205 @@
+use glam::IVec3;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rustc_hash::FxHashMap;
+
+use crate::terrain::{
+    block::{registry::BlockRegistry, BlockId, BLOCK_AIR},
+    position_types::{ChunkPosition, GlobalBlockPosition, LocalBlockPosition},
+};
+
+/// Blocks placed by features, grouped by the chunk containing them
+pub type ChunkPlacements = FxHashMap<ChunkPosition, Vec<(LocalBlockPosition, BlockId)>>;
+
+/// Collects the blocks placed by the features in a chunk during the decoration stage.
+/// Features may place blocks in the chunks neighbouring the chunk being decorated, but no
+/// further away
+#[derive(Clone, Debug, Default)]
+pub struct FeaturePlacements {
+    placements: ChunkPlacements,
+}
+
+impl FeaturePlacements {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Place a block at the given position in the world
+    pub fn place(&mut self, pos: GlobalBlockPosition, block_id: BlockId) {
+        let (local_pos, chunk_pos) = pos.get_local_and_chunk_pos();
+
+        self.placements
+            .entry(chunk_pos)
+            .or_default()
+            .push((local_pos, block_id));
+    }
+
+    /// Returns the placed blocks grouped by chunk
+    pub fn into_chunk_placements(self) -> ChunkPlacements {
+        self.placements
+    }
+}
+
+/// Returns the block resulting from placing a feature block over an existing block.
+/// Features only replace air and blocks marked as `replaceable`, so they never overwrite solid
+/// terrain or blocks placed by the player. A replaceable feature block only replaces air, so where
+/// two feature blocks of the same kind overlap, the one placed first is kept, and features are
+/// placed in a fixed order within each chunk
+pub fn merge_feature_block(
+    existing: BlockId,
+    placed: BlockId,
+    block_registry: &BlockRegistry,
+) -> BlockId {
+    let replaces_existing = existing == BLOCK_AIR
+        || (block_registry[existing].replaceable && !block_registry[placed].replaceable);
+
+    if replaces_existing {
+        placed
+    } else {
+        existing
+    }
+}
+
+/// Returns a random number generator for the features in a chunk, which depends only on the world
+/// seed and the position of the chunk
+pub fn chunk_rng(seed: u64, chunk_pos: ChunkPosition) -> StdRng {
+    let mut hash = seed;
+    for component in chunk_pos.as_ivec3().to_array() {
+        hash = split_mix_64(hash ^ component as u32 as u64);
+    }
+
+    StdRng::seed_from_u64(hash)
+}
+
+/// Place a tree with its trunk starting at `base_pos`
+pub fn place_tree(
+    placements: &mut FeaturePlacements,
+    rng: &mut impl Rng,
+    base_pos: GlobalBlockPosition,
+    block_wood: BlockId,
+    block_leaves: BlockId,
+) {
+    let trunk_height = rng.gen_range(4..=6);
+
+    // leaves: two wide layers around the top of the trunk, then two narrow layers above
+    let top = trunk_height - 1;
+    for y in (top - 2)..=(top + 1) {
+        let radius: i32 = if y < top { 2 } else { 1 };
+
+        for z in -radius..=radius {
+            for x in -radius..=radius {
+                // randomly trim the corners
+                let is_corner = x.abs() == radius && z.abs() == radius;
+                if is_corner && (y == top + 1 || rng.gen_bool(0.5)) {
+                    continue;
+                }
+
+                placements.place(
+                    base_pos + GlobalBlockPosition::from(IVec3::new(x, y, z)),
+                    block_leaves,
+                );
+            }
+        }
+    }
+
+    for y in 0..trunk_height {
+        placements.place(
+            base_pos + GlobalBlockPosition::from(IVec3::new(0, y, 0)),
+            block_wood,
+        );
+    }
+}
+
+/// Place a roughly spherical boulder centred on `centre_pos`
+pub fn place_boulder(
+    placements: &mut FeaturePlacements,
+    rng: &mut impl Rng,
+    centre_pos: GlobalBlockPosition,
+    block_stone: BlockId,
+) {
+    let radius: f32 = rng.gen_range(1.0..2.5);
+    let extent = radius.ceil() as i32;
+
+    for z in -extent..=extent {
+        for y in -extent..=extent {
+            for x in -extent..=extent {
+                let offset = IVec3::new(x, y, z);
+                if offset.as_vec3().length() <= radius {
+                    placements.place(centre_pos + GlobalBlockPosition::from(offset), block_stone);
+                }
+            }
+        }
+    }
+}
+
+/// SplitMix64 hash function
+fn split_mix_64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+
+    use super::*;
+    use crate::terrain::block::registry::test_registry;
+
+    #[test]
+    fn merging() {
+        let block_registry = test_registry(&[
+            "name = \"leaves\"\nreplaceable = true",
+            "name = \"tall_grass\"\nreplaceable = true",
+            "name = \"stone\"",
+            "name = \"wood\"",
+        ]);
+        let leaves = block_registry.get_id("leaves").unwrap();
+        let tall_grass = block_registry.get_id("tall_grass").unwrap();
+        let stone = block_registry.get_id("stone").unwrap();
+        let wood = block_registry.get_id("wood").unwrap();
+
+        // a trunk replaces leaves whichever is placed first
+        for placed in [leaves, wood].into_iter().permutations(2) {
+            let merged = placed.iter().fold(BLOCK_AIR, |existing, &block_id| {
+                merge_feature_block(existing, block_id, &block_registry)
+            });
+            assert_eq!(merged, wood);
+        }
+
+        // features never overwrite solid blocks, whatever their IDs
+        for existing in [stone, wood] {
+            for placed in [leaves, tall_grass, stone, wood] {
+                assert_eq!(
+                    merge_feature_block(existing, placed, &block_registry),
+                    existing
+                );
+            }
+        }
+
+        // replaceable blocks only replace air
+        assert_eq!(
+            merge_feature_block(tall_grass, stone, &block_registry),
+            stone
+        );
+        assert_eq!(
+            merge_feature_block(tall_grass, leaves, &block_registry),
+            tall_grass
+        );
+        assert_eq!(
+            merge_feature_block(BLOCK_AIR, leaves, &block_registry),
+            leaves
+        );
+    }
+
+    #[test]
+    fn chunk_rng_is_deterministic() {
+        let chunk_pos = ChunkPosition::new(-3, 1, 7);
+        let a: [u64; 4] = chunk_rng(42, chunk_pos).gen();
+        let b: [u64; 4] = chunk_rng(42, chunk_pos).gen();
+        let c: [u64; 4] = chunk_rng(42, ChunkPosition::new(-3, 1, 8)).gen();
+
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+}
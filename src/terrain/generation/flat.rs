@@ -0,0 +1,138 @@
+use super::{require_block, GeneratorError, TerrainGenerator};
+use crate::terrain::{
+    block::{registry::BlockRegistry, BlockId, BLOCK_AIR},
+    chunk::{CHUNK_SIZE, CHUNK_SIZE_CUBED, CHUNK_SIZE_I32, CHUNK_SIZE_SQUARED},
+    position_types::ChunkPosition,
+};
+
+/// Greatest total thickness of the layers of a flat world
+pub const MAX_FLAT_HEIGHT: u64 = 4096;
+
+/// Generates flat terrain made from horizontal layers of blocks. The bottom layer starts at y = 0,
+/// and everything below the bottom layer and above the top layer is air
+#[derive(Clone, Debug)]
+pub struct FlatGenerator {
+    /// Block at each y position, starting from y = 0
+    column: Vec<BlockId>,
+}
+
+impl FlatGenerator {
+    /// Create a flat generator from a list of layers, ordered from bottom to top
+    pub fn new(
+        layers: &[FlatLayer],
+        block_registry: &BlockRegistry,
+    ) -> Result<Self, GeneratorError> {
+        let height = layers
+            .iter()
+            .map(|layer| u64::from(layer.thickness))
+            .sum::<u64>();
+        if height > MAX_FLAT_HEIGHT {
+            return Err(GeneratorError::FlatWorldTooTall(height, MAX_FLAT_HEIGHT));
+        }
+
+        let mut column = Vec::with_capacity(height as usize);
+
+        for layer in layers {
+            let block_id = require_block(block_registry, &layer.block)?;
+            column.extend(std::iter::repeat_n(block_id, layer.thickness as usize));
+        }
+
+        Ok(Self { column })
+    }
+
+    /// Parse a layer spec: a comma-separated list of layers from bottom to top, where each layer
+    /// is a block name optionally preceded by a thickness, e.g. `3*dirt,grass`
+    pub fn parse_layer_spec(spec: &str) -> Result<Vec<FlatLayer>, GeneratorError> {
+        let invalid = || GeneratorError::InvalidLayerSpec(spec.to_string());
+
+        spec.split(',')
+            .map(|layer| {
+                let layer = layer.trim();
+                let (thickness, block) = match layer.split_once('*') {
+                    Some((thickness, block)) => (
+                        thickness.trim().parse().map_err(|_| invalid())?,
+                        block.trim(),
+                    ),
+                    None => (1, layer),
+                };
+
+                if block.is_empty() {
+                    return Err(invalid());
+                }
+
+                Ok(FlatLayer {
+                    block: block.to_string(),
+                    thickness,
+                })
+            })
+            .collect()
+    }
+}
+
+impl TerrainGenerator for FlatGenerator {
+    fn generate_chunk(&self, chunk_pos: ChunkPosition) -> Vec<BlockId> {
+        let mut blocks = vec![BLOCK_AIR; CHUNK_SIZE_CUBED];
+
+        for y in 0..CHUNK_SIZE {
+            let global_y = chunk_pos.y() * CHUNK_SIZE_I32 + y as i32;
+
+            if let Some(&block_id) = usize::try_from(global_y)
+                .ok()
+                .and_then(|global_y| self.column.get(global_y))
+            {
+                blocks[y * CHUNK_SIZE_SQUARED..(y + 1) * CHUNK_SIZE_SQUARED].fill(block_id);
+            }
+        }
+
+        blocks
+    }
+}
+
+/// Horizontal layer of a single kind of block
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FlatLayer {
+    pub block: String,
+    pub thickness: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::block::registry::test_registry;
+
+    #[test]
+    fn parse_layer_spec() {
+        assert_eq!(
+            FlatGenerator::parse_layer_spec("3*dirt, grass").unwrap(),
+            vec![
+                FlatLayer {
+                    block: "dirt".to_string(),
+                    thickness: 3
+                },
+                FlatLayer {
+                    block: "grass".to_string(),
+                    thickness: 1
+                },
+            ]
+        );
+        assert!(FlatGenerator::parse_layer_spec("x*dirt").is_err());
+        assert!(FlatGenerator::parse_layer_spec("dirt,,grass").is_err());
+    }
+
+    #[test]
+    fn height_is_limited() {
+        let block_registry = test_registry(&["name = \"dirt\""]);
+
+        let layers = FlatGenerator::parse_layer_spec("4000000000*dirt").unwrap();
+        assert!(matches!(
+            FlatGenerator::new(&layers, &block_registry),
+            Err(GeneratorError::FlatWorldTooTall(
+                4_000_000_000,
+                MAX_FLAT_HEIGHT
+            ))
+        ));
+
+        let layers = FlatGenerator::parse_layer_spec("4095*dirt,dirt").unwrap();
+        assert!(FlatGenerator::new(&layers, &block_registry).is_ok());
+    }
+}
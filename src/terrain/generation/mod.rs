@@ -0,0 +1,100 @@
+use std::{fmt, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+
+use self::{
+    biome::ChunkBiomes, debug::DebugGenerator, feature::FeaturePlacements, flat::FlatGenerator,
+    noise::NoiseGenerator, void::VoidGenerator,
+};
+use super::{
+    block::{registry::BlockRegistry, BlockId},
+    position_types::ChunkPosition,
+};
+
+pub mod biome;
+pub mod debug;
+pub mod feature;
+pub mod flat;
+pub mod noise;
+pub mod void;
+
+/// Decides the blocks in newly generated chunks.
+/// Generation happens in two stages. First the shape of each chunk is generated by
+/// `generate_chunk`. Then, once all of a chunk's neighbours have been loaded, `decorate_chunk` adds
+/// features such as trees, which may extend into the neighbouring chunks. Both stages must depend
+/// only on the seed and chunk position so that the world is the same no matter which order chunks
+/// are loaded in.
+/// Generators are shared between the worker threads generating chunks, so any expensive state
+/// (such as noise generators) should be built once when the generator is created
+pub trait TerrainGenerator: Send + Sync {
+    /// Returns the blocks in the chunk at the given position, ordered by y, then z, then x
+    fn generate_chunk(&self, chunk_pos: ChunkPosition) -> Vec<BlockId>;
+
+    /// Returns the biome of each column in the chunk at the given position
+    fn generate_biomes(&self, _chunk_pos: ChunkPosition) -> ChunkBiomes {
+        ChunkBiomes::default()
+    }
+
+    /// Place the features belonging to the chunk at the given position
+    fn decorate_chunk(&self, _chunk_pos: ChunkPosition, _placements: &mut FeaturePlacements) {}
+}
+
+impl fmt::Debug for dyn TerrainGenerator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TerrainGenerator").finish_non_exhaustive()
+    }
+}
+
+/// Selects the generator used for a world. This is chosen when the world is created and stored
+/// with the world
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GeneratorSettings {
+    /// Hilly terrain with caves, made from noise
+    #[default]
+    Noise,
+    /// Flat terrain made from horizontal layers of blocks, described by a layer spec (see
+    /// `FlatGenerator::parse_layer_spec`)
+    Flat { layers: String },
+    /// Nothing but air
+    Void,
+    /// Every registered block laid out in a grid
+    Debug,
+}
+
+impl GeneratorSettings {
+    /// Create the generator with these settings for a world with the given seed
+    pub fn build(
+        &self,
+        seed: u64,
+        block_registry: &BlockRegistry,
+    ) -> Result<Arc<dyn TerrainGenerator>, GeneratorError> {
+        Ok(match self {
+            Self::Noise => Arc::new(NoiseGenerator::new(seed, block_registry)?),
+            Self::Flat { layers } => Arc::new(FlatGenerator::new(
+                &FlatGenerator::parse_layer_spec(layers)?,
+                block_registry,
+            )?),
+            Self::Void => Arc::new(VoidGenerator),
+            Self::Debug => Arc::new(DebugGenerator::new(block_registry)),
+        })
+    }
+}
+
+/// Errors returned when creating a `TerrainGenerator`
+#[derive(Debug, thiserror::Error)]
+pub enum GeneratorError {
+    #[error("generator requires the block `{0}`, which is not registered")]
+    UnknownBlock(String),
+    #[error("invalid layer spec `{0}`")]
+    InvalidLayerSpec(String),
+    #[error("flat world layers are {0} blocks thick, more than the limit of {1}")]
+    FlatWorldTooTall(u64, u64),
+}
+
+/// Returns the ID of the block with the given name, or an error if it is not registered
+fn require_block(block_registry: &BlockRegistry, name: &str) -> Result<BlockId, GeneratorError> {
+    block_registry
+        .get_id(name)
+        .ok_or_else(|| GeneratorError::UnknownBlock(name.to_string()))
+}
@@ -0,0 +1,201 @@
+use bracket_noise::prelude::*;
+use glam::{UVec3, Vec3};
+
+use itertools::Itertools;
+use rand::Rng;
+
+use super::{
+    biome::{BiomeSource, ChunkBiomes, ColumnInfo},
+    feature::{chunk_rng, place_boulder, place_tree, FeaturePlacements},
+    require_block, GeneratorError, TerrainGenerator,
+};
+use crate::{
+    terrain::{
+        block::{registry::BlockRegistry, BlockId, BLOCK_AIR},
+        chunk::{CHUNK_SIZE, CHUNK_SIZE_CUBED, CHUNK_SIZE_U32},
+        position_types::{ChunkPosition, GlobalBlockPosition, LocalBlockPosition},
+    },
+    util::size::Size3,
+};
+
+/// Width of the square cells into which chunks are divided for feature placement. Each cell
+/// contains at most one feature
+const FEATURE_CELL_SIZE: u32 = 8;
+
+/// Generates hilly terrain with caves using 3D noise. The shape of the terrain, its surface
+/// blocks and the density of trees and boulders depend on the biome of each column
+pub struct NoiseGenerator {
+    seed: u64,
+    terrain_noise: FastNoise,
+    cave_noise: FastNoise,
+    biome_source: BiomeSource,
+    block_wood: BlockId,
+    block_leaves: BlockId,
+    block_stone: BlockId,
+}
+
+impl NoiseGenerator {
+    pub fn new(seed: u64, block_registry: &BlockRegistry) -> Result<Self, GeneratorError> {
+        let mut terrain_noise = FastNoise::seeded(seed);
+        terrain_noise.set_noise_type(NoiseType::SimplexFractal);
+        terrain_noise.set_fractal_octaves(7);
+        terrain_noise.set_frequency(0.003);
+
+        let mut cave_noise = FastNoise::seeded(seed.wrapping_add(1));
+        cave_noise.set_noise_type(NoiseType::SimplexFractal);
+        cave_noise.set_fractal_octaves(3);
+        cave_noise.set_frequency(0.03);
+
+        Ok(Self {
+            seed,
+            terrain_noise,
+            cave_noise,
+            biome_source: BiomeSource::new(seed, block_registry)?,
+            block_wood: require_block(block_registry, "wood")?,
+            block_leaves: require_block(block_registry, "leaves")?,
+            block_stone: require_block(block_registry, "stone")?,
+        })
+    }
+
+    /// Returns the biome and height parameters of each column in the chunk, ordered by z, then x
+    fn columns(&self, chunk_pos: ChunkPosition) -> Vec<ColumnInfo> {
+        let chunk_offset = chunk_pos.as_vec3() * (CHUNK_SIZE as f32);
+
+        (0..CHUNK_SIZE_U32)
+            .flat_map(|z| (0..CHUNK_SIZE_U32).map(move |x| (x, z)))
+            .map(|(x, z)| {
+                self.biome_source
+                    .column(chunk_offset.x + x as f32, chunk_offset.z + z as f32)
+            })
+            .collect()
+    }
+
+    fn is_solid(&self, pos: Vec3, column: &ColumnInfo) -> bool {
+        self.terrain_noise.get_noise3d(pos.x, pos.y, pos.z) * column.height_scale
+            > (pos.y - column.height_offset) * 0.01
+    }
+
+    fn is_cave(&self, pos: Vec3) -> bool {
+        self.cave_noise.get_noise3d(pos.x, pos.y, pos.z) >= 0.4
+    }
+
+    /// If the column at the given position in the chunk has a surface block on top, returns the
+    /// position of the surface block
+    fn find_surface(
+        &self,
+        chunk_pos: ChunkPosition,
+        x: u32,
+        z: u32,
+        column: &ColumnInfo,
+    ) -> Option<GlobalBlockPosition> {
+        let chunk_offset = chunk_pos.as_vec3() * (CHUNK_SIZE as f32);
+
+        let pos_above = UVec3::new(x, CHUNK_SIZE_U32, z).as_vec3() + chunk_offset;
+        if self.is_solid(pos_above, column) {
+            return None;
+        }
+
+        // the surface block is placed on the highest solid block in the chunk, unless it is
+        // carved out by a cave
+        (0..CHUNK_SIZE_U32)
+            .rev()
+            .find(|&y| self.is_solid(UVec3::new(x, y, z).as_vec3() + chunk_offset, column))
+            .filter(|&y| !self.is_cave(UVec3::new(x, y, z).as_vec3() + chunk_offset))
+            .map(|y| {
+                GlobalBlockPosition::from_local_and_chunk_pos(
+                    LocalBlockPosition::new(x, y, z),
+                    chunk_pos,
+                )
+            })
+    }
+}
+
+impl TerrainGenerator for NoiseGenerator {
+    fn generate_chunk(&self, chunk_pos: ChunkPosition) -> Vec<BlockId> {
+        let mut blocks = vec![BLOCK_AIR; CHUNK_SIZE_CUBED];
+
+        let chunk_offset = chunk_pos.as_vec3() * (CHUNK_SIZE as f32);
+        let columns = self.columns(chunk_pos);
+
+        for z in 0..CHUNK_SIZE_U32 {
+            for x in 0..CHUNK_SIZE_U32 {
+                let column = &columns[(z * CHUNK_SIZE_U32 + x) as usize];
+                let biome_blocks = self.biome_source.blocks(column.biome_id);
+
+                let pos_above = UVec3::new(x, CHUNK_SIZE_U32, z).as_vec3() + chunk_offset;
+                let mut solid_above = self.is_solid(pos_above, column);
+
+                for y in 0..CHUNK_SIZE_U32 {
+                    let y = CHUNK_SIZE_U32 - 1 - y;
+                    let index = Size3::splat(CHUNK_SIZE).flatten(UVec3::new(x, z, y));
+
+                    let pos = UVec3::new(x, y, z).as_vec3() + chunk_offset;
+
+                    if self.is_solid(pos, column) {
+                        if !self.is_cave(pos) {
+                            if solid_above {
+                                blocks[index] = biome_blocks.subsurface;
+                            } else {
+                                blocks[index] = biome_blocks.surface;
+                            }
+                        }
+                        solid_above = true;
+                    }
+                }
+            }
+        }
+
+        blocks
+    }
+
+    fn generate_biomes(&self, chunk_pos: ChunkPosition) -> ChunkBiomes {
+        ChunkBiomes::new(
+            &self
+                .columns(chunk_pos)
+                .iter()
+                .map(|column| column.biome_id)
+                .collect_vec(),
+        )
+    }
+
+    fn decorate_chunk(&self, chunk_pos: ChunkPosition, placements: &mut FeaturePlacements) {
+        let mut rng = chunk_rng(self.seed, chunk_pos);
+        let chunk_offset = chunk_pos.as_vec3() * (CHUNK_SIZE as f32);
+
+        for cell_z in (0..CHUNK_SIZE_U32).step_by(FEATURE_CELL_SIZE as usize) {
+            for cell_x in (0..CHUNK_SIZE_U32).step_by(FEATURE_CELL_SIZE as usize) {
+                // always draw the same random numbers for each cell so that the features in one
+                // cell don't affect the features in the others
+                let roll: f32 = rng.gen();
+                let x = cell_x + rng.gen_range(0..FEATURE_CELL_SIZE);
+                let z = cell_z + rng.gen_range(0..FEATURE_CELL_SIZE);
+                let mut feature_rng = chunk_rng(rng.gen(), chunk_pos);
+
+                let column = self
+                    .biome_source
+                    .column(chunk_offset.x + x as f32, chunk_offset.z + z as f32);
+                let biome = column.biome_id.biome();
+
+                if roll >= biome.tree_chance + biome.boulder_chance {
+                    continue;
+                }
+
+                let Some(surface_pos) = self.find_surface(chunk_pos, x, z, &column) else {
+                    continue;
+                };
+
+                if roll < biome.tree_chance {
+                    place_tree(
+                        placements,
+                        &mut feature_rng,
+                        surface_pos + GlobalBlockPosition::new(0, 1, 0),
+                        self.block_wood,
+                        self.block_leaves,
+                    );
+                } else {
+                    place_boulder(placements, &mut feature_rng, surface_pos, self.block_stone);
+                }
+            }
+        }
+    }
+}
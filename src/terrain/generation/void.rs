@@ -0,0 +1,16 @@
+use super::TerrainGenerator;
+use crate::terrain::{
+    block::{BlockId, BLOCK_AIR},
+    chunk::CHUNK_SIZE_CUBED,
+    position_types::ChunkPosition,
+};
+
+/// Generates empty chunks
+#[derive(Clone, Copy, Debug, Default)]
+pub struct VoidGenerator;
+
+impl TerrainGenerator for VoidGenerator {
+    fn generate_chunk(&self, _chunk_pos: ChunkPosition) -> Vec<BlockId> {
+        vec![BLOCK_AIR; CHUNK_SIZE_CUBED]
+    }
+}
@@ -0,0 +1,261 @@
+use std::{collections::VecDeque, mem};
+
+use super::{block::BlockId, position_types::GlobalBlockPosition};
+
+/// Default limit on the memory used by the edits stored in an `EditHistory`, in bytes
+pub const DEFAULT_HISTORY_MEMORY_LIMIT: usize = 16 * 1024 * 1024;
+
+/// A change to a single block
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockEdit {
+    pub pos: GlobalBlockPosition,
+    pub old_id: BlockId,
+    pub new_id: BlockId,
+}
+
+impl BlockEdit {
+    /// Returns the edit which reverts this edit
+    pub fn inverse(self) -> Self {
+        Self {
+            pos: self.pos,
+            old_id: self.new_id,
+            new_id: self.old_id,
+        }
+    }
+}
+
+/// Edits which are undone and redone together as a single step, such as all of the blocks changed
+/// by a fill or a brush stroke
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EditTransaction {
+    /// Edits in the order they were made
+    edits: Vec<BlockEdit>,
+}
+
+impl EditTransaction {
+    /// Returns the edits which revert the transaction, in the order they should be applied
+    pub fn inverse(&self) -> Vec<BlockEdit> {
+        self.edits.iter().rev().map(|edit| edit.inverse()).collect()
+    }
+
+    /// Approximate memory used by the transaction, in bytes
+    fn memory_size(&self) -> usize {
+        mem::size_of::<Self>() + self.edits.capacity() * mem::size_of::<BlockEdit>()
+    }
+}
+
+/// History of the edits made to the terrain, which can be undone and redone.
+/// Edits recorded between `begin_transaction` and the matching `end_transaction` are grouped into
+/// one transaction; edits recorded outside of a transaction are each a transaction of their own.
+/// Transactions may be nested, in which case the edits are grouped into the outermost transaction.
+/// When the memory used by the stored transactions exceeds the limit, the oldest transactions are
+/// forgotten
+#[derive(Clone, Debug)]
+pub struct EditHistory {
+    /// Transactions which can be undone, oldest first
+    undo_stack: VecDeque<EditTransaction>,
+    /// Transactions which have been undone and can be redone, most recently undone last
+    redo_stack: Vec<EditTransaction>,
+    /// Edits recorded since the outermost open transaction began
+    open_transaction: EditTransaction,
+    /// Number of transactions which have begun but not ended
+    transaction_depth: u32,
+    /// Limit on the memory used by the stored transactions, in bytes
+    memory_limit: usize,
+    /// Memory used by the transactions in the undo and redo stacks, in bytes
+    memory_used: usize,
+}
+
+impl Default for EditHistory {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY_MEMORY_LIMIT)
+    }
+}
+
+impl EditHistory {
+    /// Create an empty history that stores at most `memory_limit` bytes of edits
+    pub fn new(memory_limit: usize) -> Self {
+        Self {
+            undo_stack: VecDeque::new(),
+            redo_stack: Vec::new(),
+            open_transaction: EditTransaction::default(),
+            transaction_depth: 0,
+            memory_limit,
+            memory_used: 0,
+        }
+    }
+
+    /// Begin grouping the recorded edits into one transaction
+    pub fn begin_transaction(&mut self) {
+        self.transaction_depth += 1;
+    }
+
+    /// End the transaction begun by the matching call to `begin_transaction`. Once the outermost
+    /// transaction ends, its edits become a single step in the history
+    pub fn end_transaction(&mut self) {
+        debug_assert!(
+            self.transaction_depth > 0,
+            "`end_transaction` called without a matching `begin_transaction`"
+        );
+        self.transaction_depth = self.transaction_depth.saturating_sub(1);
+
+        if self.transaction_depth == 0 {
+            let transaction = mem::take(&mut self.open_transaction);
+            self.push_transaction(transaction);
+        }
+    }
+
+    /// True if a transaction has begun but not ended
+    pub fn is_in_transaction(&self) -> bool {
+        self.transaction_depth > 0
+    }
+
+    /// Record an edit, forgetting any undone transactions. Edits which don't change the block are
+    /// ignored
+    pub fn record(&mut self, edit: BlockEdit) {
+        if edit.old_id == edit.new_id {
+            return;
+        }
+
+        for transaction in self.redo_stack.drain(..) {
+            self.memory_used -= transaction.memory_size();
+        }
+
+        if self.is_in_transaction() {
+            self.open_transaction.edits.push(edit);
+        } else {
+            self.push_transaction(EditTransaction { edits: vec![edit] });
+        }
+    }
+
+    /// Move the most recent transaction to the redo stack, returning the edits which revert it.
+    /// Returns None if there is nothing to undo or a transaction is open
+    pub fn undo(&mut self) -> Option<Vec<BlockEdit>> {
+        if self.is_in_transaction() {
+            return None;
+        }
+
+        let transaction = self.undo_stack.pop_back()?;
+        let edits = transaction.inverse();
+        self.redo_stack.push(transaction);
+
+        Some(edits)
+    }
+
+    /// Move the most recently undone transaction back to the undo stack, returning the edits which
+    /// reapply it.
+    /// Returns None if there is nothing to redo or a transaction is open
+    pub fn redo(&mut self) -> Option<Vec<BlockEdit>> {
+        if self.is_in_transaction() {
+            return None;
+        }
+
+        let transaction = self.redo_stack.pop()?;
+        let edits = transaction.edits.clone();
+        self.undo_stack.push_back(transaction);
+
+        Some(edits)
+    }
+
+    /// Number of transactions which can be undone
+    pub fn undo_count(&self) -> usize {
+        self.undo_stack.len()
+    }
+
+    /// Number of transactions which can be redone
+    pub fn redo_count(&self) -> usize {
+        self.redo_stack.len()
+    }
+
+    /// Approximate memory used by the stored transactions, in bytes
+    pub fn memory_used(&self) -> usize {
+        self.memory_used
+    }
+
+    /// Forget all stored transactions
+    pub fn clear(&mut self) {
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.memory_used = 0;
+    }
+
+    /// Add a finished transaction to the undo stack, forgetting the oldest transactions if the
+    /// memory limit is exceeded
+    fn push_transaction(&mut self, mut transaction: EditTransaction) {
+        if transaction.edits.is_empty() {
+            return;
+        }
+
+        transaction.edits.shrink_to_fit();
+        self.memory_used += transaction.memory_size();
+        self.undo_stack.push_back(transaction);
+
+        while self.memory_used > self.memory_limit {
+            let Some(oldest) = self.undo_stack.pop_front() else {
+                break;
+            };
+            self.memory_used -= oldest.memory_size();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edit(x: i32, old_id: u16, new_id: u16) -> BlockEdit {
+        BlockEdit {
+            pos: GlobalBlockPosition::new(x, 0, 0),
+            old_id: BlockId(old_id),
+            new_id: BlockId(new_id),
+        }
+    }
+
+    #[test]
+    fn transactions_undo_and_redo() {
+        let mut history = EditHistory::default();
+
+        history.record(edit(0, 0, 1));
+        history.begin_transaction();
+        history.record(edit(1, 0, 2));
+        history.begin_transaction();
+        history.record(edit(1, 2, 3));
+        history.end_transaction();
+        assert_eq!(history.undo(), None);
+        history.end_transaction();
+        assert_eq!(history.undo_count(), 2);
+
+        // the nested transaction is undone as one step, in reverse order
+        assert_eq!(history.undo(), Some(vec![edit(1, 3, 2), edit(1, 2, 0)]));
+        assert_eq!(history.redo(), Some(vec![edit(1, 0, 2), edit(1, 2, 3)]));
+        assert_eq!(history.undo().map(|edits| edits.len()), Some(2));
+        assert_eq!(history.undo(), Some(vec![edit(0, 1, 0)]));
+        assert_eq!(history.undo(), None);
+
+        // a new edit forgets the undone transactions
+        history.record(edit(2, 0, 1));
+        assert_eq!(history.redo_count(), 0);
+        assert_eq!(history.redo(), None);
+    }
+
+    #[test]
+    fn memory_limit() {
+        let transaction_size = EditTransaction {
+            edits: vec![edit(0, 0, 1)],
+        }
+        .memory_size();
+        let mut history = EditHistory::new(transaction_size * 3);
+
+        for x in 0..5 {
+            history.record(edit(x, 0, 1));
+        }
+        assert_eq!(history.undo_count(), 3);
+        assert_eq!(history.memory_used(), transaction_size * 3);
+
+        // the oldest edits were forgotten
+        assert_eq!(history.undo(), Some(vec![edit(4, 1, 0)]));
+        assert_eq!(history.undo(), Some(vec![edit(3, 1, 0)]));
+        assert_eq!(history.undo(), Some(vec![edit(2, 1, 0)]));
+        assert_eq!(history.undo(), None);
+    }
+}
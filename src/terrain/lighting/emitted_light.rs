@@ -3,7 +3,7 @@ use rustc_hash::FxHashSet;
 
 use crate::{
     terrain::{
-        block::{BlockId, BLOCKS},
+        block::{registry::BlockRegistry, BlockId},
         chunk::{
             block_store::ChunkBlockStore, light_store, side::ChunkSideLight, CHUNK_SIZE,
             CHUNK_SIZE_I32, CHUNK_SIZE_LOG2,
@@ -25,6 +25,7 @@ pub fn propagate_emitted_light<Store: LightStore<EmittedLight>>(
     light_propagation_queue: &mut LightPropagationQueue<EmittedLight>,
     light_updates_outside_chunk: &mut LightUpdatesOutsideChunk,
     blocks: &ChunkBlockStore,
+    block_registry: &BlockRegistry,
 ) {
     while let Some(step) = light_propagation_queue.pop_front() {
         // calculate new light value
@@ -47,17 +48,23 @@ pub fn propagate_emitted_light<Store: LightStore<EmittedLight>>(
             continue;
         }
 
+        let block = &block_registry[blocks.get_block(step.position)];
+
         // propagate light to neighbours
         for (face_index, neighbour_offset) in FACE_NORMALS.iter().enumerate() {
+            // light can't leave through the opaque faces of partial blocks
+            if !block.can_light_exit(FaceIndex(face_index)) {
+                continue;
+            }
+
             if let Some(neighbour_pos) = step.position.try_add(*neighbour_offset) {
                 // work out if the light can pass into the neighbouring block
                 let neighbour_block_id = blocks.get_block(neighbour_pos);
-                let neighbour_block = &BLOCKS[neighbour_block_id.as_usize()];
+                let neighbour_block = &block_registry[neighbour_block_id];
                 let existing_light_value = light_store.read(neighbour_pos);
 
-                let can_travel_into_block = neighbour_block
-                    .model
-                    .is_transparent_in_direction(FaceIndex(face_index).opposite());
+                let can_travel_into_block =
+                    neighbour_block.is_transparent_in_direction(FaceIndex(face_index).opposite());
 
                 // work out if propagating light to the neighbouring block would increase the light
                 // value in that block
@@ -96,6 +103,7 @@ pub fn propagate_emitted_light_shadow<Store: LightStore<EmittedLight>>(
     light_propagation_queue: &mut LightPropagationQueue<EmittedLight>,
     light_updates_outside_chunk: &mut LightUpdatesOutsideChunk,
     blocks: &ChunkBlockStore,
+    block_registry: &BlockRegistry,
 ) {
     let mut visited = FxHashSet::default();
 
@@ -123,7 +131,7 @@ pub fn propagate_emitted_light_shadow<Store: LightStore<EmittedLight>>(
         // Repair lighting by re-queueing any light emitting blocks encompassed in the shadow for
         // propagation
         let block_id = blocks.get_block(step.position);
-        let block = &BLOCKS[block_id.as_usize()];
+        let block = &block_registry[block_id];
         if block.emission != IVec3::ZERO {
             light_propagation_queue.push_back(LightPropagationStep {
                 position: step.position,
@@ -159,13 +167,14 @@ pub fn propagate_emitted_light_shadow<Store: LightStore<EmittedLight>>(
 pub fn get_initial_emitted_light_queue(
     blocks: &[BlockId],
     surrounding_sides_light: &[Option<ChunkSideLight>],
+    block_registry: &BlockRegistry,
 ) -> LightPropagationQueue<EmittedLight> {
     // blocks within chunk
     let mut light_queue: LightPropagationQueue<EmittedLight> = blocks
         .iter()
         .enumerate()
         .filter_map(|(block_index, block_id)| {
-            let block = &BLOCKS[block_id.0 as usize];
+            let block = &block_registry[*block_id];
 
             if block.emission != IVec3::ZERO {
                 Some(LightPropagationStep {
@@ -209,11 +218,9 @@ pub fn get_initial_emitted_light_queue(
                     })
                     .filter(move |step| {
                         let block_id = blocks[step.position.get_array_index()];
-                        let block = &BLOCKS[block_id.as_usize()];
+                        let block = &block_registry[block_id];
 
-                        block
-                            .model
-                            .is_transparent_in_direction(FaceIndex(side_index))
+                        block.is_transparent_in_direction(FaceIndex(side_index))
                     })
             })
             .flatten(),
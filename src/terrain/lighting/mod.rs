@@ -32,6 +32,7 @@ pub enum LightUpdate {
     EmittedLight(LightPropagationStep<EmittedLight>),
     EmittedLightShadow(ShadowPropagationStep),
     Skylight(LightPropagationStep<Skylight>),
+    SkylightShadow(ShadowPropagationStep),
 }
 
 pub type LightUpdatesOutsideChunk = Vec<(FaceIndex, LightUpdate)>;
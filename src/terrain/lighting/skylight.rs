@@ -1,8 +1,9 @@
 use glam::IVec3;
+use rustc_hash::FxHashSet;
 
 use crate::{
     terrain::{
-        block::{BlockId, BLOCKS},
+        block::{registry::BlockRegistry, BlockId},
         chunk::{
             block_store::ChunkBlockStore, side::ChunkSideLight, CHUNK_SIZE, CHUNK_SIZE_I32,
             CHUNK_SIZE_LOG2, CHUNK_SIZE_SQUARED,
@@ -15,6 +16,7 @@ use crate::{
 
 use super::{
     LightPropagationQueue, LightPropagationStep, LightStore, LightUpdate, LightUpdatesOutsideChunk,
+    ShadowPropagationQueue, ShadowPropagationStep,
 };
 
 /// Propagate skylight within a chunk, returning the light updates to be
@@ -24,6 +26,7 @@ pub fn propagate_skylight<Store: LightStore<Skylight>>(
     light_propagation_queue: &mut LightPropagationQueue<Skylight>,
     light_updates_outside_chunk: &mut LightUpdatesOutsideChunk,
     blocks: &ChunkBlockStore,
+    block_registry: &BlockRegistry,
 ) {
     while let Some(step) = light_propagation_queue.pop_front() {
         // calculate new light value
@@ -40,7 +43,14 @@ pub fn propagate_skylight<Store: LightStore<Skylight>>(
         // update light value
         light_store.write(step.position, light_new);
 
+        let block = &block_registry[blocks.get_block(step.position)];
+
         for (face_index, neighbour_offset) in FACE_NORMALS.iter().enumerate() {
+            // light can't leave through the opaque faces of partial blocks
+            if !block.can_light_exit(FaceIndex(face_index)) {
+                continue;
+            }
+
             // calculate new light value to propagate to neighbours
             let light_diminished = if FaceIndex(face_index) == FaceIndex::NEG_Y {
                 // Propagate infinitely downwards
@@ -56,12 +66,11 @@ pub fn propagate_skylight<Store: LightStore<Skylight>>(
             if let Some(neighbour_pos) = step.position.try_add(*neighbour_offset) {
                 // work out if the light can pass into the neighbouring block
                 let neighbour_block_id = blocks.get_block(neighbour_pos);
-                let neighbour_block = &BLOCKS[neighbour_block_id.as_usize()];
+                let neighbour_block = &block_registry[neighbour_block_id];
                 let existing_light_value = light_store.read(neighbour_pos);
 
-                let can_travel_into_block = neighbour_block
-                    .model
-                    .is_transparent_in_direction(FaceIndex(face_index).opposite());
+                let can_travel_into_block =
+                    neighbour_block.is_transparent_in_direction(FaceIndex(face_index).opposite());
 
                 // work out if propagating light to the neighbouring block would increase the light
                 // value in that block
@@ -91,15 +100,101 @@ pub fn propagate_skylight<Store: LightStore<Skylight>>(
     }
 }
 
+/// Propagate absense of skylight within a chunk, returning the shadow updates to be applied
+/// outside of the chunk
+pub fn propagate_skylight_shadow<Store: LightStore<Skylight>>(
+    light_store: &mut Store,
+    shadow_propagation_queue: &mut ShadowPropagationQueue,
+    light_propagation_queue: &mut LightPropagationQueue<Skylight>,
+    light_updates_outside_chunk: &mut LightUpdatesOutsideChunk,
+) {
+    // Since skylight travels downwards without diminishing, the shadow must also travel downwards
+    // without losing depth. Steps are therefore processed in order of decreasing depth so that
+    // each block is reached with the greatest depth possible; otherwise a block could be repaired
+    // using light that is about to be removed
+    let mut steps_by_depth = vec![Vec::new(); Skylight::MAX_VALUE as usize + 1];
+    for step in shadow_propagation_queue.drain(..) {
+        let depth = step.depth.min(Skylight::MAX_VALUE as u32) as usize;
+        steps_by_depth[depth].push(step.position);
+    }
+
+    let mut visited = FxHashSet::default();
+
+    for depth in (0..steps_by_depth.len()).rev() {
+        while let Some(position) = steps_by_depth[depth].pop() {
+            if !visited.insert(position) {
+                continue;
+            }
+
+            let light_old = light_store.read(position);
+
+            // Light which came through the start of the shadow is at most as bright as the depth,
+            // so brighter light came from elsewhere and is left as the edge of the shadow.
+            // Unlit blocks have no light to remove or pass on, so the shadow stops at them too,
+            // other than at the start, where a removed block is unlit
+            if light_old == Skylight::ZERO && depth < Skylight::MAX_VALUE as usize {
+                continue;
+            }
+
+            if depth == 0 || light_old.0 as usize > depth {
+                // Repair lighting by re-queueing the light at the edge of the shadow
+                // for propagation
+                if light_old != Skylight::ZERO {
+                    light_propagation_queue.push_back(LightPropagationStep {
+                        position,
+                        light: light_old,
+                        is_repair_step: true,
+                    });
+                }
+
+                continue;
+            }
+
+            light_store.write(position, Skylight::ZERO);
+
+            // Propagate shadow to neighbours
+            for (neighbour_index, neighbour_offset) in FACE_NORMALS.iter().enumerate() {
+                // Light passing downwards through a lit block may have come from anywhere above it,
+                // so the shadow follows it all the way down
+                let neighbour_depth = if FaceIndex(neighbour_index) == FaceIndex::NEG_Y
+                    && light_old != Skylight::ZERO
+                {
+                    depth
+                } else {
+                    depth - 1
+                };
+
+                if let Some(neighbour_pos) = position.try_add(*neighbour_offset) {
+                    if !visited.contains(&neighbour_pos) {
+                        steps_by_depth[neighbour_depth].push(neighbour_pos);
+                    }
+                } else {
+                    let pos_in_neighbour_chunk = position.wrapping_add(*neighbour_offset);
+
+                    light_updates_outside_chunk.push((
+                        FaceIndex(neighbour_index),
+                        LightUpdate::SkylightShadow(ShadowPropagationStep {
+                            position: pos_in_neighbour_chunk,
+                            depth: neighbour_depth as u32,
+                        }),
+                    ))
+                }
+            }
+        }
+    }
+}
+
 /// Returns a LightPropagationQueue for all of the blocks on the sides of the chunk into which
 /// skylight can propagate from neighbouring chunks
 pub fn get_initial_skylight_queue(
     blocks: &[BlockId],
     surrounding_sides_light: &[Option<ChunkSideLight>],
+    block_registry: &BlockRegistry,
 ) -> LightPropagationQueue<Skylight> {
     fn add_light_values_for_side(
         result: &mut LightPropagationQueue<Skylight>,
         blocks: &[BlockId],
+        block_registry: &BlockRegistry,
         side_index: usize,
         get_light_fn: impl Fn(usize) -> Skylight,
     ) {
@@ -119,12 +214,9 @@ pub fn get_initial_skylight_queue(
             );
 
             let block_id = blocks[position.get_array_index()];
-            let block = &BLOCKS[block_id.as_usize()];
+            let block = &block_registry[block_id];
 
-            if !block
-                .model
-                .is_transparent_in_direction(FaceIndex(side_index))
-            {
+            if !block.is_transparent_in_direction(FaceIndex(side_index)) {
                 continue;
             }
 
@@ -141,19 +233,27 @@ pub fn get_initial_skylight_queue(
     for (side_index, side_opt) in surrounding_sides_light.iter().enumerate() {
         if FaceIndex(side_index) == FaceIndex::POS_Y {
             if let Some(side) = side_opt {
-                add_light_values_for_side(&mut result, blocks, side_index, |index_in_side| {
-                    side.sky[index_in_side]
-                })
+                add_light_values_for_side(
+                    &mut result,
+                    blocks,
+                    block_registry,
+                    side_index,
+                    |index_in_side| side.sky[index_in_side],
+                )
             } else {
-                add_light_values_for_side(&mut result, blocks, side_index, |_| {
+                add_light_values_for_side(&mut result, blocks, block_registry, side_index, |_| {
                     Skylight(Skylight::MAX_VALUE)
                 })
             }
         } else {
             if let Some(side) = side_opt {
-                add_light_values_for_side(&mut result, blocks, side_index, |index_in_side| {
-                    side.sky[index_in_side].decrement_and_saturate()
-                })
+                add_light_values_for_side(
+                    &mut result,
+                    blocks,
+                    block_registry,
+                    side_index,
+                    |index_in_side| side.sky[index_in_side].decrement_and_saturate(),
+                )
             }
         }
     }
@@ -176,3 +276,112 @@ impl Skylight {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{propagate_skylight_shadow, Skylight};
+    use crate::terrain::{
+        block::{registry::test_registry, BLOCK_AIR},
+        chunk::{Chunk, CHUNK_SIZE_CUBED},
+        generation::biome::ChunkBiomes,
+        lighting::{
+            LightPropagationQueue, LightStore, LightUpdatesOutsideChunk, ShadowPropagationQueue,
+            ShadowPropagationStep,
+        },
+        position_types::{ChunkPosition, LocalBlockPosition},
+    };
+
+    /// Light store counting the number of values written to it
+    struct CountingLightStore {
+        light: Vec<Skylight>,
+        write_count: usize,
+    }
+
+    impl LightStore<Skylight> for CountingLightStore {
+        fn read(&self, pos: LocalBlockPosition) -> Skylight {
+            self.light[pos.get_array_index()]
+        }
+
+        fn write(&mut self, pos: LocalBlockPosition, value: Skylight) {
+            self.light[pos.get_array_index()] = value;
+            self.write_count += 1;
+        }
+    }
+
+    #[test]
+    fn skylight_shadow_is_bounded() {
+        // flat ground below y = 4 with open sky above it
+        let mut light_store = CountingLightStore {
+            light: vec![Skylight::ZERO; CHUNK_SIZE_CUBED],
+            write_count: 0,
+        };
+        for (x, y, z) in itertools::iproduct!(0..32, 4..32, 0..32) {
+            light_store.write(LocalBlockPosition::new(x, y, z), Skylight(15));
+        }
+        light_store.write_count = 0;
+
+        // placing a block at y = 20 only shadows the column below it, down to the ground
+        let mut shadow_queue = ShadowPropagationQueue::new();
+        shadow_queue.push_back(ShadowPropagationStep {
+            position: LocalBlockPosition::new(16, 20, 16),
+            depth: Skylight::MAX_VALUE as u32,
+        });
+        let mut light_queue = LightPropagationQueue::new();
+        let mut light_updates_outside_chunk = LightUpdatesOutsideChunk::new();
+        propagate_skylight_shadow(
+            &mut light_store,
+            &mut shadow_queue,
+            &mut light_queue,
+            &mut light_updates_outside_chunk,
+        );
+
+        // the column from y = 20 to y = 4, and the ground below it
+        assert_eq!(light_store.write_count, 18);
+        assert!(light_updates_outside_chunk.is_empty());
+        // the light around the column repairs it
+        assert!(light_queue
+            .iter()
+            .all(|step| step.is_repair_step && step.light == Skylight(15)));
+    }
+
+    #[test]
+    fn skylight_shadow() {
+        let block_registry = test_registry(&[r#"
+            name = "stone"
+            model = { type = "full_block", textures = { all = "stone" } }
+        "#]);
+        let stone = block_registry.get_id("stone").unwrap();
+
+        let mut chunk = Chunk::new(
+            ChunkPosition::new(0, 0, 0),
+            &vec![BLOCK_AIR; CHUNK_SIZE_CUBED],
+            ChunkBiomes::default(),
+            &block_registry,
+        );
+        chunk.initialize_lighting(&vec![None; 6], &block_registry);
+        chunk.update_lighting(&block_registry);
+
+        let skylight_at = |chunk: &Chunk, x, y, z| {
+            chunk
+                .light_store()
+                .get_skylight(LocalBlockPosition::new(x, y, z))
+        };
+        assert_eq!(skylight_at(&chunk, 16, 0, 16), Skylight(15));
+
+        // a single block casts a shadow all the way down, which is lit from the sides
+        // (placed low enough that the shadow doesn't leave the top of the chunk)
+        chunk.set_block(LocalBlockPosition::new(16, 5, 16), stone);
+        chunk.update_lighting(&block_registry);
+        assert_eq!(skylight_at(&chunk, 16, 5, 16), Skylight(0));
+        assert_eq!(skylight_at(&chunk, 16, 4, 16), Skylight(14));
+        assert_eq!(skylight_at(&chunk, 16, 0, 16), Skylight(14));
+        assert_eq!(skylight_at(&chunk, 17, 0, 16), Skylight(15));
+        assert_eq!(skylight_at(&chunk, 16, 6, 16), Skylight(15));
+
+        // removing the block lets the skylight back in
+        chunk.set_block(LocalBlockPosition::new(16, 5, 16), BLOCK_AIR);
+        chunk.update_lighting(&block_registry);
+        assert_eq!(skylight_at(&chunk, 16, 5, 16), Skylight(15));
+        assert_eq!(skylight_at(&chunk, 16, 0, 16), Skylight(15));
+    }
+}
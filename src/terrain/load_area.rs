@@ -3,7 +3,7 @@ use generational_arena::Index;
 use glam::{Vec3, Vec3Swizzles};
 
 use super::position_types::ChunkPosition;
-use crate::util::{size::Size3, vector_map::VectorMapExt};
+use crate::util::size::Size3;
 
 /// An `LoadArea` represents a region of terrain that is loaded in memory.
 /// The `LoadArea` provides O(1) lookup for the chunks it contains
@@ -157,12 +157,33 @@ impl LoadArea {
         }
     }
 
-    /// Update the size of this area in chunks
-    /// This will only mark the area as dirty if the new position is different
+    /// Update the size of this area in chunks, keeping the position of its lower corner.
+    /// The states of the chunks within both the old and the new bounds are kept, so that the
+    /// terrain doesn't load them again. This will only mark the area as dirty if the new size is
+    /// different
     pub fn set_size(&mut self, new_size: Size3) {
-        if new_size != self.size {
-            self.size = new_size;
-            self.state = LoadAreaState::Dirty;
+        if new_size == self.size {
+            return;
+        }
+
+        let old_chunk_states = std::mem::replace(
+            &mut self.chunk_states,
+            vec![ChunkState::Unloaded; new_size.product()],
+        );
+        self.size = new_size;
+        self.center_pos = self.position.as_vec3() + 0.5 * self.size.as_vec3();
+        self.size_recip = self.size.as_vec3().recip();
+        self.state = LoadAreaState::Dirty;
+
+        for chunk_state in old_chunk_states {
+            let chunk_pos = match chunk_state {
+                ChunkState::Unloaded => continue,
+                ChunkState::Loading(chunk_pos) | ChunkState::Loaded(chunk_pos, _) => chunk_pos,
+            };
+
+            if let Some(array_index) = self.get_array_index(&chunk_pos) {
+                self.chunk_states[array_index] = chunk_state;
+            }
         }
     }
 
@@ -241,3 +262,39 @@ pub enum AreaShape {
     /// Chunks are loaded in a cylinder around the y axis
     Cylindrical,
 }
+
+#[cfg(test)]
+mod tests {
+    use generational_arena::Arena;
+
+    use super::*;
+
+    #[test]
+    fn resizing() {
+        let mut chunk_indices = Arena::new();
+        let mut load_area = LoadArea::new(ChunkPosition::ZERO, Size3::splat(4), AreaShape::Cubic);
+
+        let loaded = ChunkPosition::new(1, 2, 3);
+        let loading = ChunkPosition::new(3, 0, 0);
+        let chunk_index = chunk_indices.insert(());
+        load_area.mark_loaded(&loaded, chunk_index);
+        load_area.mark_loading(&loading);
+
+        // chunks inside both the old and new bounds keep their state
+        load_area.set_size(Size3::new(5, 4, 4));
+        assert!(load_area.state().is_dirty());
+        assert_eq!(load_area.get_chunk_index(&loaded), Some(chunk_index));
+        assert!(load_area.is_loading(&loading));
+
+        load_area.set_size(Size3::new(5, 2, 4));
+        assert_eq!(load_area.center(), Vec3::new(2.5, 1.0, 2.0));
+        assert_eq!(load_area.get_chunk_index(&loaded), None);
+        assert!(load_area.is_loading(&loading));
+        assert_eq!(load_area.iter_positions().count(), 5 * 2 * 4);
+
+        load_area.set_size(Size3::new(8, 8, 8));
+        assert!(load_area.is_loading(&loading));
+        assert!(load_area.is_unloaded(&loaded));
+        assert!(load_area.is_unloaded(&ChunkPosition::new(7, 7, 7)));
+    }
+}
@@ -1,38 +1,100 @@
 use std::{
     collections::VecDeque,
-    sync::mpsc::{self, Receiver, Sender},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    time::Duration,
 };
 
 use generational_arena::{Arena, Index};
 use glam::{IVec3, Vec3};
 use itertools::Itertools;
+use rand::Rng;
+use rustc_hash::FxHashMap;
 
 use self::{
-    block::BlockId,
-    chunk::{side::ChunkSideLight, Chunk, CHUNK_SIZE, CHUNK_SIZE_RECIP},
+    block::{entity::BlockEntity, registry::BlockRegistry, Block, BlockId, BLOCK_AIR},
+    chunk::{side::ChunkSideLight, Chunk, CHUNK_SIZE, CHUNK_SIZE_CUBED},
+    edit::{Clipboard, EditOperation, EditShape},
     event::TerrainEvent,
-    lighting::LightUpdatesOutsideChunk,
+    fluid::FluidSimulation,
+    generation::{
+        biome::BiomeId,
+        feature::{merge_feature_block, ChunkPlacements, FeaturePlacements},
+        TerrainGenerator,
+    },
+    history::{BlockEdit, EditHistory},
+    lighting::{skylight::Skylight, LightPropagationStep, LightUpdate, LightUpdatesOutsideChunk},
     load_area::{LoadArea, LoadAreaState},
-    position_types::{ChunkPosition, GlobalBlockPosition},
+    pathfinding::{Path, PathfindingSettings, TerrainSnapshot},
+    position_types::{ChunkPosition, GlobalBlockPosition, LocalBlockPosition},
+    ray::RayHit,
+    save::{SavedChunk, WorldSave},
+    tick::{BlockBehaviour, BlockBehaviours, ScheduledTick, RANDOM_TICKS_PER_CHUNK},
 };
 use crate::{
     core::tasks::{TaskPriority, Tasks},
-    util::{face::FACE_NORMALS, vector_map::VectorMapExt},
-    CHUNK_LOADING_PRIORITY,
+    util::{
+        face::{FaceIndex, FACE_NORMALS},
+        size::AsSize3,
+    },
+    CHUNK_LOADING_PRIORITY, CHUNK_SAVING_PRIORITY, PATHFINDING_PRIORITY,
 };
 
 pub mod block;
 pub mod chunk;
+pub mod collision;
+pub mod edit;
 pub mod event;
+pub mod fluid;
+pub mod generation;
+pub mod history;
 pub mod lighting;
 pub mod load_area;
+pub mod pathfinding;
 pub mod position_types;
-pub mod temporary_generation;
+pub mod ray;
+pub mod save;
+pub mod schematic;
+pub mod tick;
+pub mod vox;
+
+/// Duration of one terrain tick
+pub const TICK_DURATION: Duration = Duration::from_millis(50);
+
+/// Number of ticks between each fluid update
+pub const FLUID_TICK_INTERVAL: u64 = 5;
+
+/// Maximum number of ticks run in one call to `Terrain::update`. If the ticks take longer than
+/// `TICK_DURATION` to run, the terrain falls behind rather than spending longer and longer
+/// catching up
+const MAX_TICKS_PER_UPDATE: u32 = 10;
+
+/// Distance in blocks around the start and goal of a path search which is copied for the search
+const PATHFINDING_SNAPSHOT_MARGIN: i32 = CHUNK_SIZE as i32;
+
+/// Where the terrain gets its chunks from
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChunkSource {
+    /// Chunks are loaded from the world save or generated, and the terrain is ticked and saved
+    #[default]
+    Local,
+    /// Chunks are received from a server with `receive_chunk`, which also sends the changes made
+    /// to them. The terrain isn't ticked, decorated or saved, since the server does that
+    Remote,
+}
 
 /// Manages the voxel terrain, responsible for loading/unloading chunks and submitting terrain
 /// generation tasks
 #[derive(Debug)]
 pub struct Terrain {
+    /// Kinds of block that can exist in the terrain
+    block_registry: Arc<BlockRegistry>,
+    /// Save from which chunks are loaded and to which modified chunks are written
+    world_save: Arc<WorldSave>,
+    /// Generator for chunks which have not been saved
+    generator: Arc<dyn TerrainGenerator>,
     /// Currently loaded chunks
     chunks: Arena<Chunk>,
     /// Areas around which chunks are loaded
@@ -43,32 +105,105 @@ pub struct Terrain {
     loaded_chunk_tx: Sender<LoadedChunkInfo>,
     /// Receiver for loaded chunks
     loaded_chunk_rx: Receiver<LoadedChunkInfo>,
+    /// Sender for the features placed by decorated chunks
+    decorated_chunk_tx: Sender<DecoratedChunkInfo>,
+    /// Receiver for the features placed by decorated chunks
+    decorated_chunk_rx: Receiver<DecoratedChunkInfo>,
+    /// Feature blocks waiting to be placed in chunks which are not loaded
+    pending_feature_placements: ChunkPlacements,
     /// Indices of chunks requiring lighting updates
     chunks_requiring_light_updates: VecDeque<Index>,
+    /// Fluid flow through the loaded chunks
+    fluids: FluidSimulation,
+    /// Hooks run when blocks receive scheduled or random ticks
+    block_behaviours: BlockBehaviours,
+    /// Number of ticks run in the world so far
+    tick_index: u64,
+    /// Time elapsed since the last tick
+    tick_accumulator: Duration,
+    /// Edits made with `set_block`, which can be undone and redone
+    history: EditHistory,
+    /// Edits from undoing or redoing which are waiting for their chunks to finish loading, or for
+    /// a task patching their saved chunks to finish
+    pending_history_edits: FxHashMap<ChunkPosition, Vec<BlockEdit>>,
+    /// Chunks whose saved data is being patched with edits from undoing or redoing, with the
+    /// number of edits the task applies
+    patching_chunks: FxHashMap<ChunkPosition, usize>,
+    /// Sender for the positions of chunks whose saved data has been patched
+    patched_chunk_tx: Sender<ChunkPosition>,
+    /// Receiver for the positions of chunks whose saved data has been patched
+    patched_chunk_rx: Receiver<ChunkPosition>,
+    /// Where chunks come from
+    chunk_source: ChunkSource,
 }
 
 impl Terrain {
-    pub fn new() -> Self {
+    pub fn new(
+        block_registry: Arc<BlockRegistry>,
+        world_save: Arc<WorldSave>,
+        generator: Arc<dyn TerrainGenerator>,
+    ) -> Self {
         let (loaded_chunk_tx, loaded_chunk_rx) = mpsc::channel();
+        let (decorated_chunk_tx, decorated_chunk_rx) = mpsc::channel();
+        let (patched_chunk_tx, patched_chunk_rx) = mpsc::channel();
+
+        let pending_feature_placements = world_save.load_feature_placements().unwrap_or_else(|e| {
+            log::error!("failed to load feature placements: {}", e);
+            ChunkPlacements::default()
+        });
+
+        let block_behaviours = BlockBehaviours::with_default_behaviours(&block_registry);
+        let tick_index = world_save.tick_index();
 
         Self {
+            block_registry,
+            world_save,
+            generator,
             chunks: Arena::new(),
             load_areas: Arena::new(),
             events: Vec::new(),
             loaded_chunk_tx,
             loaded_chunk_rx,
+            decorated_chunk_tx,
+            decorated_chunk_rx,
+            pending_feature_placements,
             chunks_requiring_light_updates: VecDeque::new(),
+            fluids: FluidSimulation::new(),
+            block_behaviours,
+            tick_index,
+            tick_accumulator: Duration::ZERO,
+            history: EditHistory::default(),
+            pending_history_edits: FxHashMap::default(),
+            patching_chunks: FxHashMap::default(),
+            patched_chunk_tx,
+            patched_chunk_rx,
+            chunk_source: ChunkSource::Local,
         }
     }
 
-    /// Called each frame to update the chunks
-    pub fn update(&mut self, tasks: &mut Tasks, camera_pos: Vec3) {
+    /// Called each frame to update the chunks. `delta` is the time since the last update
+    pub fn update(&mut self, tasks: &mut Tasks, camera_pos: Vec3, delta: Duration) {
         // check for newly loaded chunks
         while let Ok(chunk) = self.loaded_chunk_rx.try_recv() {
-            self.finished_loading_chunk(chunk);
+            self.finished_loading_chunk(tasks, chunk);
+        }
+
+        self.receive_decorated_chunks();
+        self.receive_patched_chunks(tasks);
+
+        // run the ticks that have elapsed since the last update
+        self.tick_accumulator += delta;
+        let mut tick_count = 0;
+        while self.tick_accumulator >= TICK_DURATION {
+            self.tick_accumulator -= TICK_DURATION;
+
+            if tick_count < MAX_TICKS_PER_UPDATE && self.chunk_source == ChunkSource::Local {
+                self.tick();
+                tick_count += 1;
+            }
         }
 
-        self.check_chunks_to_unload();
+        self.check_chunks_to_unload(tasks);
         self.check_chunks_to_load(tasks, camera_pos);
 
         // mark all areas as clean
@@ -80,7 +215,7 @@ impl Terrain {
         while let Some(chunk_index) = self.chunks_requiring_light_updates.pop_front() {
             if let Some(chunk) = self.chunks.get_mut(chunk_index) {
                 if chunk.requires_light_updates() {
-                    let light_updates_outside_chunk = chunk.update_lighting();
+                    let light_updates_outside_chunk = chunk.update_lighting(&self.block_registry);
                     let chunk_pos = chunk.position();
 
                     self.handle_light_updates_outside_chunk(
@@ -137,8 +272,191 @@ impl Terrain {
             .map(|chunk| chunk.get_block(local_block_pos))
     }
 
+    /// Returns a copy of the blocks of the loaded chunks in the given load area which overlap the
+    /// box between the two positions (inclusive)
+    pub fn snapshot_region(
+        &self,
+        load_area_index: Index,
+        a: GlobalBlockPosition,
+        b: GlobalBlockPosition,
+    ) -> TerrainSnapshot {
+        let (_, min_chunk) =
+            GlobalBlockPosition::from(a.as_ivec3().min(b.as_ivec3())).get_local_and_chunk_pos();
+        let (_, max_chunk) =
+            GlobalBlockPosition::from(a.as_ivec3().max(b.as_ivec3())).get_local_and_chunk_pos();
+
+        let mut snapshot = TerrainSnapshot::new();
+        for (x, y, z) in itertools::iproduct!(
+            min_chunk.x()..=max_chunk.x(),
+            min_chunk.y()..=max_chunk.y(),
+            min_chunk.z()..=max_chunk.z()
+        ) {
+            let chunk_pos = ChunkPosition::new(x, y, z);
+            if let Some(chunk) = self.get_chunk(load_area_index, &chunk_pos) {
+                snapshot.insert_chunk(chunk_pos, chunk.block_store().clone());
+            }
+        }
+
+        snapshot
+    }
+
+    /// Submit a task to find a path from `start` to `goal` with `pathfinding::find_path`, searching
+    /// a snapshot of the loaded chunks around them. The result is sent through the returned
+    /// receiver once the search finishes
+    pub fn submit_path_search(
+        &self,
+        tasks: &mut Tasks,
+        load_area_index: Index,
+        start: GlobalBlockPosition,
+        goal: GlobalBlockPosition,
+        settings: PathfindingSettings,
+    ) -> Receiver<Option<Path>> {
+        let margin = IVec3::splat(PATHFINDING_SNAPSHOT_MARGIN);
+        let min = start.as_ivec3().min(goal.as_ivec3()) - margin;
+        let max = start.as_ivec3().max(goal.as_ivec3()) + margin;
+        let snapshot = self.snapshot_region(load_area_index, min.into(), max.into());
+
+        let block_registry = self.block_registry.clone();
+        let (path_tx, path_rx) = mpsc::channel();
+
+        tasks.submit(
+            TaskPriority {
+                class_priority: PATHFINDING_PRIORITY,
+                priority_within_class: 0,
+            },
+            move || {
+                let path = snapshot.find_path(start, goal, &settings, &block_registry);
+
+                if let Err(e) = path_tx.send(path) {
+                    log::trace!(
+                        "sending path from pathfinding thread to main thread returned error: {}",
+                        e
+                    );
+                }
+            },
+        );
+
+        path_rx
+    }
+
+    /// If the position is inside a loaded chunk within the given load area, returns the biome ID
+    /// of the column containing that position. Otherwise returns None
+    pub fn get_biome(
+        &self,
+        load_area_index: Index,
+        global_block_pos: &GlobalBlockPosition,
+    ) -> Option<BiomeId> {
+        let (local_block_pos, chunk_pos) = global_block_pos.get_local_and_chunk_pos();
+
+        self.get_chunk(load_area_index, &chunk_pos)
+            .map(|chunk| chunk.get_biome(local_block_pos))
+    }
+
+    /// If the position is inside a loaded chunk in any load area, returns the block ID at that
+    /// position. Otherwise returns None
+    pub fn get_loaded_block(&self, global_block_pos: &GlobalBlockPosition) -> Option<BlockId> {
+        let (local_block_pos, chunk_pos) = global_block_pos.get_local_and_chunk_pos();
+
+        self.find_chunk_index(&chunk_pos)
+            .map(|chunk_index| self.chunks[chunk_index].get_block(local_block_pos))
+    }
+
+    /// If the position is inside a loaded chunk in any load area, returns the skylight at that
+    /// position. Otherwise returns None
+    pub fn get_loaded_skylight(&self, global_block_pos: &GlobalBlockPosition) -> Option<Skylight> {
+        let (local_block_pos, chunk_pos) = global_block_pos.get_local_and_chunk_pos();
+
+        self.find_chunk_index(&chunk_pos).map(|chunk_index| {
+            self.chunks[chunk_index]
+                .light_store()
+                .get_skylight(local_block_pos)
+        })
+    }
+
+    /// If the position is inside a loaded chunk in any load area, sets the block at that position
+    /// in the same way as `set_block` and returns true. Otherwise returns false
+    pub fn set_loaded_block(
+        &mut self,
+        global_block_pos: &GlobalBlockPosition,
+        new_id: BlockId,
+    ) -> bool {
+        let (_, chunk_pos) = global_block_pos.get_local_and_chunk_pos();
+
+        if let Some(chunk_index) = self.find_chunk_index(&chunk_pos) {
+            self.set_block_in_chunk(chunk_index, global_block_pos, new_id);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// If the position is inside a loaded chunk in any load area, returns the block entity at that
+    /// position, if there is one
+    pub fn get_block_entity(&self, global_block_pos: &GlobalBlockPosition) -> Option<&BlockEntity> {
+        let (local_block_pos, chunk_pos) = global_block_pos.get_local_and_chunk_pos();
+
+        self.find_chunk_index(&chunk_pos)
+            .and_then(|chunk_index| self.chunks[chunk_index].get_block_entity(local_block_pos))
+    }
+
+    /// If there is a block entity at the given position in a loaded chunk, modifies it with the
+    /// given function, fires a `BlockEntityModified` event and returns true. Otherwise returns
+    /// false
+    pub fn modify_block_entity(
+        &mut self,
+        global_block_pos: &GlobalBlockPosition,
+        modify: impl FnOnce(&mut BlockEntity),
+    ) -> bool {
+        let (local_block_pos, chunk_pos) = global_block_pos.get_local_and_chunk_pos();
+
+        let Some(block_entity) = self
+            .find_chunk_index(&chunk_pos)
+            .and_then(|chunk_index| self.chunks[chunk_index].get_block_entity_mut(local_block_pos))
+        else {
+            return false;
+        };
+
+        modify(block_entity);
+        self.events.push(TerrainEvent::BlockEntityModified(
+            chunk_pos,
+            local_block_pos,
+        ));
+
+        true
+    }
+
+    /// Request a scheduled tick for the block at the given position after the given number of
+    /// ticks. The request is saved with the chunk containing the position, so it is kept while
+    /// the chunk is unloaded and runs once the chunk is loaded again.
+    /// Returns false if the position isn't inside a loaded chunk, in which case the request is
+    /// dropped
+    pub fn schedule_tick(&mut self, global_block_pos: &GlobalBlockPosition, delay: u64) -> bool {
+        let (local_block_pos, chunk_pos) = global_block_pos.get_local_and_chunk_pos();
+
+        if let Some(chunk_index) = self.find_chunk_index(&chunk_pos) {
+            self.chunks[chunk_index].schedule_tick(ScheduledTick {
+                pos: local_block_pos,
+                tick_index: self.tick_index + delay.max(1),
+            });
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Number of ticks run in the world so far
+    pub fn tick_index(&self) -> u64 {
+        self.tick_index
+    }
+
+    /// The hooks run when blocks receive scheduled or random ticks
+    pub fn block_behaviours_mut(&mut self) -> &mut BlockBehaviours {
+        &mut self.block_behaviours
+    }
+
     /// If the global block position is inside a loaded chunk within this area, sets the block
-    /// ID at the given index to the provided ID and fire a `BlockModified` event
+    /// ID at the given index to the provided ID, fire a `BlockModified` event and record the edit
+    /// in the edit history
     /// Otherwise returns false
     pub fn set_block(
         &mut self,
@@ -146,7 +464,7 @@ impl Terrain {
         global_block_pos: &GlobalBlockPosition,
         new_id: BlockId,
     ) -> bool {
-        let (local_block_pos, chunk_pos) = global_block_pos.get_local_and_chunk_pos();
+        let (_, chunk_pos) = global_block_pos.get_local_and_chunk_pos();
 
         let load_area = self
             .load_areas
@@ -155,73 +473,285 @@ impl Terrain {
 
         let chunk_index = load_area.get_chunk_index(&chunk_pos);
 
-        if let Some(chunk) = chunk_index.and_then(|chunk_index| self.chunks.get_mut(chunk_index)) {
-            chunk.set_block(local_block_pos, new_id);
+        if let Some(old_id) = chunk_index
+            .and_then(|chunk_index| self.set_block_in_chunk(chunk_index, global_block_pos, new_id))
+        {
+            self.history.record(BlockEdit {
+                pos: *global_block_pos,
+                old_id,
+                new_id,
+            });
+            true
+        } else {
+            false
+        }
+    }
 
-            if chunk.requires_light_updates() {
-                self.chunks_requiring_light_updates
-                    .push_back(chunk_index.unwrap())
+    /// Add a chunk received from a server to a terrain with a remote `ChunkSource`, as if it had
+    /// been loaded from a save. The chunk is only added if it is within a load area
+    pub fn receive_chunk(
+        &mut self,
+        tasks: &mut Tasks,
+        chunk_pos: ChunkPosition,
+        saved_chunk: SavedChunk,
+    ) {
+        // biomes aren't sent, since they only depend on the seed
+        let biomes = self.generator.generate_biomes(chunk_pos);
+        let chunk = Chunk::from_saved_chunk(chunk_pos, saved_chunk, biomes, &self.block_registry);
+
+        self.finished_loading_chunk(
+            tasks,
+            LoadedChunkInfo {
+                chunk,
+                // fluids are simulated by the server
+                unsettled_fluids: Vec::new(),
+            },
+        );
+    }
+
+    /// Set a block to the value sent by a server, without recording it in the edit history.
+    /// Returns false if the block's chunk isn't loaded
+    pub fn apply_remote_block(
+        &mut self,
+        global_block_pos: &GlobalBlockPosition,
+        new_id: BlockId,
+    ) -> bool {
+        let (_, chunk_pos) = global_block_pos.get_local_and_chunk_pos();
+
+        self.find_chunk_index(&chunk_pos)
+            .and_then(|chunk_index| self.set_block_in_chunk(chunk_index, global_block_pos, new_id))
+            .is_some()
+    }
+
+    /// Set a block entity to the value sent by a server, firing a `BlockEntityModified` event.
+    /// Returns false if the block's chunk isn't loaded
+    pub fn apply_remote_block_entity(
+        &mut self,
+        global_block_pos: &GlobalBlockPosition,
+        block_entity: Option<BlockEntity>,
+    ) -> bool {
+        let (local_block_pos, chunk_pos) = global_block_pos.get_local_and_chunk_pos();
+
+        let Some(chunk_index) = self.find_chunk_index(&chunk_pos) else {
+            return false;
+        };
+
+        self.chunks[chunk_index].set_block_entity(local_block_pos, block_entity);
+        self.events.push(TerrainEvent::BlockEntityModified(
+            chunk_pos,
+            local_block_pos,
+        ));
+
+        true
+    }
+
+    /// Where the terrain gets its chunks from
+    pub fn chunk_source(&self) -> ChunkSource {
+        self.chunk_source
+    }
+
+    /// Set where the terrain gets its chunks from. This should be set before any chunks are
+    /// loaded
+    pub fn set_chunk_source(&mut self, chunk_source: ChunkSource) {
+        self.chunk_source = chunk_source;
+    }
+
+    /// Begin grouping the edits made with `set_block` into one step of the edit history, until
+    /// the matching call to `end_edit_transaction`
+    pub fn begin_edit_transaction(&mut self) {
+        self.history.begin_transaction();
+    }
+
+    /// End the transaction begun by the matching call to `begin_edit_transaction`
+    pub fn end_edit_transaction(&mut self) {
+        self.history.end_transaction();
+    }
+
+    /// Revert the most recent step of the edit history, returning false if there is nothing to
+    /// undo (see `apply_history_edits`)
+    pub fn undo(&mut self, tasks: &mut Tasks) -> bool {
+        match self.history.undo() {
+            Some(edits) => {
+                self.apply_history_edits(tasks, edits);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Reapply the most recently undone step of the edit history, returning false if there is
+    /// nothing to redo (see `apply_history_edits`)
+    pub fn redo(&mut self, tasks: &mut Tasks) -> bool {
+        match self.history.redo() {
+            Some(edits) => {
+                self.apply_history_edits(tasks, edits);
+                true
             }
+            None => false,
+        }
+    }
 
-            self.events
-                .push(TerrainEvent::BlockModified(chunk_pos, local_block_pos));
+    /// The history of edits made with `set_block`, `edit_region` and `paste`
+    pub fn edit_history(&self) -> &EditHistory {
+        &self.history
+    }
 
-            true
-        } else {
-            false
+    /// Forget every step of the edit history, so that none of the edits made so far can be undone
+    pub fn clear_edit_history(&mut self) {
+        self.history.clear();
+    }
+
+    /// Apply the operation to every block of the shape inside a loaded chunk, returning the number
+    /// of blocks changed. The blocks are written to each chunk at once, firing one `ChunkModified`
+    /// event per chunk, and the edit is recorded as one step of the edit history
+    pub fn edit_region(&mut self, shape: &EditShape, operation: EditOperation) -> usize {
+        let (min, max) = shape.bounds();
+
+        self.edit_blocks(min, max, |pos, old_id| operation.apply(shape, pos, old_id))
+    }
+
+    /// Copy the blocks in the box between the given corners, which are both inside the box.
+    /// Returns None if any part of the box isn't loaded
+    pub fn copy_region(&self, a: GlobalBlockPosition, b: GlobalBlockPosition) -> Option<Clipboard> {
+        let min = a.as_ivec3().min(b.as_ivec3());
+        let max = a.as_ivec3().max(b.as_ivec3());
+        let size = (max - min + 1).as_size3();
+
+        let mut blocks = Vec::with_capacity(size.product());
+        for z in min.z..=max.z {
+            for y in min.y..=max.y {
+                for x in min.x..=max.x {
+                    blocks.push(self.get_loaded_block(&GlobalBlockPosition::new(x, y, z))?);
+                }
+            }
         }
+
+        Some(Clipboard::new(size, blocks))
+    }
+
+    /// Paste the blocks in the clipboard with the minimum corner of the copied box at `origin`,
+    /// returning the number of blocks changed. Air in the clipboard is skipped unless
+    /// `include_air` is true. Blocks outside of the loaded chunks are skipped.
+    /// Like `edit_region`, the blocks are written to each chunk at once and recorded as one step
+    /// of the edit history
+    pub fn paste(
+        &mut self,
+        clipboard: &Clipboard,
+        origin: GlobalBlockPosition,
+        include_air: bool,
+    ) -> usize {
+        let max = origin + GlobalBlockPosition::from(clipboard.size().as_ivec3() - 1);
+
+        self.edit_blocks(origin, max, |pos, old_id| {
+            let new_id = clipboard.get((pos - origin).as_ivec3().as_uvec3());
+
+            (new_id != old_id && (include_air || new_id != BLOCK_AIR)).then_some(new_id)
+        })
+    }
+
+    /// Returns the first block along the ray in the given load area for which `predicate`
+    /// returns true, e.g. `Block::is_targetable`. Unloaded cells are passed through
+    pub fn raycast(
+        &self,
+        load_area_index: Index,
+        ray_origin: Vec3,
+        ray_direction: Vec3,
+        maximum_distance: f32,
+        predicate: impl Fn(BlockId, &Block) -> bool,
+    ) -> Option<RayHit> {
+        ray::cast_ray(
+            ray_origin,
+            ray_direction,
+            maximum_distance,
+            &self.block_registry,
+            |block_pos| self.get_block(load_area_index, block_pos),
+            predicate,
+        )
+        .next()
     }
 
-    /// Raymarch through the chunks in the given load area, returning the position and normal of
-    /// the first block intersected by the ray
-    pub fn raymarch(
+    /// Returns every block along the ray in the given load area up to `maximum_distance` for
+    /// which `predicate` returns true, nearest first
+    pub fn raycast_all(
         &self,
         load_area_index: Index,
         ray_origin: Vec3,
         ray_direction: Vec3,
         maximum_distance: f32,
-    ) -> Option<TerrainHit> {
-        pub const EPS: f32 = 1e-3;
+        predicate: impl Fn(BlockId, &Block) -> bool,
+    ) -> Vec<RayHit> {
+        ray::cast_ray(
+            ray_origin,
+            ray_direction,
+            maximum_distance,
+            &self.block_registry,
+            |block_pos| self.get_block(load_area_index, block_pos),
+            predicate,
+        )
+        .collect()
+    }
 
-        let dir_step = ray_direction.map(|component| if component >= 0.0 { 1.0 } else { 0.0 });
-        let dir_recip = ray_direction.recip();
+    /// True if no block for which `is_obstruction` returns true lies between the two points.
+    /// Unloaded cells don't obstruct the line of sight
+    pub fn has_line_of_sight(
+        &self,
+        load_area_index: Index,
+        from: Vec3,
+        to: Vec3,
+        is_obstruction: impl Fn(BlockId, &Block) -> bool,
+    ) -> bool {
+        self.raycast(
+            load_area_index,
+            from,
+            to - from,
+            from.distance(to),
+            is_obstruction,
+        )
+        .is_none()
+    }
+
+    /// The registry of kinds of block that can exist in the terrain
+    pub fn block_registry(&self) -> &Arc<BlockRegistry> {
+        &self.block_registry
+    }
 
-        let mut t = 0.0;
-        let mut previous_chunk_pos = None;
+    /// The save from which chunks are loaded and to which modified chunks are written
+    pub fn world_save(&self) -> &Arc<WorldSave> {
+        &self.world_save
+    }
 
-        while t < maximum_distance {
-            let ray_pos = ray_origin + ray_direction * t;
+    /// Submit tasks to save every loaded chunk that has been modified since it was last saved, and
+    /// save the feature blocks waiting to be placed in unloaded chunks. The tick index is stored
+    /// in the world metadata, to be written by `WorldSave::save_metadata`.
+    /// Any decoration tasks should have finished before this is called, so that the features
+    /// they place are not lost
+    pub fn save(&mut self, tasks: &mut Tasks) {
+        if self.chunk_source == ChunkSource::Remote {
+            return;
+        }
 
-            let chunk_pos = ChunkPosition::from((ray_pos * CHUNK_SIZE_RECIP).floor().as_ivec3());
-            if let Some(chunk) = self.get_chunk(load_area_index, &chunk_pos) {
-                let ray_origin = ray_pos - chunk_pos.as_vec3() * (CHUNK_SIZE as f32);
-
-                if let Some(hit) = chunk.raymarch(
-                    ray_origin,
-                    ray_direction,
-                    previous_chunk_pos,
-                    maximum_distance - t,
-                ) {
-                    return Some(TerrainHit {
-                        hit_pos: GlobalBlockPosition::from_local_and_chunk_pos(
-                            hit.local_hit_pos,
-                            chunk_pos,
-                        ),
-                        hit_normal: hit.hit_normal,
-                    });
-                }
-            }
+        self.receive_decorated_chunks();
+        self.receive_patched_chunks(tasks);
 
-            // advance to the next chunk position
-            let deltas = (dir_step - ray_pos * CHUNK_SIZE_RECIP).fract_gl()
-                * dir_recip
-                * (CHUNK_SIZE as f32);
-            t += deltas.min_element().max(EPS);
+        self.world_save.set_tick_index(self.tick_index);
 
-            previous_chunk_pos = Some(chunk_pos);
+        if let Err(e) = self
+            .world_save
+            .save_feature_placements(&self.pending_feature_placements)
+        {
+            log::error!("failed to save feature placements: {}", e);
         }
 
-        None
+        let modified_chunks = self
+            .chunks
+            .iter()
+            .filter(|(_, chunk)| chunk.is_modified())
+            .map(|(chunk_index, _)| chunk_index)
+            .collect_vec();
+
+        for chunk_index in modified_chunks {
+            self.save_chunk(tasks, chunk_index);
+        }
     }
 
     /// The arena of loaded chunks
@@ -244,6 +774,20 @@ impl Terrain {
         &mut self.load_areas
     }
 
+    /// Remove a load area, unloading the chunks which aren't within any other load area.
+    /// Removing the area from `load_areas_mut` instead leaves its chunks loaded until another
+    /// area moves
+    pub fn remove_load_area(
+        &mut self,
+        tasks: &mut Tasks,
+        load_area_index: Index,
+    ) -> Option<LoadArea> {
+        let load_area = self.load_areas.remove(load_area_index)?;
+        self.unload_chunks_outside_areas(tasks);
+
+        Some(load_area)
+    }
+
     /// Returns an iterator over all events that have occurred since the last call to
     /// `clear_events()` in chronological order
     pub fn events(&self) -> impl Iterator<Item = &TerrainEvent> {
@@ -255,6 +799,422 @@ impl Terrain {
         self.events.clear();
     }
 
+    /// Advance the terrain by one tick
+    fn tick(&mut self) {
+        self.tick_index += 1;
+
+        self.run_scheduled_ticks();
+        self.run_random_ticks();
+
+        if self.tick_index.is_multiple_of(FLUID_TICK_INTERVAL) {
+            self.update_fluids();
+        }
+    }
+
+    /// Run the behaviours of the blocks whose scheduled ticks are due
+    fn run_scheduled_ticks(&mut self) {
+        let tick_index = self.tick_index;
+        let due_positions = self
+            .chunks
+            .iter_mut()
+            .flat_map(|(_, chunk)| {
+                let chunk_pos = chunk.position();
+
+                chunk
+                    .take_due_ticks(tick_index)
+                    .into_iter()
+                    .map(move |local_block_pos| {
+                        GlobalBlockPosition::from_local_and_chunk_pos(local_block_pos, chunk_pos)
+                    })
+            })
+            .collect_vec();
+
+        for pos in due_positions {
+            if let Some(behaviour) = self.get_block_behaviour(&pos) {
+                behaviour.scheduled_tick(self, pos);
+            }
+        }
+    }
+
+    /// Run the behaviours of `RANDOM_TICKS_PER_CHUNK` random blocks in each loaded chunk
+    fn run_random_ticks(&mut self) {
+        let mut rng = rand::thread_rng();
+        let mut positions = Vec::new();
+
+        for (_, chunk) in &self.chunks {
+            // skip chunks made of a single block without a behaviour, such as air
+            if chunk
+                .block_store()
+                .get_single_block()
+                .is_some_and(|block_id| self.block_behaviours.get(block_id).is_none())
+            {
+                continue;
+            }
+
+            for _ in 0..RANDOM_TICKS_PER_CHUNK {
+                let local_block_pos =
+                    LocalBlockPosition::from_array_index(rng.gen_range(0..CHUNK_SIZE_CUBED));
+                positions.push(GlobalBlockPosition::from_local_and_chunk_pos(
+                    local_block_pos,
+                    chunk.position(),
+                ));
+            }
+        }
+
+        for pos in positions {
+            if let Some(behaviour) = self.get_block_behaviour(&pos) {
+                behaviour.random_tick(self, pos);
+            }
+        }
+    }
+
+    /// Returns the behaviour of the block at the given position, if it is loaded and has one
+    fn get_block_behaviour(&self, pos: &GlobalBlockPosition) -> Option<Arc<dyn BlockBehaviour>> {
+        self.get_loaded_block(pos)
+            .and_then(|block_id| self.block_behaviours.get(block_id))
+            .cloned()
+    }
+
+    /// Make the fluids flow by one block
+    fn update_fluids(&mut self) {
+        // the simulation is taken out of the terrain so that the blocks can be read while it runs
+        let mut fluids = std::mem::take(&mut self.fluids);
+        let changes = fluids.tick(|pos| self.get_loaded_block(&pos), &self.block_registry);
+        self.fluids = fluids;
+
+        for (pos, block_id) in changes {
+            self.set_loaded_block(&pos, block_id);
+        }
+    }
+
+    /// Set the block at the given position in the chunk with the given index, queueing light
+    /// updates and firing a `BlockModified` event. Returns the previous block ID, or None if the
+    /// chunk doesn't exist.
+    /// If the block changes, its block entity is removed and the new block's block entity is
+    /// created, firing `BlockEntityRemoved` and `BlockEntityCreated` events
+    fn set_block_in_chunk(
+        &mut self,
+        chunk_index: Index,
+        global_block_pos: &GlobalBlockPosition,
+        new_id: BlockId,
+    ) -> Option<BlockId> {
+        let (local_block_pos, chunk_pos) = global_block_pos.get_local_and_chunk_pos();
+        let chunk = self.chunks.get_mut(chunk_index)?;
+
+        let old_id = chunk.get_block(local_block_pos);
+        chunk.set_block(local_block_pos, new_id);
+
+        if chunk.requires_light_updates() {
+            self.chunks_requiring_light_updates.push_back(chunk_index)
+        }
+
+        self.events
+            .push(TerrainEvent::BlockModified(chunk_pos, local_block_pos));
+
+        if new_id != old_id {
+            if chunk.set_block_entity(local_block_pos, None).is_some() {
+                self.events
+                    .push(TerrainEvent::BlockEntityRemoved(chunk_pos, local_block_pos));
+            }
+
+            if let Some(kind) = self.block_registry[new_id].block_entity {
+                chunk.set_block_entity(local_block_pos, Some(kind.create()));
+                self.events
+                    .push(TerrainEvent::BlockEntityCreated(chunk_pos, local_block_pos));
+            }
+        }
+
+        // fluids may flow into or away from the block
+        self.fluids.block_changed(*global_block_pos);
+
+        Some(old_id)
+    }
+
+    /// Set the blocks in the box between `min` and `max` which are inside loaded chunks.
+    /// `new_block` is called with the position and current ID of each block and returns the ID
+    /// the block should change to, if any. The changes are written to each chunk with
+    /// `set_blocks_in_chunk` and recorded as one step of the edit history. Returns the number of
+    /// blocks changed
+    fn edit_blocks(
+        &mut self,
+        min: GlobalBlockPosition,
+        max: GlobalBlockPosition,
+        new_block: impl Fn(GlobalBlockPosition, BlockId) -> Option<BlockId>,
+    ) -> usize {
+        let (_, min_chunk_pos) = min.get_local_and_chunk_pos();
+        let (_, max_chunk_pos) = max.get_local_and_chunk_pos();
+        let mut edits = Vec::new();
+
+        // the box can be much larger than the loaded area, so the loaded chunks are searched
+        // rather than every chunk position in the box
+        let chunk_indices = self
+            .chunks
+            .iter()
+            .filter(|(_, chunk)| {
+                let chunk_pos = chunk.position().as_ivec3();
+                chunk_pos.cmpge(min_chunk_pos.as_ivec3()).all()
+                    && chunk_pos.cmple(max_chunk_pos.as_ivec3()).all()
+            })
+            .map(|(chunk_index, _)| chunk_index)
+            .collect_vec();
+
+        for chunk_index in chunk_indices {
+            let chunk = &self.chunks[chunk_index];
+            let chunk_pos = chunk.position();
+
+            // the part of the box inside this chunk
+            let chunk_min = chunk_pos.as_ivec3() * CHUNK_SIZE as i32;
+            let box_min = min.as_ivec3().max(chunk_min);
+            let box_max = max.as_ivec3().min(chunk_min + (CHUNK_SIZE as i32 - 1));
+
+            let mut changes = Vec::new();
+            for (y, z, x) in itertools::iproduct!(
+                box_min.y..=box_max.y,
+                box_min.z..=box_max.z,
+                box_min.x..=box_max.x
+            ) {
+                let pos = GlobalBlockPosition::new(x, y, z);
+                let (local_block_pos, _) = pos.get_local_and_chunk_pos();
+                let old_id = chunk.get_block(local_block_pos);
+
+                if let Some(new_id) = new_block(pos, old_id).filter(|&new_id| new_id != old_id) {
+                    changes.push((local_block_pos, new_id));
+                    edits.push(BlockEdit {
+                        pos,
+                        old_id,
+                        new_id,
+                    });
+                }
+            }
+
+            self.set_blocks_in_chunk(chunk_index, &changes);
+        }
+
+        self.history.begin_transaction();
+        for &edit in &edits {
+            self.history.record(edit);
+        }
+        self.history.end_transaction();
+
+        self.queue_fluids_after_edits(&edits);
+
+        edits.len()
+    }
+
+    /// Set many blocks in the chunk with the given index at once, in order, queueing light
+    /// updates for the whole chunk and firing a single `ChunkModified` event.
+    /// Block entities are replaced as in `set_block_in_chunk`, but fluids are not updated (see
+    /// `queue_fluids_after_edits`)
+    fn set_blocks_in_chunk(
+        &mut self,
+        chunk_index: Index,
+        changes: &[(LocalBlockPosition, BlockId)],
+    ) {
+        let Some(chunk) = self.chunks.get_mut(chunk_index) else {
+            return;
+        };
+        if changes.is_empty() {
+            return;
+        }
+        let chunk_pos = chunk.position();
+
+        for &(local_block_pos, new_id) in changes {
+            if chunk.get_block(local_block_pos) == new_id {
+                continue;
+            }
+
+            if chunk.set_block_entity(local_block_pos, None).is_some() {
+                self.events
+                    .push(TerrainEvent::BlockEntityRemoved(chunk_pos, local_block_pos));
+            }
+
+            if let Some(kind) = self.block_registry[new_id].block_entity {
+                chunk.set_block_entity(local_block_pos, Some(kind.create()));
+                self.events
+                    .push(TerrainEvent::BlockEntityCreated(chunk_pos, local_block_pos));
+            }
+        }
+
+        chunk.set_blocks(changes);
+
+        if chunk.requires_light_updates() {
+            self.chunks_requiring_light_updates.push_back(chunk_index)
+        }
+
+        self.events.push(TerrainEvent::ChunkModified(chunk_pos));
+    }
+
+    /// Queue the blocks changed by a bulk edit whose fluids may now flow differently. Fluids only
+    /// flow into air and other fluids, so only changes to or from air or fluids next to a fluid
+    /// are queued, rather than every block of a large edit
+    fn queue_fluids_after_edits(&mut self, edits: &[BlockEdit]) {
+        let is_fluid = |block_id: BlockId| self.block_registry[block_id].fluid.is_some();
+
+        let positions = edits
+            .iter()
+            .filter(|edit| {
+                if is_fluid(edit.old_id) || is_fluid(edit.new_id) {
+                    return true;
+                }
+
+                (edit.old_id == BLOCK_AIR || edit.new_id == BLOCK_AIR)
+                    && FACE_NORMALS.iter().any(|&normal| {
+                        self.get_loaded_block(&(edit.pos + normal.into()))
+                            .is_some_and(is_fluid)
+                    })
+            })
+            .map(|edit| edit.pos)
+            .collect_vec();
+
+        for pos in positions {
+            self.fluids.block_changed(pos);
+        }
+    }
+
+    /// Apply edits from undoing or redoing. Edits in loaded chunks are written with
+    /// `set_blocks_in_chunk` so that lighting and meshes are updated, edits in chunks which are
+    /// loading are applied once they finish loading, and edits in unloaded chunks are applied to
+    /// the saved chunk data
+    fn apply_history_edits(&mut self, tasks: &mut Tasks, edits: Vec<BlockEdit>) {
+        let mut loaded_changes: FxHashMap<Index, Vec<(LocalBlockPosition, BlockId)>> =
+            FxHashMap::default();
+        let mut unloaded_edits: FxHashMap<ChunkPosition, Vec<BlockEdit>> = FxHashMap::default();
+
+        for &edit in &edits {
+            let (local_block_pos, chunk_pos) = edit.pos.get_local_and_chunk_pos();
+
+            if let Some(chunk_index) = self.find_chunk_index(&chunk_pos) {
+                loaded_changes
+                    .entry(chunk_index)
+                    .or_default()
+                    .push((local_block_pos, edit.new_id));
+            } else if self.is_chunk_loading(&chunk_pos)
+                || self.patching_chunks.contains_key(&chunk_pos)
+            {
+                self.pending_history_edits
+                    .entry(chunk_pos)
+                    .or_default()
+                    .push(edit);
+            } else {
+                unloaded_edits.entry(chunk_pos).or_default().push(edit);
+            }
+        }
+
+        for (chunk_index, changes) in loaded_changes {
+            self.set_blocks_in_chunk(chunk_index, &changes);
+        }
+        self.queue_fluids_after_edits(&edits);
+
+        for (chunk_pos, edits) in unloaded_edits {
+            self.apply_edits_to_saved_chunk(tasks, chunk_pos, edits);
+        }
+    }
+
+    /// Submit a task to apply edits to the saved data of a chunk which isn't loaded and write it
+    /// to disk. The edits are kept in `pending_history_edits` until the task finishes, so that
+    /// they are also applied if the chunk is loaded before the patched data is saved, and later
+    /// edits to the chunk wait for the task instead of patching the same data at the same time
+    fn apply_edits_to_saved_chunk(
+        &mut self,
+        tasks: &mut Tasks,
+        chunk_pos: ChunkPosition,
+        edits: Vec<BlockEdit>,
+    ) {
+        self.patching_chunks.insert(chunk_pos, edits.len());
+        self.pending_history_edits.insert(chunk_pos, edits.clone());
+
+        let world_save = self.world_save.clone();
+        let block_registry = self.block_registry.clone();
+        let patched_chunk_tx = self.patched_chunk_tx.clone();
+
+        tasks.submit(
+            TaskPriority {
+                class_priority: CHUNK_SAVING_PRIORITY,
+                priority_within_class: 0,
+            },
+            move || {
+                // chunks are always saved when they are unloaded after being modified, so an
+                // edited chunk without saved data can only mean that the save couldn't be read
+                match world_save.load_chunk(chunk_pos) {
+                    Ok(Some(mut saved_chunk)) => {
+                        for edit in &edits {
+                            let (local_block_pos, _) = edit.pos.get_local_and_chunk_pos();
+
+                            if saved_chunk.block_store.get_block(local_block_pos) == edit.new_id {
+                                continue;
+                            }
+                            saved_chunk
+                                .block_store
+                                .set_block(local_block_pos, edit.new_id);
+
+                            saved_chunk.block_entities.remove(&local_block_pos);
+                            if let Some(kind) = block_registry[edit.new_id].block_entity {
+                                saved_chunk
+                                    .block_entities
+                                    .insert(local_block_pos, kind.create());
+                            }
+                        }
+
+                        world_save.queue_chunk_save(chunk_pos, Arc::new(saved_chunk));
+                        if let Err(e) = world_save.save_chunk(chunk_pos) {
+                            log::error!("failed to save chunk {:?}: {}", chunk_pos, e);
+                        }
+                    }
+                    Ok(None) => {
+                        log::warn!("no saved data to apply edits to for chunk {:?}", chunk_pos);
+                    }
+                    Err(e) => log::error!("failed to load chunk {:?}: {}", chunk_pos, e),
+                }
+
+                if let Err(e) = patched_chunk_tx.send(chunk_pos) {
+                    log::trace!(
+                        "sending patched chunk from saving thread to main thread returned error: \
+                         {}",
+                        e
+                    );
+                }
+            },
+        );
+    }
+
+    /// Called for chunks whose saved data has been patched with `apply_edits_to_saved_chunk`.
+    /// Edits made to a chunk while its task was running are applied by another task, unless the
+    /// chunk has started loading, in which case they are applied once it has loaded
+    fn receive_patched_chunks(&mut self, tasks: &mut Tasks) {
+        while let Ok(chunk_pos) = self.patched_chunk_rx.try_recv() {
+            let Some(edit_count) = self.patching_chunks.remove(&chunk_pos) else {
+                continue;
+            };
+
+            // the edits were applied to the chunk if it loaded while the task was running
+            let Some(edits) = self.pending_history_edits.get(&chunk_pos) else {
+                continue;
+            };
+
+            if self.find_chunk_index(&chunk_pos).is_some() || self.is_chunk_loading(&chunk_pos) {
+                continue;
+            }
+
+            if edits.len() > edit_count {
+                // edits are idempotent, so the ones already saved can be applied again
+                let edits = self.pending_history_edits.remove(&chunk_pos).unwrap();
+                self.apply_edits_to_saved_chunk(tasks, chunk_pos, edits);
+            } else {
+                self.pending_history_edits.remove(&chunk_pos);
+            }
+        }
+    }
+
+    /// True if the chunk at the given position is being loaded but hasn't been added to the world
+    /// yet
+    fn is_chunk_loading(&self, chunk_pos: &ChunkPosition) -> bool {
+        self.find_chunk_index(chunk_pos).is_none()
+            && self
+                .load_areas
+                .iter()
+                .any(|(_, load_area)| !load_area.is_unloaded(chunk_pos))
+    }
+
     /// Called each frame to check for new chunks to load
     fn check_chunks_to_load(&mut self, tasks: &mut Tasks, camera_pos: Vec3) {
         let load_queue = self
@@ -277,26 +1237,31 @@ impl Terrain {
     }
 
     /// Called each frame to check if any chunks should be unloaded
-    fn check_chunks_to_unload(&mut self) {
+    fn check_chunks_to_unload(&mut self, tasks: &mut Tasks) {
         if self
             .load_areas
             .iter()
             .any(|(_, area)| area.state().is_dirty())
         {
-            let unload_queue = self
-                .chunks
-                .iter()
-                .filter(|(_, chunk)| {
-                    self.load_areas
-                        .iter()
-                        .all(|(_, area)| !area.is_within_area(&chunk.position()))
-                })
-                .map(|(chunk_index, _)| chunk_index)
-                .collect_vec();
+            self.unload_chunks_outside_areas(tasks);
+        }
+    }
 
-            for chunk_index in unload_queue {
-                self.unload_chunk(chunk_index);
-            }
+    /// Unload every chunk which isn't within any load area
+    fn unload_chunks_outside_areas(&mut self, tasks: &mut Tasks) {
+        let unload_queue = self
+            .chunks
+            .iter()
+            .filter(|(_, chunk)| {
+                self.load_areas
+                    .iter()
+                    .all(|(_, area)| !area.is_within_area(&chunk.position()))
+            })
+            .map(|(chunk_index, _)| chunk_index)
+            .collect_vec();
+
+        for chunk_index in unload_queue {
+            self.unload_chunk(tasks, chunk_index);
         }
     }
 
@@ -320,12 +1285,20 @@ impl Terrain {
             load_area.mark_loading(&chunk_pos);
         }
 
+        // remote chunks are loaded once they are received from the server
+        if self.chunk_source == ChunkSource::Remote {
+            return;
+        }
+
         // assign a higher priority to chunks closer to the camera
         let priority_within_class =
             Vec3::distance_squared(chunk_pos.as_vec3(), camera_pos / (CHUNK_SIZE as f32)) as i32;
 
-        // clone sender for the worker thread
+        // clone sender, block registry, save and generator for the worker thread
         let loaded_chunk_tx = self.loaded_chunk_tx.clone();
+        let block_registry = self.block_registry.clone();
+        let world_save = self.world_save.clone();
+        let generator = self.generator.clone();
 
         tasks.submit(
             TaskPriority {
@@ -333,9 +1306,33 @@ impl Terrain {
                 priority_within_class,
             },
             move || {
-                let chunk = temporary_generation::generate_chunk(chunk_pos);
+                // biomes aren't saved, since they only depend on the seed
+                let biomes = generator.generate_biomes(chunk_pos);
+
+                let generate_chunk = |biomes| {
+                    let blocks = generator.generate_chunk(chunk_pos);
+                    Chunk::new(chunk_pos, &blocks, biomes, &block_registry)
+                };
+
+                // load the chunk from the save if it has one, otherwise generate it
+                let chunk = match world_save.load_chunk(chunk_pos) {
+                    Ok(Some(saved_chunk)) => {
+                        Chunk::from_saved_chunk(chunk_pos, saved_chunk, biomes, &block_registry)
+                    }
+                    Ok(None) => generate_chunk(biomes),
+                    Err(e) => {
+                        log::error!("failed to load chunk {:?}: {}", chunk_pos, e);
+                        generate_chunk(biomes)
+                    }
+                };
 
-                if let Err(e) = loaded_chunk_tx.send(LoadedChunkInfo { chunk }) {
+                let unsettled_fluids =
+                    fluid::find_unsettled_fluids(chunk_pos, chunk.block_store(), &block_registry);
+
+                if let Err(e) = loaded_chunk_tx.send(LoadedChunkInfo {
+                    chunk,
+                    unsettled_fluids,
+                }) {
                     log::trace!(
                         "sending chunk from loading thread to main thread returned error: {}",
                         e
@@ -346,7 +1343,7 @@ impl Terrain {
     }
 
     /// Called once a chunk has finished loading and is ready to be added to the world
-    fn finished_loading_chunk(&mut self, chunk_info: LoadedChunkInfo) {
+    fn finished_loading_chunk(&mut self, tasks: &mut Tasks, chunk_info: LoadedChunkInfo) {
         // make sure the chunk is still within a load area
         // this could be false if the area has moved since the chunk was queued for loading
         if !self
@@ -354,12 +1351,59 @@ impl Terrain {
             .iter()
             .any(|(_, area)| area.is_within_area(&chunk_info.chunk.position()))
         {
+            // edits waiting for the chunk are applied to its saved data instead, once any task
+            // already patching it has finished
+            let chunk_pos = chunk_info.chunk.position();
+            if !self.patching_chunks.contains_key(&chunk_pos) {
+                if let Some(edits) = self.pending_history_edits.remove(&chunk_pos) {
+                    self.apply_edits_to_saved_chunk(tasks, chunk_pos, edits);
+                }
+            }
             return;
         }
 
         let chunk_pos = chunk_info.chunk.position();
+
+        // a server sends a chunk again when many of its blocks change, replacing the old one
+        if let Some(old_chunk_index) = self.find_chunk_index(&chunk_pos) {
+            self.unload_chunk(tasks, old_chunk_index);
+        }
+
         let chunk_index = self.chunks.insert(chunk_info.chunk);
 
+        // apply edits from undoing or redoing while the chunk was loading
+        if let Some(edits) = self.pending_history_edits.remove(&chunk_pos) {
+            let chunk = &mut self.chunks[chunk_index];
+
+            for edit in edits {
+                let (local_block_pos, _) = edit.pos.get_local_and_chunk_pos();
+                if chunk.get_block(local_block_pos) == edit.new_id {
+                    continue;
+                }
+
+                chunk.set_block(local_block_pos, edit.new_id);
+                chunk.set_block_entity(
+                    local_block_pos,
+                    self.block_registry[edit.new_id]
+                        .block_entity
+                        .map(|kind| kind.create()),
+                );
+                self.fluids.block_changed(edit.pos);
+            }
+        }
+
+        // place features from neighbouring chunks that were decorated while this chunk was
+        // unloaded
+        if let Some(placements) = self.pending_feature_placements.remove(&chunk_pos) {
+            self.chunks[chunk_index].place_features(&placements, &self.block_registry);
+        }
+
+        // resume fluids that were flowing when the chunk was saved or that can flow into the
+        // chunk
+        for pos in chunk_info.unsettled_fluids {
+            self.fluids.queue(pos);
+        }
+
         // inform the load areas that the chunk is loaded
         self.load_areas
             .iter_mut()
@@ -384,14 +1428,145 @@ impl Terrain {
                 }
             }
         }
-        self.chunks[chunk_index].initialize_lighting(&surrounding_sides_light);
+        self.chunks[chunk_index]
+            .initialize_lighting(&surrounding_sides_light, &self.block_registry);
         self.chunks_requiring_light_updates.push_back(chunk_index);
 
         self.events.push(TerrainEvent::ChunkLoaded(chunk_pos));
+
+        // this chunk may complete the neighbourhood of itself or any of its neighbours
+        for offset in itertools::iproduct!(-1..=1, -1..=1, -1..=1) {
+            let other_chunk_pos = chunk_pos + ChunkPosition::from(IVec3::from(offset));
+            self.check_chunk_to_decorate(tasks, &other_chunk_pos);
+        }
+    }
+
+    /// Submit a task to decorate the chunk at the given position if it is loaded, has not yet
+    /// been decorated, and all 26 of its neighbours are loaded
+    fn check_chunk_to_decorate(&mut self, tasks: &mut Tasks, chunk_pos: &ChunkPosition) {
+        if self.chunk_source == ChunkSource::Remote {
+            return;
+        }
+        let Some(chunk_index) = self.find_chunk_index(chunk_pos) else {
+            return;
+        };
+        if self.chunks[chunk_index].is_decorated() {
+            return;
+        }
+
+        let neighbours_loaded = itertools::iproduct!(-1..=1, -1..=1, -1..=1).all(|offset| {
+            let neighbour_pos = *chunk_pos + ChunkPosition::from(IVec3::from(offset));
+            self.find_chunk_index(&neighbour_pos).is_some()
+        });
+        if !neighbours_loaded {
+            return;
+        }
+
+        // the chunk is marked as decorated straight away so that it is only decorated once
+        self.chunks[chunk_index].mark_decorated();
+
+        let chunk_pos = *chunk_pos;
+        let decorated_chunk_tx = self.decorated_chunk_tx.clone();
+        let generator = self.generator.clone();
+
+        tasks.submit(
+            TaskPriority {
+                class_priority: CHUNK_LOADING_PRIORITY,
+                priority_within_class: 0,
+            },
+            move || {
+                let mut placements = FeaturePlacements::new();
+                generator.decorate_chunk(chunk_pos, &mut placements);
+
+                if let Err(e) = decorated_chunk_tx.send(DecoratedChunkInfo {
+                    placements: placements.into_chunk_placements(),
+                }) {
+                    log::trace!(
+                        "sending features from decoration thread to main thread returned error: {}",
+                        e
+                    );
+                }
+            },
+        );
+    }
+
+    /// Place the features from chunks which have finished decorating
+    fn receive_decorated_chunks(&mut self) {
+        while let Ok(decorated_chunk) = self.decorated_chunk_rx.try_recv() {
+            for (chunk_pos, placements) in decorated_chunk.placements {
+                self.place_features(chunk_pos, placements);
+            }
+        }
+    }
+
+    /// Place feature blocks in the chunk at the given position, or queue them until the chunk is
+    /// loaded if it isn't loaded yet.
+    /// Blocks are merged with the existing blocks using `merge_feature_block`, so features never
+    /// overwrite solid blocks, including ones the player has placed since the chunk loaded
+    fn place_features(
+        &mut self,
+        chunk_pos: ChunkPosition,
+        placements: Vec<(LocalBlockPosition, BlockId)>,
+    ) {
+        let Some(chunk_index) = self.find_chunk_index(&chunk_pos) else {
+            self.pending_feature_placements
+                .entry(chunk_pos)
+                .or_default()
+                .extend(placements);
+            return;
+        };
+
+        for (local_block_pos, block_id) in placements {
+            let old_id = self.chunks[chunk_index].get_block(local_block_pos);
+            let new_id = merge_feature_block(old_id, block_id, &self.block_registry);
+
+            // set like any other edit, so that fluids next to the block are updated
+            if new_id != old_id {
+                self.set_block_in_chunk(
+                    chunk_index,
+                    &GlobalBlockPosition::from_local_and_chunk_pos(local_block_pos, chunk_pos),
+                    new_id,
+                );
+            }
+        }
+    }
+
+    /// Submit a task to save the chunk with the given index, if it has been modified
+    fn save_chunk(&mut self, tasks: &mut Tasks, chunk_index: Index) {
+        let chunk = &mut self.chunks[chunk_index];
+        if !chunk.is_modified() || self.chunk_source == ChunkSource::Remote {
+            return;
+        }
+
+        let chunk_pos = chunk.position();
+        self.world_save
+            .queue_chunk_save(chunk_pos, Arc::new(chunk.to_saved_chunk()));
+        chunk.mark_saved();
+
+        self.submit_chunk_save_task(tasks, chunk_pos);
     }
 
-    /// Unload the chunk with the given position
-    fn unload_chunk(&mut self, chunk_index: Index) {
+    /// Submit a task to write the chunk queued with `WorldSave::queue_chunk_save` to disk
+    fn submit_chunk_save_task(&self, tasks: &mut Tasks, chunk_pos: ChunkPosition) {
+        let world_save = self.world_save.clone();
+
+        tasks.submit(
+            TaskPriority {
+                class_priority: CHUNK_SAVING_PRIORITY,
+                priority_within_class: 0,
+            },
+            move || {
+                if let Err(e) = world_save.save_chunk(chunk_pos) {
+                    log::error!("failed to save chunk {:?}: {}", chunk_pos, e);
+                }
+            },
+        );
+    }
+
+    /// Unload the chunk with the given position, saving it if it has been modified
+    fn unload_chunk(&mut self, tasks: &mut Tasks, chunk_index: Index) {
+        self.save_chunk(tasks, chunk_index);
+
         let chunk = &self.chunks[chunk_index];
         let chunk_pos = chunk.position();
 
@@ -417,30 +1592,296 @@ impl Terrain {
             let chunk_offset = FACE_NORMALS[neighbour_index.as_usize()];
             let other_chunk_pos = *chunk_pos + ChunkPosition::from(chunk_offset);
 
-            for (_, load_area) in &self.load_areas {
-                if let Some(chunk_index) = load_area.get_chunk_index(&other_chunk_pos) {
-                    let chunk = &mut self.chunks[chunk_index];
+            let (chunk_index, light_update, neighbour_index) =
+                match (self.find_chunk_index(&other_chunk_pos), light_update) {
+                    (Some(chunk_index), light_update) => {
+                        (chunk_index, light_update, neighbour_index)
+                    }
+                    // a skylight shadow leaving the top of the loaded terrain has reached the
+                    // open sky, so the block it came from is relit from above
+                    (None, LightUpdate::SkylightShadow(step))
+                        if neighbour_index == FaceIndex::POS_Y =>
+                    {
+                        let Some(chunk_index) = self.find_chunk_index(chunk_pos) else {
+                            continue;
+                        };
+                        let light_update = LightUpdate::Skylight(LightPropagationStep {
+                            position: step.position.wrapping_add(-chunk_offset),
+                            light: Skylight(Skylight::MAX_VALUE),
+                            is_repair_step: false,
+                        });
+
+                        (chunk_index, light_update, FaceIndex::NEG_Y)
+                    }
+                    _ => continue,
+                };
 
-                    chunk
-                        .inform_light_update_from_neighbouring_chunk(light_update, neighbour_index);
+            let chunk = &mut self.chunks[chunk_index];
 
-                    if chunk.requires_light_updates() {
-                        self.chunks_requiring_light_updates.push_back(chunk_index);
-                    }
+            chunk.inform_light_update_from_neighbouring_chunk(
+                light_update,
+                neighbour_index,
+                &self.block_registry,
+            );
 
-                    break;
-                }
+            if chunk.requires_light_updates() {
+                self.chunks_requiring_light_updates.push_back(chunk_index);
             }
         }
     }
-}
 
-/// Returned by `Terrain::raymarch` when a block is intersected
-pub struct TerrainHit {
-    pub hit_pos: GlobalBlockPosition,
-    pub hit_normal: Option<IVec3>,
+    /// Returns the index of the chunk at the given position in the chunk arena, if it is loaded
+    /// in any load area
+    fn find_chunk_index(&self, chunk_pos: &ChunkPosition) -> Option<Index> {
+        self.load_areas
+            .iter()
+            .find_map(|(_, load_area)| load_area.get_chunk_index(chunk_pos))
+    }
 }
 
 struct LoadedChunkInfo {
     chunk: Chunk,
+    /// Positions in and around the chunk where fluids may be able to flow
+    unsettled_fluids: Vec<GlobalBlockPosition>,
+}
+
+struct DecoratedChunkInfo {
+    placements: ChunkPlacements,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::Instant};
+
+    use super::*;
+    use crate::{
+        terrain::{
+            block::registry::test_registry,
+            generation::GeneratorSettings,
+            load_area::{AreaShape, LoadArea},
+        },
+        util::size::Size3,
+    };
+
+    /// Create a flat terrain of one layer of stone, saved in a temporary directory named after
+    /// the test, with the blocks defined by `definitions`. Returns the terrain once the chunks in
+    /// a load area around the origin have loaded
+    pub(super) fn test_terrain(test_name: &str, definitions: &[&str]) -> (Terrain, Tasks, Index) {
+        let block_registry = Arc::new(test_registry(definitions));
+        let directory = std::env::temp_dir().join(format!(
+            "voxels-terrain-{}-test-{}",
+            test_name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&directory);
+        let world_save = Arc::new(
+            WorldSave::open_or_create(
+                &directory,
+                &block_registry,
+                7,
+                GeneratorSettings::Flat {
+                    layers: "stone".to_string(),
+                },
+            )
+            .unwrap(),
+        );
+        let generator = world_save
+            .generator_settings()
+            .build(world_save.seed(), &block_registry)
+            .unwrap();
+        let mut terrain = Terrain::new(block_registry, world_save, generator);
+        let mut tasks = Tasks::new(2);
+
+        let mut load_area = LoadArea::new(
+            ChunkPosition::ZERO,
+            Size3::new(4, 2, 4),
+            AreaShape::Cylindrical,
+        );
+        load_area.set_center(Vec3::ZERO);
+        let load_area_index = terrain.load_areas_mut().insert(load_area);
+        wait_until(&mut terrain, &mut tasks, |terrain| {
+            terrain.load_areas()[load_area_index].is_loaded(&ChunkPosition::ZERO)
+        });
+
+        (terrain, tasks, load_area_index)
+    }
+
+    /// Update the terrain until the condition is true
+    pub(super) fn wait_until(
+        terrain: &mut Terrain,
+        tasks: &mut Tasks,
+        condition: impl Fn(&Terrain) -> bool,
+    ) {
+        let start = Instant::now();
+        while !condition(terrain) {
+            assert!(start.elapsed() < Duration::from_secs(20), "timed out");
+            terrain.update(tasks, Vec3::ZERO, Duration::from_millis(10));
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn block_entities() {
+        let (mut terrain, _tasks, load_area_index) = test_terrain(
+            "block-entities",
+            &[
+                r#"name = "stone""#,
+                r#"
+                    name = "sign"
+                    block_entity = "sign"
+                "#,
+            ],
+        );
+        let stone = terrain.block_registry().get_id("stone").unwrap();
+        let sign = terrain.block_registry().get_id("sign").unwrap();
+        let pos = GlobalBlockPosition::new(1, 2, 3);
+        let (local_block_pos, chunk_pos) = pos.get_local_and_chunk_pos();
+        let block_entity_events = |terrain: &Terrain| {
+            terrain
+                .events()
+                .filter_map(|event| match event {
+                    TerrainEvent::BlockEntityCreated(chunk_pos, local_block_pos) => {
+                        Some(("created", *chunk_pos, *local_block_pos))
+                    }
+                    TerrainEvent::BlockEntityModified(chunk_pos, local_block_pos) => {
+                        Some(("modified", *chunk_pos, *local_block_pos))
+                    }
+                    TerrainEvent::BlockEntityRemoved(chunk_pos, local_block_pos) => {
+                        Some(("removed", *chunk_pos, *local_block_pos))
+                    }
+                    _ => None,
+                })
+                .collect_vec()
+        };
+        terrain.clear_events();
+
+        assert!(terrain.set_block(load_area_index, &pos, sign));
+        assert_eq!(
+            terrain.get_block_entity(&pos),
+            Some(&BlockEntity::Sign {
+                text: String::new()
+            })
+        );
+        assert_eq!(
+            block_entity_events(&terrain),
+            vec![("created", chunk_pos, local_block_pos)]
+        );
+        terrain.clear_events();
+
+        assert!(terrain.modify_block_entity(&pos, |block_entity| {
+            *block_entity = BlockEntity::Sign {
+                text: "hello".to_string(),
+            }
+        }));
+        assert_eq!(
+            block_entity_events(&terrain),
+            vec![("modified", chunk_pos, local_block_pos)]
+        );
+        terrain.clear_events();
+
+        // setting the block to the same type keeps its block entity
+        assert!(terrain.set_block(load_area_index, &pos, sign));
+        assert_eq!(
+            terrain.get_block_entity(&pos),
+            Some(&BlockEntity::Sign {
+                text: "hello".to_string()
+            })
+        );
+        assert_eq!(block_entity_events(&terrain), Vec::new());
+
+        assert!(terrain.set_block(load_area_index, &pos, stone));
+        assert_eq!(terrain.get_block_entity(&pos), None);
+        assert_eq!(
+            block_entity_events(&terrain),
+            vec![("removed", chunk_pos, local_block_pos)]
+        );
+        assert!(!terrain.modify_block_entity(&pos, |_| ()));
+    }
+
+    #[test]
+    fn undo_and_redo_in_unloaded_chunks() {
+        let (mut terrain, mut tasks, load_area_index) =
+            test_terrain("history", &[r#"name = "stone""#, r#"name = "dirt""#]);
+        let dirt = terrain.block_registry().get_id("dirt").unwrap();
+        let pos = GlobalBlockPosition::new(1, 2, 3);
+        let (local_block_pos, chunk_pos) = pos.get_local_and_chunk_pos();
+        let saved_block = |terrain: &Terrain| {
+            terrain
+                .world_save()
+                .load_chunk(chunk_pos)
+                .unwrap()
+                .map(|saved_chunk| saved_chunk.block_store.get_block(local_block_pos))
+        };
+
+        assert!(terrain.set_block(load_area_index, &pos, dirt));
+
+        // the chunk is saved when the load area moves away from it
+        terrain.load_areas_mut()[load_area_index].set_center(Vec3::new(100.0, 0.0, 0.0));
+        wait_until(&mut terrain, &mut tasks, |terrain| {
+            terrain.find_chunk_index(&chunk_pos).is_none() && saved_block(terrain) == Some(dirt)
+        });
+
+        // undoing and redoing patch the saved chunk
+        assert!(terrain.undo(&mut tasks));
+        wait_until(&mut terrain, &mut tasks, |terrain| {
+            terrain.patching_chunks.is_empty() && terrain.pending_history_edits.is_empty()
+        });
+        assert_eq!(saved_block(&terrain), Some(BLOCK_AIR));
+
+        assert!(terrain.redo(&mut tasks));
+        wait_until(&mut terrain, &mut tasks, |terrain| {
+            terrain.patching_chunks.is_empty() && terrain.pending_history_edits.is_empty()
+        });
+        assert_eq!(saved_block(&terrain), Some(dirt));
+
+        // the patched chunk is loaded again with the undone edit
+        assert!(terrain.undo(&mut tasks));
+        terrain.load_areas_mut()[load_area_index].set_center(Vec3::ZERO);
+        wait_until(&mut terrain, &mut tasks, |terrain| {
+            terrain.find_chunk_index(&chunk_pos).is_some()
+        });
+        assert_eq!(terrain.get_block(load_area_index, &pos), Some(BLOCK_AIR));
+        assert!(terrain.redo(&mut tasks));
+        assert_eq!(terrain.get_block(load_area_index, &pos), Some(dirt));
+    }
+
+    #[test]
+    fn edits_modify_each_chunk_once() {
+        let (mut terrain, mut tasks, load_area_index) =
+            test_terrain("edit", &[r#"name = "stone""#]);
+        let stone = terrain.block_registry().get_id("stone").unwrap();
+        let chunk_positions = [
+            ChunkPosition::new(-1, 0, -1),
+            ChunkPosition::new(-1, 0, 0),
+            ChunkPosition::new(0, 0, -1),
+            ChunkPosition::new(0, 0, 0),
+        ];
+        wait_until(&mut terrain, &mut tasks, |terrain| {
+            chunk_positions
+                .iter()
+                .all(|chunk_pos| terrain.load_areas()[load_area_index].is_loaded(chunk_pos))
+        });
+        terrain.clear_events();
+
+        // a box crossing the chunk boundaries in x and z
+        let shape = EditShape::cuboid(
+            GlobalBlockPosition::new(-4, 1, -4),
+            GlobalBlockPosition::new(4, 3, 4),
+        );
+        assert_eq!(
+            terrain.edit_region(&shape, EditOperation::Fill(stone)),
+            9 * 3 * 9
+        );
+
+        let modified_chunks = terrain
+            .events()
+            .filter_map(|event| match event {
+                TerrainEvent::ChunkModified(chunk_pos) => Some(*chunk_pos),
+                TerrainEvent::BlockModified(..) => panic!("expected one event per chunk"),
+                _ => None,
+            })
+            .sorted_by_key(|chunk_pos| chunk_pos.as_ivec3().to_array())
+            .collect_vec();
+        assert_eq!(modified_chunks, chunk_positions);
+    }
 }
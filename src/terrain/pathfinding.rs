@@ -0,0 +1,466 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use glam::{IVec3, Vec3};
+use rustc_hash::FxHashMap;
+
+use super::{
+    block::{registry::BlockRegistry, BlockId},
+    chunk::block_store::ChunkBlockStore,
+    collision::{self, Aabb},
+    position_types::{ChunkPosition, GlobalBlockPosition},
+};
+
+/// Cost of moving to a horizontally adjacent cell
+const HORIZONTAL_MOVE_COST: u32 = 10;
+/// Additional cost of each block climbed or dropped when moving between cells
+const VERTICAL_MOVE_COST: f32 = 5.0;
+
+/// Highest that the floor of a cell can be within the cell itself, e.g. the top of a bottom slab.
+/// Blocks any taller than this have to be stood on from the cell above
+const MAX_FLOOR_IN_CELL: f32 = 0.5;
+
+/// Horizontal directions an agent can move in
+const MOVE_DIRECTIONS: [IVec3; 4] = [IVec3::X, IVec3::NEG_X, IVec3::Z, IVec3::NEG_Z];
+
+/// Describes the agent a path is found for
+#[derive(Clone, Copy, Debug)]
+pub struct PathfindingSettings {
+    /// Distance from the center of the agent's body to each side
+    pub body_half_width: f32,
+    /// Height of the agent's body, which must fit above the floor of each cell on the path
+    pub body_height: f32,
+    /// Height of the tallest ledge the agent can step onto
+    pub max_step_up: f32,
+    /// Height of the tallest drop the agent will walk off
+    pub max_drop: f32,
+    /// Maximum number of cells visited before the search gives up, returning a partial path
+    pub search_budget: usize,
+}
+
+impl Default for PathfindingSettings {
+    fn default() -> Self {
+        Self {
+            body_half_width: 0.3,
+            body_height: 1.8,
+            max_step_up: 1.0,
+            max_drop: 3.0,
+            search_budget: 10_000,
+        }
+    }
+}
+
+/// Cells an agent can walk through, from the start to the goal or as close to it as the search
+/// got
+#[derive(Clone, Debug, PartialEq)]
+pub struct Path {
+    /// Positions of the cells containing the agent's feet, including the start
+    pub cells: Vec<GlobalBlockPosition>,
+    /// Height of the floor the agent stands on in each cell, which is above the bottom of the
+    /// cell for blocks like slabs
+    pub floor_heights: Vec<f32>,
+    /// True if the path reaches the goal. False if the goal is in a chunk which isn't loaded or
+    /// the search budget ran out, in which case the path ends at the cell nearest the goal
+    pub complete: bool,
+}
+
+/// Whether an agent can stand in a cell
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CellState {
+    /// The agent can stand in the cell, on a floor at the given height
+    Walkable(f32),
+    /// There's nothing to stand on, or not enough room for the agent's body
+    Blocked,
+    /// The cell or the one below it is not loaded
+    Unloaded,
+}
+
+/// A* search over the cells an agent can walk through
+struct PathSearch<'a, F> {
+    settings: &'a PathfindingSettings,
+    block_registry: &'a BlockRegistry,
+    get_block: F,
+    cell_states: FxHashMap<GlobalBlockPosition, CellState>,
+}
+
+impl<F: Fn(&GlobalBlockPosition) -> Option<BlockId>> PathSearch<'_, F> {
+    fn cell_state(&mut self, block_pos: GlobalBlockPosition) -> CellState {
+        if let Some(&cell_state) = self.cell_states.get(&block_pos) {
+            return cell_state;
+        }
+
+        let cell_state = self.compute_cell_state(block_pos);
+        self.cell_states.insert(block_pos, cell_state);
+
+        cell_state
+    }
+
+    fn compute_cell_state(&self, block_pos: GlobalBlockPosition) -> CellState {
+        let below_pos = block_pos - GlobalBlockPosition::new(0, 1, 0);
+        let (Some(block_id), Some(below_id)) =
+            ((self.get_block)(&block_pos), (self.get_block)(&below_pos))
+        else {
+            return CellState::Unloaded;
+        };
+
+        // the agent stands either on a low block in the cell itself or on a block below whose
+        // top covers the bottom of the cell
+        let boxes = self.block_registry[block_id].model.collision_boxes();
+        let floor_offset = if boxes.is_empty() {
+            let below_boxes = self.block_registry[below_id].model.collision_boxes();
+            if !below_boxes.iter().any(|block_box| block_box.max.y >= 1.0) {
+                return CellState::Blocked;
+            }
+
+            0.0
+        } else {
+            let top = boxes
+                .iter()
+                .map(|block_box| block_box.max.y)
+                .fold(0.0, f32::max);
+            if top > MAX_FLOOR_IN_CELL {
+                return CellState::Blocked;
+            }
+
+            top
+        };
+
+        let floor = block_pos.y() as f32 + floor_offset;
+        if self.is_clear(&self.body(block_pos, floor, self.settings.body_height)) {
+            CellState::Walkable(floor)
+        } else {
+            CellState::Blocked
+        }
+    }
+
+    /// Returns the agent's body standing at the given height in the cell, stretched to `height`
+    fn body(&self, block_pos: GlobalBlockPosition, floor: f32, height: f32) -> Aabb {
+        let center = block_pos.as_ivec3().as_vec3() + Vec3::new(0.5, 0.0, 0.5);
+
+        Aabb::from_base(
+            Vec3::new(center.x, floor, center.z),
+            self.settings.body_half_width,
+            height,
+        )
+    }
+
+    /// True if the box doesn't collide with any block. Unloaded cells are treated as solid
+    fn is_clear(&self, aabb: &Aabb) -> bool {
+        collision::block_collision_boxes(aabb, self.block_registry, &self.get_block)
+            .iter()
+            .all(|block_box| !block_box.intersects(aabb))
+    }
+
+    /// True if the agent can move between the adjacent cells, climbing or dropping between
+    /// their floors within the cell that is lower
+    fn can_move_between(
+        &self,
+        from: GlobalBlockPosition,
+        from_floor: f32,
+        to: GlobalBlockPosition,
+        to_floor: f32,
+    ) -> bool {
+        let height = self.settings.body_height;
+        let top_floor = from_floor.max(to_floor);
+
+        // rise or fall in each column, and cross between them at the height of the higher floor
+        let from_column = self.body(from, from_floor, top_floor - from_floor + height);
+        let to_column = self.body(to, to_floor, top_floor - to_floor + height);
+        let crossing = self.body(from, top_floor, height);
+        let crossing = Aabb::new(
+            crossing.min.min(to_column.min.with_y(top_floor)),
+            crossing.max.max(to_column.max),
+        );
+
+        self.is_clear(&from_column) && self.is_clear(&to_column) && self.is_clear(&crossing)
+    }
+
+    /// Returns the cells the agent can move to from the given cell, with their floor heights
+    fn neighbours(
+        &mut self,
+        block_pos: GlobalBlockPosition,
+        floor: f32,
+    ) -> Vec<(GlobalBlockPosition, f32)> {
+        let max_rise = self.settings.max_step_up.ceil() as i32;
+        let max_fall = self.settings.max_drop.ceil() as i32 + 1;
+
+        let mut neighbours = Vec::new();
+        for direction in MOVE_DIRECTIONS {
+            for dy in (-max_fall..=max_rise).rev() {
+                let neighbour_pos =
+                    block_pos + GlobalBlockPosition::from(direction + IVec3::Y * dy);
+                let CellState::Walkable(neighbour_floor) = self.cell_state(neighbour_pos) else {
+                    continue;
+                };
+
+                let rise = neighbour_floor - floor;
+                if rise <= self.settings.max_step_up
+                    && -rise <= self.settings.max_drop
+                    && self.can_move_between(block_pos, floor, neighbour_pos, neighbour_floor)
+                {
+                    neighbours.push((neighbour_pos, neighbour_floor));
+                }
+            }
+        }
+
+        neighbours
+    }
+}
+
+/// Estimated cost of moving between two cells, never more than the actual cost. The floors can
+/// be up to `MAX_FLOOR_IN_CELL` above the bottoms of their cells, so only the whole blocks of
+/// height difference beyond the first are certain to be climbed or dropped
+fn heuristic(from: GlobalBlockPosition, to: GlobalBlockPosition) -> u32 {
+    let delta = (to.as_ivec3() - from.as_ivec3()).abs();
+
+    (delta.x + delta.z) as u32 * HORIZONTAL_MOVE_COST
+        + ((delta.y as u32).saturating_sub(1) as f32 * VERTICAL_MOVE_COST).floor() as u32
+}
+
+/// Find a path for an agent walking from `start` to `goal`, which are the cells containing its
+/// feet. `get_block` returns the block at a position, or None if it is not loaded (usually
+/// `Terrain::get_block` or `TerrainSnapshot::get_block`).
+/// Returns None if the agent can't stand at the start, or if the goal is loaded but can't be
+/// reached. If the goal isn't loaded or the search budget runs out, a partial path to the
+/// visited cell nearest the goal is returned
+pub fn find_path(
+    start: GlobalBlockPosition,
+    goal: GlobalBlockPosition,
+    settings: &PathfindingSettings,
+    block_registry: &BlockRegistry,
+    get_block: impl Fn(&GlobalBlockPosition) -> Option<BlockId>,
+) -> Option<Path> {
+    let mut search = PathSearch {
+        settings,
+        block_registry,
+        get_block,
+        cell_states: FxHashMap::default(),
+    };
+
+    let CellState::Walkable(start_floor) = search.cell_state(start) else {
+        return None;
+    };
+
+    // cost to reach each visited cell, and the cell it was reached from
+    let mut visited: FxHashMap<GlobalBlockPosition, (u32, f32, Option<GlobalBlockPosition>)> =
+        FxHashMap::default();
+    visited.insert(start, (0, start_floor, None));
+
+    let mut open = BinaryHeap::new();
+    open.push(Reverse((
+        heuristic(start, goal),
+        0,
+        start.as_ivec3().to_array(),
+    )));
+
+    let mut nearest = (heuristic(start, goal), start);
+    let mut visited_count = 0;
+    let mut reached_goal = false;
+
+    while let Some(Reverse((_, cost, block_pos))) = open.pop() {
+        let block_pos = GlobalBlockPosition::from(IVec3::from_array(block_pos));
+        let (best_cost, floor, _) = visited[&block_pos];
+        if cost > best_cost {
+            // already reached more cheaply
+            continue;
+        }
+
+        if block_pos == goal {
+            reached_goal = true;
+            break;
+        }
+
+        visited_count += 1;
+        if visited_count > settings.search_budget {
+            break;
+        }
+
+        for (neighbour_pos, neighbour_floor) in search.neighbours(block_pos, floor) {
+            let neighbour_cost = cost
+                + HORIZONTAL_MOVE_COST
+                + ((neighbour_floor - floor).abs() * VERTICAL_MOVE_COST).round() as u32;
+
+            if visited
+                .get(&neighbour_pos)
+                .is_some_and(|&(visited_cost, _, _)| visited_cost <= neighbour_cost)
+            {
+                continue;
+            }
+
+            visited.insert(
+                neighbour_pos,
+                (neighbour_cost, neighbour_floor, Some(block_pos)),
+            );
+
+            let estimate = heuristic(neighbour_pos, goal);
+            if estimate < nearest.0 {
+                nearest = (estimate, neighbour_pos);
+            }
+            open.push(Reverse((
+                neighbour_cost + estimate,
+                neighbour_cost,
+                neighbour_pos.as_ivec3().to_array(),
+            )));
+        }
+    }
+
+    let end = if reached_goal {
+        goal
+    } else {
+        // the goal can't be reached if the search covered everything around it
+        let goal_is_loaded = search.cell_state(goal) != CellState::Unloaded;
+        if goal_is_loaded && visited_count <= settings.search_budget {
+            return None;
+        }
+
+        nearest.1
+    };
+
+    // follow the path back from the end
+    let mut cells = Vec::new();
+    let mut floor_heights = Vec::new();
+    let mut current = Some(end);
+    while let Some(block_pos) = current {
+        let (_, floor, previous) = visited[&block_pos];
+        cells.push(block_pos);
+        floor_heights.push(floor);
+        current = previous;
+    }
+    cells.reverse();
+    floor_heights.reverse();
+
+    Some(Path {
+        cells,
+        floor_heights,
+        complete: reached_goal,
+    })
+}
+
+/// Copy of the blocks of some loaded chunks, which can be searched on other threads while the
+/// terrain keeps changing
+#[derive(Clone, Debug, Default)]
+pub struct TerrainSnapshot {
+    chunks: FxHashMap<ChunkPosition, ChunkBlockStore>,
+}
+
+impl TerrainSnapshot {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a copy of a chunk's blocks to the snapshot
+    pub fn insert_chunk(&mut self, chunk_pos: ChunkPosition, block_store: ChunkBlockStore) {
+        self.chunks.insert(chunk_pos, block_store);
+    }
+
+    /// Returns the block at the given position, or None if its chunk isn't in the snapshot
+    pub fn get_block(&self, global_block_pos: &GlobalBlockPosition) -> Option<BlockId> {
+        let (local_block_pos, chunk_pos) = global_block_pos.get_local_and_chunk_pos();
+
+        self.chunks
+            .get(&chunk_pos)
+            .map(|block_store| block_store.get_block(local_block_pos))
+    }
+
+    /// Find a path through the chunks in the snapshot. See `find_path`
+    pub fn find_path(
+        &self,
+        start: GlobalBlockPosition,
+        goal: GlobalBlockPosition,
+        settings: &PathfindingSettings,
+        block_registry: &BlockRegistry,
+    ) -> Option<Path> {
+        find_path(start, goal, settings, block_registry, |block_pos| {
+            self.get_block(block_pos)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::block::{registry::test_registry, BLOCK_AIR};
+
+    fn test_block_registry() -> BlockRegistry {
+        test_registry(&[
+                "name = \"stone\"\n[model]\ntype = \"full_block\"\ntextures = { all = \"stone\" }",
+                "name = \"stone_slab\"\n[model]\ntype = \"slab\"\nhalf = \"bottom\"\ntextures = { all = \"stone\" }",
+        ])
+    }
+
+    #[test]
+    fn paths_over_terrain() {
+        let block_registry = test_block_registry();
+        let stone = block_registry.get_id("stone").unwrap();
+        let slab = block_registry.get_id("stone_slab").unwrap();
+
+        // a floor at y = 0 with a wall at x = 3 that is two blocks high except for a one block
+        // step at z = 4, and a slab on top of the floor at (1, 1, 0)
+        let get_block = |pos: &GlobalBlockPosition| {
+            if !(-8..8).contains(&pos.x()) || !(-8..8).contains(&pos.z()) {
+                return None;
+            }
+
+            Some(match (pos.x(), pos.y(), pos.z()) {
+                (_, 0, _) => stone,
+                (3, 1, _) => stone,
+                (3, 2, z) if z != 4 => stone,
+                (1, 1, 0) => slab,
+                _ => BLOCK_AIR,
+            })
+        };
+        let settings = PathfindingSettings::default();
+
+        let path = find_path(
+            GlobalBlockPosition::new(0, 1, 0),
+            GlobalBlockPosition::new(5, 1, 0),
+            &settings,
+            &block_registry,
+            get_block,
+        )
+        .unwrap();
+        assert!(path.complete);
+        assert!(path.cells.contains(&GlobalBlockPosition::new(3, 2, 4)));
+        assert!(path.cells.windows(2).all(|pair| {
+            let delta = (pair[1].as_ivec3() - pair[0].as_ivec3()).abs();
+            delta.x + delta.z == 1
+        }));
+
+        // standing on the slab
+        let path = find_path(
+            GlobalBlockPosition::new(1, 1, 0),
+            GlobalBlockPosition::new(1, 1, 2),
+            &settings,
+            &block_registry,
+            get_block,
+        )
+        .unwrap();
+        assert_eq!(path.floor_heights[0], 1.5);
+        assert_eq!(path.floor_heights[1], 1.0);
+
+        // the goal is outside the loaded area, so the path ends at the nearest cell
+        let path = find_path(
+            GlobalBlockPosition::new(0, 1, 0),
+            GlobalBlockPosition::new(20, 1, 0),
+            &settings,
+            &block_registry,
+            get_block,
+        )
+        .unwrap();
+        assert!(!path.complete);
+        assert_eq!(path.cells.last(), Some(&GlobalBlockPosition::new(7, 1, 0)));
+
+        // without being able to step up, the wall can't be crossed
+        let settings = PathfindingSettings {
+            max_step_up: 0.5,
+            ..settings
+        };
+        let path = find_path(
+            GlobalBlockPosition::new(0, 1, 0),
+            GlobalBlockPosition::new(5, 1, 0),
+            &settings,
+            &block_registry,
+            get_block,
+        );
+        assert_eq!(path, None);
+    }
+}
@@ -31,6 +31,10 @@ impl GlobalBlockPosition {
         (local_pos, chunk_pos)
     }
 
+    pub fn as_ivec3(&self) -> IVec3 {
+        self.0
+    }
+
     pub fn x(&self) -> i32 {
         self.0.x
     }
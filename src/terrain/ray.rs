@@ -0,0 +1,248 @@
+use glam::{IVec3, Vec3};
+
+use super::{
+    block::{model::BlockModel, registry::BlockRegistry, Block, BlockId},
+    position_types::GlobalBlockPosition,
+};
+use crate::util::face::{FaceIndex, FACE_NORMALS};
+
+/// A cell passed through by a ray
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RayCell {
+    pub block_pos: GlobalBlockPosition,
+    /// Distance along the ray at which it enters the cell
+    pub distance: f32,
+    /// Face of the cell the ray entered through, or None for the cell containing the origin
+    pub entry_face: Option<FaceIndex>,
+}
+
+/// Iterator over every cell that a ray passes through, in order, using the DDA algorithm.
+/// Cells are visited in global block coordinates so the ray crosses chunk borders seamlessly
+#[derive(Clone, Debug)]
+pub struct VoxelTraversal {
+    cell: IVec3,
+    step: IVec3,
+    /// Distance along the ray to the next cell border on each axis
+    next_border: Vec3,
+    /// Distance along the ray between cell borders on each axis
+    border_spacing: Vec3,
+    distance: f32,
+    entry_face: Option<FaceIndex>,
+    maximum_distance: f32,
+}
+
+impl VoxelTraversal {
+    /// Traverse the cells along the ray up to `maximum_distance`. The direction doesn't need to be
+    /// normalized, distances are always in blocks
+    pub fn new(ray_origin: Vec3, ray_direction: Vec3, maximum_distance: f32) -> Self {
+        let ray_direction = ray_direction.normalize_or_zero();
+        let cell = ray_origin.floor().as_ivec3();
+        let step = IVec3::from_array(std::array::from_fn(|axis| {
+            if ray_direction[axis] > 0.0 {
+                1
+            } else if ray_direction[axis] < 0.0 {
+                -1
+            } else {
+                0
+            }
+        }));
+        let border_spacing = ray_direction.abs().recip();
+
+        let next_border = Vec3::from_array(std::array::from_fn(|axis| match step[axis] {
+            1 => (cell[axis] as f32 + 1.0 - ray_origin[axis]) * border_spacing[axis],
+            -1 => (ray_origin[axis] - cell[axis] as f32) * border_spacing[axis],
+            _ => f32::INFINITY,
+        }));
+
+        Self {
+            cell,
+            step,
+            next_border,
+            border_spacing,
+            distance: 0.0,
+            entry_face: None,
+            maximum_distance,
+        }
+    }
+}
+
+impl Iterator for VoxelTraversal {
+    type Item = RayCell;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // the distance becomes infinite if the direction is zero
+        if self.distance > self.maximum_distance || self.distance.is_infinite() {
+            return None;
+        }
+
+        let ray_cell = RayCell {
+            block_pos: GlobalBlockPosition::from(self.cell),
+            distance: self.distance,
+            entry_face: self.entry_face,
+        };
+
+        // advance across the nearest cell border
+        let axis = if self.next_border.x <= self.next_border.y.min(self.next_border.z) {
+            0
+        } else if self.next_border.y <= self.next_border.z {
+            1
+        } else {
+            2
+        };
+        self.distance = self.next_border[axis];
+        self.cell[axis] += self.step[axis];
+        self.next_border[axis] += self.border_spacing[axis];
+
+        let mut entry_normal = IVec3::ZERO;
+        entry_normal[axis] = -self.step[axis];
+        self.entry_face = FaceIndex::from_dir(entry_normal);
+
+        Some(ray_cell)
+    }
+}
+
+/// Returned by ray queries when a block is intersected
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RayHit {
+    pub hit_pos: GlobalBlockPosition,
+    pub block_id: BlockId,
+    /// Distance along the ray to the intersection
+    pub distance: f32,
+    /// Point where the ray intersects the shape of the block
+    pub hit_point: Vec3,
+    /// Normal of the face hit, or None if the ray started inside the block
+    pub hit_normal: Option<IVec3>,
+}
+
+/// Returns every block along the ray up to `maximum_distance` for which `predicate` returns true,
+/// in order. Blocks with partial models are hit-tested against their real shape.
+/// `get_block` returns the block at a position, or None if it is not loaded (usually
+/// `Terrain::get_block`). Unloaded cells are passed through
+pub fn cast_ray<'a>(
+    ray_origin: Vec3,
+    ray_direction: Vec3,
+    maximum_distance: f32,
+    block_registry: &'a BlockRegistry,
+    get_block: impl Fn(&GlobalBlockPosition) -> Option<BlockId> + 'a,
+    predicate: impl Fn(BlockId, &Block) -> bool + 'a,
+) -> impl Iterator<Item = RayHit> + 'a {
+    let ray_direction = ray_direction.normalize_or_zero();
+
+    VoxelTraversal::new(ray_origin, ray_direction, maximum_distance).filter_map(move |cell| {
+        let block_id = get_block(&cell.block_pos)?;
+        let block = &block_registry[block_id];
+        if !predicate(block_id, block) {
+            return None;
+        }
+
+        let entry_normal = cell
+            .entry_face
+            .map(|face_index| FACE_NORMALS[face_index.as_usize()]);
+
+        let (distance, hit_normal) = match block.model {
+            BlockModel::FullBlock(_) => (cell.distance, entry_normal),
+            _ => {
+                // hit-test the shape of the block within the cell
+                let cell_origin = cell.block_pos.as_ivec3().as_vec3();
+                let entry_point = ray_origin + ray_direction * cell.distance;
+                let (hit_t, hit_normal) = block
+                    .model
+                    .intersect_ray(entry_point - cell_origin, ray_direction)?;
+
+                (cell.distance + hit_t, hit_normal.or(entry_normal))
+            }
+        };
+
+        (distance <= maximum_distance).then(|| RayHit {
+            hit_pos: cell.block_pos,
+            block_id,
+            distance,
+            hit_point: ray_origin + ray_direction * distance,
+            hit_normal,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::block::{registry::test_registry, BLOCK_AIR};
+
+    #[test]
+    fn traversal() {
+        // crosses from x = 1 into negative coordinates, passing a chunk border
+        let cells = VoxelTraversal::new(Vec3::new(1.5, 0.5, 0.5), Vec3::new(-1.0, 0.0, 0.0), 2.9)
+            .collect::<Vec<_>>();
+        let positions = cells
+            .iter()
+            .map(|cell| cell.block_pos.x())
+            .collect::<Vec<_>>();
+        assert_eq!(positions, vec![1, 0, -1, -2]);
+        assert_eq!(cells[0].entry_face, None);
+        assert_eq!(cells[1].entry_face, Some(FaceIndex::POS_X));
+        assert_eq!(cells[2].distance, 1.5);
+
+        // a diagonal ray visits cells sharing a face with the previous one
+        let cells = VoxelTraversal::new(Vec3::new(0.2, 0.7, 0.5), Vec3::new(1.0, -1.0, 0.0), 4.0)
+            .collect::<Vec<_>>();
+        assert!(cells.windows(2).all(|pair| {
+            (pair[1].block_pos.as_ivec3() - pair[0].block_pos.as_ivec3())
+                .abs()
+                .element_sum()
+                == 1
+        }));
+        assert!(cells
+            .windows(2)
+            .all(|pair| pair[0].distance < pair[1].distance));
+    }
+
+    #[test]
+    fn ray_hits() {
+        let block_registry = test_registry(&[
+                "name = \"glass\"\n[model]\ntype = \"full_block\"\ntextures = { all = \"glass\" }",
+                "name = \"stone_slab\"\n[model]\ntype = \"slab\"\nhalf = \"bottom\"\ntextures = { all = \"stone\" }",
+        ]);
+        let glass = block_registry.get_id("glass").unwrap();
+        let slab = block_registry.get_id("stone_slab").unwrap();
+
+        // glass at x = 2 and a slab at x = 4
+        let get_block = |pos: &GlobalBlockPosition| {
+            Some(match pos.x() {
+                2 => glass,
+                4 => slab,
+                _ => BLOCK_AIR,
+            })
+        };
+        let is_solid = |block_id: BlockId, _: &Block| block_id != BLOCK_AIR;
+        let is_not_glass =
+            |block_id: BlockId, _: &Block| block_id != BLOCK_AIR && block_id != glass;
+
+        let origin = Vec3::new(0.5, 0.25, 0.5);
+        let hits = cast_ray(origin, Vec3::X, 10.0, &block_registry, get_block, is_solid)
+            .collect::<Vec<_>>();
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].distance, 1.5);
+        assert_eq!(hits[0].hit_normal, Some(IVec3::NEG_X));
+
+        let hit = cast_ray(
+            origin,
+            Vec3::X,
+            10.0,
+            &block_registry,
+            get_block,
+            is_not_glass,
+        )
+        .next()
+        .unwrap();
+        assert_eq!(hit.hit_pos, GlobalBlockPosition::new(4, 0, 0));
+        assert_eq!(hit.hit_point, Vec3::new(4.0, 0.25, 0.5));
+
+        // the ray passes over the slab
+        let origin = Vec3::new(3.5, 0.75, 0.5);
+        assert!(
+            cast_ray(origin, Vec3::X, 10.0, &block_registry, get_block, is_solid)
+                .next()
+                .is_none()
+        );
+    }
+}
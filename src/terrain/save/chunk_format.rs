@@ -0,0 +1,339 @@
+use std::io::{Read, Write};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use itertools::Itertools;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+
+use super::{BlockIdMap, SavedChunk};
+use crate::terrain::{
+    block::{entity::BlockEntity, BlockId},
+    chunk::{
+        block_store::{BlockLayer, ChunkBlockStore},
+        CHUNK_SIZE, CHUNK_SIZE_CUBED,
+    },
+    generation::feature::ChunkPlacements,
+    position_types::{ChunkPosition, LocalBlockPosition},
+    tick::ScheduledTick,
+};
+
+/// Tag byte for a chunk made of a single block type
+const TAG_UNIFORM: u8 = 0;
+
+/// Tag byte for a chunk stored as palette-compressed layers
+const TAG_LAYERED: u8 = 1;
+
+/// Bit in the flags byte set for chunks whose features have been placed
+const FLAG_DECORATED: u8 = 1 << 0;
+
+/// Bit in the flags byte set for chunks with scheduled ticks
+const FLAG_SCHEDULED_TICKS: u8 = 1 << 1;
+
+/// Bit in the flags byte set for chunks with block entities
+const FLAG_BLOCK_ENTITIES: u8 = 1 << 2;
+
+/// Encode a chunk for storage in a region file.
+/// The data begins with a byte of flags, followed by the block data. Layered chunks are stored
+/// using the same palette encoding as `BlockLayer`, with the block IDs in each palette converted
+/// to saved IDs. If the chunk has scheduled ticks, the block data is followed by the number of
+/// scheduled ticks and the array index and tick index of each. Block entities are stored last, as
+/// the length of a TOML document followed by the document itself (see `SavedBlockEntities`). The
+/// encoded data is then zlib-compressed
+pub fn encode_chunk(saved_chunk: &SavedChunk, block_id_map: &BlockIdMap) -> Vec<u8> {
+    let mut data = Vec::new();
+
+    let mut flags = 0;
+    if saved_chunk.is_decorated {
+        flags |= FLAG_DECORATED;
+    }
+    if !saved_chunk.scheduled_ticks.is_empty() {
+        flags |= FLAG_SCHEDULED_TICKS;
+    }
+    if !saved_chunk.block_entities.is_empty() {
+        flags |= FLAG_BLOCK_ENTITIES;
+    }
+    data.push(flags);
+
+    match &saved_chunk.block_store {
+        ChunkBlockStore::Uniform(block_id) => {
+            data.push(TAG_UNIFORM);
+            data.extend_from_slice(&block_id_map.saved_id(*block_id).to_le_bytes());
+        }
+        ChunkBlockStore::Layered(layers) => {
+            data.push(TAG_LAYERED);
+
+            for layer in layers {
+                data.extend_from_slice(&(layer.palette().len() as u16).to_le_bytes());
+                for block_id in layer.palette() {
+                    data.extend_from_slice(&block_id_map.saved_id(*block_id).to_le_bytes());
+                }
+
+                data.push(layer.element_size_bits() as u8);
+                data.extend_from_slice(&(layer.segments().len() as u16).to_le_bytes());
+                for segment in layer.segments() {
+                    data.extend_from_slice(&(*segment as u64).to_le_bytes());
+                }
+            }
+        }
+    }
+
+    if !saved_chunk.scheduled_ticks.is_empty() {
+        data.extend_from_slice(&(saved_chunk.scheduled_ticks.len() as u32).to_le_bytes());
+        for scheduled_tick in &saved_chunk.scheduled_ticks {
+            data.extend_from_slice(&(scheduled_tick.pos.get_array_index() as u16).to_le_bytes());
+            data.extend_from_slice(&scheduled_tick.tick_index.to_le_bytes());
+        }
+    }
+
+    if !saved_chunk.block_entities.is_empty() {
+        let document = SavedBlockEntities::from_block_entities(&saved_chunk.block_entities);
+        let source =
+            toml::to_string(&document).expect("block entities should be serializable as TOML");
+
+        data.extend_from_slice(&(source.len() as u32).to_le_bytes());
+        data.extend_from_slice(source.as_bytes());
+    }
+
+    compress(&data)
+}
+
+/// Decode a chunk stored by `encode_chunk`, returning None if the data is invalid
+pub fn decode_chunk(compressed_data: &[u8], block_id_map: &BlockIdMap) -> Option<SavedChunk> {
+    let data = decompress(compressed_data)?;
+    let mut reader = ByteReader(&data);
+
+    let flags = reader.read_u8()?;
+
+    let block_store = match reader.read_u8()? {
+        TAG_UNIFORM => ChunkBlockStore::Uniform(block_id_map.registry_id(reader.read_u16()?)?),
+        TAG_LAYERED => {
+            let layers: Vec<BlockLayer> = (0..CHUNK_SIZE)
+                .map(|_| {
+                    let palette_len = reader.read_u16()?;
+                    let palette: Vec<BlockId> = (0..palette_len)
+                        .map(|_| block_id_map.registry_id(reader.read_u16()?))
+                        .collect::<Option<_>>()?;
+
+                    let element_size_bits = reader.read_u8()? as usize;
+                    let segment_count = reader.read_u16()?;
+                    let segments = (0..segment_count)
+                        .map(|_| reader.read_u64().map(|segment| segment as usize))
+                        .collect::<Option<_>>()?;
+
+                    BlockLayer::from_raw_parts(palette, element_size_bits, segments)
+                })
+                .collect::<Option<_>>()?;
+
+            ChunkBlockStore::Layered(
+                array_init::from_iter(layers).expect("there should be `CHUNK_SIZE` layers"),
+            )
+        }
+        _ => return None,
+    };
+
+    let scheduled_ticks = if flags & FLAG_SCHEDULED_TICKS != 0 {
+        let scheduled_tick_count = reader.read_u32()?;
+        (0..scheduled_tick_count)
+            .map(|_| {
+                let array_index = reader.read_u16()? as usize;
+                let tick_index = reader.read_u64()?;
+
+                (array_index < CHUNK_SIZE_CUBED).then(|| ScheduledTick {
+                    pos: LocalBlockPosition::from_array_index(array_index),
+                    tick_index,
+                })
+            })
+            .collect::<Option<_>>()?
+    } else {
+        Vec::new()
+    };
+
+    let block_entities = if flags & FLAG_BLOCK_ENTITIES != 0 {
+        let source_len = reader.read_u32()? as usize;
+        let source = std::str::from_utf8(reader.read_slice(source_len)?).ok()?;
+
+        toml::from_str::<SavedBlockEntities>(source)
+            .ok()?
+            .into_block_entities()?
+    } else {
+        FxHashMap::default()
+    };
+
+    // there should be no data left over
+    reader.0.is_empty().then_some(SavedChunk {
+        block_store,
+        is_decorated: flags & FLAG_DECORATED != 0,
+        scheduled_ticks,
+        block_entities,
+    })
+}
+
+/// Encode the feature blocks waiting to be placed in unloaded chunks.
+/// For each chunk, the chunk position and number of blocks are stored, followed by the array
+/// index and saved ID of each block. The encoded data is then zlib-compressed
+pub fn encode_feature_placements(
+    placements: &ChunkPlacements,
+    block_id_map: &BlockIdMap,
+) -> Vec<u8> {
+    let mut data = Vec::new();
+
+    for (chunk_pos, blocks) in placements {
+        for component in chunk_pos.as_ivec3().to_array() {
+            data.extend_from_slice(&component.to_le_bytes());
+        }
+
+        data.extend_from_slice(&(blocks.len() as u32).to_le_bytes());
+        for (local_pos, block_id) in blocks {
+            data.extend_from_slice(&(local_pos.get_array_index() as u16).to_le_bytes());
+            data.extend_from_slice(&block_id_map.saved_id(*block_id).to_le_bytes());
+        }
+    }
+
+    compress(&data)
+}
+
+/// Decode the feature blocks stored by `encode_feature_placements`, returning None if the data is
+/// invalid
+pub fn decode_feature_placements(
+    compressed_data: &[u8],
+    block_id_map: &BlockIdMap,
+) -> Option<ChunkPlacements> {
+    let data = decompress(compressed_data)?;
+    let mut reader = ByteReader(&data);
+
+    let mut placements = ChunkPlacements::default();
+
+    while !reader.0.is_empty() {
+        let chunk_pos =
+            ChunkPosition::new(reader.read_i32()?, reader.read_i32()?, reader.read_i32()?);
+
+        let block_count = reader.read_u32()?;
+        let blocks = (0..block_count)
+            .map(|_| {
+                let array_index = reader.read_u16()? as usize;
+                let block_id = block_id_map.registry_id(reader.read_u16()?)?;
+
+                (array_index < CHUNK_SIZE_CUBED)
+                    .then(|| (LocalBlockPosition::from_array_index(array_index), block_id))
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        placements.entry(chunk_pos).or_default().extend(blocks);
+    }
+
+    Some(placements)
+}
+
+/// Block entities of a chunk, stored as a TOML document so that the saved data can use the serde
+/// representation of `BlockEntity`
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedBlockEntities {
+    block_entities: Vec<SavedBlockEntity>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedBlockEntity {
+    /// Array index of the block within the chunk
+    index: u16,
+    block_entity: BlockEntity,
+}
+
+impl SavedBlockEntities {
+    fn from_block_entities(block_entities: &FxHashMap<LocalBlockPosition, BlockEntity>) -> Self {
+        let block_entities = block_entities
+            .iter()
+            .map(|(pos, block_entity)| SavedBlockEntity {
+                index: pos.get_array_index() as u16,
+                block_entity: block_entity.clone(),
+            })
+            .sorted_by_key(|saved_block_entity| saved_block_entity.index)
+            .collect();
+
+        Self { block_entities }
+    }
+
+    /// Returns None if any of the positions are out of bounds
+    fn into_block_entities(self) -> Option<FxHashMap<LocalBlockPosition, BlockEntity>> {
+        self.block_entities
+            .into_iter()
+            .map(|saved_block_entity| {
+                let array_index = saved_block_entity.index as usize;
+
+                (array_index < CHUNK_SIZE_CUBED).then(|| {
+                    (
+                        LocalBlockPosition::from_array_index(array_index),
+                        saved_block_entity.block_entity,
+                    )
+                })
+            })
+            .collect()
+    }
+}
+
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(data)
+        .expect("writing to a Vec should not fail");
+    encoder.finish().expect("writing to a Vec should not fail")
+}
+
+fn decompress(compressed_data: &[u8]) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    ZlibDecoder::new(compressed_data)
+        .read_to_end(&mut data)
+        .ok()?;
+
+    Some(data)
+}
+
+/// Reads little-endian values from a byte slice
+pub struct ByteReader<'a>(pub &'a [u8]);
+
+impl ByteReader<'_> {
+    pub fn read_slice(&mut self, len: usize) -> Option<&[u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+
+        Some(bytes)
+    }
+
+    pub fn read_bytes<const N: usize>(&mut self) -> Option<[u8; N]> {
+        if self.0.len() < N {
+            return None;
+        }
+
+        let (bytes, rest) = self.0.split_at(N);
+        self.0 = rest;
+
+        Some(bytes.try_into().unwrap())
+    }
+
+    pub fn read_u8(&mut self) -> Option<u8> {
+        self.read_bytes().map(u8::from_le_bytes)
+    }
+
+    pub fn read_u16(&mut self) -> Option<u16> {
+        self.read_bytes().map(u16::from_le_bytes)
+    }
+
+    pub fn read_u32(&mut self) -> Option<u32> {
+        self.read_bytes().map(u32::from_le_bytes)
+    }
+
+    pub fn read_i32(&mut self) -> Option<i32> {
+        self.read_bytes().map(i32::from_le_bytes)
+    }
+
+    pub fn read_u64(&mut self) -> Option<u64> {
+        self.read_bytes().map(u64::from_le_bytes)
+    }
+
+    pub fn read_f32(&mut self) -> Option<f32> {
+        self.read_bytes().map(f32::from_le_bytes)
+    }
+}
@@ -0,0 +1,559 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use glam::Vec3;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+
+use self::{
+    chunk_format::{
+        decode_chunk, decode_feature_placements, encode_chunk, encode_feature_placements,
+    },
+    region::{RegionFile, RegionPosition},
+};
+use super::{
+    block::{entity::BlockEntity, registry::BlockRegistry, BlockId, BLOCK_AIR},
+    chunk::block_store::ChunkBlockStore,
+    generation::{feature::ChunkPlacements, GeneratorSettings},
+    position_types::{ChunkPosition, LocalBlockPosition},
+    tick::ScheduledTick,
+};
+
+pub mod chunk_format;
+pub mod region;
+
+/// Version of the world save format, stored in the world metadata
+pub const WORLD_FORMAT_VERSION: u32 = 2;
+
+/// Name of the world metadata file within the world directory
+const METADATA_FILE_NAME: &str = "world.toml";
+
+/// Name of the directory containing the region files within the world directory
+const REGION_DIRECTORY_NAME: &str = "region";
+
+/// Name of the file storing feature blocks waiting to be placed in chunks which aren't loaded
+const FEATURE_PLACEMENTS_FILE_NAME: &str = "features.bin";
+
+/// A world saved on disk.
+/// The world directory contains a metadata file, a directory of region files, each storing
+/// the modified chunks in a 16x16x16 chunk region (see `RegionFile`), and a file containing the
+/// feature blocks waiting to be placed in chunks which haven't been loaded.
+/// Chunks are saved using their own table of block IDs stored in the metadata, so that saves stay
+/// valid when blocks are added to or removed from the `BlockRegistry`.
+/// All of the methods can be called from any thread
+#[derive(Debug)]
+pub struct WorldSave {
+    /// Directory containing the world
+    directory: PathBuf,
+    /// Contents of the metadata file
+    metadata: Mutex<WorldMetadata>,
+    /// Conversion between the IDs used in the registry and the IDs used in the save
+    block_id_map: BlockIdMap,
+    /// Locks guarding access to each region file
+    region_locks: Mutex<FxHashMap<RegionPosition, Arc<Mutex<()>>>>,
+    /// Chunks which have been queued for saving but not yet written to disk
+    pending_chunks: Mutex<FxHashMap<ChunkPosition, Arc<SavedChunk>>>,
+}
+
+impl WorldSave {
+    /// Open the world in the given directory, creating a new world with the given seed and
+    /// generator if the directory doesn't contain one
+    pub fn open_or_create(
+        directory: impl AsRef<Path>,
+        block_registry: &BlockRegistry,
+        seed: u64,
+        generator: GeneratorSettings,
+    ) -> Result<Self, SaveError> {
+        let directory = directory.as_ref().to_path_buf();
+        let metadata_path = directory.join(METADATA_FILE_NAME);
+
+        fs::create_dir_all(directory.join(REGION_DIRECTORY_NAME))
+            .map_err(|e| SaveError::IoError(directory.clone(), e))?;
+
+        let mut metadata = match fs::read_to_string(&metadata_path) {
+            Ok(source) => toml::from_str(&source)
+                .map_err(|e| SaveError::MetadataParseError(metadata_path.clone(), Box::new(e)))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => WorldMetadata {
+                format_version: WORLD_FORMAT_VERSION,
+                seed,
+                generator,
+                player_position: None,
+                tick_index: 0,
+                block_names: Vec::new(),
+            },
+            Err(e) => return Err(SaveError::IoError(metadata_path, e)),
+        };
+
+        if metadata.format_version != WORLD_FORMAT_VERSION {
+            return Err(SaveError::UnsupportedFormatVersion(metadata.format_version));
+        }
+
+        let block_id_map = BlockIdMap::new(&mut metadata.block_names, block_registry)?;
+
+        let world_save = Self {
+            directory,
+            metadata: Mutex::new(metadata),
+            block_id_map,
+            region_locks: Mutex::new(FxHashMap::default()),
+            pending_chunks: Mutex::new(FxHashMap::default()),
+        };
+
+        // write the metadata straight away so that any new block names are recorded before
+        // chunks using them are saved
+        world_save.save_metadata()?;
+
+        Ok(world_save)
+    }
+
+    /// Seed used to generate the world
+    pub fn seed(&self) -> u64 {
+        self.metadata.lock().expect("metadata mutex poisoned").seed
+    }
+
+    /// Settings for the generator chosen when the world was created
+    pub fn generator_settings(&self) -> GeneratorSettings {
+        self.metadata
+            .lock()
+            .expect("metadata mutex poisoned")
+            .generator
+            .clone()
+    }
+
+    /// Position of the player when the world was last saved
+    pub fn player_position(&self) -> Option<Vec3> {
+        self.metadata
+            .lock()
+            .expect("metadata mutex poisoned")
+            .player_position
+            .map(Vec3::from_array)
+    }
+
+    /// Update the position of the player stored in the metadata. The change is written to disk by
+    /// the next call to `save_metadata`
+    pub fn set_player_position(&self, player_position: Vec3) {
+        self.metadata
+            .lock()
+            .expect("metadata mutex poisoned")
+            .player_position = Some(player_position.to_array());
+    }
+
+    /// Number of terrain ticks run in the world when it was last saved
+    pub fn tick_index(&self) -> u64 {
+        self.metadata
+            .lock()
+            .expect("metadata mutex poisoned")
+            .tick_index
+    }
+
+    /// Update the tick index stored in the metadata. The change is written to disk by the next call
+    /// to `save_metadata`
+    pub fn set_tick_index(&self, tick_index: u64) {
+        self.metadata
+            .lock()
+            .expect("metadata mutex poisoned")
+            .tick_index = tick_index;
+    }
+
+    /// Write the world metadata to disk
+    pub fn save_metadata(&self) -> Result<(), SaveError> {
+        let metadata_path = self.directory.join(METADATA_FILE_NAME);
+        let source = toml::to_string(&*self.metadata.lock().expect("metadata mutex poisoned"))
+            .map_err(SaveError::MetadataSerializeError)?;
+
+        fs::write(&metadata_path, source).map_err(|e| SaveError::IoError(metadata_path, e))
+    }
+
+    /// Mark a chunk as waiting to be saved. Until `save_chunk` is called, `load_chunk` returns the
+    /// pending data rather than the outdated data on disk.
+    /// This should be called on the main thread before submitting a task to call `save_chunk`
+    pub fn queue_chunk_save(&self, chunk_pos: ChunkPosition, saved_chunk: Arc<SavedChunk>) {
+        self.pending_chunks
+            .lock()
+            .expect("pending chunks mutex poisoned")
+            .insert(chunk_pos, saved_chunk);
+    }
+
+    /// Write a chunk queued by `queue_chunk_save` to its region file
+    pub fn save_chunk(&self, chunk_pos: ChunkPosition) -> Result<(), SaveError> {
+        let (region_pos, index_in_region) = RegionPosition::from_chunk_pos(chunk_pos);
+        let region_lock = self.region_lock(region_pos);
+        let _guard = region_lock.lock().expect("region mutex poisoned");
+
+        // the pending data is only read once the region is locked, so that older data can never
+        // overwrite newer data
+        let Some(saved_chunk) = self
+            .pending_chunks
+            .lock()
+            .expect("pending chunks mutex poisoned")
+            .get(&chunk_pos)
+            .cloned()
+        else {
+            // already saved by an earlier task
+            return Ok(());
+        };
+
+        let data = encode_chunk(&saved_chunk, &self.block_id_map);
+
+        RegionFile::open_or_create(&self.region_path(region_pos))?
+            .write_chunk(index_in_region, &data)?;
+
+        // the chunk may have been queued again while it was being written, in which case it is
+        // still pending
+        let mut pending_chunks = self
+            .pending_chunks
+            .lock()
+            .expect("pending chunks mutex poisoned");
+        if pending_chunks
+            .get(&chunk_pos)
+            .is_some_and(|pending| Arc::ptr_eq(pending, &saved_chunk))
+        {
+            pending_chunks.remove(&chunk_pos);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the saved data for the chunk at the given position, or None if the chunk has never
+    /// been saved
+    pub fn load_chunk(&self, chunk_pos: ChunkPosition) -> Result<Option<SavedChunk>, SaveError> {
+        if let Some(saved_chunk) = self
+            .pending_chunks
+            .lock()
+            .expect("pending chunks mutex poisoned")
+            .get(&chunk_pos)
+        {
+            return Ok(Some(SavedChunk::clone(saved_chunk)));
+        }
+
+        let (region_pos, index_in_region) = RegionPosition::from_chunk_pos(chunk_pos);
+        let region_lock = self.region_lock(region_pos);
+        let _guard = region_lock.lock().expect("region mutex poisoned");
+
+        let Some(mut region_file) = RegionFile::open(&self.region_path(region_pos))? else {
+            return Ok(None);
+        };
+        let Some(data) = region_file.read_chunk(index_in_region)? else {
+            return Ok(None);
+        };
+
+        decode_chunk(&data, &self.block_id_map)
+            .map(Some)
+            .ok_or(SaveError::InvalidChunkData(chunk_pos))
+    }
+
+    /// Write the feature blocks waiting to be placed in unloaded chunks to disk, replacing any
+    /// previously saved placements
+    pub fn save_feature_placements(&self, placements: &ChunkPlacements) -> Result<(), SaveError> {
+        let path = self.directory.join(FEATURE_PLACEMENTS_FILE_NAME);
+        let data = encode_feature_placements(placements, &self.block_id_map);
+
+        fs::write(&path, data).map_err(|e| SaveError::IoError(path, e))
+    }
+
+    /// Returns the feature blocks saved by `save_feature_placements`
+    pub fn load_feature_placements(&self) -> Result<ChunkPlacements, SaveError> {
+        let path = self.directory.join(FEATURE_PLACEMENTS_FILE_NAME);
+
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(ChunkPlacements::default()),
+            Err(e) => return Err(SaveError::IoError(path, e)),
+        };
+
+        decode_feature_placements(&data, &self.block_id_map)
+            .ok_or(SaveError::InvalidFeaturePlacements(path))
+    }
+
+    /// Returns the lock for the region file at the given position
+    fn region_lock(&self, region_pos: RegionPosition) -> Arc<Mutex<()>> {
+        self.region_locks
+            .lock()
+            .expect("region locks mutex poisoned")
+            .entry(region_pos)
+            .or_default()
+            .clone()
+    }
+
+    /// Path of the region file at the given position
+    fn region_path(&self, region_pos: RegionPosition) -> PathBuf {
+        self.directory
+            .join(REGION_DIRECTORY_NAME)
+            .join(region_pos.file_name())
+    }
+}
+
+/// Data stored in a world save for each chunk
+#[derive(Clone, Debug)]
+pub struct SavedChunk {
+    pub block_store: ChunkBlockStore,
+    /// True if the features belonging to the chunk have been placed
+    pub is_decorated: bool,
+    /// Block updates requested for future ticks
+    pub scheduled_ticks: Vec<ScheduledTick>,
+    pub block_entities: FxHashMap<LocalBlockPosition, BlockEntity>,
+}
+
+/// Contents of the world metadata file
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct WorldMetadata {
+    format_version: u32,
+    #[serde(with = "seed_format")]
+    seed: u64,
+    #[serde(default)]
+    generator: GeneratorSettings,
+    player_position: Option<[f32; 3]>,
+    /// Number of terrain ticks run in the world, which scheduled ticks are relative to
+    #[serde(default)]
+    tick_index: u64,
+    /// Name of the block with each saved ID
+    block_names: Vec<String>,
+}
+
+/// TOML integers are signed, so the seed is stored as an `i64` with the same bits
+mod seed_format {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(seed: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i64(*seed as i64)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        i64::deserialize(deserializer).map(|seed| seed as u64)
+    }
+}
+
+/// Converts between the block IDs used by the `BlockRegistry` and the IDs used in a save
+#[derive(Clone, Debug)]
+pub struct BlockIdMap {
+    /// Saved ID for each registry ID
+    saved_ids: Vec<u16>,
+    /// Registry ID for each saved ID
+    registry_ids: Vec<BlockId>,
+}
+
+impl BlockIdMap {
+    /// Create the map from the names of the blocks with each saved ID, adding saved IDs for any
+    /// registered blocks without one.
+    /// Blocks in the save which are no longer registered are loaded as air
+    fn new(
+        block_names: &mut Vec<String>,
+        block_registry: &BlockRegistry,
+    ) -> Result<Self, SaveError> {
+        let mut registry_ids = block_names
+            .iter()
+            .map(|name| {
+                block_registry.get_id(name).unwrap_or_else(|| {
+                    log::warn!(
+                        "saved block `{}` is not registered; replacing with air",
+                        name
+                    );
+                    BLOCK_AIR
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut saved_ids = vec![0; block_registry.len()];
+
+        for (registry_id, block) in block_registry.iter() {
+            let saved_id = match block_names.iter().position(|name| *name == block.name) {
+                Some(saved_id) => saved_id,
+                None => {
+                    block_names.push(block.name.clone());
+                    registry_ids.push(registry_id);
+                    block_names.len() - 1
+                }
+            };
+
+            saved_ids[registry_id.as_usize()] =
+                u16::try_from(saved_id).map_err(|_| SaveError::TooManyBlocks)?;
+        }
+
+        Ok(Self {
+            saved_ids,
+            registry_ids,
+        })
+    }
+
+    /// Create the map from a table of block names sent by another process, e.g. a server. Blocks
+    /// only registered in this process are given IDs after the end of the table
+    pub fn from_block_names(
+        block_names: &[String],
+        block_registry: &BlockRegistry,
+    ) -> Result<Self, SaveError> {
+        Self::new(&mut block_names.to_vec(), block_registry)
+    }
+
+    /// Returns the saved ID for a registered block
+    pub fn saved_id(&self, block_id: BlockId) -> u16 {
+        self.saved_ids[block_id.as_usize()]
+    }
+
+    /// Returns the registry ID for a saved ID, if the saved ID is valid
+    pub fn registry_id(&self, saved_id: u16) -> Option<BlockId> {
+        self.registry_ids.get(saved_id as usize).copied()
+    }
+}
+
+/// Errors returned when reading or writing a world save
+#[derive(Debug, thiserror::Error)]
+pub enum SaveError {
+    #[error("io error accessing {0}: {1}")]
+    IoError(PathBuf, io::Error),
+    #[error("error parsing world metadata {0}: {1}")]
+    MetadataParseError(PathBuf, Box<toml::de::Error>),
+    #[error("error serializing world metadata: {0}")]
+    MetadataSerializeError(toml::ser::Error),
+    #[error("unsupported world format version {0}")]
+    UnsupportedFormatVersion(u32),
+    #[error("invalid region file {0}")]
+    InvalidRegionFile(PathBuf),
+    #[error("invalid data for chunk {0:?}")]
+    InvalidChunkData(ChunkPosition),
+    #[error("invalid feature placements file {0}")]
+    InvalidFeaturePlacements(PathBuf),
+    #[error("too many blocks in world save")]
+    TooManyBlocks,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::{
+        block::{entity::ItemStack, registry::test_registry},
+        chunk::{CHUNK_SIZE_CUBED, CHUNK_SIZE_SQUARED},
+    };
+
+    #[test]
+    fn save_and_load_chunks() {
+        let directory =
+            std::env::temp_dir().join(format!("voxels-save-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+
+        let block_registry = test_registry(&["name = \"dirt\"", "name = \"stone\""]);
+        let dirt = block_registry.get_id("dirt").unwrap();
+        let stone = block_registry.get_id("stone").unwrap();
+
+        let blocks = (0..CHUNK_SIZE_CUBED)
+            .map(|i| match i % 7 {
+                0 => stone,
+                1 | 2 => dirt,
+                _ => BLOCK_AIR,
+            })
+            .collect::<Vec<_>>();
+        let layered_pos = ChunkPosition::new(-1, 2, 17);
+        let uniform_pos = ChunkPosition::new(0, 0, 0);
+        // larger than the largest TOML integer
+        let seed = u64::MAX - 41;
+
+        {
+            let world_save = WorldSave::open_or_create(
+                &directory,
+                &block_registry,
+                seed,
+                GeneratorSettings::Void,
+            )
+            .unwrap();
+            world_save.set_player_position(Vec3::new(1.0, 2.0, 3.0));
+            world_save.set_tick_index(1000);
+            world_save.save_metadata().unwrap();
+
+            world_save.queue_chunk_save(
+                layered_pos,
+                Arc::new(SavedChunk {
+                    block_store: ChunkBlockStore::new(&blocks),
+                    is_decorated: true,
+                    scheduled_ticks: vec![ScheduledTick {
+                        pos: LocalBlockPosition::new(4, 5, 6),
+                        tick_index: 1234,
+                    }],
+                    block_entities: FxHashMap::from_iter([(
+                        LocalBlockPosition::new(7, 8, 9),
+                        BlockEntity::Container {
+                            items: vec![ItemStack {
+                                slot: 3,
+                                name: "dirt".to_string(),
+                                count: 12,
+                            }],
+                        },
+                    )]),
+                }),
+            );
+            world_save.queue_chunk_save(
+                uniform_pos,
+                Arc::new(SavedChunk {
+                    block_store: ChunkBlockStore::Uniform(stone),
+                    is_decorated: false,
+                    scheduled_ticks: Vec::new(),
+                    block_entities: FxHashMap::default(),
+                }),
+            );
+            world_save.save_chunk(layered_pos).unwrap();
+            world_save.save_chunk(uniform_pos).unwrap();
+
+            let mut placements = ChunkPlacements::default();
+            placements.insert(layered_pos, vec![(LocalBlockPosition::new(1, 2, 3), dirt)]);
+            world_save.save_feature_placements(&placements).unwrap();
+        }
+
+        // reopen the world with a new block registered, changing the registry IDs
+        let block_registry =
+            test_registry(&["name = \"dirt\"", "name = \"grass\"", "name = \"stone\""]);
+        let dirt = block_registry.get_id("dirt").unwrap();
+        let stone = block_registry.get_id("stone").unwrap();
+
+        let world_save =
+            WorldSave::open_or_create(&directory, &block_registry, 0, GeneratorSettings::default())
+                .unwrap();
+        assert_eq!(world_save.seed(), seed);
+        assert_eq!(world_save.generator_settings(), GeneratorSettings::Void);
+        assert_eq!(world_save.player_position(), Some(Vec3::new(1.0, 2.0, 3.0)));
+        assert_eq!(world_save.tick_index(), 1000);
+
+        let loaded = world_save.load_chunk(layered_pos).unwrap().unwrap();
+        assert!(loaded.is_decorated);
+        let loaded_blocks = loaded.block_store.as_block_array();
+        assert_eq!(loaded_blocks[0], stone);
+        assert_eq!(loaded_blocks[1], dirt);
+        assert_eq!(loaded_blocks[3], BLOCK_AIR);
+        assert_eq!(loaded_blocks[CHUNK_SIZE_SQUARED + 5], stone);
+        assert_eq!(
+            loaded.scheduled_ticks,
+            [ScheduledTick {
+                pos: LocalBlockPosition::new(4, 5, 6),
+                tick_index: 1234,
+            }]
+        );
+        assert_eq!(
+            loaded.block_entities[&LocalBlockPosition::new(7, 8, 9)],
+            BlockEntity::Container {
+                items: vec![ItemStack {
+                    slot: 3,
+                    name: "dirt".to_string(),
+                    count: 12,
+                }],
+            }
+        );
+
+        let loaded = world_save.load_chunk(uniform_pos).unwrap().unwrap();
+        assert!(!loaded.is_decorated);
+        assert_eq!(loaded.block_store.get_single_block(), Some(stone));
+        assert!(loaded.scheduled_ticks.is_empty());
+        assert!(loaded.block_entities.is_empty());
+
+        let placements = world_save.load_feature_placements().unwrap();
+        assert_eq!(
+            placements[&layered_pos],
+            [(LocalBlockPosition::new(1, 2, 3), dirt)]
+        );
+
+        assert!(world_save
+            .load_chunk(ChunkPosition::new(5, 5, 5))
+            .unwrap()
+            .is_none());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,237 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use glam::IVec3;
+
+use super::SaveError;
+use crate::terrain::position_types::ChunkPosition;
+
+/// Number of chunks along each axis of a region
+pub const REGION_SIZE: usize = 16;
+pub const REGION_SIZE_LOG2: usize = 4;
+pub const REGION_SIZE_I32: i32 = REGION_SIZE as i32;
+pub const REGION_CHUNK_COUNT: usize = REGION_SIZE * REGION_SIZE * REGION_SIZE;
+
+/// File extension of region files
+pub const REGION_FILE_EXTENSION: &str = "region";
+
+/// Region files are divided into sectors of this many bytes. Each chunk occupies a whole number
+/// of consecutive sectors
+const SECTOR_SIZE: u64 = 4096;
+
+/// Bytes at the start of every region file
+const MAGIC: [u8; 4] = *b"VXRG";
+
+/// Version of the region file layout
+const REGION_FORMAT_VERSION: u32 = 1;
+
+/// Size in bytes of the region file header: the magic bytes, the format version and the offset
+/// table
+const HEADER_SIZE: u64 = 8 + 8 * REGION_CHUNK_COUNT as u64;
+
+/// Number of sectors reserved for the header at the start of each region file
+const HEADER_SECTORS: u32 = HEADER_SIZE.div_ceil(SECTOR_SIZE) as u32;
+
+/// Position of a region, in units of regions
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RegionPosition(IVec3);
+
+impl RegionPosition {
+    /// Returns the position of the region containing the given chunk and the index of the chunk
+    /// within that region
+    pub fn from_chunk_pos(chunk_pos: ChunkPosition) -> (Self, usize) {
+        let chunk_pos = chunk_pos.as_ivec3();
+        let region_pos = chunk_pos >> REGION_SIZE_LOG2 as i32;
+        let pos_in_region = (chunk_pos & (REGION_SIZE_I32 - 1)).as_uvec3();
+
+        let index_in_region = ((pos_in_region.y as usize * REGION_SIZE + pos_in_region.z as usize)
+            * REGION_SIZE)
+            + pos_in_region.x as usize;
+
+        (Self(region_pos), index_in_region)
+    }
+
+    /// Name of the file storing this region
+    pub fn file_name(&self) -> String {
+        format!(
+            "r.{}.{}.{}.{}",
+            self.0.x, self.0.y, self.0.z, REGION_FILE_EXTENSION
+        )
+    }
+}
+
+/// A file storing the saved chunks in a region of 16x16x16 chunks.
+/// The file begins with a header containing an offset table with the location of each chunk in
+/// the file. The chunk data follows, aligned to sectors of `SECTOR_SIZE` bytes. When a chunk is
+/// rewritten it is stored in place if it still fits in its sectors, and appended to the end of the
+/// file otherwise
+pub struct RegionFile {
+    file: File,
+    path: PathBuf,
+    offsets: Box<[ChunkOffset]>,
+}
+
+impl RegionFile {
+    /// Open an existing region file, returning None if it does not exist
+    pub fn open(path: &Path) -> Result<Option<Self>, SaveError> {
+        match File::options().read(true).write(true).open(path) {
+            Ok(file) => Self::read_header(file, path).map(Some),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(SaveError::IoError(path.to_path_buf(), e)),
+        }
+    }
+
+    /// Open a region file, creating an empty one if it does not exist
+    pub fn open_or_create(path: &Path) -> Result<Self, SaveError> {
+        if let Some(region_file) = Self::open(path)? {
+            return Ok(region_file);
+        }
+
+        let io_error = |e| SaveError::IoError(path.to_path_buf(), e);
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .map_err(io_error)?;
+
+        let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+        header.extend_from_slice(&MAGIC);
+        header.extend_from_slice(&REGION_FORMAT_VERSION.to_le_bytes());
+        header.resize(HEADER_SIZE as usize, 0);
+
+        file.write_all(&header).map_err(io_error)?;
+
+        Ok(Self {
+            file,
+            path: path.to_path_buf(),
+            offsets: vec![ChunkOffset::EMPTY; REGION_CHUNK_COUNT].into_boxed_slice(),
+        })
+    }
+
+    /// Returns the stored data for the chunk with the given index in the region, or None if the
+    /// chunk has not been saved
+    pub fn read_chunk(&mut self, index_in_region: usize) -> Result<Option<Vec<u8>>, SaveError> {
+        let offset = self.offsets[index_in_region];
+        if offset == ChunkOffset::EMPTY {
+            return Ok(None);
+        }
+
+        let mut data = vec![0; offset.length as usize];
+
+        self.file
+            .seek(SeekFrom::Start(offset.sector as u64 * SECTOR_SIZE))
+            .and_then(|_| self.file.read_exact(&mut data))
+            .map_err(|e| SaveError::IoError(self.path.clone(), e))?;
+
+        Ok(Some(data))
+    }
+
+    /// Store the data for the chunk with the given index in the region
+    pub fn write_chunk(&mut self, index_in_region: usize, data: &[u8]) -> Result<(), SaveError> {
+        let io_error = |e| SaveError::IoError(self.path.clone(), e);
+
+        let old_offset = self.offsets[index_in_region];
+        let sector = if old_offset != ChunkOffset::EMPTY
+            && sector_count(data.len()) <= sector_count(old_offset.length as usize)
+        {
+            old_offset.sector
+        } else {
+            // append to the end of the file
+            let file_length = self.file.seek(SeekFrom::End(0)).map_err(io_error)?;
+            (file_length.div_ceil(SECTOR_SIZE) as u32).max(HEADER_SECTORS)
+        };
+
+        let new_offset = ChunkOffset {
+            sector,
+            length: data.len() as u32,
+        };
+
+        // pad the data to a whole number of sectors so that the file length stays aligned
+        let mut padded_data = data.to_vec();
+        padded_data.resize(sector_count(data.len()) as usize * SECTOR_SIZE as usize, 0);
+
+        self.file
+            .seek(SeekFrom::Start(sector as u64 * SECTOR_SIZE))
+            .and_then(|_| self.file.write_all(&padded_data))
+            .map_err(io_error)?;
+
+        // only update the offset table once the data is written, so that an interrupted write
+        // can't leave the table pointing to incomplete data
+        self.file
+            .seek(SeekFrom::Start(8 + 8 * index_in_region as u64))
+            .and_then(|_| self.file.write_all(&new_offset.to_bytes()))
+            .map_err(io_error)?;
+
+        self.offsets[index_in_region] = new_offset;
+
+        Ok(())
+    }
+
+    fn read_header(mut file: File, path: &Path) -> Result<Self, SaveError> {
+        let mut header = vec![0; HEADER_SIZE as usize];
+        file.read_exact(&mut header).map_err(|e| {
+            if e.kind() == io::ErrorKind::UnexpectedEof {
+                SaveError::InvalidRegionFile(path.to_path_buf())
+            } else {
+                SaveError::IoError(path.to_path_buf(), e)
+            }
+        })?;
+
+        let version = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        if header[0..4] != MAGIC || version != REGION_FORMAT_VERSION {
+            return Err(SaveError::InvalidRegionFile(path.to_path_buf()));
+        }
+
+        let offsets = header[8..]
+            .chunks_exact(8)
+            .map(ChunkOffset::from_bytes)
+            .collect();
+
+        Ok(Self {
+            file,
+            path: path.to_path_buf(),
+            offsets,
+        })
+    }
+}
+
+/// Entry in the offset table of a region file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct ChunkOffset {
+    /// Index of the first sector containing the chunk data
+    sector: u32,
+    /// Length of the chunk data in bytes
+    length: u32,
+}
+
+impl ChunkOffset {
+    /// Offset of a chunk which has not been saved
+    const EMPTY: Self = Self {
+        sector: 0,
+        length: 0,
+    };
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        Self {
+            sector: u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+            length: u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+        }
+    }
+
+    fn to_bytes(self) -> [u8; 8] {
+        let mut bytes = [0; 8];
+        bytes[0..4].copy_from_slice(&self.sector.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.length.to_le_bytes());
+        bytes
+    }
+}
+
+/// Returns the number of sectors needed to store the given number of bytes
+fn sector_count(length: usize) -> u32 {
+    (length as u64).div_ceil(SECTOR_SIZE).max(1) as u32
+}
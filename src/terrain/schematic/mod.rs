@@ -0,0 +1,645 @@
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use glam::{IVec3, UVec3};
+use itertools::Itertools;
+use rustc_hash::FxHashMap;
+
+use self::nbt::{read_nbt, write_nbt, NbtCompound, NbtError, NbtTag};
+use super::{
+    block::{
+        registry::{BlockRegistry, AIR_NAME},
+        BlockId, BLOCK_AIR,
+    },
+    edit::Clipboard,
+    position_types::GlobalBlockPosition,
+    Terrain,
+};
+use crate::util::size::Size3;
+
+pub mod nbt;
+
+/// Namespace of blocks without an entry in the `NamespacedIds` table
+pub const DEFAULT_NAMESPACE: &str = "voxels";
+
+/// Namespaced ID of air, which is built in rather than listed in the table
+const AIR_NAMESPACED_ID: &str = "minecraft:air";
+
+/// Minecraft data version written to schematics (Minecraft 1.20.1). Other tools use it to decide
+/// how to upgrade the block IDs, which we don't need to do
+const DATA_VERSION: i32 = 3465;
+
+/// Version of the Sponge schematic format to write
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchematicVersion {
+    V2,
+    V3,
+}
+
+/// Table of the namespaced IDs, such as `minecraft:stone`, used for blocks in schematics, loaded
+/// from a TOML file mapping block names to namespaced IDs.
+/// Blocks which aren't listed use the ID `voxels:<name>`
+#[derive(Clone, Debug, Default)]
+pub struct NamespacedIds {
+    /// Namespaced ID for each block name
+    namespaced_ids: FxHashMap<String, String>,
+    /// Block name for each namespaced ID
+    block_names: FxHashMap<String, String>,
+}
+
+impl NamespacedIds {
+    /// Load the table from a TOML file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SchematicError> {
+        let path = path.as_ref();
+        let source =
+            fs::read_to_string(path).map_err(|e| SchematicError::IoError(path.to_path_buf(), e))?;
+
+        Self::parse(&source)
+            .map_err(|e| SchematicError::IdTableParseError(path.to_path_buf(), Box::new(e)))
+    }
+
+    /// Parse the table from the source of a TOML file
+    pub fn parse(source: &str) -> Result<Self, toml::de::Error> {
+        let namespaced_ids: FxHashMap<String, String> = toml::from_str(source)?;
+        let block_names = namespaced_ids
+            .iter()
+            .map(|(block_name, namespaced_id)| (namespaced_id.clone(), block_name.clone()))
+            .collect();
+
+        Ok(Self {
+            namespaced_ids,
+            block_names,
+        })
+    }
+
+    /// Returns the namespaced ID of the block type with the given name
+    pub fn namespaced_id(&self, block_name: &str) -> String {
+        if block_name == AIR_NAME {
+            return AIR_NAMESPACED_ID.to_string();
+        }
+
+        self.namespaced_ids
+            .get(block_name)
+            .cloned()
+            .unwrap_or_else(|| format!("{}:{}", DEFAULT_NAMESPACE, block_name))
+    }
+
+    /// Returns the name of the block type with the given namespaced ID, if there is one
+    pub fn block_name<'a>(&'a self, namespaced_id: &'a str) -> Option<&'a str> {
+        if namespaced_id == AIR_NAMESPACED_ID {
+            return Some(AIR_NAME);
+        }
+
+        self.block_names
+            .get(namespaced_id)
+            .map(String::as_str)
+            .or_else(|| {
+                namespaced_id
+                    .strip_prefix(DEFAULT_NAMESPACE)
+                    .and_then(|rest| rest.strip_prefix(':'))
+            })
+    }
+
+    /// Returns the schematic palette entry for a block: the namespaced ID of the block type
+    /// followed by the values of all of its properties, e.g.
+    /// `minecraft:stone_brick_stairs[facing=neg_x,half=top]`
+    pub fn block_state_string(&self, block_id: BlockId, block_registry: &BlockRegistry) -> String {
+        let block = &block_registry[block_id];
+        let namespaced_id = self.namespaced_id(&block_registry[block.default_state].name);
+
+        if block.properties.is_empty() {
+            return namespaced_id;
+        }
+
+        let values = block
+            .properties
+            .iter()
+            .map(|&property| {
+                format!(
+                    "{}={}",
+                    property.name(),
+                    property.value_names()[block.state.value(property)]
+                )
+            })
+            .join(",");
+
+        format!("{}[{}]", namespaced_id, values)
+    }
+
+    /// Returns the block for a schematic palette entry written by `block_state_string`, or None if
+    /// the block type isn't registered or a property has an unknown value. Properties which the
+    /// block type doesn't have are ignored
+    pub fn parse_block_state(
+        &self,
+        block_state_string: &str,
+        block_registry: &BlockRegistry,
+    ) -> Option<BlockId> {
+        let (namespaced_id, values) = match block_state_string.split_once('[') {
+            Some((namespaced_id, rest)) => (namespaced_id, rest.strip_suffix(']')?),
+            None => (block_state_string, ""),
+        };
+
+        let block_id = block_registry.get_id(self.block_name(namespaced_id)?)?;
+        let block = &block_registry[block_id];
+        let mut state = block.state;
+
+        for pair in values.split(',').filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=')?;
+
+            let Some(&property) = block
+                .properties
+                .iter()
+                .find(|property| property.name() == name)
+            else {
+                continue;
+            };
+            let value_index = property
+                .value_names()
+                .iter()
+                .position(|&value_name| value_name == value)?;
+
+            state = state.with_value(property, value_index);
+        }
+
+        Some(block_registry.with_state(block_id, &state))
+    }
+}
+
+/// A box of blocks stored in the Sponge schematic format, used to share builds with other tools.
+/// Schematics are gzipped NBT documents. Blocks are identified by entries in a palette of block
+/// states (see `NamespacedIds::block_state_string`), so they can be read without our
+/// `BlockRegistry`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Schematic {
+    pub size: Size3,
+    /// Position of the schematic's origin relative to its minimum corner. It is written and read
+    /// for other tools but not used when placing schematics
+    pub offset: IVec3,
+    /// Block states used in the schematic
+    pub palette: Vec<String>,
+    /// Palette index of each block, ordered by y, then z, then x
+    pub blocks: Vec<u32>,
+}
+
+/// Result of placing a schematic in the terrain
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SchematicImport {
+    /// Number of blocks changed in the terrain
+    pub blocks_changed: usize,
+    /// Palette entries which didn't match a registered block, which are placed as air
+    pub unknown_blocks: Vec<String>,
+}
+
+impl Schematic {
+    /// Create a schematic containing the blocks in a clipboard
+    pub fn from_clipboard(
+        clipboard: &Clipboard,
+        block_registry: &BlockRegistry,
+        namespaced_ids: &NamespacedIds,
+    ) -> Self {
+        let size = clipboard.size();
+        let mut palette = Vec::new();
+        let mut palette_indices: FxHashMap<BlockId, u32> = FxHashMap::default();
+        let mut blocks = Vec::with_capacity(size.product());
+
+        for (y, z, x) in itertools::iproduct!(0..size.y, 0..size.z, 0..size.x) {
+            let block_id = clipboard.get(UVec3::new(x as u32, y as u32, z as u32));
+
+            let palette_index = *palette_indices.entry(block_id).or_insert_with(|| {
+                palette.push(namespaced_ids.block_state_string(block_id, block_registry));
+                palette.len() as u32 - 1
+            });
+            blocks.push(palette_index);
+        }
+
+        Self {
+            size,
+            offset: IVec3::ZERO,
+            palette,
+            blocks,
+        }
+    }
+
+    /// Convert the schematic to a clipboard of registered blocks, returning the palette entries
+    /// which didn't match a registered block. Unknown blocks are replaced with air
+    pub fn to_clipboard(
+        &self,
+        block_registry: &BlockRegistry,
+        namespaced_ids: &NamespacedIds,
+    ) -> (Clipboard, Vec<String>) {
+        let mut unknown_blocks = Vec::new();
+        let palette_ids = self
+            .palette
+            .iter()
+            .map(|block_state_string| {
+                namespaced_ids
+                    .parse_block_state(block_state_string, block_registry)
+                    .unwrap_or_else(|| {
+                        unknown_blocks.push(block_state_string.clone());
+                        BLOCK_AIR
+                    })
+            })
+            .collect_vec();
+
+        // clipboards are ordered by z, then y, then x
+        let mut clipboard_blocks = vec![BLOCK_AIR; self.size.product()];
+        for (index, &palette_index) in self.blocks.iter().enumerate() {
+            let x = index % self.size.x;
+            let z = (index / self.size.x) % self.size.z;
+            let y = index / (self.size.x * self.size.z);
+
+            clipboard_blocks[self.size.flatten(UVec3::new(x as u32, y as u32, z as u32))] =
+                palette_ids[palette_index as usize];
+        }
+
+        (Clipboard::new(self.size, clipboard_blocks), unknown_blocks)
+    }
+
+    /// Copy the blocks in the box between the given corners, which are both inside the box, into
+    /// a schematic. Returns None if any part of the box isn't loaded
+    pub fn copy_from_terrain(
+        terrain: &Terrain,
+        a: GlobalBlockPosition,
+        b: GlobalBlockPosition,
+        namespaced_ids: &NamespacedIds,
+    ) -> Option<Self> {
+        let clipboard = terrain.copy_region(a, b)?;
+
+        Some(Self::from_clipboard(
+            &clipboard,
+            terrain.block_registry(),
+            namespaced_ids,
+        ))
+    }
+
+    /// Place the schematic in the terrain with its minimum corner at `origin`, using
+    /// `Terrain::paste`. Blocks outside of the loaded chunks are skipped
+    pub fn place_in_terrain(
+        &self,
+        terrain: &mut Terrain,
+        origin: GlobalBlockPosition,
+        namespaced_ids: &NamespacedIds,
+    ) -> SchematicImport {
+        let (clipboard, unknown_blocks) =
+            self.to_clipboard(terrain.block_registry(), namespaced_ids);
+
+        if !unknown_blocks.is_empty() {
+            log::warn!(
+                "schematic contains unknown blocks, which are placed as air: {}",
+                unknown_blocks.join(", ")
+            );
+        }
+
+        SchematicImport {
+            blocks_changed: terrain.paste(&clipboard, origin, true),
+            unknown_blocks,
+        }
+    }
+
+    /// Read a schematic from a file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SchematicError> {
+        let path = path.as_ref();
+        let data = fs::read(path).map_err(|e| SchematicError::IoError(path.to_path_buf(), e))?;
+
+        Self::read(&data)
+    }
+
+    /// Write the schematic to a file
+    pub fn save(
+        &self,
+        path: impl AsRef<Path>,
+        version: SchematicVersion,
+    ) -> Result<(), SchematicError> {
+        let path = path.as_ref();
+        let data = self.write(version)?;
+
+        fs::write(path, data).map_err(|e| SchematicError::IoError(path.to_path_buf(), e))
+    }
+
+    /// Read a schematic in any version of the Sponge format from gzipped NBT
+    pub fn read(compressed_data: &[u8]) -> Result<Self, SchematicError> {
+        let mut data = Vec::new();
+        GzDecoder::new(compressed_data)
+            .read_to_end(&mut data)
+            .map_err(SchematicError::DecompressError)?;
+
+        let (_, root) = read_nbt(&data)?;
+
+        // version 3 wraps the schematic in an unnamed root compound
+        let schematic = match root.get("Schematic").and_then(NbtTag::as_compound) {
+            Some(schematic) => schematic,
+            None => &root,
+        };
+
+        let version = get_i32(schematic, "Version")?;
+        let (palette, block_data) = match version {
+            1 | 2 => (schematic.get("Palette"), schematic.get("BlockData")),
+            3 => {
+                let blocks = schematic
+                    .get("Blocks")
+                    .and_then(NbtTag::as_compound)
+                    .ok_or(SchematicError::InvalidField("Blocks"))?;
+
+                (blocks.get("Palette"), blocks.get("Data"))
+            }
+            _ => return Err(SchematicError::UnsupportedVersion(version)),
+        };
+
+        // sizes are stored as shorts but treated as unsigned
+        let get_size = |name| get_i32(schematic, name).map(|size| size as u16 as usize);
+        let size = Size3::new(get_size("Width")?, get_size("Height")?, get_size("Length")?);
+
+        let offset = match schematic.get("Offset") {
+            Some(NbtTag::IntArray(offset)) if offset.len() == 3 => {
+                IVec3::new(offset[0], offset[1], offset[2])
+            }
+            Some(_) => return Err(SchematicError::InvalidField("Offset")),
+            None => IVec3::ZERO,
+        };
+
+        let palette = read_palette(palette)?;
+
+        let Some(NbtTag::ByteArray(block_data)) = block_data else {
+            return Err(SchematicError::InvalidField("BlockData"));
+        };
+        let blocks = read_varints(block_data).ok_or(SchematicError::InvalidField("BlockData"))?;
+        if blocks.len() != size.product()
+            || blocks
+                .iter()
+                .any(|&palette_index| palette_index as usize >= palette.len())
+        {
+            return Err(SchematicError::InvalidField("BlockData"));
+        }
+
+        Ok(Self {
+            size,
+            offset,
+            palette,
+            blocks,
+        })
+    }
+
+    /// Write the schematic in the given version of the Sponge format as gzipped NBT
+    pub fn write(&self, version: SchematicVersion) -> Result<Vec<u8>, SchematicError> {
+        let to_short = |size: usize| {
+            u16::try_from(size)
+                .map(|size| NbtTag::Short(size as i16))
+                .map_err(|_| SchematicError::TooLarge(self.size))
+        };
+
+        let palette = NbtTag::Compound(self.palette.iter().enumerate().fold(
+            NbtCompound::new(),
+            |palette, (palette_index, block_state_string)| {
+                palette.with(block_state_string, NbtTag::Int(palette_index as i32))
+            },
+        ));
+        let block_data = NbtTag::ByteArray(write_varints(&self.blocks));
+
+        let schematic = NbtCompound::new()
+            .with(
+                "Version",
+                NbtTag::Int(match version {
+                    SchematicVersion::V2 => 2,
+                    SchematicVersion::V3 => 3,
+                }),
+            )
+            .with("DataVersion", NbtTag::Int(DATA_VERSION))
+            .with("Width", to_short(self.size.x)?)
+            .with("Height", to_short(self.size.y)?)
+            .with("Length", to_short(self.size.z)?)
+            .with(
+                "Offset",
+                NbtTag::IntArray(vec![self.offset.x, self.offset.y, self.offset.z]),
+            );
+
+        let data = match version {
+            SchematicVersion::V2 => write_nbt(
+                "Schematic",
+                &schematic
+                    .with("PaletteMax", NbtTag::Int(self.palette.len() as i32))
+                    .with("Palette", palette)
+                    .with("BlockData", block_data),
+            ),
+            SchematicVersion::V3 => {
+                let blocks = NbtCompound::new()
+                    .with("Palette", palette)
+                    .with("Data", block_data);
+
+                write_nbt(
+                    "",
+                    &NbtCompound::new().with(
+                        "Schematic",
+                        NbtTag::Compound(schematic.with("Blocks", NbtTag::Compound(blocks))),
+                    ),
+                )
+            }
+        };
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(&data)
+            .expect("writing to a Vec should not fail");
+        Ok(encoder.finish().expect("writing to a Vec should not fail"))
+    }
+}
+
+/// Returns the integer tag with the given name
+fn get_i32(compound: &NbtCompound, name: &'static str) -> Result<i32, SchematicError> {
+    compound
+        .get(name)
+        .and_then(NbtTag::as_i32)
+        .ok_or(SchematicError::InvalidField(name))
+}
+
+/// Read a palette compound mapping block state strings to palette indices. Unused indices are
+/// filled with air
+fn read_palette(palette: Option<&NbtTag>) -> Result<Vec<String>, SchematicError> {
+    let palette = palette
+        .and_then(NbtTag::as_compound)
+        .ok_or(SchematicError::InvalidField("Palette"))?;
+
+    let mut entries = Vec::new();
+    for (block_state_string, palette_index) in palette.iter() {
+        let palette_index = palette_index
+            .as_i32()
+            .and_then(|palette_index| u16::try_from(palette_index).ok())
+            .ok_or(SchematicError::InvalidField("Palette"))?;
+
+        entries.push((palette_index as usize, block_state_string.to_string()));
+    }
+
+    let palette_len = entries
+        .iter()
+        .map(|(index, _)| index + 1)
+        .max()
+        .unwrap_or(0);
+    let mut palette = vec![AIR_NAMESPACED_ID.to_string(); palette_len];
+    for (palette_index, block_state_string) in entries {
+        palette[palette_index] = block_state_string;
+    }
+
+    Ok(palette)
+}
+
+/// Decode a sequence of unsigned LEB128 varints, as used for schematic block data
+fn read_varints(data: &[u8]) -> Option<Vec<u32>> {
+    let mut values = Vec::new();
+    let mut value = 0u32;
+    let mut shift = 0;
+
+    for &byte in data {
+        if shift >= 32 {
+            return None;
+        }
+
+        value |= ((byte & 0x7f) as u32) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            values.push(value);
+            value = 0;
+            shift = 0;
+        }
+    }
+
+    // the data shouldn't end in the middle of a value
+    (shift == 0).then_some(values)
+}
+
+/// Encode values as a sequence of unsigned LEB128 varints
+fn write_varints(values: &[u32]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(values.len());
+
+    for &value in values {
+        let mut value = value;
+        while value >= 0x80 {
+            data.push((value & 0x7f) as u8 | 0x80);
+            value >>= 7;
+        }
+        data.push(value as u8);
+    }
+
+    data
+}
+
+/// Errors returned when reading or writing a schematic
+#[derive(Debug, thiserror::Error)]
+pub enum SchematicError {
+    #[error("io error accessing {0}: {1}")]
+    IoError(PathBuf, io::Error),
+    #[error("error parsing namespaced ID table {0}: {1}")]
+    IdTableParseError(PathBuf, Box<toml::de::Error>),
+    #[error("error decompressing schematic: {0}")]
+    DecompressError(io::Error),
+    #[error("invalid NBT: {0}")]
+    NbtError(#[from] NbtError),
+    #[error("unsupported schematic version {0}")]
+    UnsupportedVersion(i32),
+    #[error("missing or invalid field `{0}`")]
+    InvalidField(&'static str),
+    #[error("schematic of size {0:?} is too large")]
+    TooLarge(Size3),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::block::{model::SlabHalf, registry::test_registry, state::BlockState};
+
+    fn test_block_registry() -> BlockRegistry {
+        test_registry(&[
+            r#"
+                name = "stone"
+                model = { type = "full_block", textures = { all = "stone" } }
+            "#,
+            r#"
+                name = "stairs"
+                properties = ["facing", "half"]
+                model = { type = "stairs", facing = "neg_x", textures = { all = "stone" } }
+            "#,
+        ])
+    }
+
+    #[test]
+    fn varints() {
+        let values = [0, 1, 127, 128, 300, u32::MAX];
+        assert_eq!(read_varints(&write_varints(&values)), Some(values.to_vec()));
+        assert_eq!(read_varints(&[0x80]), None);
+    }
+
+    #[test]
+    fn round_trip() {
+        let block_registry = test_block_registry();
+        let namespaced_ids = NamespacedIds::parse("stone = \"minecraft:stone\"").unwrap();
+
+        let stone = block_registry.get_id("stone").unwrap();
+        let stairs = block_registry.with_state(
+            block_registry.get_id("stairs").unwrap(),
+            &BlockState {
+                half: SlabHalf::Top,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            namespaced_ids.block_state_string(stairs, &block_registry),
+            "voxels:stairs[facing=pos_x,half=top]"
+        );
+
+        let size = Size3::new(3, 2, 4);
+        let blocks = (0..size.product())
+            .map(|index| [BLOCK_AIR, stone, stairs][index % 3])
+            .collect_vec();
+        let clipboard = Clipboard::new(size, blocks);
+
+        let schematic = Schematic::from_clipboard(&clipboard, &block_registry, &namespaced_ids);
+        assert!(schematic.palette.contains(&"minecraft:stone".to_string()));
+
+        for version in [SchematicVersion::V2, SchematicVersion::V3] {
+            let data = schematic.write(version).unwrap();
+            let read_schematic = Schematic::read(&data).unwrap();
+            assert_eq!(read_schematic, schematic);
+
+            let (read_clipboard, unknown_blocks) =
+                read_schematic.to_clipboard(&block_registry, &namespaced_ids);
+            assert_eq!(read_clipboard, clipboard);
+            assert!(unknown_blocks.is_empty());
+        }
+    }
+
+    #[test]
+    fn unknown_blocks() {
+        let block_registry = test_block_registry();
+        let namespaced_ids = NamespacedIds::default();
+
+        let schematic = Schematic {
+            size: Size3::new(2, 1, 1),
+            offset: IVec3::ZERO,
+            palette: vec![
+                "minecraft:diamond_block".to_string(),
+                "voxels:stairs[facing=up,waterlogged=false]".to_string(),
+                "voxels:stone[waterlogged=false]".to_string(),
+            ],
+            blocks: vec![0, 2],
+        };
+        let (clipboard, unknown_blocks) = schematic.to_clipboard(&block_registry, &namespaced_ids);
+
+        assert_eq!(
+            unknown_blocks,
+            vec![
+                "minecraft:diamond_block",
+                "voxels:stairs[facing=up,waterlogged=false]"
+            ]
+        );
+        assert_eq!(clipboard.get(UVec3::new(0, 0, 0)), BLOCK_AIR);
+        assert_eq!(
+            clipboard.get(UVec3::new(1, 0, 0)),
+            block_registry.get_id("stone").unwrap()
+        );
+
+        assert!(Schematic::read(&[1, 2, 3]).is_err());
+    }
+}
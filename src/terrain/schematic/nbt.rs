@@ -0,0 +1,353 @@
+/// Maximum depth of nested lists and compounds accepted when reading, so that malicious data
+/// can't overflow the stack
+const MAX_DEPTH: usize = 512;
+
+const TAG_END: u8 = 0;
+const TAG_BYTE: u8 = 1;
+const TAG_SHORT: u8 = 2;
+const TAG_INT: u8 = 3;
+const TAG_LONG: u8 = 4;
+const TAG_FLOAT: u8 = 5;
+const TAG_DOUBLE: u8 = 6;
+const TAG_BYTE_ARRAY: u8 = 7;
+const TAG_STRING: u8 = 8;
+const TAG_LIST: u8 = 9;
+const TAG_COMPOUND: u8 = 10;
+const TAG_INT_ARRAY: u8 = 11;
+const TAG_LONG_ARRAY: u8 = 12;
+
+/// A value in an NBT document
+#[derive(Clone, Debug, PartialEq)]
+pub enum NbtTag {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<u8>),
+    String(String),
+    /// Every element of a list has the same type
+    List(Vec<NbtTag>),
+    Compound(NbtCompound),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+impl NbtTag {
+    pub fn as_i32(&self) -> Option<i32> {
+        match *self {
+            Self::Byte(value) => Some(value as i32),
+            Self::Short(value) => Some(value as i32),
+            Self::Int(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_compound(&self) -> Option<&NbtCompound> {
+        match self {
+            Self::Compound(compound) => Some(compound),
+            _ => None,
+        }
+    }
+
+    fn tag_type(&self) -> u8 {
+        match self {
+            Self::Byte(_) => TAG_BYTE,
+            Self::Short(_) => TAG_SHORT,
+            Self::Int(_) => TAG_INT,
+            Self::Long(_) => TAG_LONG,
+            Self::Float(_) => TAG_FLOAT,
+            Self::Double(_) => TAG_DOUBLE,
+            Self::ByteArray(_) => TAG_BYTE_ARRAY,
+            Self::String(_) => TAG_STRING,
+            Self::List(_) => TAG_LIST,
+            Self::Compound(_) => TAG_COMPOUND,
+            Self::IntArray(_) => TAG_INT_ARRAY,
+            Self::LongArray(_) => TAG_LONG_ARRAY,
+        }
+    }
+}
+
+/// Named tags, kept in the order they were inserted or read
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NbtCompound {
+    entries: Vec<(String, NbtTag)>,
+}
+
+impl NbtCompound {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the tag with the given name, if there is one
+    pub fn get(&self, name: &str) -> Option<&NbtTag> {
+        self.entries
+            .iter()
+            .find(|(entry_name, _)| entry_name == name)
+            .map(|(_, tag)| tag)
+    }
+
+    /// Set the tag with the given name, replacing any existing tag with that name
+    pub fn insert(&mut self, name: impl Into<String>, tag: NbtTag) {
+        let name = name.into();
+
+        match self
+            .entries
+            .iter_mut()
+            .find(|(entry_name, _)| *entry_name == name)
+        {
+            Some((_, existing_tag)) => *existing_tag = tag,
+            None => self.entries.push((name, tag)),
+        }
+    }
+
+    /// Returns a copy of the compound with the given tag set, for building compounds
+    pub fn with(mut self, name: impl Into<String>, tag: NbtTag) -> Self {
+        self.insert(name, tag);
+        self
+    }
+
+    /// Iterator over the names and values of the tags
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &NbtTag)> {
+        self.entries.iter().map(|(name, tag)| (name.as_str(), tag))
+    }
+}
+
+/// Read an uncompressed NBT document, the binary tag format used by schematics, returning the
+/// name and contents of the root compound
+pub fn read_nbt(data: &[u8]) -> Result<(String, NbtCompound), NbtError> {
+    let mut reader = NbtReader(data);
+
+    if reader.read_u8()? != TAG_COMPOUND {
+        return Err(NbtError::RootNotCompound);
+    }
+
+    let name = reader.read_string()?;
+    match reader.read_payload(TAG_COMPOUND, 0)? {
+        NbtTag::Compound(compound) => Ok((name, compound)),
+        _ => unreachable!(),
+    }
+}
+
+/// Write an NBT document with the given root compound
+pub fn write_nbt(name: &str, root: &NbtCompound) -> Vec<u8> {
+    let mut data = vec![TAG_COMPOUND];
+    write_string(&mut data, name);
+    write_compound(&mut data, root);
+
+    data
+}
+
+fn write_string(data: &mut Vec<u8>, value: &str) {
+    data.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    data.extend_from_slice(value.as_bytes());
+}
+
+fn write_compound(data: &mut Vec<u8>, compound: &NbtCompound) {
+    for (name, tag) in &compound.entries {
+        data.push(tag.tag_type());
+        write_string(data, name);
+        write_payload(data, tag);
+    }
+    data.push(TAG_END);
+}
+
+fn write_payload(data: &mut Vec<u8>, tag: &NbtTag) {
+    match tag {
+        NbtTag::Byte(value) => data.push(*value as u8),
+        NbtTag::Short(value) => data.extend_from_slice(&value.to_be_bytes()),
+        NbtTag::Int(value) => data.extend_from_slice(&value.to_be_bytes()),
+        NbtTag::Long(value) => data.extend_from_slice(&value.to_be_bytes()),
+        NbtTag::Float(value) => data.extend_from_slice(&value.to_be_bytes()),
+        NbtTag::Double(value) => data.extend_from_slice(&value.to_be_bytes()),
+        NbtTag::ByteArray(values) => {
+            data.extend_from_slice(&(values.len() as i32).to_be_bytes());
+            data.extend_from_slice(values);
+        }
+        NbtTag::String(value) => write_string(data, value),
+        NbtTag::List(elements) => {
+            data.push(elements.first().map_or(TAG_END, NbtTag::tag_type));
+            data.extend_from_slice(&(elements.len() as i32).to_be_bytes());
+            for element in elements {
+                write_payload(data, element);
+            }
+        }
+        NbtTag::Compound(compound) => write_compound(data, compound),
+        NbtTag::IntArray(values) => {
+            data.extend_from_slice(&(values.len() as i32).to_be_bytes());
+            for value in values {
+                data.extend_from_slice(&value.to_be_bytes());
+            }
+        }
+        NbtTag::LongArray(values) => {
+            data.extend_from_slice(&(values.len() as i32).to_be_bytes());
+            for value in values {
+                data.extend_from_slice(&value.to_be_bytes());
+            }
+        }
+    }
+}
+
+/// Reads big-endian NBT values from a byte slice
+struct NbtReader<'a>(&'a [u8]);
+
+impl NbtReader<'_> {
+    fn read_slice(&mut self, len: usize) -> Result<&[u8], NbtError> {
+        if self.0.len() < len {
+            return Err(NbtError::UnexpectedEnd);
+        }
+
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+
+        Ok(bytes)
+    }
+
+    fn read_bytes<const N: usize>(&mut self) -> Result<[u8; N], NbtError> {
+        self.read_slice(N).map(|bytes| bytes.try_into().unwrap())
+    }
+
+    fn read_u8(&mut self) -> Result<u8, NbtError> {
+        self.read_bytes().map(u8::from_be_bytes)
+    }
+
+    /// Read the length of an array or list, which is stored as a signed int
+    fn read_len(&mut self) -> Result<usize, NbtError> {
+        let len = i32::from_be_bytes(self.read_bytes()?);
+        usize::try_from(len).map_err(|_| NbtError::InvalidLength(len))
+    }
+
+    fn read_string(&mut self) -> Result<String, NbtError> {
+        let len = u16::from_be_bytes(self.read_bytes()?) as usize;
+
+        String::from_utf8(self.read_slice(len)?.to_vec()).map_err(|_| NbtError::InvalidString)
+    }
+
+    fn read_payload(&mut self, tag_type: u8, depth: usize) -> Result<NbtTag, NbtError> {
+        if depth > MAX_DEPTH {
+            return Err(NbtError::TooDeep);
+        }
+
+        Ok(match tag_type {
+            TAG_BYTE => NbtTag::Byte(i8::from_be_bytes(self.read_bytes()?)),
+            TAG_SHORT => NbtTag::Short(i16::from_be_bytes(self.read_bytes()?)),
+            TAG_INT => NbtTag::Int(i32::from_be_bytes(self.read_bytes()?)),
+            TAG_LONG => NbtTag::Long(i64::from_be_bytes(self.read_bytes()?)),
+            TAG_FLOAT => NbtTag::Float(f32::from_be_bytes(self.read_bytes()?)),
+            TAG_DOUBLE => NbtTag::Double(f64::from_be_bytes(self.read_bytes()?)),
+            TAG_BYTE_ARRAY => {
+                let len = self.read_len()?;
+                NbtTag::ByteArray(self.read_slice(len)?.to_vec())
+            }
+            TAG_STRING => NbtTag::String(self.read_string()?),
+            TAG_LIST => {
+                let element_type = self.read_u8()?;
+                let len = self.read_len()?;
+
+                // every element takes at least one byte, so a list longer than the remaining data
+                // is invalid
+                if element_type != TAG_END && len > self.0.len() {
+                    return Err(NbtError::UnexpectedEnd);
+                }
+
+                let elements = (0..len)
+                    .map(|_| self.read_payload(element_type, depth + 1))
+                    .collect::<Result<Vec<_>, _>>()?;
+                NbtTag::List(elements)
+            }
+            TAG_COMPOUND => {
+                let mut compound = NbtCompound::new();
+
+                loop {
+                    let tag_type = self.read_u8()?;
+                    if tag_type == TAG_END {
+                        break;
+                    }
+
+                    let name = self.read_string()?;
+                    let tag = self.read_payload(tag_type, depth + 1)?;
+                    compound.entries.push((name, tag));
+                }
+
+                NbtTag::Compound(compound)
+            }
+            TAG_INT_ARRAY => {
+                let len = self.read_len()?;
+                let bytes = self.read_slice(len.checked_mul(4).ok_or(NbtError::UnexpectedEnd)?)?;
+
+                NbtTag::IntArray(
+                    bytes
+                        .chunks_exact(4)
+                        .map(|bytes| i32::from_be_bytes(bytes.try_into().unwrap()))
+                        .collect(),
+                )
+            }
+            TAG_LONG_ARRAY => {
+                let len = self.read_len()?;
+                let bytes = self.read_slice(len.checked_mul(8).ok_or(NbtError::UnexpectedEnd)?)?;
+
+                NbtTag::LongArray(
+                    bytes
+                        .chunks_exact(8)
+                        .map(|bytes| i64::from_be_bytes(bytes.try_into().unwrap()))
+                        .collect(),
+                )
+            }
+            _ => return Err(NbtError::InvalidTagType(tag_type)),
+        })
+    }
+}
+
+/// Errors returned when reading an NBT document
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum NbtError {
+    #[error("unexpected end of data")]
+    UnexpectedEnd,
+    #[error("invalid tag type {0}")]
+    InvalidTagType(u8),
+    #[error("invalid length {0}")]
+    InvalidLength(i32),
+    #[error("string is not valid UTF-8")]
+    InvalidString,
+    #[error("root tag is not a compound")]
+    RootNotCompound,
+    #[error("tags are nested too deeply")]
+    TooDeep,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let root = NbtCompound::new()
+            .with("byte", NbtTag::Byte(-3))
+            .with("short", NbtTag::Short(300))
+            .with("long", NbtTag::Long(-1 << 40))
+            .with("double", NbtTag::Double(0.25))
+            .with("bytes", NbtTag::ByteArray(vec![1, 2, 255]))
+            .with(
+                "list",
+                NbtTag::List(vec![
+                    NbtTag::String("a".to_string()),
+                    NbtTag::String("ü".to_string()),
+                ]),
+            )
+            .with("empty_list", NbtTag::List(Vec::new()))
+            .with(
+                "nested",
+                NbtTag::Compound(NbtCompound::new().with("ints", NbtTag::IntArray(vec![1, -2]))),
+            )
+            .with("longs", NbtTag::LongArray(vec![i64::MAX]));
+
+        let data = write_nbt("root", &root);
+        assert_eq!(read_nbt(&data), Ok(("root".to_string(), root)));
+
+        // truncated data is reported rather than panicking
+        for len in 0..data.len() {
+            assert!(read_nbt(&data[..len]).is_err());
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use std::{fmt::Debug, sync::Arc};
+
+use glam::IVec3;
+use rand::Rng;
+
+use super::{
+    block::{registry::BlockRegistry, BlockId},
+    lighting::skylight::Skylight,
+    position_types::{GlobalBlockPosition, LocalBlockPosition},
+    Terrain,
+};
+
+/// Number of random positions in each loaded chunk that receive a random tick on every tick
+pub const RANDOM_TICKS_PER_CHUNK: usize = 3;
+
+/// A block update requested for a future tick, stored with the chunk containing the block so that
+/// it is saved and loaded along with the chunk
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ScheduledTick {
+    pub pos: LocalBlockPosition,
+    /// Index of the tick on which the update runs
+    pub tick_index: u64,
+}
+
+/// Behaviour of a kind of block over time.
+/// Scheduled ticks run when a tick requested with `Terrain::schedule_tick` is due, and random
+/// ticks run when the block is chosen at random by the terrain, which happens on average once
+/// every `CHUNK_SIZE_CUBED / RANDOM_TICKS_PER_CHUNK` ticks for each block
+pub trait BlockBehaviour: Debug + Send + Sync {
+    /// Called when a tick scheduled at the position of a block of this type is due
+    fn scheduled_tick(&self, _terrain: &mut Terrain, _pos: GlobalBlockPosition) {}
+
+    /// Called when a block of this type is chosen to receive a random tick
+    fn random_tick(&self, _terrain: &mut Terrain, _pos: GlobalBlockPosition) {}
+}
+
+/// The behaviour registered for each block type, indexed by block ID
+#[derive(Clone, Debug, Default)]
+pub struct BlockBehaviours {
+    behaviours: Vec<Option<Arc<dyn BlockBehaviour>>>,
+}
+
+impl BlockBehaviours {
+    /// Create with the built-in behaviours for any of the blocks in the registry that use them
+    pub fn with_default_behaviours(block_registry: &BlockRegistry) -> Self {
+        let mut behaviours = Self::default();
+
+        if let (Some(grass), Some(dirt)) = (
+            block_registry.get_id("grass"),
+            block_registry.get_id("dirt"),
+        ) {
+            behaviours.register(grass, GrassBehaviour { grass, dirt });
+        }
+
+        behaviours
+    }
+
+    /// Set the behaviour of the block with the given ID, replacing any existing behaviour
+    pub fn register(&mut self, block_id: BlockId, behaviour: impl BlockBehaviour + 'static) {
+        let index = block_id.as_usize();
+        if index >= self.behaviours.len() {
+            self.behaviours.resize(index + 1, None);
+        }
+
+        self.behaviours[index] = Some(Arc::new(behaviour));
+    }
+
+    /// Returns the behaviour of the block with the given ID, if it has one
+    pub fn get(&self, block_id: BlockId) -> Option<&Arc<dyn BlockBehaviour>> {
+        self.behaviours.get(block_id.as_usize())?.as_ref()
+    }
+}
+
+/// Grass spreads onto nearby dirt which is lit from above, and turns back into dirt when it is
+/// covered by an opaque block
+#[derive(Clone, Copy, Debug)]
+pub struct GrassBehaviour {
+    pub grass: BlockId,
+    pub dirt: BlockId,
+}
+
+impl GrassBehaviour {
+    /// Minimum skylight above a dirt block for grass to spread onto it
+    const MIN_SKYLIGHT: u8 = 9;
+
+    /// True if the block at the given position has nothing opaque above it and is lit well enough
+    /// for grass to grow
+    fn can_grow(terrain: &Terrain, pos: GlobalBlockPosition) -> bool {
+        let above_pos = pos + GlobalBlockPosition::from(IVec3::Y);
+
+        let is_covered = terrain
+            .get_loaded_block(&above_pos)
+            .is_none_or(|above_id| terrain.block_registry()[above_id].is_opaque());
+
+        !is_covered
+            && terrain
+                .get_loaded_skylight(&above_pos)
+                .is_some_and(|Skylight(skylight)| skylight >= Self::MIN_SKYLIGHT)
+    }
+}
+
+impl BlockBehaviour for GrassBehaviour {
+    fn random_tick(&self, terrain: &mut Terrain, pos: GlobalBlockPosition) {
+        let above_pos = pos + GlobalBlockPosition::from(IVec3::Y);
+        if terrain
+            .get_loaded_block(&above_pos)
+            .is_some_and(|above_id| terrain.block_registry()[above_id].is_opaque())
+        {
+            terrain.set_loaded_block(&pos, self.dirt);
+            return;
+        }
+
+        // spread to a random dirt block nearby, mostly below the grass so that it can climb down
+        // slopes
+        let mut rng = rand::thread_rng();
+        let target_pos = pos
+            + GlobalBlockPosition::from(IVec3::new(
+                rng.gen_range(-1..=1),
+                rng.gen_range(-3..=1),
+                rng.gen_range(-1..=1),
+            ));
+
+        if terrain.get_loaded_block(&target_pos) == Some(self.dirt)
+            && Self::can_grow(terrain, target_pos)
+        {
+            terrain.set_loaded_block(&target_pos, self.grass);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::terrain::{
+        block::BLOCK_AIR,
+        tests::{test_terrain, wait_until},
+    };
+
+    const BLOCK_DEFINITIONS: [&str; 3] = [
+        r#"
+            name = "stone"
+            model = { type = "full_block", textures = { all = "stone" } }
+        "#,
+        r#"
+            name = "dirt"
+            model = { type = "full_block", textures = { all = "dirt" } }
+        "#,
+        r#"
+            name = "grass"
+            model = { type = "full_block", textures = { all = "grass" } }
+        "#,
+    ];
+
+    /// Records the positions and tick indices of the scheduled ticks it receives
+    #[derive(Debug, Default)]
+    struct TickRecorder {
+        ticks: Arc<Mutex<Vec<(GlobalBlockPosition, u64)>>>,
+    }
+
+    impl BlockBehaviour for TickRecorder {
+        fn scheduled_tick(&self, terrain: &mut Terrain, pos: GlobalBlockPosition) {
+            self.ticks.lock().unwrap().push((pos, terrain.tick_index()));
+        }
+    }
+
+    #[test]
+    fn scheduled_ticks() {
+        let (mut terrain, _tasks, load_area_index) =
+            test_terrain("scheduled-ticks", &BLOCK_DEFINITIONS);
+        let stone = terrain.block_registry().get_id("stone").unwrap();
+        let recorder = TickRecorder::default();
+        let ticks = recorder.ticks.clone();
+        terrain.block_behaviours_mut().register(stone, recorder);
+
+        let pos = GlobalBlockPosition::new(1, 1, 1);
+        assert!(terrain.set_block(load_area_index, &pos, stone));
+        let start = terrain.tick_index();
+        assert!(terrain.schedule_tick(&pos, 3));
+        // the same request is only run once
+        assert!(terrain.schedule_tick(&pos, 3));
+
+        terrain.tick();
+        terrain.tick();
+        assert!(ticks.lock().unwrap().is_empty());
+        terrain.tick();
+        assert_eq!(*ticks.lock().unwrap(), vec![(pos, start + 3)]);
+        terrain.tick();
+        assert_eq!(ticks.lock().unwrap().len(), 1);
+
+        // requests for blocks outside of the loaded chunks are dropped
+        assert!(!terrain.schedule_tick(&GlobalBlockPosition::new(1000, 1, 1), 1));
+
+        // the tick runs the behaviour of the block at the position when it is due, so nothing
+        // happens once the block has changed
+        assert!(terrain.schedule_tick(&pos, 1));
+        assert!(terrain.set_block(load_area_index, &pos, BLOCK_AIR));
+        terrain.tick();
+        assert_eq!(ticks.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn grass() {
+        let (mut terrain, mut tasks, load_area_index) = test_terrain("grass", &BLOCK_DEFINITIONS);
+        let stone = terrain.block_registry().get_id("stone").unwrap();
+        let dirt = terrain.block_registry().get_id("dirt").unwrap();
+        let grass = terrain.block_registry().get_id("grass").unwrap();
+        let behaviour = GrassBehaviour { grass, dirt };
+
+        let grass_pos = GlobalBlockPosition::new(1, 1, 1);
+        let dirt_pos = GlobalBlockPosition::new(2, 1, 1);
+        let above_dirt_pos = dirt_pos + GlobalBlockPosition::from(IVec3::Y);
+        assert!(terrain.set_block(load_area_index, &grass_pos, grass));
+        assert!(terrain.set_block(load_area_index, &dirt_pos, dirt));
+        wait_until(&mut terrain, &mut tasks, |terrain| {
+            terrain.get_loaded_skylight(&above_dirt_pos) == Some(Skylight(Skylight::MAX_VALUE))
+        });
+
+        // grass spreads onto the dirt, which is open to the sky
+        assert!(GrassBehaviour::can_grow(&terrain, dirt_pos));
+        for _ in 0..10000 {
+            behaviour.random_tick(&mut terrain, grass_pos);
+            if terrain.get_loaded_block(&dirt_pos) == Some(grass) {
+                break;
+            }
+        }
+        assert_eq!(terrain.get_loaded_block(&dirt_pos), Some(grass));
+
+        // covered grass turns back into dirt, and can't grow again
+        assert!(terrain.set_block(load_area_index, &above_dirt_pos, stone));
+        assert!(!GrassBehaviour::can_grow(&terrain, dirt_pos));
+        behaviour.random_tick(&mut terrain, dirt_pos);
+        assert_eq!(terrain.get_loaded_block(&dirt_pos), Some(dirt));
+    }
+}
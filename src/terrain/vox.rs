@@ -0,0 +1,1043 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use glam::{I64Vec3, IVec3, UVec3};
+use itertools::Itertools;
+use rustc_hash::FxHashMap;
+
+use super::{
+    block::{model::BlockModel, registry::BlockRegistry, Block, BlockId, BLOCK_AIR},
+    edit::Clipboard,
+    position_types::GlobalBlockPosition,
+    Terrain,
+};
+use crate::util::{face::FaceIndex, size::Size3};
+
+/// Version number written to the header of .vox files
+const VOX_VERSION: i32 = 150;
+
+/// Largest size of a model in each direction
+pub const MAX_MODEL_SIZE: u32 = 256;
+
+/// Largest number of blocks in the bounding box of a scene read from a file, so that models placed
+/// far apart can't make `to_clipboard` allocate more memory than is available
+pub const MAX_SCENE_VOLUME: u64 = 1 << 26;
+
+/// Number of colours in a palette, including the unused colour index 0
+const PALETTE_SIZE: usize = 256;
+
+/// Colour of blocks with no texture when exporting
+const FALLBACK_COLOR: [u8; 3] = [255, 0, 255];
+
+/// A MagicaVoxel scene: a set of models with positions, sharing one palette.
+/// MagicaVoxel uses a Z-up coordinate system, so the vox position (x, y, z) corresponds to the
+/// block position (x, z, -y)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VoxScene {
+    pub models: Vec<VoxModel>,
+    /// RGBA colour of each colour index. Index 0 means there is no voxel and is never used
+    pub palette: Vec<[u8; 4]>,
+}
+
+/// A model of at most `MAX_MODEL_SIZE` voxels in each direction
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VoxModel {
+    pub size: UVec3,
+    /// Position of the minimum corner of the model in the scene
+    pub offset: IVec3,
+    pub voxels: Vec<Voxel>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Voxel {
+    /// Position of the voxel within the model
+    pub pos: [u8; 3],
+    /// Index of the voxel's colour in the palette, never 0
+    pub color_index: u8,
+}
+
+impl VoxScene {
+    /// Read a scene from a file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, VoxError> {
+        let path = path.as_ref();
+        let data = fs::read(path).map_err(|e| VoxError::IoError(path.to_path_buf(), e))?;
+
+        Self::read(&data)
+    }
+
+    /// Write the scene to a file
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), VoxError> {
+        let path = path.as_ref();
+
+        fs::write(path, self.write()).map_err(|e| VoxError::IoError(path.to_path_buf(), e))
+    }
+
+    /// Read a scene in the .vox format.
+    /// The translations of the scene graph are applied to the models; rotations are ignored
+    pub fn read(data: &[u8]) -> Result<Self, VoxError> {
+        let mut reader = VoxReader { data };
+
+        if reader.read_bytes(4)? != b"VOX " {
+            return Err(VoxError::InvalidHeader);
+        }
+        let _version = reader.read_i32()?;
+
+        let (id, _, mut children) = reader.read_chunk()?;
+        if id != b"MAIN" {
+            return Err(VoxError::InvalidChunk("MAIN"));
+        }
+
+        let mut sizes = Vec::new();
+        let mut models = Vec::new();
+        let mut palette = default_palette();
+        let mut nodes = FxHashMap::default();
+
+        while !children.data.is_empty() {
+            let (id, mut content, _) = children.read_chunk()?;
+
+            match id {
+                b"SIZE" => {
+                    let size = content.read_ivec3()?;
+                    if size.cmplt(IVec3::ONE).any()
+                        || size.cmpgt(IVec3::splat(MAX_MODEL_SIZE as i32)).any()
+                    {
+                        return Err(VoxError::InvalidChunk("SIZE"));
+                    }
+                    sizes.push(size.as_uvec3());
+                }
+                b"XYZI" => {
+                    let size = *sizes
+                        .get(models.len())
+                        .ok_or(VoxError::InvalidChunk("XYZI"))?;
+                    let voxel_count = content.read_len()?;
+
+                    let mut voxels = Vec::with_capacity(voxel_count.min(content.data.len() / 4));
+                    for _ in 0..voxel_count {
+                        let bytes = content.read_bytes(4)?;
+                        let voxel = Voxel {
+                            pos: [bytes[0], bytes[1], bytes[2]],
+                            color_index: bytes[3],
+                        };
+
+                        if UVec3::from(voxel.pos.map(u32::from)).cmpge(size).any() {
+                            return Err(VoxError::InvalidChunk("XYZI"));
+                        }
+                        if voxel.color_index != 0 {
+                            voxels.push(voxel);
+                        }
+                    }
+
+                    models.push(VoxModel {
+                        size,
+                        offset: IVec3::ZERO,
+                        voxels,
+                    });
+                }
+                b"RGBA" => {
+                    // entry i of the chunk is the colour with index i + 1
+                    for color in &mut palette[1..] {
+                        let bytes = content.read_bytes(4)?;
+                        *color = [bytes[0], bytes[1], bytes[2], bytes[3]];
+                    }
+                }
+                b"nTRN" => {
+                    let node_id = content.read_i32()?;
+                    let _attributes = content.read_dict()?;
+                    let child_id = content.read_i32()?;
+                    let _reserved = content.read_i32()?;
+                    let _layer_id = content.read_i32()?;
+                    let frame_count = content.read_len()?;
+
+                    let mut translation = IVec3::ZERO;
+                    for frame_index in 0..frame_count {
+                        let frame = content.read_dict()?;
+                        if frame_index != 0 {
+                            continue;
+                        }
+
+                        if let Some((_, value)) = frame.iter().find(|(key, _)| key == "_t") {
+                            translation =
+                                parse_translation(value).ok_or(VoxError::InvalidChunk("nTRN"))?;
+                        }
+                        if frame.iter().any(|(key, value)| key == "_r" && value != "4") {
+                            log::warn!("rotations in .vox files are not supported");
+                        }
+                    }
+
+                    nodes.insert(
+                        node_id,
+                        SceneNode::Transform {
+                            translation,
+                            child_id,
+                        },
+                    );
+                }
+                b"nGRP" => {
+                    let node_id = content.read_i32()?;
+                    let _attributes = content.read_dict()?;
+                    let child_count = content.read_len()?;
+                    let child_ids = (0..child_count)
+                        .map(|_| content.read_i32())
+                        .collect::<Result<Vec<_>, _>>()?;
+
+                    nodes.insert(node_id, SceneNode::Group { child_ids });
+                }
+                b"nSHP" => {
+                    let node_id = content.read_i32()?;
+                    let _attributes = content.read_dict()?;
+                    let model_count = content.read_len()?;
+
+                    let mut model_ids = Vec::new();
+                    for _ in 0..model_count {
+                        model_ids.push(content.read_i32()?);
+                        let _model_attributes = content.read_dict()?;
+                    }
+
+                    nodes.insert(node_id, SceneNode::Shape { model_ids });
+                }
+                // other chunks, such as materials and layers, don't affect the blocks
+                _ => (),
+            }
+        }
+
+        // without a scene graph, all models are at the origin
+        if nodes.contains_key(&0) {
+            let mut placed_models = Vec::new();
+            place_scene_node(&nodes, &models, 0, IVec3::ZERO, 0, &mut placed_models)?;
+            models = placed_models;
+        }
+
+        let scene = Self { models, palette };
+        let volume = scene.bounding_box_volume();
+        if volume > MAX_SCENE_VOLUME {
+            return Err(VoxError::SceneTooLarge(volume, MAX_SCENE_VOLUME));
+        }
+
+        Ok(scene)
+    }
+
+    /// Returns the number of voxels in the bounding box of the models
+    fn bounding_box_volume(&self) -> u64 {
+        let Some(min) = self
+            .models
+            .iter()
+            .map(|model| model.offset.as_i64vec3())
+            .reduce(I64Vec3::min)
+        else {
+            return 0;
+        };
+        let max = self
+            .models
+            .iter()
+            .map(|model| model.offset.as_i64vec3() + model.size.as_i64vec3())
+            .reduce(I64Vec3::max)
+            .unwrap_or(min);
+
+        (max - min).as_u64vec3().to_array().into_iter().product()
+    }
+
+    /// Write the scene in the .vox format, with a scene graph giving the position of each model
+    pub fn write(&self) -> Vec<u8> {
+        let mut children = Vec::new();
+
+        for model in &self.models {
+            let mut size = Vec::new();
+            write_ivec3(&mut size, model.size.as_ivec3());
+            write_chunk(&mut children, b"SIZE", &size, &[]);
+
+            let mut xyzi = Vec::with_capacity(4 + 4 * model.voxels.len());
+            write_i32(&mut xyzi, model.voxels.len() as i32);
+            for voxel in &model.voxels {
+                xyzi.extend_from_slice(&voxel.pos);
+                xyzi.push(voxel.color_index);
+            }
+            write_chunk(&mut children, b"XYZI", &xyzi, &[]);
+        }
+
+        // scene graph: a root transform containing a group with a transform and shape per model
+        let group_id = 1;
+        let child_ids = (0..self.models.len())
+            .map(|model_index| 2 + 2 * model_index as i32)
+            .collect_vec();
+
+        write_chunk(
+            &mut children,
+            b"nTRN",
+            &transform_node(0, group_id, IVec3::ZERO),
+            &[],
+        );
+
+        let mut group = Vec::new();
+        write_i32(&mut group, group_id);
+        write_dict(&mut group, &[]);
+        write_i32(&mut group, child_ids.len() as i32);
+        for &child_id in &child_ids {
+            write_i32(&mut group, child_id);
+        }
+        write_chunk(&mut children, b"nGRP", &group, &[]);
+
+        for (model_index, (model, &node_id)) in self.models.iter().zip(&child_ids).enumerate() {
+            // MagicaVoxel positions models by their centre
+            let translation = model.offset + (model.size / 2).as_ivec3();
+            write_chunk(
+                &mut children,
+                b"nTRN",
+                &transform_node(node_id, node_id + 1, translation),
+                &[],
+            );
+
+            let mut shape = Vec::new();
+            write_i32(&mut shape, node_id + 1);
+            write_dict(&mut shape, &[]);
+            write_i32(&mut shape, 1);
+            write_i32(&mut shape, model_index as i32);
+            write_dict(&mut shape, &[]);
+            write_chunk(&mut children, b"nSHP", &shape, &[]);
+        }
+
+        let rgba = self.palette[1..]
+            .iter()
+            .chain(&[[0; 4]])
+            .flatten()
+            .copied()
+            .collect_vec();
+        write_chunk(&mut children, b"RGBA", &rgba, &[]);
+
+        let mut data = Vec::new();
+        data.extend_from_slice(b"VOX ");
+        write_i32(&mut data, VOX_VERSION);
+        write_chunk(&mut data, b"MAIN", &[], &children);
+        data
+    }
+
+    /// Create a scene containing the blocks in a clipboard, split into models of at most
+    /// `MAX_MODEL_SIZE` blocks in each direction. Each block is given the colour from
+    /// `block_colors`; air is left empty.
+    /// Returns an error if the blocks have more colours than fit in the palette
+    pub fn from_clipboard(
+        clipboard: &Clipboard,
+        block_registry: &BlockRegistry,
+        block_colors: &BlockColors,
+    ) -> Result<Self, VoxError> {
+        let size = clipboard.size();
+        let vox_size = UVec3::new(size.x as u32, size.z as u32, size.y as u32);
+        let model_counts = (vox_size + MAX_MODEL_SIZE - 1) / MAX_MODEL_SIZE;
+
+        let mut palette = vec![[0; 4]; PALETTE_SIZE];
+        let mut color_indices: FxHashMap<BlockId, u8> = FxHashMap::default();
+        let mut color_count = 0;
+
+        let mut models = Vec::new();
+        for (z, y, x) in
+            itertools::iproduct!(0..model_counts.z, 0..model_counts.y, 0..model_counts.x)
+        {
+            let offset = UVec3::new(x, y, z) * MAX_MODEL_SIZE;
+            let model_size = (vox_size - offset).min(UVec3::splat(MAX_MODEL_SIZE));
+            let mut voxels = Vec::new();
+
+            for (vz, vy, vx) in
+                itertools::iproduct!(0..model_size.z, 0..model_size.y, 0..model_size.x)
+            {
+                let vox_pos = offset + UVec3::new(vx, vy, vz);
+                let block_id =
+                    clipboard.get(UVec3::new(vox_pos.x, vox_pos.z, vox_size.y - 1 - vox_pos.y));
+                if block_id == BLOCK_AIR {
+                    continue;
+                }
+
+                let color_index = match color_indices.get(&block_id) {
+                    Some(&color_index) => color_index,
+                    None => {
+                        let [r, g, b] = block_colors.color_of_block(block_id, block_registry);
+                        let existing_index = palette[1..=color_count]
+                            .iter()
+                            .position(|&color| color == [r, g, b, 255])
+                            .map(|index| index as u8 + 1);
+
+                        let color_index = match existing_index {
+                            Some(color_index) => color_index,
+                            None if color_count + 1 < PALETTE_SIZE => {
+                                color_count += 1;
+                                palette[color_count] = [r, g, b, 255];
+                                color_count as u8
+                            }
+                            None => return Err(VoxError::TooManyColors),
+                        };
+
+                        color_indices.insert(block_id, color_index);
+                        color_index
+                    }
+                };
+
+                voxels.push(Voxel {
+                    pos: [vx as u8, vy as u8, vz as u8],
+                    color_index,
+                });
+            }
+
+            models.push(VoxModel {
+                size: model_size,
+                offset: offset.as_ivec3(),
+                voxels,
+            });
+        }
+
+        Ok(Self { models, palette })
+    }
+
+    /// Convert the scene to a clipboard covering the bounding box of its models. Each colour is
+    /// replaced with the block chosen by `block_colors`; empty space becomes air
+    pub fn to_clipboard(&self, block_colors: &BlockColors) -> Clipboard {
+        let Some(min) = self
+            .models
+            .iter()
+            .map(|model| model.offset)
+            .reduce(IVec3::min)
+        else {
+            return Clipboard::new(Size3::ZERO, Vec::new());
+        };
+        let max = self
+            .models
+            .iter()
+            .map(|model| model.offset + model.size.as_ivec3())
+            .reduce(IVec3::max)
+            .unwrap_or(min);
+
+        let vox_size = (max - min).as_uvec3();
+        let size = Size3::new(
+            vox_size.x as usize,
+            vox_size.z as usize,
+            vox_size.y as usize,
+        );
+
+        let block_ids = self
+            .palette
+            .iter()
+            .map(|&[r, g, b, _]| block_colors.block_for_color([r, g, b]).unwrap_or(BLOCK_AIR))
+            .collect_vec();
+
+        let mut blocks = vec![BLOCK_AIR; size.product()];
+        for model in &self.models {
+            let offset = (model.offset - min).as_uvec3();
+
+            for voxel in &model.voxels {
+                let vox_pos = offset + UVec3::from(voxel.pos.map(u32::from));
+                let pos = UVec3::new(vox_pos.x, vox_pos.z, vox_size.y - 1 - vox_pos.y);
+
+                blocks[size.flatten(pos)] = block_ids[voxel.color_index as usize];
+            }
+        }
+
+        Clipboard::new(size, blocks)
+    }
+
+    /// Copy the blocks in the box between the given corners, which are both inside the box, into
+    /// a scene
+    pub fn copy_from_terrain(
+        terrain: &Terrain,
+        a: GlobalBlockPosition,
+        b: GlobalBlockPosition,
+        block_colors: &BlockColors,
+    ) -> Result<Self, VoxError> {
+        let clipboard = terrain.copy_region(a, b).ok_or(VoxError::RegionNotLoaded)?;
+
+        Self::from_clipboard(&clipboard, terrain.block_registry(), block_colors)
+    }
+}
+
+/// Colours used to match blocks to the colours of .vox palettes.
+/// Palette colours listed in a mapping file are replaced with the mapped block; other colours are
+/// replaced with the block type with the nearest colour
+#[derive(Clone, Debug, Default)]
+pub struct BlockColors {
+    /// Colour of each block, including every state
+    block_colors: FxHashMap<BlockId, [u8; 3]>,
+    /// Block types which can be chosen by nearest colour
+    candidates: Vec<(BlockId, [u8; 3])>,
+    /// Blocks assigned to palette colours by a mapping file
+    mapped_blocks: FxHashMap<[u8; 3], BlockId>,
+}
+
+impl BlockColors {
+    /// Find the colour of each block using a function returning the colour of a block, if it has
+    /// one. Only the default states of block types with a colour, excluding flowing fluids, are
+    /// chosen by nearest colour
+    pub fn from_fn(
+        block_registry: &BlockRegistry,
+        mut block_color: impl FnMut(&Block) -> Option<[u8; 3]>,
+    ) -> Self {
+        let block_colors: FxHashMap<_, _> = block_registry
+            .iter()
+            .filter(|&(block_id, _)| block_id != BLOCK_AIR)
+            .filter_map(|(block_id, block)| Some((block_id, block_color(block)?)))
+            .collect();
+
+        let candidates = block_registry
+            .iter()
+            .filter(|(block_id, block)| {
+                *block_id == block.default_state
+                    && block.fluid.is_none_or(|fluid| fluid.is_source())
+            })
+            .filter_map(|(block_id, _)| Some((block_id, *block_colors.get(&block_id)?)))
+            .collect();
+
+        Self {
+            block_colors,
+            candidates,
+            mapped_blocks: FxHashMap::default(),
+        }
+    }
+
+    /// Use the average colour of the top texture of each block, loaded from `texture_dir`
+    pub fn from_textures(
+        block_registry: &BlockRegistry,
+        texture_dir: impl AsRef<Path>,
+    ) -> Result<Self, VoxError> {
+        let texture_colors = block_registry
+            .texture_names()
+            .iter()
+            .map(|texture_name| {
+                let path = texture_dir
+                    .as_ref()
+                    .join(texture_name)
+                    .with_extension("png");
+                let image = image::open(&path)
+                    .map_err(|e| VoxError::ImageError(path.clone(), e))?
+                    .into_rgba8();
+
+                Ok(average_color(image.pixels().map(|pixel| pixel.0)))
+            })
+            .collect::<Result<Vec<_>, VoxError>>()?;
+
+        Ok(Self::from_fn(block_registry, |block| {
+            let face = match block.model {
+                BlockModel::Cross(face) => Some(face),
+                _ => block.model.box_face(FaceIndex::POS_Y),
+            };
+
+            texture_colors[face?.texture_index]
+        }))
+    }
+
+    /// Load a mapping file assigning blocks to palette colours
+    pub fn load_mapping(
+        &mut self,
+        path: impl AsRef<Path>,
+        block_registry: &BlockRegistry,
+    ) -> Result<(), VoxError> {
+        let path = path.as_ref();
+        let source =
+            fs::read_to_string(path).map_err(|e| VoxError::IoError(path.to_path_buf(), e))?;
+
+        self.parse_mapping(&source, block_registry)
+    }
+
+    /// Parse a mapping file, a TOML table of block names keyed by hex colour, e.g.
+    /// `"#7f7f7f" = "stone"`
+    pub fn parse_mapping(
+        &mut self,
+        source: &str,
+        block_registry: &BlockRegistry,
+    ) -> Result<(), VoxError> {
+        let mapping: FxHashMap<String, String> =
+            toml::from_str(source).map_err(|e| VoxError::MappingParseError(Box::new(e)))?;
+
+        for (color, block_name) in mapping {
+            let color =
+                parse_hex_color(&color).ok_or_else(|| VoxError::InvalidMappingColor(color))?;
+            let block_id = block_registry
+                .get_id(&block_name)
+                .ok_or_else(|| VoxError::UnknownMappingBlock(block_name))?;
+
+            self.mapped_blocks.insert(color, block_id);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the block for a palette colour: the block it is mapped to, or else the block type
+    /// with the nearest colour. Returns None if there are no blocks to choose from
+    pub fn block_for_color(&self, color: [u8; 3]) -> Option<BlockId> {
+        if let Some(&block_id) = self.mapped_blocks.get(&color) {
+            return Some(block_id);
+        }
+
+        self.candidates
+            .iter()
+            .min_by_key(|(_, candidate_color)| color_distance_squared(color, *candidate_color))
+            .map(|&(block_id, _)| block_id)
+    }
+
+    /// Returns the palette colour for a block: a colour mapped to the block, or else the colour of
+    /// the block itself or its default state
+    pub fn color_of_block(&self, block_id: BlockId, block_registry: &BlockRegistry) -> [u8; 3] {
+        let default_state = block_registry[block_id].default_state;
+
+        self.mapped_blocks
+            .iter()
+            .filter(|&(_, &mapped_id)| mapped_id == block_id)
+            .map(|(&color, _)| color)
+            .min()
+            .or_else(|| self.block_colors.get(&block_id).copied())
+            .or_else(|| self.block_colors.get(&default_state).copied())
+            .unwrap_or(FALLBACK_COLOR)
+    }
+}
+
+/// Node of the scene graph of a .vox file
+enum SceneNode {
+    Transform { translation: IVec3, child_id: i32 },
+    Group { child_ids: Vec<i32> },
+    Shape { model_ids: Vec<i32> },
+}
+
+/// Depth limit when traversing the scene graph, so that cycles don't recurse forever
+const MAX_SCENE_DEPTH: u32 = 64;
+
+/// Add the models below a node of the scene graph to `placed_models`, moved by the translations
+/// of the transform nodes above them
+fn place_scene_node(
+    nodes: &FxHashMap<i32, SceneNode>,
+    models: &[VoxModel],
+    node_id: i32,
+    translation: IVec3,
+    depth: u32,
+    placed_models: &mut Vec<VoxModel>,
+) -> Result<(), VoxError> {
+    if depth > MAX_SCENE_DEPTH {
+        return Err(VoxError::InvalidSceneGraph);
+    }
+
+    match nodes.get(&node_id) {
+        Some(SceneNode::Transform {
+            translation: node_translation,
+            child_id,
+        }) => {
+            let translation = checked_add_ivec3(translation, *node_translation)
+                .ok_or(VoxError::InvalidSceneGraph)?;
+            place_scene_node(
+                nodes,
+                models,
+                *child_id,
+                translation,
+                depth + 1,
+                placed_models,
+            )
+        }
+        Some(SceneNode::Group { child_ids }) => child_ids.iter().try_for_each(|&child_id| {
+            place_scene_node(
+                nodes,
+                models,
+                child_id,
+                translation,
+                depth + 1,
+                placed_models,
+            )
+        }),
+        Some(SceneNode::Shape { model_ids }) => {
+            for &model_id in model_ids {
+                let mut model = usize::try_from(model_id)
+                    .ok()
+                    .and_then(|model_id| models.get(model_id))
+                    .ok_or(VoxError::InvalidChunk("nSHP"))?
+                    .clone();
+
+                // the translation is the position of the centre of the model
+                model.offset = checked_add_ivec3(translation, -(model.size / 2).as_ivec3())
+                    .ok_or(VoxError::InvalidSceneGraph)?;
+                placed_models.push(model);
+            }
+            Ok(())
+        }
+        None => Err(VoxError::InvalidSceneGraph),
+    }
+}
+
+/// Add two vectors, returning None if any component overflows
+fn checked_add_ivec3(a: IVec3, b: IVec3) -> Option<IVec3> {
+    Some(IVec3::new(
+        a.x.checked_add(b.x)?,
+        a.y.checked_add(b.y)?,
+        a.z.checked_add(b.z)?,
+    ))
+}
+
+/// Returns the palette MagicaVoxel uses when a file has no RGBA chunk
+fn default_palette() -> Vec<[u8; 4]> {
+    const CUBE_LEVELS: [u8; 6] = [0xff, 0xcc, 0x99, 0x66, 0x33, 0x00];
+    const RAMP_LEVELS: [u8; 10] = [0xee, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+
+    let mut palette = vec![[0; 4]];
+
+    // a 6x6x6 colour cube with blue changing fastest, without black
+    for (r, g, b) in itertools::iproduct!(CUBE_LEVELS, CUBE_LEVELS, CUBE_LEVELS) {
+        if (r, g, b) != (0, 0, 0) {
+            palette.push([r, g, b, 255]);
+        }
+    }
+
+    // ramps of red, green, blue and grey
+    palette.extend(RAMP_LEVELS.map(|level| [level, 0, 0, 255]));
+    palette.extend(RAMP_LEVELS.map(|level| [0, level, 0, 255]));
+    palette.extend(RAMP_LEVELS.map(|level| [0, 0, level, 255]));
+    palette.extend(RAMP_LEVELS.map(|level| [level, level, level, 255]));
+
+    palette
+}
+
+/// Returns the average colour of the pixels which aren't fully transparent, or None if every
+/// pixel is transparent
+fn average_color(pixels: impl Iterator<Item = [u8; 4]>) -> Option<[u8; 3]> {
+    let (sum, count) = pixels.filter(|&[_, _, _, a]| a != 0).fold(
+        ([0u64; 3], 0u64),
+        |(sum, count), [r, g, b, _]| {
+            (
+                [sum[0] + r as u64, sum[1] + g as u64, sum[2] + b as u64],
+                count + 1,
+            )
+        },
+    );
+
+    (count != 0).then(|| sum.map(|channel| (channel / count) as u8))
+}
+
+fn color_distance_squared(a: [u8; 3], b: [u8; 3]) -> u32 {
+    a.iter()
+        .zip(&b)
+        .map(|(&a, &b)| (a as i32 - b as i32).pow(2) as u32)
+        .sum()
+}
+
+/// Parse a colour written as `#rrggbb`
+fn parse_hex_color(color: &str) -> Option<[u8; 3]> {
+    let hex = color.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    Some([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+}
+
+/// Parse the translation of a transform node, written as `x y z`
+fn parse_translation(value: &str) -> Option<IVec3> {
+    let (x, y, z) = value
+        .split_whitespace()
+        .map(|component| component.parse().ok())
+        .collect_tuple()?;
+
+    Some(IVec3::new(x?, y?, z?))
+}
+
+/// Reads the little-endian values of a .vox file
+struct VoxReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> VoxReader<'a> {
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], VoxError> {
+        if self.data.len() < len {
+            return Err(VoxError::UnexpectedEnd);
+        }
+
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn read_i32(&mut self) -> Result<i32, VoxError> {
+        let bytes = self.read_bytes(4)?;
+        Ok(i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Read a non-negative length or count
+    fn read_len(&mut self) -> Result<usize, VoxError> {
+        let len = self.read_i32()?;
+        usize::try_from(len).map_err(|_| VoxError::InvalidLength(len))
+    }
+
+    fn read_ivec3(&mut self) -> Result<IVec3, VoxError> {
+        Ok(IVec3::new(
+            self.read_i32()?,
+            self.read_i32()?,
+            self.read_i32()?,
+        ))
+    }
+
+    fn read_string(&mut self) -> Result<String, VoxError> {
+        let len = self.read_len()?;
+        String::from_utf8(self.read_bytes(len)?.to_vec()).map_err(|_| VoxError::InvalidString)
+    }
+
+    fn read_dict(&mut self) -> Result<Vec<(String, String)>, VoxError> {
+        let len = self.read_len()?;
+
+        (0..len)
+            .map(|_| Ok((self.read_string()?, self.read_string()?)))
+            .collect()
+    }
+
+    /// Read a chunk, returning its ID and readers for its content and its children
+    fn read_chunk(&mut self) -> Result<(&'a [u8], Self, Self), VoxError> {
+        let id = self.read_bytes(4)?;
+        let content_len = self.read_len()?;
+        let children_len = self.read_len()?;
+
+        let content = Self {
+            data: self.read_bytes(content_len)?,
+        };
+        let children = Self {
+            data: self.read_bytes(children_len)?,
+        };
+
+        Ok((id, content, children))
+    }
+}
+
+fn write_i32(data: &mut Vec<u8>, value: i32) {
+    data.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_ivec3(data: &mut Vec<u8>, value: IVec3) {
+    write_i32(data, value.x);
+    write_i32(data, value.y);
+    write_i32(data, value.z);
+}
+
+fn write_string(data: &mut Vec<u8>, value: &str) {
+    write_i32(data, value.len() as i32);
+    data.extend_from_slice(value.as_bytes());
+}
+
+fn write_dict(data: &mut Vec<u8>, entries: &[(&str, &str)]) {
+    write_i32(data, entries.len() as i32);
+    for (key, value) in entries {
+        write_string(data, key);
+        write_string(data, value);
+    }
+}
+
+fn write_chunk(data: &mut Vec<u8>, id: &[u8; 4], content: &[u8], children: &[u8]) {
+    data.extend_from_slice(id);
+    write_i32(data, content.len() as i32);
+    write_i32(data, children.len() as i32);
+    data.extend_from_slice(content);
+    data.extend_from_slice(children);
+}
+
+/// Returns the content of a transform node with one frame
+fn transform_node(node_id: i32, child_id: i32, translation: IVec3) -> Vec<u8> {
+    let mut content = Vec::new();
+    write_i32(&mut content, node_id);
+    write_dict(&mut content, &[]);
+    write_i32(&mut content, child_id);
+    write_i32(&mut content, -1);
+    write_i32(&mut content, -1);
+    write_i32(&mut content, 1);
+
+    let translation = format!("{} {} {}", translation.x, translation.y, translation.z);
+    write_dict(&mut content, &[("_t", &translation)]);
+    content
+}
+
+/// Errors returned when reading or writing a .vox file
+#[derive(Debug, thiserror::Error)]
+pub enum VoxError {
+    #[error("io error accessing {0}: {1}")]
+    IoError(PathBuf, io::Error),
+    #[error("error loading texture {0}: {1}")]
+    ImageError(PathBuf, image::ImageError),
+    #[error("error parsing colour mapping: {0}")]
+    MappingParseError(Box<toml::de::Error>),
+    #[error("invalid colour `{0}` in colour mapping, expected `#rrggbb`")]
+    InvalidMappingColor(String),
+    #[error("unknown block `{0}` in colour mapping")]
+    UnknownMappingBlock(String),
+    #[error("not a .vox file")]
+    InvalidHeader,
+    #[error("unexpected end of data")]
+    UnexpectedEnd,
+    #[error("invalid length {0}")]
+    InvalidLength(i32),
+    #[error("invalid UTF-8 string")]
+    InvalidString,
+    #[error("invalid {0} chunk")]
+    InvalidChunk(&'static str),
+    #[error("invalid scene graph")]
+    InvalidSceneGraph,
+    #[error("the scene's bounding box has {0} voxels, more than the limit of {1}")]
+    SceneTooLarge(u64, u64),
+    #[error("the blocks have more colours than fit in the palette")]
+    TooManyColors,
+    #[error("the region is not loaded")]
+    RegionNotLoaded,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::block::registry::test_registry;
+
+    fn test_registry_and_colors() -> (BlockRegistry, BlockColors) {
+        let block_registry = test_registry(&[
+            "name = \"stone\"\nmodel = { type = \"full_block\", textures = { all = \"stone\" } }",
+            "name = \"grass\"\nmodel = { type = \"full_block\", textures = { all = \"grass\" } }",
+            "name = \"sand\"\nmodel = { type = \"full_block\", textures = { all = \"sand\" } }",
+        ]);
+
+        let block_colors =
+            BlockColors::from_fn(&block_registry, |block| match block.name.as_str() {
+                "stone" => Some([128, 128, 128]),
+                "grass" => Some([40, 160, 40]),
+                "sand" => Some([220, 200, 140]),
+                _ => None,
+            });
+
+        (block_registry, block_colors)
+    }
+
+    #[test]
+    fn round_trip() {
+        let (block_registry, block_colors) = test_registry_and_colors();
+        let stone = block_registry.get_id("stone").unwrap();
+        let grass = block_registry.get_id("grass").unwrap();
+        let sand = block_registry.get_id("sand").unwrap();
+
+        let size = Size3::new(3, 4, 2);
+        let blocks = (0..size.product())
+            .map(|index| [BLOCK_AIR, stone, grass, sand][index % 4])
+            .collect_vec();
+        let clipboard = Clipboard::new(size, blocks);
+
+        let scene = VoxScene::from_clipboard(&clipboard, &block_registry, &block_colors).unwrap();
+        assert_eq!(scene.models.len(), 1);
+        assert_eq!(scene.models[0].size, UVec3::new(3, 2, 4));
+
+        let read_scene = VoxScene::read(&scene.write()).unwrap();
+        assert_eq!(read_scene, scene);
+        assert_eq!(read_scene.to_clipboard(&block_colors), clipboard);
+
+        assert!(VoxScene::read(b"VOX").is_err());
+    }
+
+    #[test]
+    fn split_into_models() {
+        let (block_registry, block_colors) = test_registry_and_colors();
+        let stone = block_registry.get_id("stone").unwrap();
+
+        // tall enough to need two models stacked on top of each other
+        let size = Size3::new(1, 300, 2);
+        let blocks = (0..size.product())
+            .map(|index| if index % 7 == 0 { stone } else { BLOCK_AIR })
+            .collect_vec();
+        let clipboard = Clipboard::new(size, blocks);
+
+        let scene = VoxScene::from_clipboard(&clipboard, &block_registry, &block_colors).unwrap();
+        assert_eq!(
+            scene
+                .models
+                .iter()
+                .map(|model| (model.offset, model.size))
+                .collect_vec(),
+            vec![
+                (IVec3::ZERO, UVec3::new(1, 2, 256)),
+                (IVec3::new(0, 0, 256), UVec3::new(1, 2, 44))
+            ]
+        );
+
+        let read_scene = VoxScene::read(&scene.write()).unwrap();
+        assert_eq!(read_scene.to_clipboard(&block_colors), clipboard);
+    }
+
+    #[test]
+    fn scene_limits() {
+        let model = VoxModel {
+            size: UVec3::ONE,
+            offset: IVec3::ZERO,
+            voxels: vec![Voxel {
+                pos: [0; 3],
+                color_index: 1,
+            }],
+        };
+
+        // translations which overflow when added together
+        let nodes = FxHashMap::from_iter([
+            (
+                0,
+                SceneNode::Transform {
+                    translation: IVec3::splat(i32::MAX),
+                    child_id: 1,
+                },
+            ),
+            (
+                1,
+                SceneNode::Transform {
+                    translation: IVec3::ONE,
+                    child_id: 2,
+                },
+            ),
+            (2, SceneNode::Shape { model_ids: vec![0] }),
+        ]);
+        let mut placed_models = Vec::new();
+        assert!(matches!(
+            place_scene_node(
+                &nodes,
+                std::slice::from_ref(&model),
+                0,
+                IVec3::ZERO,
+                0,
+                &mut placed_models
+            ),
+            Err(VoxError::InvalidSceneGraph)
+        ));
+
+        // models so far apart that the bounding box is too large to convert to a clipboard
+        let scene = VoxScene {
+            models: vec![
+                model.clone(),
+                VoxModel {
+                    offset: IVec3::splat(1 << 20),
+                    ..model
+                },
+            ],
+            palette: default_palette(),
+        };
+        assert!(matches!(
+            VoxScene::read(&scene.write()),
+            Err(VoxError::SceneTooLarge(..))
+        ));
+    }
+
+    #[test]
+    fn color_matching() {
+        let (block_registry, mut block_colors) = test_registry_and_colors();
+        let stone = block_registry.get_id("stone").unwrap();
+        let sand = block_registry.get_id("sand").unwrap();
+
+        assert_eq!(block_colors.block_for_color([100, 110, 120]), Some(stone));
+        assert_eq!(block_colors.block_for_color([255, 255, 0]), Some(sand));
+
+        block_colors
+            .parse_mapping("\"#ff0000\" = \"stone\"", &block_registry)
+            .unwrap();
+        assert_eq!(block_colors.block_for_color([255, 0, 0]), Some(stone));
+        assert_eq!(
+            block_colors.color_of_block(stone, &block_registry),
+            [255, 0, 0]
+        );
+
+        assert!(block_colors
+            .parse_mapping("\"#ff0000\" = \"diamond\"", &block_registry)
+            .is_err());
+        assert!(block_colors
+            .parse_mapping("\"red\" = \"stone\"", &block_registry)
+            .is_err());
+
+        let palette = default_palette();
+        assert_eq!(palette.len(), PALETTE_SIZE);
+        assert_eq!(palette[1], [255, 255, 255, 255]);
+        assert_eq!(palette[255], [0x11, 0x11, 0x11, 255]);
+    }
+}
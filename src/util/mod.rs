@@ -2,7 +2,6 @@ pub mod face;
 pub mod measure_time;
 pub mod size;
 pub mod transform;
-pub mod vector_map;
 
 /// Size of one degree in radians
 pub const DEGREE: f32 = std::f32::consts::PI / 180.0;